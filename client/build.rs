@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // only the grpc feature needs the generated code, so only it needs protoc
+    #[cfg(feature = "grpc")]
+    {
+        let mut config = prost_build::Config::new();
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_with_config(config, &["proto/bridge.proto"], &["proto"])?;
+    }
+    Ok(())
+}
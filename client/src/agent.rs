@@ -0,0 +1,317 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Args;
+use color_eyre::eyre::{Result, WrapErr as _};
+use shared::{AgentMessage, AgentText};
+use tokio::{io::AsyncWriteExt as _, process::Command};
+use tokio_serial::SerialPortBuilderExt as _;
+
+use crate::output::Event;
+
+/// The most of the clipboard the agent sends, the link only manages a few KiB a second.
+const CLIPBOARD_LIMIT: usize = 4096;
+
+#[derive(Args, Debug)]
+pub struct AgentArgs {
+    /// the teensy's own serial port on this machine, e.g. /dev/ttyACM0
+    port: PathBuf,
+
+    /// how often to look for changes
+    #[arg(long, default_value_t = 500)]
+    interval_ms: u64,
+
+    /// send the clipboard too, whenever it changes, from wl-paste or xclip
+    #[arg(long)]
+    clipboard: bool,
+}
+
+/// Run on the target, telling the client on the other end of the teensy about this
+/// machine's lock LEDs, keyboard layout and optionally its clipboard until stopped.
+pub async fn run(args: AgentArgs) -> Result<()> {
+    // it's a USB serial port so the baud rate is only for show
+    let mut port = tokio_serial::new(args.port.to_string_lossy(), shared::DEFAULT_BAUD_RATE)
+        .open_native_async()
+        .wrap_err_with(|| format!("Failed to open {}", args.port.display()))?;
+
+    let mut interval = tokio::time::interval(Duration::from_millis(args.interval_ms.max(1)));
+    let (mut sent_leds, mut sent_layout, mut sent_clipboard) = (None, None, None);
+    loop {
+        interval.tick().await;
+        let mut messages = Vec::new();
+
+        let leds = leds();
+        if sent_leds != Some(leds) {
+            messages.push(AgentMessage::Leds(leds));
+            sent_leds = Some(leds);
+        }
+        let layout = layout().await;
+        if layout.is_some() && layout != sent_layout {
+            let name = layout.as_deref().unwrap_or_default();
+            messages.push(AgentMessage::Layout(AgentText::new(name.as_bytes())));
+            sent_layout = layout;
+        }
+        if args.clipboard {
+            let clipboard = clipboard().await;
+            if clipboard.is_some() && clipboard != sent_clipboard {
+                let text = clipboard.as_deref().unwrap_or_default();
+                messages.extend(clipboard_messages(text));
+                sent_clipboard = clipboard;
+            }
+        }
+
+        for message in messages {
+            let mut buf = [0; shared::MAX_FRAME_LEN];
+            let frame = postcard::to_slice_cobs(&message, &mut buf)?;
+            port.write_all(frame)
+                .await
+                .wrap_err("Lost the teensy's serial port")?;
+        }
+    }
+}
+
+/// Which lock LEDs any keyboard on this machine has lit, going by the LED class devices
+/// the kernel makes for them like `input3::capslock`.
+fn leds() -> u8 {
+    let Ok(entries) = std::fs::read_dir("/sys/class/leds") else {
+        return 0;
+    };
+    let mut leds = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let bit = match name.to_string_lossy().rsplit_once("::") {
+            Some((_, "numlock")) => shared::LED_NUM_LOCK,
+            Some((_, "capslock")) => shared::LED_CAPS_LOCK,
+            Some((_, "scrolllock")) => shared::LED_SCROLL_LOCK,
+            _ => continue,
+        };
+        let brightness = std::fs::read_to_string(entry.path().join("brightness"));
+        if brightness.is_ok_and(|b| b.trim() != "0") {
+            leds |= bit;
+        }
+    }
+    leds
+}
+
+/// The X keyboard layout, like `us` or `us(intl)`, from the session if there is one and
+/// the system default otherwise.
+pub async fn layout() -> Option<String> {
+    if let Some(out) = command_output("setxkbmap", &["-query"]).await {
+        if let Some(layout) = parse_layout(&out, "layout:", "variant:") {
+            return Some(layout);
+        }
+    }
+    let out = command_output("localectl", &["status"]).await?;
+    parse_layout(&out, "X11 Layout:", "X11 Variant:")
+}
+
+fn parse_layout(out: &str, layout: &str, variant: &str) -> Option<String> {
+    let field = |name: &str| {
+        let mut lines = out.lines().map(str::trim);
+        let value = lines.find_map(|line| line.strip_prefix(name))?.trim();
+        Some(value.to_owned()).filter(|value| !value.is_empty())
+    };
+    let layout = field(layout)?;
+    Some(match field(variant) {
+        Some(variant) => format!("{layout}({variant})"),
+        None => layout,
+    })
+}
+
+async fn clipboard() -> Option<String> {
+    let mut text = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        command_output("wl-paste", &["--no-newline"]).await?
+    } else {
+        command_output("xclip", &["-selection", "clipboard", "-o"]).await?
+    };
+    if text.len() > CLIPBOARD_LIMIT {
+        let mut end = CLIPBOARD_LIMIT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    Some(text)
+}
+
+// stdout of a command which worked, nothing if it isn't installed or fails
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().await.ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout).ok()
+}
+
+/// The clipboard split up to fit in messages.
+fn clipboard_messages(text: &str) -> Vec<AgentMessage> {
+    let mut chunks: Vec<_> = text.as_bytes().chunks(shared::AGENT_TEXT_LEN).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let last = chunks.len() - 1;
+    let chunks = chunks.into_iter().enumerate();
+    chunks
+        .map(|(i, chunk)| AgentMessage::Clipboard {
+            text: AgentText::new(chunk),
+            first: i == 0,
+            last: i == last,
+        })
+        .collect()
+}
+
+/// What the agent on the target has said about it, for frontends.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AgentReport {
+    /// Which lock LEDs are lit, see [`shared::LED_CAPS_LOCK`] etc.
+    pub leds: Option<u8>,
+    pub layout: Option<String>,
+}
+
+/// Puts what the agent sends back together on the client's side.
+#[derive(Debug, Default)]
+pub struct Listener {
+    report: AgentReport,
+    // the clipboard so far, None if we've come in part way through
+    clipboard: Option<Vec<u8>>,
+}
+
+impl Listener {
+    pub fn report(&self) -> &AgentReport {
+        &self.report
+    }
+
+    /// Take in a message from the agent, saying what's changed if it's finished anything.
+    pub fn handle(&mut self, message: AgentMessage) -> Option<Event<'_>> {
+        match message {
+            AgentMessage::Leds(leds) => {
+                if self.report.leds == Some(leds) {
+                    return None;
+                }
+                self.report.leds = Some(leds);
+                Some(Event::TargetLeds {
+                    num_lock: leds & shared::LED_NUM_LOCK != 0,
+                    caps_lock: leds & shared::LED_CAPS_LOCK != 0,
+                    scroll_lock: leds & shared::LED_SCROLL_LOCK != 0,
+                })
+            }
+            AgentMessage::Layout(name) => {
+                let name = String::from_utf8_lossy(name.as_bytes()).into_owned();
+                if self.report.layout.as_ref() == Some(&name) {
+                    return None;
+                }
+                let layout = self.report.layout.insert(name);
+                Some(Event::TargetLayout { layout })
+            }
+            AgentMessage::Clipboard { text, first, last } => {
+                if first {
+                    self.clipboard = Some(Vec::new());
+                }
+                let clipboard = self.clipboard.as_mut()?;
+                clipboard.extend_from_slice(text.as_bytes());
+                // more than the agent would send, something's gone wrong
+                if clipboard.len() > CLIPBOARD_LIMIT {
+                    self.clipboard = None;
+                    return None;
+                }
+                if !last {
+                    return None;
+                }
+                let clipboard = self.clipboard.take()?;
+                Some(Event::TargetClipboard {
+                    text: String::from_utf8_lossy(&clipboard).into_owned(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_round_trip() {
+        // long enough for a few messages, with a character split between two of them
+        let text = format!(
+            "{}é and the rest of it",
+            "x".repeat(shared::AGENT_TEXT_LEN - 1)
+        );
+        let messages = clipboard_messages(&text);
+        assert_eq!(messages.len(), 2);
+
+        let mut listener = Listener::default();
+        // joining part way through gets nothing until the next one starts
+        assert!(listener.handle(messages[1]).is_none());
+        assert!(listener.handle(messages[0]).is_none());
+        let Some(Event::TargetClipboard { text: received }) = listener.handle(messages[1]) else {
+            panic!("the clipboard should be finished");
+        };
+        assert_eq!(received, text);
+
+        let empty = clipboard_messages("");
+        let Some(Event::TargetClipboard { text }) = listener.handle(empty[0]) else {
+            panic!("an empty clipboard is one message");
+        };
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn test_messages_fit_in_a_frame() {
+        let message = shared::DeviceMessage::Agent(AgentMessage::Clipboard {
+            text: AgentText::new(&[0xff; shared::AGENT_TEXT_LEN]),
+            first: true,
+            last: true,
+        });
+        let mut buf = [0; shared::MAX_FRAME_LEN];
+        assert!(postcard::to_slice_cobs(&message, &mut buf).is_ok());
+
+        // the firmware's crash reports are pieced up the same way
+        let message = shared::DeviceMessage::Crashed {
+            text: AgentText::new(&[0xff; shared::AGENT_TEXT_LEN]),
+            first: true,
+            last: true,
+        };
+        assert!(postcard::to_slice_cobs(&message, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn test_listener_only_reports_changes() {
+        let mut listener = Listener::default();
+        let caps = AgentMessage::Leds(shared::LED_CAPS_LOCK);
+        assert!(matches!(
+            listener.handle(caps),
+            Some(Event::TargetLeds {
+                caps_lock: true,
+                num_lock: false,
+                ..
+            })
+        ));
+        assert!(listener.handle(caps).is_none());
+
+        let layout = AgentMessage::Layout(AgentText::new(b"de"));
+        assert!(listener.handle(layout).is_some());
+        assert!(listener.handle(layout).is_none());
+        assert_eq!(
+            listener.report(),
+            &AgentReport {
+                leds: Some(shared::LED_CAPS_LOCK),
+                layout: Some("de".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        let setxkbmap = "rules:      evdev\nmodel:      pc105\nlayout:     us\nvariant:    intl\n";
+        assert_eq!(
+            parse_layout(setxkbmap, "layout:", "variant:").as_deref(),
+            Some("us(intl)")
+        );
+        let localectl = "   System Locale: LANG=en_GB.UTF-8\n       X11 Layout: gb\n";
+        assert_eq!(
+            parse_layout(localectl, "X11 Layout:", "X11 Variant:").as_deref(),
+            Some("gb")
+        );
+        assert_eq!(parse_layout("", "layout:", "variant:"), None);
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+
+use clap::Args;
+use color_eyre::eyre::{bail, eyre, Result, WrapErr as _};
+use evdev::Key;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+    select,
+    signal::unix::{signal, SignalKind},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    keyset::{self, KeySet},
+    output::{Event, Output},
+    pointer::{self, Pointer, ScreenSize},
+    sender::{self, KeypressSender},
+    SerialArgs,
+};
+
+/// The port Barrier and InputLeap servers listen on unless told otherwise.
+const DEFAULT_PORT: u16 = 24800;
+
+/// The protocol version we speak, 1.6 is what Barrier and InputLeap both use.
+const PROTOCOL_VERSION: (u16, u16) = (1, 6);
+
+/// Anything longer than this isn't something we'd want to act on anyway, and it stops a
+/// bad length making us allocate the world.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// How far one click of the wheel turns in the protocol.
+const WHEEL_CLICK: i32 = 120;
+
+#[derive(Args, Debug)]
+pub struct BarrierArgs {
+    /// the Barrier or InputLeap server to connect to as HOST or HOST:PORT, the port is
+    /// 24800 if not specified
+    server: String,
+
+    /// the screen name to connect as, which needs adding to the server's layout,
+    /// defaults to this machine's hostname
+    #[arg(long)]
+    name: Option<String>,
+
+    /// the size of the target's screen as WIDTHxHEIGHT, which the server moves the
+    /// pointer around
+    #[arg(long, default_value = "1920x1080")]
+    screen: ScreenSize,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Connect to a Barrier server as one of its screens and forward whatever it sends us.
+pub async fn run(args: BarrierArgs, output: Output) -> Result<()> {
+    let name = match args.name {
+        Some(name) => name,
+        None => std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .wrap_err("Failed to find the hostname, pass --name")?
+            .trim()
+            .to_owned(),
+    };
+    let mut sender = crate::open_sender(args.serial, output).await?;
+
+    let server = if args.server.contains(':') {
+        args.server
+    } else {
+        format!("{}:{DEFAULT_PORT}", args.server)
+    };
+    let mut stream = TcpStream::connect(&server)
+        .await
+        .wrap_err_with(|| format!("Failed to connect to the Barrier server at {server}"))?;
+    output.status(format_args!("Connected to {server} as {name}."));
+
+    let token = CancellationToken::new();
+    let cloned_token = token.clone();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+        cloned_token.cancel();
+    });
+
+    let mut client = Client::new(name, args.screen);
+    let result = client.run(&mut stream, &mut sender, &token).await;
+
+    // however we got here release all keys so nothing is left held down on the target
+    let released = client.release_all(&mut sender).await;
+    output.event(&Event::Disconnected);
+
+    result.and(released)
+}
+
+/// A message from the server, anything we don't act on is left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Message {
+    Hello {
+        magic: String,
+        major: u16,
+        minor: u16,
+    },
+    QueryInfo,
+    KeepAlive,
+    Enter,
+    Leave,
+    KeyDown {
+        id: u16,
+        button: u16,
+    },
+    KeyUp {
+        id: u16,
+        button: u16,
+    },
+    MouseDown(u8),
+    MouseUp(u8),
+    MouseMove {
+        x: i16,
+        y: i16,
+    },
+    MouseRelativeMove {
+        dx: i16,
+        dy: i16,
+    },
+    MouseWheel {
+        dy: i16,
+    },
+    Close,
+    Error(&'static str),
+    Other,
+}
+
+impl Message {
+    fn parse(payload: &[u8]) -> Result<Self> {
+        for magic in ["Barrier", "Synergy"] {
+            if let Some(rest) = payload.strip_prefix(magic.as_bytes()) {
+                let mut fields = Fields(rest);
+                return Ok(Self::Hello {
+                    magic: magic.to_owned(),
+                    major: fields.u16()?,
+                    minor: fields.u16()?,
+                });
+            }
+        }
+
+        let Some((code, rest)) = payload.split_first_chunk::<4>() else {
+            bail!("Message too short: {payload:02x?}");
+        };
+        let mut fields = Fields(rest);
+        let message = match code {
+            b"QINF" => Self::QueryInfo,
+            b"CALV" => Self::KeepAlive,
+            b"CINN" => Self::Enter,
+            b"COUT" => Self::Leave,
+            b"DKDN" => {
+                let id = fields.u16()?;
+                let _mask = fields.u16()?;
+                let button = fields.u16()?;
+                Self::KeyDown { id, button }
+            }
+            b"DKUP" => {
+                let id = fields.u16()?;
+                let _mask = fields.u16()?;
+                let button = fields.u16()?;
+                Self::KeyUp { id, button }
+            }
+            b"DMDN" => Self::MouseDown(fields.u8()?),
+            b"DMUP" => Self::MouseUp(fields.u8()?),
+            b"DMMV" => Self::MouseMove {
+                x: fields.u16()? as i16,
+                y: fields.u16()? as i16,
+            },
+            b"DMRM" => Self::MouseRelativeMove {
+                dx: fields.u16()? as i16,
+                dy: fields.u16()? as i16,
+            },
+            b"DMWM" => {
+                let _dx = fields.u16()?;
+                Self::MouseWheel {
+                    dy: fields.u16()? as i16,
+                }
+            }
+            b"CBYE" => Self::Close,
+            b"EICV" => Self::Error("the server doesn't speak our protocol version"),
+            b"EBSY" => Self::Error("the server already has a screen with this name"),
+            b"EUNK" => Self::Error("the server doesn't know a screen with this name"),
+            b"EBAD" => Self::Error("the server didn't understand us"),
+            _ => Self::Other,
+        };
+        Ok(message)
+    }
+}
+
+// every number in the protocol is big endian
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn u8(&mut self) -> Result<u8> {
+        let (&value, rest) = self
+            .0
+            .split_first()
+            .ok_or_else(|| eyre!("Message too short"))?;
+        self.0 = rest;
+        Ok(value)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let (value, rest) = self
+            .0
+            .split_first_chunk()
+            .ok_or_else(|| eyre!("Message too short"))?;
+        self.0 = rest;
+        Ok(u16::from_be_bytes(*value))
+    }
+}
+
+/// What the server has pressed on the target.
+struct Client {
+    name: String,
+    screen: ScreenSize,
+    keyboard: KeySet,
+    // the key each of the server's key buttons pressed, as the key id can change between
+    // pressing and releasing it if a modifier changes in between
+    held: HashMap<u16, Key>,
+    pointer: Pointer,
+    // smooth scrolling sends parts of a click
+    wheel: i32,
+}
+
+impl Client {
+    fn new(name: String, screen: ScreenSize) -> Self {
+        Self {
+            name,
+            screen,
+            keyboard: KeySet::new(),
+            held: HashMap::new(),
+            pointer: Pointer::new(screen),
+            wheel: 0,
+        }
+    }
+
+    async fn run(
+        &mut self,
+        stream: &mut TcpStream,
+        sender: &mut KeypressSender,
+        token: &CancellationToken,
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut heartbeat = sender::heartbeat_interval();
+        loop {
+            // only ever stop between messages so we never leave half a frame on the wire,
+            // and only wait for the next message to start so we never drop half of one
+            select! {
+                _ = token.cancelled() => return Ok(()),
+                _ = heartbeat.tick() => {
+                    sender.send_heartbeat().await?;
+                    continue;
+                }
+                ready = stream.readable() => ready.wrap_err("Lost the connection to the server")?,
+            }
+            let len = stream
+                .read_u32()
+                .await
+                .wrap_err("Lost the connection to the server")? as usize;
+            if len > MAX_MESSAGE_LEN {
+                // clipboards can be big, skip over anything we'd ignore anyway
+                tokio::io::copy(&mut (&mut *stream).take(len as u64), &mut tokio::io::sink())
+                    .await?;
+                continue;
+            }
+            buf.resize(len, 0);
+            stream.read_exact(&mut buf).await?;
+
+            let reply = match Message::parse(&buf)? {
+                Message::Close => return Ok(()),
+                Message::Error(e) => bail!("Barrier server refused us: {e}"),
+                message => self.handle(message, sender).await?,
+            };
+            if let Some(reply) = reply {
+                let len = u32::try_from(reply.len())?;
+                stream.write_all(&len.to_be_bytes()).await?;
+                stream.write_all(&reply).await?;
+            }
+        }
+    }
+
+    // returns what to send back to the server, if anything
+    async fn handle(
+        &mut self,
+        message: Message,
+        sender: &mut KeypressSender,
+    ) -> Result<Option<Vec<u8>>> {
+        match message {
+            Message::Hello { magic, major, .. } => {
+                if major != PROTOCOL_VERSION.0 {
+                    bail!("The server speaks protocol version {major}, we only speak 1");
+                }
+                return Ok(Some(hello(&magic, &self.name)));
+            }
+            Message::QueryInfo => return Ok(Some(info(self.screen))),
+            Message::KeepAlive => return Ok(Some(b"CALV".to_vec())),
+            Message::Enter => {}
+            // the server releases everything when it leaves, but it's as well to be sure
+            Message::Leave => self.release_all(sender).await?,
+            Message::KeyDown { id, button } => {
+                if let Some(key) = key_for_id(id) {
+                    self.held.insert(button, key);
+                    self.keyboard.press_key(key);
+                    sender.send_state_update(self.keyboard).await?;
+                }
+            }
+            Message::KeyUp { button, .. } => {
+                if let Some(key) = self.held.remove(&button) {
+                    self.keyboard.release_key(key);
+                    sender.send_state_update(self.keyboard).await?;
+                }
+            }
+            Message::MouseDown(button) | Message::MouseUp(button) => {
+                let mask = match button {
+                    1 => pointer::LEFT,
+                    2 => pointer::MIDDLE,
+                    3 => pointer::RIGHT,
+                    _ => return Ok(None),
+                };
+                self.pointer
+                    .set_button(mask, matches!(message, Message::MouseDown(_)));
+            }
+            Message::MouseMove { x, y } => self.pointer.move_to(x.into(), y.into()),
+            Message::MouseRelativeMove { dx, dy } => self.pointer.move_by(dx.into(), dy.into()),
+            Message::MouseWheel { dy } => {
+                self.wheel += i32::from(dy);
+                let clicks = self.wheel / WHEEL_CLICK;
+                self.wheel %= WHEEL_CLICK;
+                self.pointer.scroll(clicks);
+            }
+            Message::Close | Message::Error(_) | Message::Other => {}
+        }
+
+        if let Some(state) = self.pointer.take_update() {
+            sender.send_pointer_update(state).await?;
+        }
+        Ok(None)
+    }
+
+    async fn release_all(&mut self, sender: &mut KeypressSender) -> Result<()> {
+        self.held.clear();
+        self.keyboard = KeySet::new();
+        sender.send_state_update(self.keyboard).await?;
+        sender
+            .send_pointer_update(self.pointer.release_buttons())
+            .await
+    }
+}
+
+fn hello(magic: &str, name: &str) -> Vec<u8> {
+    let mut reply = magic.as_bytes().to_vec();
+    reply.extend(PROTOCOL_VERSION.0.to_be_bytes());
+    reply.extend(PROTOCOL_VERSION.1.to_be_bytes());
+    reply.extend((name.len() as u32).to_be_bytes());
+    reply.extend(name.as_bytes());
+    reply
+}
+
+fn info(screen: ScreenSize) -> Vec<u8> {
+    let size = |pixels: u32| i16::try_from(pixels).unwrap_or(i16::MAX);
+    // where the screen is, its size, the jump zone size (unused) and the pointer position
+    let fields = [0, 0, size(screen.width), size(screen.height), 0, 0, 0];
+    let mut reply = b"DINF".to_vec();
+    for field in fields {
+        reply.extend(field.to_be_bytes());
+    }
+    reply
+}
+
+/// The key on a US layout that types a Barrier key id, which is the character for
+/// anything printable and close to an X keysym for everything else.
+fn key_for_id(id: u16) -> Option<Key> {
+    let key = match id {
+        0xEF08 => Key::KEY_BACKSPACE,
+        0xEF09 => Key::KEY_TAB,
+        0xEF0D => Key::KEY_ENTER,
+        0xEF13 => Key::KEY_PAUSE,
+        0xEF14 => Key::KEY_SCROLLLOCK,
+        0xEF1B => Key::KEY_ESC,
+        0xEF50 => Key::KEY_HOME,
+        0xEF51 => Key::KEY_LEFT,
+        0xEF52 => Key::KEY_UP,
+        0xEF53 => Key::KEY_RIGHT,
+        0xEF54 => Key::KEY_DOWN,
+        0xEF55 => Key::KEY_PAGEUP,
+        0xEF56 => Key::KEY_PAGEDOWN,
+        0xEF57 => Key::KEY_END,
+        0xEF61 => Key::KEY_SYSRQ,
+        0xEF63 => Key::KEY_INSERT,
+        0xEF67 => Key::KEY_COMPOSE,
+        0xEF7F => Key::KEY_NUMLOCK,
+        0xEF8D => Key::KEY_KPENTER,
+        0xEF95 => Key::KEY_KP7,
+        0xEF96 => Key::KEY_KP4,
+        0xEF97 => Key::KEY_KP8,
+        0xEF98 => Key::KEY_KP6,
+        0xEF99 => Key::KEY_KP2,
+        0xEF9A => Key::KEY_KP9,
+        0xEF9B => Key::KEY_KP3,
+        0xEF9C => Key::KEY_KP1,
+        0xEF9D => Key::KEY_KP5,
+        0xEF9E => Key::KEY_KP0,
+        0xEF9F => Key::KEY_KPDOT,
+        0xEFAA => Key::KEY_KPASTERISK,
+        0xEFAB => Key::KEY_KPPLUS,
+        0xEFAD => Key::KEY_KPMINUS,
+        0xEFAE => Key::KEY_KPDOT,
+        0xEFAF => Key::KEY_KPSLASH,
+        0xEFB0..=0xEFB9 => [
+            Key::KEY_KP0,
+            Key::KEY_KP1,
+            Key::KEY_KP2,
+            Key::KEY_KP3,
+            Key::KEY_KP4,
+            Key::KEY_KP5,
+            Key::KEY_KP6,
+            Key::KEY_KP7,
+            Key::KEY_KP8,
+            Key::KEY_KP9,
+        ][usize::from(id - 0xEFB0)],
+        0xEFBD => Key::KEY_KPEQUAL,
+        0xEFBE..=0xEFC9 => [
+            Key::KEY_F1,
+            Key::KEY_F2,
+            Key::KEY_F3,
+            Key::KEY_F4,
+            Key::KEY_F5,
+            Key::KEY_F6,
+            Key::KEY_F7,
+            Key::KEY_F8,
+            Key::KEY_F9,
+            Key::KEY_F10,
+            Key::KEY_F11,
+            Key::KEY_F12,
+        ][usize::from(id - 0xEFBE)],
+        0xEFE1 => Key::KEY_LEFTSHIFT,
+        0xEFE2 => Key::KEY_RIGHTSHIFT,
+        0xEFE3 => Key::KEY_LEFTCTRL,
+        0xEFE4 => Key::KEY_RIGHTCTRL,
+        0xEFE5 => Key::KEY_CAPSLOCK,
+        0xEFE7 | 0xEFEB => Key::KEY_LEFTMETA,
+        0xEFE8 | 0xEFEC => Key::KEY_RIGHTMETA,
+        0xEFE9 => Key::KEY_LEFTALT,
+        0xEFEA => Key::KEY_RIGHTALT,
+        0xEFFF => Key::KEY_DELETE,
+        // shifted characters give the same key, the server sends the shift separately
+        _ => return keyset::key_for_char(char::from_u32(id.into())?).map(|(key, _)| key),
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_messages() {
+        assert_eq!(
+            Message::parse(b"Barrier\x00\x01\x00\x06").unwrap(),
+            Message::Hello {
+                magic: "Barrier".to_owned(),
+                major: 1,
+                minor: 6
+            }
+        );
+        assert_eq!(
+            Message::parse(b"DKDN\x00\x41\x00\x01\x00\x26").unwrap(),
+            Message::KeyDown {
+                id: 0x41,
+                button: 0x26
+            }
+        );
+        assert_eq!(
+            Message::parse(b"DMMV\xff\xff\x00\x10").unwrap(),
+            Message::MouseMove { x: -1, y: 16 }
+        );
+        assert_eq!(Message::parse(b"DCLP\x00").unwrap(), Message::Other);
+        assert!(Message::parse(b"DKDN\x00").is_err());
+    }
+
+    #[test]
+    fn test_replies() {
+        assert_eq!(
+            hello("Barrier", "teensy"),
+            b"Barrier\x00\x01\x00\x06\x00\x00\x00\x06teensy"
+        );
+        let size = ScreenSize {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(
+            info(size),
+            b"DINF\x00\x00\x00\x00\x07\x80\x04\x38\x00\x00\x00\x00\x00\x00"
+        );
+    }
+
+    #[test]
+    fn test_key_for_id() {
+        assert_eq!(key_for_id(u16::from(b'a')), Some(Key::KEY_A));
+        assert_eq!(key_for_id(u16::from(b'A')), Some(Key::KEY_A));
+        assert_eq!(key_for_id(u16::from(b'!')), Some(Key::KEY_1));
+        assert_eq!(key_for_id(0xEFC9), Some(Key::KEY_F12));
+        assert_eq!(key_for_id(0xEFE3), Some(Key::KEY_LEFTCTRL));
+        assert_eq!(key_for_id(0x00E9), None);
+    }
+}
@@ -0,0 +1,1230 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::PoisonError,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::Result;
+use evdev::{Device, EventStream, EventType, InputEvent, InputEventKind, Key};
+use tokio::{
+    select,
+    signal::unix::Signal,
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use zeroize::{Zeroize as _, Zeroizing};
+
+use crate::{
+    agent::{self, AgentReport},
+    config::{Action, Config, MacroStep},
+    gamepad::Gamepad,
+    keyset::{self, KeySet},
+    led::{CaptureLed, Led},
+    local_keys::LocalKeys,
+    metrics::{Counter, Metrics},
+    mouse_keys,
+    oneshot::Command,
+    output::{self, Event, Output},
+    pointer::{Edge, Pointer, RelativeMouse, ScreenSize},
+    schedule::Scheduler,
+    sender::{self, KeypressSender},
+};
+
+/// Holding all of these lets go of the keyboard and pauses forwarding, whatever else is going on.
+const EMERGENCY_CHORD: [Key; 3] = [Key::KEY_LEFTCTRL, Key::KEY_RIGHTCTRL, Key::KEY_ESC];
+/// How long the emergency chord has to be held before it does anything.
+const EMERGENCY_CHORD_HOLD: Duration = Duration::from_secs(2);
+/// How often to look for a device which has gone away, to pick it up again.
+const REACQUIRE_INTERVAL: Duration = Duration::from_secs(1);
+/// How many of the last characters typed to keep for matching snippets against.
+const SNIPPET_MEMORY: usize = 64;
+/// How often to ask the teensy how it's getting on, with `--metrics`.
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What the bridge is up to, for frontends to show.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub paused: bool,
+    /// The keys held down on the target, after remapping.
+    pub held_keys: Vec<Key>,
+    /// The profile from the config file in use, if any.
+    pub profile: Option<String>,
+    /// How far along the switching edge the target's pointer was when it went off it, if
+    /// that's why forwarding was paused, so the local pointer can come back at the same place.
+    pub left_along: Option<f64>,
+    /// What the agent on the target has said about it, if there is one.
+    pub agent: AgentReport,
+}
+
+/// Requests from a frontend to a running bridge.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+    /// The same as holding the emergency chord.
+    TogglePause,
+    /// Pause or resume forwarding, if it isn't already.
+    SetPaused(bool),
+    /// Type some text on the target in its layout, skipping anything untypeable.
+    Type(String),
+    /// Run one of the macros from the config file.
+    RunMacro(String),
+    /// Type the expansion of one of the snippets from the config file.
+    TypeSnippet(String),
+    /// Run the commands in a file, as taken by `client stdin`.
+    Replay(PathBuf),
+    /// Press and release a chord, on top of anything held down.
+    #[cfg_attr(not(any(feature = "web", feature = "mqtt")), allow(dead_code))]
+    Press(Vec<Key>),
+    /// Switch to one of the profiles from the config file.
+    UseProfile(String),
+    /// The local pointer has gone off the switching edge, `along` of the way along it,
+    /// so start forwarding with the target's pointer at the same place.
+    #[cfg_attr(not(feature = "screen-edge"), allow(dead_code))]
+    Enter { along: f64 },
+}
+
+/// Forwards events from the local input devices to the teensy.
+pub struct Bridge {
+    // None when only driven remotely
+    keyboard: Option<EventStream>,
+    // lit on the keyboard while forwarding
+    capture_led: Option<CaptureLed>,
+    gamepad: Option<(EventStream, Gamepad)>,
+    pointer_events: Option<EventStream>,
+    pointer: Pointer,
+    // set by --relative-pointer, forwarding the pointer device to the firmware's mouse
+    relative_mouse: Option<RelativeMouse>,
+    sender: KeypressSender,
+    config: Config,
+    // runs the config's scheduled jobs
+    scheduler: Scheduler,
+    // where to re-read the config from when we get a SIGHUP
+    config_path: Option<PathBuf>,
+    sighup: Option<Signal>,
+    grab: bool,
+    output: Output,
+    // for frontends like the GUI and tray icon to watch and poke us with
+    status: Option<watch::Sender<Status>>,
+    control: Option<mpsc::UnboundedReceiver<Control>>,
+    // where the panic hook writes, to be moved along with the sender
+    panic_port: Option<sender::PanicPort>,
+
+    keyboard_state: KeySet,
+    // what each physically held key was remapped to when it was pressed, so that
+    // reloading the config mid-press still releases the key we actually sent
+    held: HashMap<Key, Key>,
+    // the modifiers let go of on the target for each physically held key which was morphed
+    stripped: HashMap<Key, Vec<Key>>,
+    // keys which set off a hotkey, which do nothing more until they're let go of
+    hotkey_keys: HashSet<Key>,
+    // the media key held down on the target's consumer control, which only holds one
+    consumer_held: Option<Key>,
+    // the characters typed since anything other than typing happened, for snippets
+    typed: Zeroizing<String>,
+    // when the emergency chord will have been held long enough to fire
+    emergency_deadline: Option<Instant>,
+    paused: bool,
+    // set by --bios, taps each key rather than holding it down
+    bios: bool,
+    // whether the keyboard is driving the pointer rather than typing
+    mouse_keys: bool,
+    // passes the profile's local keys back to this machine, made when first needed
+    local_keys: Option<LocalKeys>,
+    // so we don't move the target's cursor when letting go of a pointer we never used
+    pointer_used: bool,
+    // with screen edge switching, the edge of the target's screen that leads back
+    return_edge: Option<Edge>,
+    // where along the return edge the target's pointer left, until it's back
+    left_along: Option<f64>,
+    // what we've heard from the agent on the target, through the teensy
+    agent: agent::Listener,
+    // devices which have been unplugged, to pick up again when they come back
+    lost: Vec<LostDevice>,
+    // set by --probe, sending a probe with each heartbeat and timing the reply
+    probing: bool,
+    // the nonce of the last probe and when it went, until it's answered
+    probe: (u32, Option<Instant>),
+}
+
+impl Bridge {
+    pub fn new(keyboard: Option<EventStream>, mut sender: KeypressSender, config: Config) -> Self {
+        sender.set_typing_delay(config.typing_delay());
+        sender.set_report_delays(config.report_interval(), config.key_delays());
+        let scheduler = Scheduler::new(&config.schedule);
+        Self {
+            keyboard,
+            capture_led: None,
+            gamepad: None,
+            pointer_events: None,
+            pointer: Pointer::new(ScreenSize::default()),
+            relative_mouse: None,
+            sender,
+            config,
+            scheduler,
+            config_path: None,
+            sighup: None,
+            grab: false,
+            output: Output::Human,
+            status: None,
+            control: None,
+            panic_port: None,
+            keyboard_state: KeySet::new(),
+            held: HashMap::new(),
+            stripped: HashMap::new(),
+            hotkey_keys: HashSet::new(),
+            consumer_held: None,
+            typed: Zeroizing::default(),
+            emergency_deadline: None,
+            paused: false,
+            bios: false,
+            mouse_keys: false,
+            local_keys: None,
+            pointer_used: false,
+            return_edge: None,
+            left_along: None,
+            agent: agent::Listener::default(),
+            lost: Vec::new(),
+            probing: false,
+            probe: (0, None),
+        }
+    }
+
+    pub fn with_gamepad(mut self, gamepad: Option<(EventStream, Gamepad)>) -> Self {
+        self.gamepad = gamepad;
+        self
+    }
+
+    /// Use `pointer` for the pointer device, if there is one, and for mouse keys.
+    pub fn with_pointer(mut self, events: Option<EventStream>, mut pointer: Pointer) -> Self {
+        pointer.set_motion(self.config.motion());
+        self.pointer_events = events;
+        self.pointer = pointer;
+        self
+    }
+
+    /// Forward the pointer device as a relative mouse, rather than moving the absolute
+    /// pointer around.
+    pub fn with_relative_mouse(mut self, relative: bool) -> Self {
+        self.relative_mouse = relative.then(RelativeMouse::default);
+        self
+    }
+
+    pub fn reload_config_on(mut self, sighup: Signal, path: Option<PathBuf>) -> Self {
+        self.sighup = Some(sighup);
+        self.config_path = path;
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Keep `status` up to date as keys are pressed and forwarding is paused.
+    pub fn with_status(mut self, status: watch::Sender<Status>) -> Self {
+        self.status = Some(status);
+        self.publish_status();
+        self
+    }
+
+    pub fn with_control(mut self, control: mpsc::UnboundedReceiver<Control>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Point `panic_port` at whichever port we fail over to.
+    pub fn with_panic_port(mut self, panic_port: sender::PanicPort) -> Self {
+        self.panic_port = Some(panic_port);
+        self
+    }
+
+    /// Hand over to the target when the local pointer goes off `edge` of the screen, and
+    /// back when the target's pointer goes off the other side. We start off paused as the
+    /// local pointer starts off on the local screen.
+    #[cfg_attr(not(feature = "screen-edge"), allow(dead_code))]
+    pub fn with_screen_edge(mut self, edge: Option<Edge>) -> Self {
+        self.return_edge = edge.map(Edge::opposite);
+        self.paused = edge.is_some();
+        self
+    }
+
+    /// Light `led` on the keyboard while forwarding.
+    pub fn with_capture_led(mut self, led: Option<Led>) -> Self {
+        self.capture_led = led
+            .zip(self.keyboard.as_ref())
+            .map(|(led, keyboard)| CaptureLed::new(keyboard.device(), led));
+        self
+    }
+
+    /// Time how long the teensy takes to answer a probe sent along with each heartbeat, for
+    /// `--metrics`' round trip histogram.
+    pub fn with_probes(mut self, probing: bool) -> Self {
+        self.probing = probing;
+        self
+    }
+
+    /// Press and release each key in one go when it's pressed rather than forwarding it being
+    /// held, as firmware setup screens tend to see a key held for a moment as several.
+    pub fn bios_safe(mut self, bios: bool) -> Self {
+        self.bios = bios;
+        self
+    }
+
+    /// Grab the keyboard and pointer so their events only go to the teensy.
+    pub fn grab(mut self, grab: bool) -> Result<Self> {
+        self.grab = grab;
+        if !self.paused {
+            self.set_grabbed(true)?;
+        }
+        Ok(self)
+    }
+
+    pub fn sender(&self) -> &KeypressSender {
+        &self.sender
+    }
+
+    pub async fn run(&mut self, token: &CancellationToken) -> Result<()> {
+        self.show_capture();
+        self.sender.set_lock_chord(self.config.lock_chord()).await?;
+        let mut heartbeat = sender::heartbeat_interval();
+        let mut reacquire = tokio::time::interval(REACQUIRE_INTERVAL);
+        let mut telemetry = tokio::time::interval(TELEMETRY_INTERVAL);
+        loop {
+            let event = select! {
+                _ = token.cancelled() => return Ok(()),
+                _ = heartbeat.tick() => {
+                    self.sender.send_heartbeat().await?;
+                    if self.probing {
+                        self.send_probe().await?;
+                    }
+                    continue;
+                }
+                message = self.sender.recv_message() => {
+                    match message? {
+                        shared::DeviceMessage::Agent(message) => {
+                            if let Some(event) = self.agent.handle(message) {
+                                self.output.event(&event);
+                                self.publish_status();
+                            }
+                        }
+                        shared::DeviceMessage::HelloAck { nonce } => self.probe_answered(nonce),
+                        shared::DeviceMessage::Telemetry(telemetry) => {
+                            if let Some(metrics) = self.sender.metrics() {
+                                metrics.teensy_reported(telemetry);
+                            }
+                        }
+                        // only ever asked for while connecting
+                        shared::DeviceMessage::Baud(_)
+                        | shared::DeviceMessage::Setting(..)
+                        | shared::DeviceMessage::Identity(_)
+                        | shared::DeviceMessage::Nak { .. }
+                        | shared::DeviceMessage::Ack { .. }
+                        | shared::DeviceMessage::Crashed { .. } => {}
+                        // the sender says again what's held with the next frame
+                        shared::DeviceMessage::Overflow { dropped } => {
+                            self.output.event(&Event::Overflow { dropped });
+                        }
+                    }
+                    continue;
+                }
+                _ = recv_signal(&mut self.sighup) => {
+                    self.reload_config().await?;
+                    continue;
+                }
+                control = recv_control(&mut self.control) => {
+                    self.handle_control(control).await?;
+                    continue;
+                }
+                control = self.scheduler.next() => {
+                    self.handle_control(control).await?;
+                    continue;
+                }
+                _ = telemetry.tick(), if self.sender.metrics().is_some() => {
+                    self.sender.request_telemetry().await?;
+                    continue;
+                }
+                _ = reacquire.tick(), if !self.lost.is_empty() => {
+                    self.reacquire()?;
+                    continue;
+                }
+                _ = sleep_until_deadline(self.emergency_deadline) => {
+                    self.emergency_deadline = None;
+                    self.toggle_pause().await?;
+                    continue;
+                }
+                event = next_event(&mut self.gamepad) => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(e) => {
+                            self.lose(DeviceKind::Gamepad, e).await?;
+                            continue;
+                        }
+                    };
+                    self.count(|metrics| &metrics.gamepad_events);
+                    let frames_sent = self.frames_sent();
+                    let Some((_, gamepad)) = &mut self.gamepad else {
+                        continue;
+                    };
+                    if let Some(state) = gamepad.handle_event(event) {
+                        if !self.paused {
+                            self.sender.send_gamepad_update(state).await?;
+                        }
+                    }
+                    self.time_capture(event.timestamp(), frames_sent);
+                    continue;
+                }
+                event = next_device_event(&mut self.pointer_events) => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(e) => {
+                            self.lose(DeviceKind::Pointer, e).await?;
+                            continue;
+                        }
+                    };
+                    self.count(|metrics| &metrics.pointer_events);
+                    let frames_sent = self.frames_sent();
+                    if let Some(mouse) = &mut self.relative_mouse {
+                        if let Some(state) = mouse.handle_event(event) {
+                            if !self.paused {
+                                self.sender.send_mouse_update(state).await?;
+                            }
+                        }
+                        self.time_capture(event.timestamp(), frames_sent);
+                        continue;
+                    }
+                    if let Some(state) = self.pointer.handle_event(event) {
+                        if !self.paused {
+                            self.pointer_used = true;
+                            self.sender.send_pointer_update(state).await?;
+                        }
+                    }
+                    self.time_capture(event.timestamp(), frames_sent);
+                    let leaving = self
+                        .return_edge
+                        .filter(|&edge| self.pointer.pushed_past(edge));
+                    if let Some(edge) = leaving.filter(|_| !self.paused) {
+                        self.left_along = Some(self.pointer.along(edge));
+                        self.toggle_pause().await?;
+                    }
+                    continue;
+                }
+                event = next_device_event(&mut self.keyboard) => match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        self.lose(DeviceKind::Keyboard, e).await?;
+                        continue;
+                    }
+                },
+            };
+
+            let frames_sent = self.frames_sent();
+            self.handle_keyboard_event(event).await?;
+            self.time_capture(event.timestamp(), frames_sent);
+        }
+    }
+
+    /// Carry on through `sender` now the link to the teensy has failed, putting back what's
+    /// held down so the target doesn't notice the switch.
+    pub async fn fail_over(&mut self, sender: KeypressSender) -> Result<()> {
+        let old = std::mem::replace(&mut self.sender, sender);
+        self.sender.take_over(old);
+        if let Some(panic_port) = &self.panic_port {
+            let port = self.sender.dup_port()?;
+            *panic_port.lock().unwrap_or_else(PoisonError::into_inner) = Some(port);
+        }
+        self.sender.send_state_update(self.report()).await?;
+        if let Some(usage) = self.consumer_held.and_then(keyset::consumer_usage) {
+            self.sender.send_consumer(usage).await?;
+        }
+        if let Some((_, gamepad)) = &self.gamepad {
+            self.sender.send_gamepad_update(gamepad.state()).await?;
+        }
+        if self.pointer_used {
+            self.sender
+                .send_pointer_update(self.pointer.state())
+                .await?;
+        }
+        if let Some(mouse) = &self.relative_mouse {
+            self.sender.send_mouse_update(mouse.state()).await?;
+        }
+        Ok(())
+    }
+
+    /// Let go of everything on the target, whatever state we think it's in.
+    pub async fn release_all(&mut self) -> Result<()> {
+        self.keyboard_state = KeySet::new();
+        self.stripped.clear();
+        self.typed.zeroize();
+        if let Some(status) = &self.status {
+            status.send_modify(|status| status.held_keys.clear());
+        }
+        self.sender.send_state_update(self.keyboard_state).await?;
+        if self.consumer_held.take().is_some() {
+            self.sender.send_consumer(0).await?;
+        }
+        if self.gamepad.is_some() {
+            self.sender
+                .send_gamepad_update(shared::GamepadState::default())
+                .await?;
+        }
+        if self.pointer_used {
+            let state = self.pointer.release_buttons();
+            self.sender.send_pointer_update(state).await?;
+        }
+        if let Some(mouse) = &mut self.relative_mouse {
+            let state = mouse.release_buttons();
+            self.sender.send_mouse_update(state).await?;
+        }
+        // and on this machine, as we're not going to pass back the keys being let go of
+        if let Some(local_keys) = &mut self.local_keys {
+            let _ = local_keys.release_all();
+        }
+        Ok(())
+    }
+
+    /// Lock the target if the profile says to, as the client is stopping, and tell the
+    /// firmware not to do it again when the heartbeats stop.
+    pub async fn lock_on_exit(&mut self) -> Result<()> {
+        if self.config.lock_chord().is_none() {
+            return Ok(());
+        }
+        self.lock_target().await?;
+        self.sender.set_lock_chord(None).await
+    }
+
+    /// Press the profile's lock chord on its own, if it has one.
+    async fn lock_target(&mut self) -> Result<()> {
+        let Some(chord) = self.config.lock_chord().map(<[_]>::to_vec) else {
+            return Ok(());
+        };
+        self.sender.tap(&chord).await?;
+        self.output.event(&Event::Locked);
+        Ok(())
+    }
+
+    async fn handle_keyboard_event(&mut self, event: InputEvent) -> Result<()> {
+        let InputEventKind::Key(key) = event.kind() else {
+            return Ok(());
+        };
+        self.count(|metrics| &metrics.key_events);
+
+        if self.pointer.is_scroll_key(key) && !self.paused {
+            // key repeats don't change anything
+            if event.value() != 2 {
+                self.pointer.set_scrolling(event.value() == 1);
+            }
+            return Ok(());
+        }
+        if key == mouse_keys::TOGGLE && !self.paused {
+            if event.value() == 1 {
+                self.toggle_mouse_keys().await?;
+            }
+            return Ok(());
+        }
+        // keys already held down for typing carry on being typed until they're released
+        if self.mouse_keys
+            && !self.paused
+            && !self.held.contains_key(&key)
+            && mouse_keys::handle_key(&mut self.pointer, key, event.value())
+        {
+            if let Some(state) = self.pointer.take_update() {
+                self.pointer_used = true;
+                self.sender.send_pointer_update(state).await?;
+            }
+            return Ok(());
+        }
+        // likewise a key held down before the config made it a macropad key
+        if !self.held.contains_key(&key) {
+            if let Some(control) = self.config.pad_action(key).map(Action::control) {
+                if event.value() == 1 {
+                    self.handle_control(control).await?;
+                }
+                return Ok(());
+            }
+        }
+        if self.config.is_local(key) {
+            // this machine has seen it already unless it's grabbed
+            if self.grab && !self.paused || event.value() == 0 {
+                self.pass_back(key, event.value());
+            }
+            return Ok(());
+        }
+        if self.hotkey_keys.contains(&key) {
+            if event.value() == 0 {
+                self.hotkey_keys.remove(&key);
+            }
+            return Ok(());
+        }
+        if event.value() == 1 {
+            let mods = self
+                .held
+                .keys()
+                .copied()
+                .filter(|&key| keyset::is_modifier(key));
+            if let Some(control) = self.config.hotkey(mods, key).map(Action::control) {
+                self.hotkey_keys.insert(key);
+                self.handle_control(control).await?;
+                return Ok(());
+            }
+        }
+
+        let remapped = match event.value() {
+            // zero is key up
+            0 => {
+                self.stripped.remove(&key);
+                self.held.remove(&key).unwrap_or(key)
+            }
+            // one is key down
+            1 => {
+                let mut remapped = self.config.remap(key);
+                if let Some(morph) = self.config.morph(remapped, &self.keyboard_state) {
+                    remapped = morph.send;
+                    if !morph.keep_mods {
+                        let pressed = morph.mods.iter().copied();
+                        let pressed = pressed.filter(|&m| self.keyboard_state.is_pressed(m));
+                        self.stripped.insert(key, pressed.collect());
+                    }
+                }
+                self.held.insert(key, remapped);
+                remapped
+            }
+            // two is key hold just ignore as it doesn't change the state of pressed keys
+            _ => return Ok(()),
+        };
+
+        // this looks at the physical keys so nothing in the config can get in the way
+        let chord_held = EMERGENCY_CHORD
+            .iter()
+            .all(|key| self.held.contains_key(key));
+        if !chord_held {
+            self.emergency_deadline = None;
+        } else if self.emergency_deadline.is_none() {
+            self.emergency_deadline = Some(Instant::now() + EMERGENCY_CHORD_HOLD);
+        }
+
+        if self.paused || !self.config.allows(remapped) {
+            return Ok(());
+        }
+
+        if let Some(usage) = keyset::consumer_usage(remapped) {
+            // setup screens only take a boot keyboard, which has no media keys
+            if self.bios {
+                return Ok(());
+            }
+            return self
+                .send_consumer(remapped, usage, event.value() == 1)
+                .await;
+        }
+
+        // modifiers are still held so they can go with the keys pressed while they're down
+        if self.bios && !keyset::is_modifier(remapped) {
+            if event.value() == 1 {
+                self.sender.tap_on(self.report(), &[remapped]).await?;
+                for pressed in [true, false] {
+                    let key = remapped;
+                    self.output.event(&Event::Key { key, pressed });
+                }
+            }
+            return Ok(());
+        }
+
+        if event.value() == 0 {
+            self.keyboard_state.release_key(remapped);
+        } else {
+            self.keyboard_state.press_key(remapped);
+        }
+
+        self.publish_status();
+
+        // which keys are held is as good as what's typed
+        if cfg!(debug_assertions) && !output::sensitive() {
+            eprintln!("{:?}", self.keyboard_state);
+        }
+
+        self.sender.send_state_update(self.report()).await?;
+        self.output.event(&Event::Key {
+            key: remapped,
+            pressed: event.value() != 0,
+        });
+        if event.value() == 1 {
+            self.expand_snippet(key, remapped).await?;
+        }
+        Ok(())
+    }
+
+    /// Hold down or let go of a media key on the consumer control, where pressing another
+    /// lets go of the last.
+    async fn send_consumer(&mut self, key: Key, usage: u16, pressed: bool) -> Result<()> {
+        if pressed {
+            self.consumer_held = Some(key);
+            self.sender.send_consumer(usage).await?;
+        } else if self.consumer_held == Some(key) {
+            self.consumer_held = None;
+            self.sender.send_consumer(0).await?;
+        }
+        self.output.event(&Event::Key { key, pressed });
+        Ok(())
+    }
+
+    /// Keep track of what's being typed, and replace any abbreviation from the config with
+    /// its expansion as soon as it's been typed.
+    async fn expand_snippet(&mut self, key: Key, remapped: Key) -> Result<()> {
+        if keyset::is_modifier(remapped) {
+            return Ok(());
+        }
+        if remapped == Key::KEY_BACKSPACE {
+            self.typed.pop();
+            return Ok(());
+        }
+        let state = self.report();
+        let shifted = [Key::KEY_LEFTSHIFT, Key::KEY_RIGHTSHIFT];
+        let shifted = shifted.into_iter().any(|key| state.is_pressed(key));
+        let chord = [
+            Key::KEY_LEFTCTRL,
+            Key::KEY_RIGHTCTRL,
+            Key::KEY_LEFTALT,
+            Key::KEY_RIGHTALT,
+            Key::KEY_LEFTMETA,
+            Key::KEY_RIGHTMETA,
+        ];
+        let chord = chord.into_iter().any(|key| state.is_pressed(key));
+        // anything else, like enter or moving the cursor, means we're typing somewhere else
+        let Some(c) = keyset::char_for_key(remapped, shifted).filter(|_| !chord) else {
+            self.typed.zeroize();
+            return Ok(());
+        };
+        self.typed.push(c);
+        if self.typed.chars().count() > SNIPPET_MEMORY {
+            self.typed.remove(0);
+        }
+
+        let Some((abbreviation, expansion)) = self.config.snippet(&self.typed) else {
+            return Ok(());
+        };
+        let backspaces = abbreviation.chars().count();
+        let expansion = Zeroizing::new(expansion.to_owned());
+        self.typed.zeroize();
+        // the key finishing the abbreviation has done its job, its release does nothing
+        self.held.remove(&key);
+        self.stripped.remove(&key);
+        self.keyboard_state.release_key(remapped);
+        for _ in 0..backspaces {
+            self.sender.tap(&[Key::KEY_BACKSPACE]).await?;
+        }
+        self.sender.type_text(&expansion).await?;
+        self.publish_status();
+        // put back whatever is really held down
+        self.sender.send_state_update(self.report()).await
+    }
+
+    async fn handle_control(&mut self, control: Control) -> Result<()> {
+        match control {
+            Control::TogglePause => self.toggle_pause().await?,
+            Control::SetPaused(paused) if paused != self.paused => {
+                self.toggle_pause().await?;
+            }
+            Control::SetPaused(_) => {}
+            Control::Type(text) => self.type_text(&Zeroizing::new(text)).await?,
+            Control::RunMacro(name) => self.run_macro(&name).await?,
+            Control::TypeSnippet(abbreviation) => self.type_snippet(&abbreviation).await?,
+            Control::Replay(path) => self.replay(&path).await?,
+            Control::Press(chord) => self.press(&chord).await?,
+            Control::UseProfile(name) => self.use_profile(&name).await?,
+            Control::Enter { along } => self.enter(along).await?,
+        }
+        Ok(())
+    }
+
+    /// Pass a key event back to this machine, making the keyboard to do it with if need be.
+    fn pass_back(&mut self, key: Key, value: i32) {
+        let local_keys = match &mut self.local_keys {
+            Some(local_keys) => local_keys,
+            // it can't have seen the key go down
+            None if value != 1 => return,
+            None => match LocalKeys::new(self.config.all_local_keys()) {
+                Ok(local_keys) => self.local_keys.insert(local_keys),
+                Err(e) => {
+                    self.output.event(&Event::Error {
+                        message: format!("Can't pass keys back to this machine: {e}"),
+                    });
+                    return;
+                }
+            },
+        };
+        if let Err(e) = local_keys.key(key, value) {
+            self.output.event(&Event::Error {
+                message: format!("Failed to pass {key:?} back to this machine: {e}"),
+            });
+        }
+    }
+
+    async fn toggle_mouse_keys(&mut self) -> Result<()> {
+        self.mouse_keys = !self.mouse_keys;
+        if !self.mouse_keys && self.pointer_used {
+            // don't leave a button held down when going back to typing
+            let state = self.pointer.release_buttons();
+            self.sender.send_pointer_update(state).await?;
+        }
+        self.output.event(&Event::MouseKeys {
+            enabled: self.mouse_keys,
+        });
+        Ok(())
+    }
+
+    async fn type_text(&mut self, text: &str) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        self.sender.type_text(text).await?;
+        // put back whatever is really held down
+        self.sender.send_state_update(self.report()).await
+    }
+
+    async fn press(&mut self, chord: &[Key]) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        self.sender.tap_on(self.report(), chord).await
+    }
+
+    async fn run_macro(&mut self, name: &str) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        let steps = self
+            .config
+            .macro_steps(name)
+            .map(|steps| Zeroizing::new(steps.to_vec()));
+        let Some(steps) = steps else {
+            self.output.event(&Event::Error {
+                message: format!("There's no macro called {name:?} in the config"),
+            });
+            return Ok(());
+        };
+        for step in steps.iter() {
+            match step {
+                MacroStep::Text { text } => self.sender.type_text(text).await?,
+                MacroStep::Chord { chord } => self.sender.tap(chord).await?,
+                MacroStep::Delay { delay_ms } => {
+                    self.sender.wait(Duration::from_millis(*delay_ms)).await;
+                }
+            }
+        }
+        // put back whatever is really held down
+        self.sender.send_state_update(self.report()).await
+    }
+
+    async fn type_snippet(&mut self, abbreviation: &str) -> Result<()> {
+        let expansion = self.config.expansion(abbreviation);
+        let Some(expansion) = expansion.map(|expansion| Zeroizing::new(expansion.to_owned()))
+        else {
+            self.output.event(&Event::Error {
+                message: format!("There's no snippet {abbreviation:?} in the config"),
+            });
+            return Ok(());
+        };
+        self.type_text(&expansion).await
+    }
+
+    async fn replay(&mut self, path: &Path) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        let script = tokio::fs::read_to_string(path).await.map(Zeroizing::new);
+        let commands = match script
+            .map_err(Into::into)
+            .and_then(|s| Command::parse_script(&s))
+        {
+            Ok(commands) => commands,
+            Err(e) => {
+                self.output.event(&Event::Error {
+                    message: format!("Not replaying {}: {e:#}", path.display()),
+                });
+                return Ok(());
+            }
+        };
+        for command in commands {
+            match command {
+                Command::Type(text) => self.sender.type_text(&text).await?,
+                Command::Press(chords) => {
+                    for chord in &chords {
+                        self.sender.tap(chord).await?;
+                    }
+                }
+                Command::Sleep(duration) => self.sender.wait(duration).await,
+            }
+        }
+        // put back whatever is really held down
+        self.sender.send_state_update(self.report()).await
+    }
+
+    async fn use_profile(&mut self, name: &str) -> Result<()> {
+        // the serial port is opened once at startup, there's no moving to another one
+        let send_to = self
+            .config
+            .profiles
+            .get(name)
+            .and_then(|profile| profile.send_to.clone());
+        if let (Some(send_to), Some(port)) = (send_to, self.sender.port_name()) {
+            if send_to != port {
+                self.output.event(&Event::Error {
+                    message: format!(
+                        "The {name} profile sends to {send_to} not {port}, \
+                         restart with --profile {name} to use it"
+                    ),
+                });
+                return Ok(());
+            }
+        }
+        if let Err(e) = self.config.use_profile(name) {
+            self.output.event(&Event::Error {
+                message: format!("{e:#}"),
+            });
+            return Ok(());
+        }
+
+        // what's held down might not be allowed or remapped the same any more
+        self.held.clear();
+        self.release_all().await?;
+        self.sender.set_typing_delay(self.config.typing_delay());
+        let key_delays = self.config.key_delays();
+        self.sender
+            .set_report_delays(self.config.report_interval(), key_delays);
+        self.pointer.set_motion(self.config.motion());
+        self.sender.set_lock_chord(self.config.lock_chord()).await?;
+        self.output.event(&Event::ProfileChanged { name });
+        self.publish_status();
+        Ok(())
+    }
+
+    /// Let go of everything when a device stops working, most likely because it's been
+    /// unplugged, and keep an eye out for it coming back rather than giving up.
+    async fn lose(&mut self, kind: DeviceKind, error: std::io::Error) -> Result<()> {
+        // before the gamepad goes, or its state wouldn't be released
+        self.held.clear();
+        self.hotkey_keys.clear();
+        self.emergency_deadline = None;
+        self.release_all().await?;
+
+        let stream = match kind {
+            DeviceKind::Keyboard => self.keyboard.take(),
+            DeviceKind::Gamepad => self.gamepad.take().map(|(stream, _)| stream),
+            DeviceKind::Pointer => self.pointer_events.take(),
+        };
+        if let Some(stream) = stream {
+            self.count(|metrics| &metrics.devices_lost);
+            let lost = LostDevice::new(kind, stream.device());
+            self.output.event(&Event::DeviceLost {
+                device: lost.name(),
+                reason: error.to_string(),
+            });
+            self.lost.push(lost);
+        }
+        self.publish_status();
+        Ok(())
+    }
+
+    fn reacquire(&mut self) -> Result<()> {
+        let mut found = Vec::new();
+        for (_, device) in evdev::enumerate() {
+            if let Some(i) = self.lost.iter().position(|lost| lost.matches(&device)) {
+                found.push((self.lost.swap_remove(i), device));
+            }
+        }
+
+        for (lost, mut device) in found {
+            if self.grab && !self.paused {
+                device.grab()?;
+            }
+            match lost.kind {
+                DeviceKind::Keyboard => self.keyboard = Some(device.into_event_stream()?),
+                DeviceKind::Gamepad => {
+                    let gamepad = Gamepad::new(&device)?;
+                    self.gamepad = Some((device.into_event_stream()?, gamepad));
+                }
+                DeviceKind::Pointer => self.pointer_events = Some(device.into_event_stream()?),
+            }
+            self.output.event(&Event::DeviceFound {
+                device: lost.name(),
+            });
+        }
+        self.show_capture();
+        Ok(())
+    }
+
+    async fn enter(&mut self, along: f64) -> Result<()> {
+        let Some(edge) = self.return_edge else {
+            return Ok(());
+        };
+        if !self.paused {
+            return Ok(());
+        }
+        self.pointer.enter_at(edge, along);
+        if let Some(state) = self.pointer.take_update() {
+            self.pointer_used = true;
+            self.sender.send_pointer_update(state).await?;
+        }
+        self.toggle_pause().await
+    }
+
+    // only does anything with --grab
+    fn set_grabbed(&mut self, grabbed: bool) -> std::io::Result<()> {
+        if !self.grab {
+            return Ok(());
+        }
+        let devices = self.keyboard.iter_mut().chain(&mut self.pointer_events);
+        // carry on past errors so we let go of as much as we can
+        let mut result = Ok(());
+        for device in devices {
+            let grab = if grabbed {
+                device.device_mut().grab()
+            } else {
+                device.device_mut().ungrab()
+            };
+            result = result.and(grab);
+        }
+        result
+    }
+
+    fn show_capture(&mut self) {
+        if let (Some(led), Some(keyboard)) = (&self.capture_led, &mut self.keyboard) {
+            led.show(keyboard.device_mut(), !self.paused);
+        }
+    }
+
+    /// Add one to a counter, if we're counting.
+    fn count(&self, counter: impl FnOnce(&Metrics) -> &Counter) {
+        if let Some(metrics) = self.sender.metrics() {
+            counter(metrics).inc();
+        }
+    }
+
+    /// How many frames have been sent so far, if we're counting.
+    fn frames_sent(&self) -> Option<u64> {
+        let metrics = self.sender.metrics()?;
+        Some(metrics.frames_sent.get())
+    }
+
+    /// Record how long an input event took to reach the serial port, going by the kernel's
+    /// timestamp, if handling it sent any frames since `frames_sent`.
+    fn time_capture(&self, captured: SystemTime, frames_sent: Option<u64>) {
+        let Some(metrics) = self.sender.metrics() else {
+            return;
+        };
+        if frames_sent.is_some_and(|before| metrics.frames_sent.get() > before) {
+            // the kernel's clock can be stepped backwards under us
+            if let Ok(latency) = captured.elapsed() {
+                metrics.capture_to_write.record(latency);
+            }
+        }
+    }
+
+    async fn send_probe(&mut self) -> Result<()> {
+        let nonce = self.probe.0.wrapping_add(1);
+        self.probe = (nonce, Some(Instant::now()));
+        self.sender
+            .send_message(shared::HostMessage::Probe { nonce })
+            .await
+    }
+
+    fn probe_answered(&mut self, nonce: u32) {
+        // an answer to one we've given up on, or a second answer, says nothing
+        if nonce != self.probe.0 {
+            return;
+        }
+        let (Some(sent), Some(metrics)) = (self.probe.1.take(), self.sender.metrics()) else {
+            return;
+        };
+        metrics.round_trip.record(sent.elapsed());
+    }
+
+    /// What's held down on the target, less any modifiers a morphed key is hiding.
+    fn report(&self) -> KeySet {
+        let mut report = self.keyboard_state;
+        for &modifier in self.stripped.values().flatten() {
+            report.release_key(modifier);
+        }
+        report
+    }
+
+    fn publish_status(&self) {
+        let Some(status) = &self.status else {
+            return;
+        };
+        let mut held_keys: Vec<_> = if self.paused || output::sensitive() {
+            Vec::new()
+        } else {
+            let held = self.held.values().copied();
+            held.filter(|&key| self.config.allows(key)).collect()
+        };
+        held_keys.sort_by_key(|key| key.code());
+        status.send_replace(Status {
+            paused: self.paused,
+            held_keys,
+            profile: self.config.profile_name().map(str::to_owned),
+            left_along: self.left_along,
+            agent: self.agent.report().clone(),
+        });
+    }
+
+    async fn reload_config(&mut self) -> Result<()> {
+        let Some(path) = &self.config_path else {
+            self.output
+                .status("Got SIGHUP but there is no config file to reload");
+            return Ok(());
+        };
+        // stay on the same profile, which had better still be there
+        let loaded = Config::load(path).and_then(|mut config| {
+            if let Some(name) = self.config.profile_name() {
+                config.use_profile(name)?;
+            }
+            Ok(config)
+        });
+        match loaded {
+            Ok(config) => {
+                self.output.event(&Event::ConfigReloaded { path });
+                self.config = config;
+                self.sender.set_typing_delay(self.config.typing_delay());
+                let key_delays = self.config.key_delays();
+                self.sender
+                    .set_report_delays(self.config.report_interval(), key_delays);
+                self.pointer.set_motion(self.config.motion());
+                self.scheduler = Scheduler::new(&self.config.schedule);
+                // there could be more keys to pass back now
+                self.local_keys = None;
+                self.publish_status();
+                self.sender.set_lock_chord(self.config.lock_chord()).await?;
+            }
+            Err(e) => self.output.event(&Event::Error {
+                message: format!("Keeping the old config: {e:#}"),
+            }),
+        }
+        Ok(())
+    }
+
+    async fn toggle_pause(&mut self) -> Result<()> {
+        self.paused = !self.paused;
+        if self.paused {
+            // no `?` until we've let go, we must never be stuck holding the keyboard
+            let ungrabbed = self.set_grabbed(false);
+            let released = self.release_all().await;
+            // not when the pointer has only gone back to this machine over the switching edge
+            let locked = match self.left_along {
+                Some(_) => Ok(()),
+                None => self.lock_target().await,
+            };
+            self.output.event(&Event::Paused);
+            ungrabbed?;
+            released?;
+            locked?;
+        } else {
+            self.left_along = None;
+            self.set_grabbed(true)?;
+            self.output.event(&Event::Resumed);
+        }
+        self.show_capture();
+        self.publish_status();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeviceKind {
+    Keyboard,
+    Gamepad,
+    Pointer,
+}
+
+/// Enough about a device to know it again when it's plugged back in, which could be
+/// at a different /dev/input/eventN.
+#[derive(Debug)]
+struct LostDevice {
+    kind: DeviceKind,
+    name: Option<String>,
+    vendor: u16,
+    product: u16,
+    // a receiver can have a keyboard and a mouse with the same name and ids
+    events: Vec<EventType>,
+}
+
+impl LostDevice {
+    fn new(kind: DeviceKind, device: &Device) -> Self {
+        let id = device.input_id();
+        Self {
+            kind,
+            name: device.name().map(str::to_owned),
+            vendor: id.vendor(),
+            product: id.product(),
+            events: device.supported_events().iter().collect(),
+        }
+    }
+
+    fn matches(&self, device: &Device) -> bool {
+        let id = device.input_id();
+        device.name() == self.name.as_deref()
+            && id.vendor() == self.vendor
+            && id.product() == self.product
+            && device
+                .supported_events()
+                .iter()
+                .eq(self.events.iter().copied())
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(match self.kind {
+            DeviceKind::Keyboard => "the keyboard",
+            DeviceKind::Gamepad => "the gamepad",
+            DeviceKind::Pointer => "the pointer",
+        })
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        if let (Some(led), Some(keyboard)) = (&self.capture_led, &mut self.keyboard) {
+            led.restore(keyboard.device_mut());
+        }
+    }
+}
+
+async fn next_event<T>(device: &mut Option<(EventStream, T)>) -> std::io::Result<InputEvent> {
+    match device {
+        Some((stream, _)) => stream.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_device_event(events: &mut Option<EventStream>) -> std::io::Result<InputEvent> {
+    match events {
+        Some(stream) => stream.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_signal(signal: &mut Option<Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_control(control: &mut Option<mpsc::UnboundedReceiver<Control>>) -> Control {
+    // once every sender has gone there's nothing more to wait for
+    match control {
+        Some(control) => match control.recv().await {
+            Some(control) => control,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,689 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Result, WrapErr as _};
+use evdev::Key;
+use serde::Deserialize;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    bridge::Control,
+    keyset::{self, KeySet, Layout},
+    pointer::Motion,
+    schedule::Job,
+};
+
+/// The optional TOML config file passed with `--config`, re-read on SIGHUP.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Keys to swap for another before forwarding, e.g. `KEY_CAPSLOCK = "KEY_ESC"`
+    #[serde(default)]
+    pub remap: HashMap<Key, Key>,
+    /// Named lists of steps which can be run remotely, e.g.
+    /// `unlock = [{ chord = ["KEY_LEFTCTRL"] }, { delay_ms = 500 }, { text = "hunter2\n" }]`
+    #[serde(default)]
+    pub macros: HashMap<String, Vec<MacroStep>>,
+    /// Abbreviations to swap for longer text as soon as they're typed, e.g.
+    /// `";sig" = "Best wishes,\nSam"`
+    #[serde(default)]
+    pub snippets: HashMap<String, String>,
+    /// Keys which send something else while a modifier is held, checked after remapping, e.g.
+    /// `morph = [{ key = "KEY_BACKSPACE", mods = ["KEY_LEFTSHIFT"], send = "KEY_DELETE" }]`
+    #[serde(default)]
+    pub morph: Vec<Morph>,
+    /// The target's keyboard layout for typing text, used when `--layout` isn't given and
+    /// this machine's layout isn't one there's a table for, e.g. `layout = "us-intl"`
+    pub layout: Option<Layout>,
+    /// How to scale movements from a mouse given as `--pointer`, e.g.
+    /// `pointer = { sensitivity = 1.5, acceleration = 0.05 }`
+    pub pointer: Option<Motion>,
+    /// Macros to run or text to type at set times while running, e.g.
+    /// `schedule = [{ cron = "0 3 * * *", macro = "accept" }]`
+    #[serde(default)]
+    pub schedule: Vec<Job>,
+    /// Keys which do something to the bridge rather than being forwarded, going by the key
+    /// pressed before any remapping, e.g. to use the numpad as a macropad with
+    /// `[macropad]` then `KEY_KP1 = { macro = "login" }`
+    #[serde(default)]
+    pub macropad: HashMap<Key, Action>,
+    /// Chords which do something to the bridge rather than being forwarded, going by the
+    /// keys pressed before any remapping, e.g. `[hotkeys]` then `"ctrl+alt+p" = "pause"`
+    #[serde(default)]
+    pub hotkeys: HashMap<Hotkey, Action>,
+    /// Settings for particular targets which go on top of the ones above, picked with
+    /// `--profile` or switched between while running, e.g. `[profiles.bios]`
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    // the profile in use, if any
+    #[serde(skip)]
+    profile: Option<String>,
+}
+
+/// The settings for one target.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// More remaps, these win over the top level ones.
+    #[serde(default)]
+    pub remap: HashMap<Key, Key>,
+    /// More macros, these win over the top level ones with the same name.
+    #[serde(default)]
+    pub macros: HashMap<String, Vec<MacroStep>>,
+    /// More snippets, these win over the top level ones with the same abbreviation.
+    #[serde(default)]
+    pub snippets: HashMap<String, String>,
+    /// More morphs, these are checked before the top level ones.
+    #[serde(default)]
+    pub morph: Vec<Morph>,
+    /// More macropad keys, these win over the top level ones.
+    #[serde(default)]
+    pub macropad: HashMap<Key, Action>,
+    /// More hotkeys, these win over the top level ones.
+    #[serde(default)]
+    pub hotkeys: HashMap<Hotkey, Action>,
+    /// Keys to pass back to this machine rather than forward, going by the key pressed
+    /// before any remapping, so e.g. `["KEY_VOLUMEUP", "KEY_MICMUTE"]` keep working with
+    /// the keyboard grabbed.
+    #[serde(default)]
+    pub local_keys: HashSet<Key>,
+    /// Only forward these keys (after remapping), for targets where a stray key does damage.
+    pub allowed_keys: Option<HashSet<Key>>,
+    /// How long to hold each key down when typing text, for targets which miss quick presses.
+    pub typing_delay_ms: Option<u64>,
+    /// The least time between one report and the next, for targets like KVMs which drop
+    /// keys when reports come back to back.
+    pub report_interval_ms: Option<u64>,
+    /// Extra time to wait after a report with one of these keys held before sending the
+    /// next, e.g. `{ KEY_ENTER = 100 }`
+    #[serde(default)]
+    pub key_delays_ms: HashMap<Key, u64>,
+    /// Keys to press together to lock the target when the client stops, forwarding is paused
+    /// or the teensy stops hearing from the client, e.g. `["KEY_LEFTMETA", "KEY_L"]`
+    pub lock_chord: Option<Vec<Key>>,
+    /// The serial port this target's teensy is on, used when `--send-to` isn't given.
+    pub send_to: Option<String>,
+    /// Another way to reach the same teensy if `send_to` fails, used when
+    /// `--backup-send-to` isn't given, e.g. `rfc2217://ser2net.lan:4001`
+    pub backup_send_to: Option<String>,
+    /// Mouse scaling instead of the top level one.
+    pub pointer: Option<Motion>,
+}
+
+/// A key which sends another while any of some modifiers are held.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Morph {
+    pub key: Key,
+    /// Holding any of these when `key` goes down sends `send` instead.
+    pub mods: Vec<Key>,
+    pub send: Key,
+    /// Carry on sending the held modifiers along with `send`, e.g. so shift+esc can send
+    /// shift+grave for ~, rather than letting go of them on the target until `key` is.
+    #[serde(default)]
+    pub keep_mods: bool,
+}
+
+/// One step of a macro.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum MacroStep {
+    /// Type some text in the target's layout.
+    Text { text: String },
+    /// Press some keys together and let go of them.
+    Chord { chord: Vec<Key> },
+    /// Wait before the next step.
+    Delay { delay_ms: u64 },
+}
+
+// macros and snippets can have passwords in, wiped when the config is reloaded or we stop
+impl Drop for Config {
+    fn drop(&mut self) {
+        wipe(&mut self.macros, &mut self.snippets);
+        for profile in self.profiles.values_mut() {
+            wipe(&mut profile.macros, &mut profile.snippets);
+        }
+    }
+}
+
+fn wipe(macros: &mut HashMap<String, Vec<MacroStep>>, snippets: &mut HashMap<String, String>) {
+    macros.values_mut().for_each(Zeroize::zeroize);
+    snippets.values_mut().for_each(Zeroize::zeroize);
+}
+
+impl Zeroize for MacroStep {
+    fn zeroize(&mut self) {
+        if let Self::Text { text } = self {
+            text.zeroize();
+        }
+    }
+}
+
+/// What a macropad key or hotkey does when it's pressed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Action {
+    /// One of the things the bridge can do by itself, e.g. `"pause"`.
+    Builtin(Builtin),
+    /// Run one of the macros.
+    Macro {
+        #[serde(rename = "macro")]
+        name: String,
+    },
+    /// Type some text in the target's layout.
+    Text { text: String },
+    /// Switch to another profile, e.g. to send to another target.
+    Profile { profile: String },
+    /// Type the expansion of one of the snippets.
+    Snippet { snippet: String },
+    /// Run the commands in a file, as taken by `client stdin`.
+    Replay { replay: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Builtin {
+    /// Pause or resume forwarding, the same as holding the emergency chord.
+    Pause,
+}
+
+impl Action {
+    pub fn control(&self) -> Control {
+        match self {
+            Self::Builtin(Builtin::Pause) => Control::TogglePause,
+            Self::Macro { name } => Control::RunMacro(name.clone()),
+            Self::Text { text } => Control::Type(text.clone()),
+            Self::Profile { profile } => Control::UseProfile(profile.clone()),
+            Self::Snippet { snippet } => Control::TypeSnippet(snippet.clone()),
+            Self::Replay { replay } => Control::Replay(replay.clone()),
+        }
+    }
+}
+
+/// Some modifiers and a key, written like `ctrl+alt+1` as for `client press`. Which side
+/// of the keyboard the modifiers are on doesn't matter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Hotkey {
+    // the left hand ones, sorted
+    mods: Vec<Key>,
+    key: Key,
+}
+
+impl Hotkey {
+    pub fn new(mods: impl IntoIterator<Item = Key>, key: Key) -> Self {
+        let mut mods: Vec<_> = mods.into_iter().map(left_hand).collect();
+        mods.sort_by_key(|key| key.code());
+        mods.dedup();
+        Self { mods, key }
+    }
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut keys = keyset::parse_chord(&value)?;
+        let key = keys.pop().filter(|&key| !keyset::is_modifier(key));
+        let Some(key) = key.filter(|_| keys.iter().all(|&key| keyset::is_modifier(key))) else {
+            return Err(format!(
+                "{value:?} isn't some modifiers followed by one key"
+            ));
+        };
+        Ok(Self::new(keys, key))
+    }
+}
+
+fn left_hand(key: Key) -> Key {
+    match key {
+        Key::KEY_RIGHTCTRL => Key::KEY_LEFTCTRL,
+        Key::KEY_RIGHTSHIFT => Key::KEY_LEFTSHIFT,
+        Key::KEY_RIGHTALT => Key::KEY_LEFTALT,
+        Key::KEY_RIGHTMETA => Key::KEY_LEFTMETA,
+        key => key,
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        // it can have passwords in macros, which shouldn't outlive the config
+        let contents = std::fs::read_to_string(path)
+            .map(Zeroizing::new)
+            .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Use the settings from the profile called `name` from now on.
+    pub fn use_profile(&mut self, name: &str) -> Result<()> {
+        if !self.profiles.contains_key(name) {
+            bail!("There's no profile called {name:?} in the config");
+        }
+        self.profile = Some(name.to_owned());
+        Ok(())
+    }
+
+    pub fn profile_name(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profiles.get(self.profile.as_deref()?)
+    }
+
+    pub fn remap(&self, key: Key) -> Key {
+        self.profile()
+            .and_then(|profile| profile.remap.get(&key))
+            .or_else(|| self.remap.get(&key))
+            .copied()
+            .unwrap_or(key)
+    }
+
+    /// Whether `key` is handled by this machine rather than the target.
+    pub fn is_local(&self, key: Key) -> bool {
+        self.profile()
+            .is_some_and(|profile| profile.local_keys.contains(&key))
+    }
+
+    /// Every key any profile handles on this machine.
+    pub fn all_local_keys(&self) -> HashSet<Key> {
+        let profiles = self.profiles.values();
+        profiles
+            .flat_map(|profile| &profile.local_keys)
+            .copied()
+            .collect()
+    }
+
+    /// Whether `key` can be forwarded, which is anything unless the profile says otherwise.
+    pub fn allows(&self, key: Key) -> bool {
+        self.profile()
+            .and_then(|profile| profile.allowed_keys.as_ref())
+            .is_none_or(|allowed| allowed.contains(&key))
+    }
+
+    /// The morph for `key` going down while `pressed` is held on the target, if any.
+    pub fn morph(&self, key: Key, pressed: &KeySet) -> Option<&Morph> {
+        let profile = self.profile().map(|profile| profile.morph.as_slice());
+        profile
+            .unwrap_or_default()
+            .iter()
+            .chain(&self.morph)
+            .find(|morph| morph.key == key && morph.mods.iter().any(|&m| pressed.is_pressed(m)))
+    }
+
+    /// The abbreviation `typed` ends with and what to replace it with, the longest if
+    /// several match.
+    pub fn snippet(&self, typed: &str) -> Option<(&str, &str)> {
+        let profile = self.profile().map(|profile| &profile.snippets);
+        // the profile's go last so they win ties, as max_by_key picks the last
+        [&self.snippets]
+            .into_iter()
+            .chain(profile)
+            .flatten()
+            .filter(|(abbreviation, _)| typed.ends_with(abbreviation.as_str()))
+            .max_by_key(|(abbreviation, _)| abbreviation.len())
+            .map(|(abbreviation, expansion)| (abbreviation.as_str(), expansion.as_str()))
+    }
+
+    /// What `key` does if it's one of the macropad keys.
+    pub fn pad_action(&self, key: Key) -> Option<&Action> {
+        self.profile()
+            .and_then(|profile| profile.macropad.get(&key))
+            .or_else(|| self.macropad.get(&key))
+    }
+
+    /// What `key` going down with the modifiers `mods` held does if it's one of the hotkeys.
+    pub fn hotkey(&self, mods: impl IntoIterator<Item = Key>, key: Key) -> Option<&Action> {
+        let hotkey = Hotkey::new(mods, key);
+        self.profile()
+            .and_then(|profile| profile.hotkeys.get(&hotkey))
+            .or_else(|| self.hotkeys.get(&hotkey))
+    }
+
+    /// What the snippet `abbreviation` expands to.
+    pub fn expansion(&self, abbreviation: &str) -> Option<&str> {
+        self.profile()
+            .and_then(|profile| profile.snippets.get(abbreviation))
+            .or_else(|| self.snippets.get(abbreviation))
+            .map(String::as_str)
+    }
+
+    pub fn macro_steps(&self, name: &str) -> Option<&[MacroStep]> {
+        self.profile()
+            .and_then(|profile| profile.macros.get(name))
+            .or_else(|| self.macros.get(name))
+            .map(Vec::as_slice)
+    }
+
+    pub fn motion(&self) -> Motion {
+        self.profile()
+            .and_then(|profile| profile.pointer)
+            .or(self.pointer)
+            .unwrap_or_default()
+    }
+
+    pub fn report_interval(&self) -> Duration {
+        let delay_ms = self
+            .profile()
+            .and_then(|profile| profile.report_interval_ms);
+        Duration::from_millis(delay_ms.unwrap_or(0))
+    }
+
+    pub fn key_delays(&self) -> HashMap<Key, Duration> {
+        let Some(profile) = self.profile() else {
+            return HashMap::new();
+        };
+        let delays = profile.key_delays_ms.iter();
+        delays
+            .map(|(&key, &delay_ms)| (key, Duration::from_millis(delay_ms)))
+            .collect()
+    }
+
+    pub fn lock_chord(&self) -> Option<&[Key]> {
+        self.profile()?.lock_chord.as_deref()
+    }
+
+    pub fn typing_delay(&self) -> Option<Duration> {
+        let delay_ms = self.profile()?.typing_delay_ms?;
+        Some(Duration::from_millis(delay_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remap() {
+        let config: Config = toml::from_str(
+            r#"
+            [remap]
+            KEY_CAPSLOCK = "KEY_ESC"
+            KEY_ESC = "KEY_CAPSLOCK"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.remap(Key::KEY_CAPSLOCK), Key::KEY_ESC);
+        assert_eq!(config.remap(Key::KEY_ESC), Key::KEY_CAPSLOCK);
+        assert_eq!(config.remap(Key::KEY_A), Key::KEY_A);
+    }
+
+    #[test]
+    fn test_parse_macros() {
+        let config: Config = toml::from_str(
+            r#"
+            [macros]
+            login = [{ text = "root\n" }, { delay_ms = 500 }, { chord = ["KEY_LEFTCTRL", "KEY_L"] }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.macros["login"],
+            [
+                MacroStep::Text {
+                    text: "root\n".to_owned()
+                },
+                MacroStep::Delay { delay_ms: 500 },
+                MacroStep::Chord {
+                    chord: vec![Key::KEY_LEFTCTRL, Key::KEY_L]
+                },
+            ]
+        );
+
+        let mut step = config.macros["login"][0].clone();
+        step.zeroize();
+        let wiped = MacroStep::Text {
+            text: String::new(),
+        };
+        assert_eq!(step, wiped);
+    }
+
+    #[test]
+    fn test_morph() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[morph]]
+            key = "KEY_BACKSPACE"
+            mods = ["KEY_LEFTSHIFT", "KEY_RIGHTSHIFT"]
+            send = "KEY_DELETE"
+
+            [[morph]]
+            key = "KEY_ESC"
+            mods = ["KEY_LEFTSHIFT"]
+            send = "KEY_GRAVE"
+            keep_mods = true
+
+            [profiles.vim]
+            morph = [{ key = "KEY_ESC", mods = ["KEY_LEFTSHIFT"], send = "KEY_CAPSLOCK" }]
+            "#,
+        )
+        .unwrap();
+        let mut pressed = KeySet::new();
+        assert_eq!(config.morph(Key::KEY_BACKSPACE, &pressed), None);
+
+        pressed.press_key(Key::KEY_RIGHTSHIFT);
+        let morph = config.morph(Key::KEY_BACKSPACE, &pressed).unwrap();
+        assert_eq!(morph.send, Key::KEY_DELETE);
+        assert!(!morph.keep_mods);
+        assert_eq!(config.morph(Key::KEY_ESC, &pressed), None);
+
+        pressed.press_key(Key::KEY_LEFTSHIFT);
+        let morph = config.morph(Key::KEY_ESC, &pressed).unwrap();
+        assert_eq!(morph.send, Key::KEY_GRAVE);
+        assert!(morph.keep_mods);
+
+        config.use_profile("vim").unwrap();
+        let morph = config.morph(Key::KEY_ESC, &pressed).unwrap();
+        assert_eq!(morph.send, Key::KEY_CAPSLOCK);
+        let morph = config.morph(Key::KEY_BACKSPACE, &pressed).unwrap();
+        assert_eq!(morph.send, Key::KEY_DELETE);
+    }
+
+    #[test]
+    fn test_snippets() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [snippets]
+            ";sig" = "Best wishes,\nSam"
+            "btw" = "by the way"
+            "xbtw" = "by the way, again"
+
+            [profiles.work.snippets]
+            ";sig" = "Regards,\nSam"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.snippet("hello"), None);
+        assert_eq!(config.snippet("oh btw"), Some(("btw", "by the way")));
+        assert_eq!(config.snippet("xbtw"), Some(("xbtw", "by the way, again")));
+        assert_eq!(
+            config.snippet("hi ;sig"),
+            Some((";sig", "Best wishes,\nSam"))
+        );
+
+        config.use_profile("work").unwrap();
+        assert_eq!(config.snippet("hi ;sig"), Some((";sig", "Regards,\nSam")));
+        assert_eq!(config.snippet("btw"), Some(("btw", "by the way")));
+    }
+
+    #[test]
+    fn test_macropad() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [macropad]
+            KEY_KP1 = { macro = "login" }
+            KEY_KP2 = { text = "hello\n" }
+            KEY_KP0 = { profile = "bios" }
+
+            [profiles.bios.macropad]
+            KEY_KP1 = { text = "\n" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.pad_action(Key::KEY_KP1).map(Action::control),
+            Some(Control::RunMacro("login".to_owned()))
+        );
+        assert_eq!(
+            config.pad_action(Key::KEY_KP0).map(Action::control),
+            Some(Control::UseProfile("bios".to_owned()))
+        );
+        assert_eq!(config.pad_action(Key::KEY_KP3), None);
+
+        config.use_profile("bios").unwrap();
+        assert_eq!(
+            config.pad_action(Key::KEY_KP1),
+            Some(&Action::Text {
+                text: "\n".to_owned()
+            })
+        );
+        assert_eq!(
+            config.pad_action(Key::KEY_KP2).map(Action::control),
+            Some(Control::Type("hello\n".to_owned()))
+        );
+
+        let both = "macropad = { KEY_KP1 = { macro = \"a\", text = \"b\" } }";
+        assert!(toml::from_str::<Config>(both).is_err());
+    }
+
+    #[test]
+    fn test_hotkeys() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [hotkeys]
+            "ctrl+alt+1" = { macro = "login" }
+            "super+p" = "pause"
+            "ctrl+shift+s" = { snippet = ";sig" }
+            "ctrl+alt+r" = { replay = "boot.txt" }
+
+            [snippets]
+            ";sig" = "Best wishes,\nSam"
+
+            [profiles.bios.hotkeys]
+            "ctrl+alt+1" = { text = "\n" }
+            "#,
+        )
+        .unwrap();
+        let ctrl_alt = [Key::KEY_RIGHTALT, Key::KEY_LEFTCTRL];
+        assert_eq!(
+            config.hotkey(ctrl_alt, Key::KEY_1).map(Action::control),
+            Some(Control::RunMacro("login".to_owned()))
+        );
+        assert_eq!(
+            config.hotkey([Key::KEY_RIGHTMETA], Key::KEY_P),
+            Some(&Action::Builtin(Builtin::Pause))
+        );
+        assert_eq!(
+            config.hotkey(ctrl_alt, Key::KEY_R).map(Action::control),
+            Some(Control::Replay(PathBuf::from("boot.txt")))
+        );
+        // the modifiers have to be just the ones given
+        assert_eq!(config.hotkey([Key::KEY_LEFTCTRL], Key::KEY_1), None);
+        assert_eq!(config.hotkey([], Key::KEY_P), None);
+        assert_eq!(config.expansion(";sig"), Some("Best wishes,\nSam"));
+
+        config.use_profile("bios").unwrap();
+        assert_eq!(
+            config.hotkey(ctrl_alt, Key::KEY_1).map(Action::control),
+            Some(Control::Type("\n".to_owned()))
+        );
+        let ctrl_shift = [Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT];
+        assert_eq!(
+            config.hotkey(ctrl_shift, Key::KEY_S).map(Action::control),
+            Some(Control::TypeSnippet(";sig".to_owned()))
+        );
+
+        let no_key = "hotkeys = { \"ctrl+alt\" = \"pause\" }";
+        assert!(toml::from_str::<Config>(no_key).is_err());
+        let two_keys = "hotkeys = { \"ctrl+a+b\" = \"pause\" }";
+        assert!(toml::from_str::<Config>(two_keys).is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut config: Config = toml::from_str(
+            r#"
+            layout = "us-intl"
+            pointer = { sensitivity = 2.0 }
+
+            [remap]
+            KEY_CAPSLOCK = "KEY_ESC"
+
+            [profiles.windows]
+            remap = { KEY_LEFTMETA = "KEY_LEFTCTRL" }
+            pointer = { acceleration = 0.05 }
+            local_keys = ["KEY_VOLUMEUP", "KEY_VOLUMEDOWN"]
+
+            [profiles.bios]
+            send_to = "/dev/ttyUSB1"
+            backup_send_to = "rfc2217://ser2net.lan:4001"
+            lock_chord = ["KEY_LEFTCTRL", "KEY_LEFTALT", "KEY_L"]
+            typing_delay_ms = 50
+            report_interval_ms = 20
+            key_delays_ms = { KEY_ENTER = 100 }
+            allowed_keys = ["KEY_UP", "KEY_DOWN", "KEY_ENTER", "KEY_ESC", "KEY_F10"]
+            remap = { KEY_CAPSLOCK = "KEY_F10" }
+            "#,
+        )
+        .unwrap();
+        assert!(config.allows(Key::KEY_A));
+        assert_eq!(config.layout, Some(Layout::UsIntl));
+        assert_eq!(config.typing_delay(), None);
+        assert_eq!(config.report_interval(), Duration::ZERO);
+        assert!(config.key_delays().is_empty());
+        assert_eq!(config.lock_chord(), None);
+        assert_eq!(config.motion().sensitivity, 2.0);
+
+        assert!(!config.is_local(Key::KEY_VOLUMEUP));
+
+        config.use_profile("windows").unwrap();
+        assert!(config.is_local(Key::KEY_VOLUMEUP));
+        assert!(!config.is_local(Key::KEY_MUTE));
+        assert_eq!(config.remap(Key::KEY_LEFTMETA), Key::KEY_LEFTCTRL);
+        assert_eq!(config.remap(Key::KEY_CAPSLOCK), Key::KEY_ESC);
+        assert_eq!(config.lock_chord(), None);
+        assert_eq!(
+            config.motion(),
+            Motion {
+                acceleration: 0.05,
+                ..Motion::default()
+            }
+        );
+
+        config.use_profile("bios").unwrap();
+        assert_eq!(config.remap(Key::KEY_LEFTMETA), Key::KEY_LEFTMETA);
+        assert_eq!(config.remap(Key::KEY_CAPSLOCK), Key::KEY_F10);
+        assert!(config.allows(Key::KEY_ENTER));
+        assert!(!config.allows(Key::KEY_A));
+        assert_eq!(config.typing_delay(), Some(Duration::from_millis(50)));
+        assert_eq!(config.report_interval(), Duration::from_millis(20));
+        assert_eq!(
+            config.key_delays(),
+            HashMap::from([(Key::KEY_ENTER, Duration::from_millis(100))])
+        );
+        assert_eq!(
+            config.lock_chord(),
+            Some([Key::KEY_LEFTCTRL, Key::KEY_LEFTALT, Key::KEY_L].as_slice())
+        );
+        assert_eq!(
+            config.profile().unwrap().send_to.as_deref(),
+            Some("/dev/ttyUSB1")
+        );
+        assert_eq!(
+            config.profile().unwrap().backup_send_to.as_deref(),
+            Some("rfc2217://ser2net.lan:4001")
+        );
+
+        assert!(!config.is_local(Key::KEY_VOLUMEUP));
+        assert_eq!(
+            config.all_local_keys(),
+            HashSet::from([Key::KEY_VOLUMEUP, Key::KEY_VOLUMEDOWN])
+        );
+
+        assert!(config.use_profile("linux").is_err());
+        assert_eq!(config.profile_name(), Some("bios"));
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    io::IsTerminal as _,
+    os::unix::fs::{FileTypeExt as _, PermissionsExt as _},
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use color_eyre::eyre::{bail, eyre, Result, WrapErr as _};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{UnixListener, UnixStream},
+    select,
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::bridge::{Control, Status};
+
+#[derive(Args, Debug)]
+pub struct ControlArgs {
+    /// the control socket of a running bridge, e.g. one forwarded with
+    /// `ssh -L /tmp/bridge.sock:/run/bridge.sock lab-box`
+    socket: PathBuf,
+
+    /// a file holding the same token as the bridge's --control-token-file
+    #[arg(long)]
+    token_file: PathBuf,
+
+    /// what to do: status, pause, resume, type TEXT, macro NAME or profile NAME, each
+    /// line of stdin is typed if nothing is given
+    command: Vec<String>,
+}
+
+/// Listen on a unix socket for commands to a running bridge, until `token` is cancelled.
+///
+/// Each line is a command, the first of which has to be `auth <token>`, after which
+/// `status`, `pause`, `resume`, `type <text>`, `macro <name>` and `profile <name>` can be
+/// sent, where `\n`, `\t` and `\\` in the text are a newline, a tab and a backslash.
+/// Every command is answered with a line starting with `ok` or `error`.
+pub async fn serve(
+    path: PathBuf,
+    secret: String,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+    token: CancellationToken,
+) -> Result<()> {
+    remove_stale_socket(&path)?;
+    let listener = UnixListener::bind(&path)
+        .wrap_err_with(|| format!("Failed to listen on {}", path.display()))?;
+    // the token is the real check, but there's no need to let anyone else try
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    tokio::spawn(async move {
+        loop {
+            let stream = select! {
+                _ = token.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(_) => continue,
+                },
+            };
+            let session = Session {
+                secret: secret.clone(),
+                status: status.clone(),
+                control: control.clone(),
+            };
+            // a connection going wrong is the other end's problem
+            tokio::spawn(async move { session.run(stream).await.ok() });
+        }
+        let _ = std::fs::remove_file(&path);
+    });
+
+    Ok(())
+}
+
+/// Send a command to a running bridge's control socket and print the answer.
+pub async fn send(args: ControlArgs) -> Result<()> {
+    let secret = read_token(&args.token_file)?;
+    let stream = UnixStream::connect(&args.socket)
+        .await
+        .wrap_err_with(|| format!("Failed to connect to {}", args.socket.display()))?;
+    let mut stream = BufReader::new(stream);
+
+    request(&mut stream, &format!("auth {secret}")).await?;
+
+    if !args.command.is_empty() {
+        let reply = request(&mut stream, &args.command.join(" ")).await?;
+        let reply = reply.trim_start_matches("ok").trim_start();
+        if !reply.is_empty() {
+            println!("{reply}");
+        }
+        return Ok(());
+    }
+
+    if std::io::stdin().is_terminal() {
+        eprintln!("Typing each line onto the target, press Ctrl+D to stop.");
+    }
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = stdin.next_line().await? {
+        request(&mut stream, &format!("type {}\\n", escape(&line))).await?;
+    }
+
+    Ok(())
+}
+
+async fn request(stream: &mut BufReader<UnixStream>, line: &str) -> Result<String> {
+    stream.write_all(format!("{line}\n").as_bytes()).await?;
+    let mut reply = String::new();
+    if stream.read_line(&mut reply).await? == 0 {
+        bail!("The bridge hung up");
+    }
+    let reply = reply.trim_end();
+    match reply.strip_prefix("error") {
+        Some(e) => bail!("The bridge said{e}"),
+        None => Ok(reply.to_owned()),
+    }
+}
+
+struct Session {
+    secret: String,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+}
+
+impl Session {
+    async fn run(self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        let authed = match lines.next_line().await? {
+            Some(line) => line
+                .strip_prefix("auth ")
+                .is_some_and(|secret| same_secret(secret, &self.secret)),
+            None => false,
+        };
+        if !authed {
+            writer.write_all(b"error wrong token\n").await?;
+            return Ok(());
+        }
+        writer.write_all(b"ok\n").await?;
+
+        while let Some(line) = lines.next_line().await? {
+            let reply = match self.handle(&line) {
+                Ok(reply) => format!("ok{reply}\n"),
+                Err(e) => format!("error {e}\n"),
+            };
+            writer.write_all(reply.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    // returns anything to say after the `ok`
+    fn handle(&self, line: &str) -> Result<String> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let (paused, profile) = {
+            let status = self.status.borrow();
+            (status.paused, status.profile.clone())
+        };
+        let control = match command {
+            "status" => {
+                let state = if paused { "paused" } else { "forwarding" };
+                return Ok(match profile {
+                    Some(profile) => format!(" {state} {profile}"),
+                    None => format!(" {state}"),
+                });
+            }
+            "pause" => Control::SetPaused(true),
+            "resume" => Control::SetPaused(false),
+            "type" if paused => bail!("forwarding is paused"),
+            "type" => Control::Type(unescape(rest)),
+            "macro" if paused => bail!("forwarding is paused"),
+            "macro" => Control::RunMacro(rest.to_owned()),
+            "profile" => Control::UseProfile(rest.to_owned()),
+            _ => bail!("unknown command {command:?}"),
+        };
+        self.control
+            .send(control)
+            .map_err(|_| eyre!("the bridge is stopping"))?;
+        Ok(String::new())
+    }
+}
+
+/// Read the shared token, it's up to whoever makes it to keep it secret.
+pub fn read_token(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read the token from {}", path.display()))?;
+    let token = token.trim();
+    if token.is_empty() || token.contains(char::is_whitespace) {
+        bail!("The token in {} should be one word", path.display());
+    }
+    Ok(token.to_owned())
+}
+
+// a socket left behind by a bridge that didn't get to clean up, but not anything else
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => Ok(std::fs::remove_file(path)?),
+        Ok(_) => bail!("{} already exists and isn't a socket", path.display()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Compare tokens without giving away how much was right by how long it took to say no.
+pub fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t")
+}
+
+/// Undo the `\n`, `\t` and `\\` escapes in text sent over the socket.
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escaping() {
+        let text = "a\\b\tc";
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape("one\\ntwo\\\\n"), "one\ntwo\\n");
+    }
+
+    #[test]
+    fn test_session_commands() {
+        let (status_tx, status) = watch::channel(Status::default());
+        let (control, mut control_rx) = mpsc::unbounded_channel();
+        let session = Session {
+            secret: "hunter2".to_owned(),
+            status,
+            control,
+        };
+
+        assert_eq!(session.handle("status").unwrap(), " forwarding");
+        session.handle("type hi\\n").unwrap();
+        assert_eq!(
+            control_rx.try_recv().unwrap(),
+            Control::Type("hi\n".to_owned())
+        );
+
+        status_tx.send_modify(|status| status.paused = true);
+        assert!(session.handle("type hi").is_err());
+        session.handle("resume").unwrap();
+        assert_eq!(control_rx.try_recv().unwrap(), Control::SetPaused(false));
+        assert!(session.handle("reboot").is_err());
+
+        status_tx.send_modify(|status| status.profile = Some("bios".to_owned()));
+        assert_eq!(session.handle("status").unwrap(), " paused bios");
+        session.handle("profile windows").unwrap();
+        assert_eq!(
+            control_rx.try_recv().unwrap(),
+            Control::UseProfile("windows".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_same_secret() {
+        assert!(same_secret("hunter2", "hunter2"));
+        assert!(!same_secret("hunter2", "hunter3"));
+        assert!(!same_secret("hunter2", "hunter"));
+    }
+}
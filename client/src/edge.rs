@@ -0,0 +1,142 @@
+use std::{thread, time::Duration};
+
+use color_eyre::eyre::{Result, WrapErr as _};
+use tokio::sync::{mpsc, watch};
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConnectionExt as _, Window},
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    bridge::{Control, Status},
+    output::{Event, Output},
+    pointer::Edge,
+};
+
+/// How often to look where the local pointer is, X11 won't tell us when it moves.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How far to move the local pointer back from the edge when the target hands back, so
+/// it doesn't go straight back over.
+const BOUNCE: i16 = 8;
+
+/// Watch the local pointer and ask the bridge to take over when it goes off `edge` of the
+/// screen, and put it back where the target's pointer went off once the bridge hands back.
+///
+/// There's no way to find the pointer on Wayland so this needs an X11 session.
+pub fn spawn(
+    edge: Edge,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+    output: Output,
+) -> Result<()> {
+    let (connection, screen) =
+        x11rb::connect(None).wrap_err("--switch-edge needs an X11 display to watch")?;
+    let root = &connection.setup().roots[screen];
+    let watcher = Watcher {
+        edge,
+        root: root.root,
+        width: root.width_in_pixels as i16,
+        height: root.height_in_pixels as i16,
+        connection,
+    };
+
+    thread::spawn(move || {
+        if let Err(e) = watcher.run(status, control) {
+            output.event(&Event::Error {
+                message: format!("Stopped watching for the pointer leaving the screen: {e:#}"),
+            });
+        }
+    });
+
+    Ok(())
+}
+
+struct Watcher {
+    edge: Edge,
+    root: Window,
+    width: i16,
+    height: i16,
+    connection: RustConnection,
+}
+
+impl Watcher {
+    fn run(
+        &self,
+        mut status: watch::Receiver<Status>,
+        control: mpsc::UnboundedSender<Control>,
+    ) -> Result<()> {
+        let mut paused = status.borrow().paused;
+        // the bridge has gone once nobody is listening
+        while !control.is_closed() {
+            let (now_paused, left_along) = {
+                let status = status.borrow_and_update();
+                (status.paused, status.left_along)
+            };
+            if now_paused && !paused {
+                self.bounce(left_along)?;
+            }
+            paused = now_paused;
+
+            if paused {
+                let pointer = self.connection.query_pointer(self.root)?.reply()?;
+                if let Some(along) = self.off_edge(pointer.root_x, pointer.root_y) {
+                    let _ = control.send(Control::Enter { along });
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    // how far along the edge the pointer is, if it's on it
+    fn off_edge(&self, x: i16, y: i16) -> Option<f64> {
+        let (right, bottom) = (self.width - 1, self.height - 1);
+        let along = |value: i16, max: i16| f64::from(value) / f64::from(max.max(1));
+        match self.edge {
+            Edge::Left if x <= 0 => Some(along(y, bottom)),
+            Edge::Right if x >= right => Some(along(y, bottom)),
+            Edge::Top if y <= 0 => Some(along(x, right)),
+            Edge::Bottom if y >= bottom => Some(along(x, right)),
+            _ => None,
+        }
+    }
+
+    // `along` the edge if the target's pointer went off it, otherwise wherever it is
+    fn bounce(&self, along: Option<f64>) -> Result<()> {
+        let (dx, dy) = match self.edge {
+            Edge::Left => (BOUNCE, 0),
+            Edge::Right => (-BOUNCE, 0),
+            Edge::Top => (0, BOUNCE),
+            Edge::Bottom => (0, -BOUNCE),
+        };
+        match along {
+            Some(along) => {
+                let (x, y) = self.on_edge(along);
+                self.connection
+                    .warp_pointer(x11rb::NONE, self.root, 0, 0, 0, 0, x + dx, y + dy)?;
+            }
+            None => {
+                self.connection
+                    .warp_pointer(x11rb::NONE, x11rb::NONE, 0, 0, 0, 0, dx, dy)?;
+            }
+        }
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    // the spot `along` the edge, the other way round to off_edge
+    fn on_edge(&self, along: f64) -> (i16, i16) {
+        let (right, bottom) = (self.width - 1, self.height - 1);
+        let at = |max: i16| (along.clamp(0.0, 1.0) * f64::from(max)).round() as i16;
+        match self.edge {
+            Edge::Left => (0, at(bottom)),
+            Edge::Right => (right, at(bottom)),
+            Edge::Top => (at(right), 0),
+            Edge::Bottom => (at(right), bottom),
+        }
+    }
+}
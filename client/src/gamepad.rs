@@ -0,0 +1,162 @@
+use color_eyre::eyre::Result;
+use evdev::{AbsoluteAxisType, Device, InputEvent, InputEventKind, Key, Synchronization};
+
+/// Which bit of the report each evdev button sets.
+const BUTTONS: [Key; 16] = [
+    Key::BTN_SOUTH,
+    Key::BTN_EAST,
+    Key::BTN_WEST,
+    Key::BTN_NORTH,
+    Key::BTN_TL,
+    Key::BTN_TR,
+    Key::BTN_TL2,
+    Key::BTN_TR2,
+    Key::BTN_SELECT,
+    Key::BTN_START,
+    Key::BTN_THUMBL,
+    Key::BTN_THUMBR,
+    Key::BTN_DPAD_UP,
+    Key::BTN_DPAD_DOWN,
+    Key::BTN_DPAD_LEFT,
+    Key::BTN_DPAD_RIGHT,
+];
+
+// a lot of pads report the dpad as a hat rather than buttons
+const DPAD_UP: u16 = 1 << 12;
+const DPAD_DOWN: u16 = 1 << 13;
+const DPAD_LEFT: u16 = 1 << 14;
+const DPAD_RIGHT: u16 = 1 << 15;
+
+/// Turns evdev events from a gamepad into the state we send to the firmware.
+pub struct Gamepad {
+    state: shared::GamepadState,
+    // the (min, max) range of each axis, indexed by the axis code
+    ranges: Vec<(i32, i32)>,
+    changed: bool,
+}
+
+impl Gamepad {
+    pub fn new(device: &Device) -> Result<Self> {
+        let ranges = device
+            .get_abs_state()?
+            .iter()
+            .map(|info| (info.minimum, info.maximum))
+            .collect();
+
+        Ok(Self {
+            state: shared::GamepadState::default(),
+            ranges,
+            changed: false,
+        })
+    }
+
+    /// What the gamepad on the target should be doing now.
+    pub fn state(&self) -> shared::GamepadState {
+        self.state
+    }
+
+    /// Update the state from an event, returning the new state once the device has
+    /// finished sending a batch of changes.
+    pub fn handle_event(&mut self, event: InputEvent) -> Option<shared::GamepadState> {
+        match event.kind() {
+            InputEventKind::Key(key) => {
+                let bit = BUTTONS.iter().position(|&button| button == key)?;
+                self.set_buttons(1 << bit, event.value() != 0);
+            }
+            InputEventKind::AbsAxis(axis) => self.set_axis(axis, event.value()),
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) if self.changed => {
+                self.changed = false;
+                return Some(self.state);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn set_buttons(&mut self, mask: u16, pressed: bool) {
+        let buttons = if pressed {
+            self.state.buttons | mask
+        } else {
+            self.state.buttons & !mask
+        };
+        self.changed |= buttons != self.state.buttons;
+        self.state.buttons = buttons;
+    }
+
+    fn set_axis(&mut self, axis: AbsoluteAxisType, value: i32) {
+        let scaled = self.scale(axis, value);
+        let slot = match axis {
+            AbsoluteAxisType::ABS_X => &mut self.state.x,
+            AbsoluteAxisType::ABS_Y => &mut self.state.y,
+            AbsoluteAxisType::ABS_Z => &mut self.state.z,
+            AbsoluteAxisType::ABS_RX => &mut self.state.rx,
+            AbsoluteAxisType::ABS_RY => &mut self.state.ry,
+            AbsoluteAxisType::ABS_RZ => &mut self.state.rz,
+            AbsoluteAxisType::ABS_HAT0X => {
+                self.set_buttons(DPAD_LEFT, value < 0);
+                self.set_buttons(DPAD_RIGHT, value > 0);
+                return;
+            }
+            AbsoluteAxisType::ABS_HAT0Y => {
+                self.set_buttons(DPAD_UP, value < 0);
+                self.set_buttons(DPAD_DOWN, value > 0);
+                return;
+            }
+            _ => return,
+        };
+        self.changed |= *slot != scaled;
+        *slot = scaled;
+    }
+
+    // map the axis' range onto -127..=127
+    fn scale(&self, axis: AbsoluteAxisType, value: i32) -> i8 {
+        let Some(&(min, max)) = self.ranges.get(axis.0 as usize) else {
+            return 0;
+        };
+        if max <= min {
+            return 0;
+        }
+
+        let value = i64::from(value.clamp(min, max));
+        let (min, max) = (i64::from(min), i64::from(max));
+        ((value - min) * 254 / (max - min) - 127) as i8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gamepad() -> Gamepad {
+        Gamepad {
+            state: shared::GamepadState::default(),
+            ranges: vec![(0, 255), (-32768, 32767)],
+            changed: false,
+        }
+    }
+
+    #[test]
+    fn test_scale_axes() {
+        let gamepad = gamepad();
+        assert_eq!(gamepad.scale(AbsoluteAxisType::ABS_X, 0), -127);
+        assert_eq!(gamepad.scale(AbsoluteAxisType::ABS_X, 255), 127);
+        assert_eq!(gamepad.scale(AbsoluteAxisType::ABS_Y, -32768), -127);
+        assert_eq!(gamepad.scale(AbsoluteAxisType::ABS_Y, 32767), 127);
+        // no range info means we can't say anything sensible
+        assert_eq!(gamepad.scale(AbsoluteAxisType::ABS_Z, 100), 0);
+    }
+
+    #[test]
+    fn test_report_on_sync() {
+        let mut gamepad = gamepad();
+        let press = InputEvent::new(evdev::EventType::KEY, Key::BTN_EAST.code(), 1);
+        let sync = InputEvent::new(evdev::EventType::SYNCHRONIZATION, 0, 0);
+
+        assert_eq!(gamepad.handle_event(press), None);
+        let state = gamepad.handle_event(sync).unwrap();
+        assert_eq!(state.buttons, 0b10);
+        // nothing changed so nothing to send
+        assert_eq!(gamepad.handle_event(sync), None);
+    }
+}
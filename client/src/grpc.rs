@@ -0,0 +1,198 @@
+use std::{net::SocketAddr, time::Duration};
+
+use color_eyre::eyre::{bail, Result, WrapErr as _};
+use tokio::sync::{mpsc, watch};
+use tokio_util::sync::CancellationToken;
+use tonic::{
+    transport::{server::TcpIncoming, Server},
+    Request, Response,
+};
+
+use crate::{
+    bridge::{Control, Status},
+    control,
+};
+
+mod proto {
+    tonic::include_proto!("teensy_bridge.v1");
+}
+
+use proto::bridge_server::{Bridge, BridgeServer};
+
+/// How long to wait for the bridge to pause or resume before giving up on it.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serve the API in proto/bridge.proto until `token` is cancelled, checking for
+/// `authorization: Bearer <secret>` on every call if there's a secret.
+pub fn serve(
+    addr: SocketAddr,
+    secret: Option<String>,
+    target: String,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+    token: CancellationToken,
+) -> Result<()> {
+    if secret.is_none() && !addr.ip().is_loopback() {
+        bail!("Anyone could drive the bridge, pass --control-token-file to listen on {addr}");
+    }
+    let incoming =
+        TcpIncoming::bind(addr).wrap_err_with(|| format!("Failed to listen on {addr}"))?;
+
+    let service = Service {
+        target,
+        status,
+        control,
+    };
+    let check_secret = move |request: Request<()>| {
+        let Some(secret) = &secret else {
+            return Ok(request);
+        };
+        let given = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or("");
+        if control::same_secret(given, secret) {
+            Ok(request)
+        } else {
+            Err(tonic::Status::unauthenticated("wrong token"))
+        }
+    };
+
+    let server = Server::builder()
+        .add_service(BridgeServer::with_interceptor(service, check_secret))
+        .serve_with_incoming_shutdown(incoming, token.cancelled_owned());
+    tokio::spawn(server);
+
+    Ok(())
+}
+
+struct Service {
+    target: String,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+}
+
+impl Service {
+    fn status(&self) -> proto::Status {
+        let status = self.status.borrow();
+        proto::Status {
+            paused: status.paused,
+            held_keys: status
+                .held_keys
+                .iter()
+                .map(|key| format!("{key:?}"))
+                .collect(),
+            target: self.target.clone(),
+            profile: status.profile.clone().unwrap_or_default(),
+        }
+    }
+
+    fn send(&self, control: Control) -> Result<(), tonic::Status> {
+        self.control
+            .send(control)
+            .map_err(|_| tonic::Status::unavailable("the bridge is stopping"))
+    }
+
+    fn check_forwarding(&self) -> Result<(), tonic::Status> {
+        if self.status.borrow().paused {
+            return Err(tonic::Status::failed_precondition("forwarding is paused"));
+        }
+        Ok(())
+    }
+
+    async fn set_paused(&self, paused: bool) -> Result<Response<proto::Status>, tonic::Status> {
+        self.send(Control::SetPaused(paused))?;
+        let mut status = self.status.clone();
+        tokio::time::timeout(SETTLE_TIMEOUT, status.wait_for(|s| s.paused == paused))
+            .await
+            .map_err(|_| tonic::Status::deadline_exceeded("the bridge didn't answer in time"))?
+            .map_err(|_| tonic::Status::unavailable("the bridge is stopping"))?;
+        Ok(Response::new(self.status()))
+    }
+}
+
+#[tonic::async_trait]
+impl Bridge for Service {
+    async fn get_status(
+        &self,
+        _: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, tonic::Status> {
+        Ok(Response::new(self.status()))
+    }
+
+    async fn pause(
+        &self,
+        _: Request<proto::PauseRequest>,
+    ) -> Result<Response<proto::Status>, tonic::Status> {
+        self.set_paused(true).await
+    }
+
+    async fn resume(
+        &self,
+        _: Request<proto::ResumeRequest>,
+    ) -> Result<Response<proto::Status>, tonic::Status> {
+        self.set_paused(false).await
+    }
+
+    async fn type_text(
+        &self,
+        request: Request<proto::TypeTextRequest>,
+    ) -> Result<Response<proto::TypeTextReply>, tonic::Status> {
+        self.check_forwarding()?;
+        self.send(Control::Type(request.into_inner().text))?;
+        Ok(Response::new(proto::TypeTextReply {}))
+    }
+
+    async fn run_macro(
+        &self,
+        request: Request<proto::RunMacroRequest>,
+    ) -> Result<Response<proto::RunMacroReply>, tonic::Status> {
+        self.check_forwarding()?;
+        self.send(Control::RunMacro(request.into_inner().name))?;
+        Ok(Response::new(proto::RunMacroReply {}))
+    }
+
+    async fn switch_target(
+        &self,
+        _: Request<proto::SwitchTargetRequest>,
+    ) -> Result<Response<proto::Status>, tonic::Status> {
+        Err(tonic::Status::unimplemented(
+            "this bridge only has the one target",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pause_waits_for_the_bridge() {
+        let (status_tx, status) = watch::channel(Status::default());
+        let (control, mut control_rx) = mpsc::unbounded_channel();
+        let service = Service {
+            target: "/dev/ttyUSB0".to_owned(),
+            status,
+            control,
+        };
+
+        // stand in for the bridge doing what it's told
+        tokio::spawn(async move {
+            while let Some(control) = control_rx.recv().await {
+                if let Control::SetPaused(paused) = control {
+                    status_tx.send_modify(|status| status.paused = paused);
+                }
+            }
+        });
+
+        let status = service.set_paused(true).await.unwrap().into_inner();
+        assert!(status.paused);
+        assert_eq!(status.target, "/dev/ttyUSB0");
+        assert_eq!(
+            service.check_forwarding().unwrap_err().code(),
+            tonic::Code::FailedPrecondition
+        );
+    }
+}
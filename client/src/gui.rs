@@ -0,0 +1,244 @@
+use std::{ffi::OsString, time::Duration};
+
+use clap::Parser;
+use color_eyre::eyre::{eyre, Result};
+use eframe::egui;
+use tokio::{runtime::Handle, sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    bridge::Status,
+    list::{self, InputDevice, SerialPort},
+    output::Output,
+    RunArgs,
+};
+
+/// The same options as `client run`, so anything without a widget gets its usual default.
+#[derive(Parser)]
+struct GuiRunArgs {
+    #[command(flatten)]
+    args: RunArgs,
+}
+
+/// Pick a keyboard and a serial port, start and stop forwarding, and see what's held down.
+pub fn run() -> Result<()> {
+    let handle = Handle::current();
+    // eframe wants the thread to itself for the event loop
+    tokio::task::block_in_place(|| {
+        eframe::run_native(
+            "Teensy keyboard bridge",
+            eframe::NativeOptions::default(),
+            Box::new(|_| Ok(Box::new(App::new(handle)))),
+        )
+    })
+    .map_err(|e| eyre!("GUI failed: {e}"))
+}
+
+struct App {
+    handle: Handle,
+    devices: Vec<InputDevice>,
+    ports: Vec<SerialPort>,
+    keyboard: Option<usize>,
+    port: Option<usize>,
+    baud: u32,
+    grab: bool,
+    running: Option<Running>,
+    status: String,
+}
+
+struct Running {
+    token: CancellationToken,
+    bridge_status: watch::Receiver<Status>,
+    status: watch::Receiver<String>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl App {
+    fn new(handle: Handle) -> Self {
+        let mut app = Self {
+            handle,
+            devices: Vec::new(),
+            ports: Vec::new(),
+            keyboard: None,
+            port: None,
+            baud: shared::DEFAULT_BAUD_RATE,
+            grab: false,
+            running: None,
+            status: "Stopped".to_owned(),
+        };
+        app.refresh();
+        app
+    }
+
+    fn refresh(&mut self) {
+        self.devices = list::input_devices();
+        self.devices
+            .retain(|device| device.capabilities.contains(&"keyboard"));
+        self.ports = list::serial_ports().unwrap_or_default();
+        self.keyboard = (!self.devices.is_empty()).then_some(0);
+        self.port = (!self.ports.is_empty()).then_some(0);
+    }
+
+    fn start(&mut self) {
+        let (Some(keyboard), Some(port)) = (self.keyboard, self.port) else {
+            self.status = "Pick a keyboard and a serial port first".to_owned();
+            return;
+        };
+
+        let mut argv: Vec<OsString> = vec![
+            "client".into(),
+            "--keyboard".into(),
+            self.devices[keyboard].path.clone().into(),
+            "--send-to".into(),
+            self.ports[port].path.clone().into(),
+            "--baud".into(),
+            self.baud.to_string().into(),
+        ];
+        if self.grab {
+            argv.push("--grab".into());
+        }
+        let args = match GuiRunArgs::try_parse_from(argv) {
+            Ok(parsed) => parsed.args,
+            Err(e) => {
+                self.status = e.to_string();
+                return;
+            }
+        };
+
+        let token = CancellationToken::new();
+        let (bridge_status_tx, bridge_status) = watch::channel(Status::default());
+        let (status_tx, status) = watch::channel("Connecting...".to_owned());
+        let cloned_token = token.clone();
+        let task = self.handle.spawn(async move {
+            let (bridge, mut failover) = crate::connect(args, Output::Human).await?;
+            let mut bridge = bridge.with_status(bridge_status_tx);
+            status_tx.send_replace("Forwarding".to_owned());
+            let result = failover.run(&mut bridge, &cloned_token).await;
+            // however we got here release all keys so nothing is left held down on the target
+            let released = bridge.release_all().await;
+            let locked = bridge.lock_on_exit().await;
+            result.and(released).and(locked)
+        });
+
+        self.running = Some(Running {
+            token,
+            bridge_status,
+            status,
+            task,
+        });
+    }
+
+    // notice when forwarding has stopped, whether we asked it to or not
+    fn poll_running(&mut self) {
+        let Some(running) = &self.running else {
+            return;
+        };
+        if !running.task.is_finished() {
+            self.status = running.status.borrow().clone();
+            return;
+        }
+
+        let Some(running) = self.running.take() else {
+            return;
+        };
+        self.status = match self.handle.block_on(running.task) {
+            Ok(Ok(())) => "Stopped".to_owned(),
+            Ok(Err(e)) => format!("Stopped: {e:#}"),
+            Err(e) => format!("Stopped: {e}"),
+        };
+    }
+}
+
+impl Drop for App {
+    // closing the window mustn't leave keys held down on the target
+    fn drop(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.token.cancel();
+            let _ = self.handle.block_on(running.task);
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_running();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let running = self.running.is_some();
+
+            ui.add_enabled_ui(!running, |ui| {
+                egui::ComboBox::from_label("Keyboard")
+                    .selected_text(
+                        self.keyboard
+                            .map_or("-".to_owned(), |i| device_label(&self.devices[i])),
+                    )
+                    .show_ui(ui, |ui| {
+                        for (i, device) in self.devices.iter().enumerate() {
+                            ui.selectable_value(&mut self.keyboard, Some(i), device_label(device));
+                        }
+                    });
+                egui::ComboBox::from_label("Serial port")
+                    .selected_text(
+                        self.port
+                            .map_or("-".to_owned(), |i| port_label(&self.ports[i])),
+                    )
+                    .show_ui(ui, |ui| {
+                        for (i, port) in self.ports.iter().enumerate() {
+                            ui.selectable_value(&mut self.port, Some(i), port_label(port));
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("Baud rate");
+                    ui.add(egui::DragValue::new(&mut self.baud));
+                });
+                ui.checkbox(&mut self.grab, "Grab the keyboard");
+                if ui.button("Refresh devices").clicked() {
+                    self.refresh();
+                }
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if !running && ui.button("Start").clicked() {
+                    self.start();
+                }
+                if let Some(running) = &self.running {
+                    if ui.button("Stop").clicked() {
+                        running.token.cancel();
+                    }
+                }
+                ui.label(&self.status);
+            });
+
+            if let Some(running) = &self.running {
+                let status = running.bridge_status.borrow();
+                if status.paused {
+                    ui.label("Paused, hold the emergency chord again to resume");
+                }
+                let held_keys: Vec<_> = status
+                    .held_keys
+                    .iter()
+                    .map(|key| format!("{key:?}"))
+                    .collect();
+                ui.label(format!("Held: {}", held_keys.join(" ")));
+            }
+        });
+
+        // nothing tells egui when a key changes, so keep looking while we're running
+        if self.running.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+    }
+}
+
+fn device_label(device: &InputDevice) -> String {
+    let name = device.name.as_deref().unwrap_or("Unnamed device");
+    format!("{name} ({})", device.path.display())
+}
+
+fn port_label(port: &SerialPort) -> String {
+    match &port.product {
+        Some(product) => format!("{product} ({})", port.path),
+        None => port.path.clone(),
+    }
+}
@@ -0,0 +1,1451 @@
+use clap::ValueEnum;
+use enumflags2::{bitflags, BitFlag, BitFlags};
+use evdev::Key;
+use serde::Deserialize;
+use usbd_hid::descriptor::KeyboardUsage;
+
+#[rustfmt::skip]
+#[bitflags]
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UsbHidModifier {
+    LeftControl  = 0b0000_0001,
+    LeftShift    = 0b0000_0010,
+    LeftAlt      = 0b0000_0100,
+    LeftMeta     = 0b0000_1000,
+    RightControl = 0b0001_0000,
+    RightShift   = 0b0010_0000,
+    RightAlt     = 0b0100_0000,
+    RightMeta    = 0b1000_0000,
+}
+
+impl UsbHidModifier {
+    fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::KEY_LEFTCTRL => Some(Self::LeftControl),
+            Key::KEY_RIGHTCTRL => Some(Self::RightControl),
+            Key::KEY_LEFTSHIFT => Some(Self::LeftShift),
+            Key::KEY_RIGHTSHIFT => Some(Self::RightShift),
+            Key::KEY_LEFTALT => Some(Self::LeftAlt),
+            Key::KEY_RIGHTALT => Some(Self::RightAlt),
+            Key::KEY_LEFTMETA => Some(Self::LeftMeta),
+            Key::KEY_RIGHTMETA => Some(Self::RightMeta),
+            _ => None,
+        }
+    }
+
+    fn key(self) -> Key {
+        match self {
+            Self::LeftControl => Key::KEY_LEFTCTRL,
+            Self::RightControl => Key::KEY_RIGHTCTRL,
+            Self::LeftShift => Key::KEY_LEFTSHIFT,
+            Self::RightShift => Key::KEY_RIGHTSHIFT,
+            Self::LeftAlt => Key::KEY_LEFTALT,
+            Self::RightAlt => Key::KEY_RIGHTALT,
+            Self::LeftMeta => Key::KEY_LEFTMETA,
+            Self::RightMeta => Key::KEY_RIGHTMETA,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeySet {
+    modifier: BitFlags<UsbHidModifier>,
+    keys: [u8; 6],
+}
+
+impl From<KeySet> for shared::WhyNoDeriveDeserializeManSadFaceHere {
+    fn from(value: KeySet) -> Self {
+        shared::WhyNoDeriveDeserializeManSadFaceHere {
+            modifier: value.modifier.bits(),
+            keys: value.keys,
+        }
+    }
+}
+
+impl KeySet {
+    pub fn new() -> Self {
+        Self {
+            modifier: UsbHidModifier::empty(),
+            keys: [0; 6],
+        }
+    }
+
+    pub fn press_key(&mut self, key: Key) {
+        if let Some(modifier) = UsbHidModifier::from_key(key) {
+            self.modifier.set(modifier, true);
+        } else if let Some(usage_id) = key_to_hid_usage_id(key) {
+            let usage_id = usage_id as u8;
+            if self.keys.contains(&usage_id) {
+                return;
+            }
+
+            if let Some(slot) = self.keys.iter_mut().find(|id| **id == 0) {
+                *slot = usage_id;
+            }
+        }
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        if let Some(modifier) = UsbHidModifier::from_key(key) {
+            self.modifier.contains(modifier)
+        } else if let Some(usage_id) = key_to_hid_usage_id(key) {
+            self.keys.contains(&(usage_id as u8))
+        } else {
+            false
+        }
+    }
+
+    pub fn release_key(&mut self, key: Key) {
+        if let Some(modifier) = UsbHidModifier::from_key(key) {
+            self.modifier.set(modifier, false);
+        } else if let Some(usage_id) = key_to_hid_usage_id(key) {
+            let usage_id = usage_id as u8;
+            if !self.keys.contains(&usage_id) {
+                return;
+            }
+
+            if let Some(slot) = self.keys.iter_mut().find(|id| **id == usage_id) {
+                *slot = 0;
+            }
+
+            self.keys.sort_by(|a, b| b.cmp(a));
+        }
+    }
+}
+
+fn key_to_hid_usage_id(key: Key) -> Option<KeyboardUsage> {
+    let usage_id = match key {
+        Key::KEY_ESC => KeyboardUsage::KeyboardEscape,
+        Key::KEY_1 => KeyboardUsage::Keyboard1Exclamation,
+        Key::KEY_2 => KeyboardUsage::Keyboard2At,
+        Key::KEY_3 => KeyboardUsage::Keyboard3Hash,
+        Key::KEY_4 => KeyboardUsage::Keyboard4Dollar,
+        Key::KEY_5 => KeyboardUsage::Keyboard5Percent,
+        Key::KEY_6 => KeyboardUsage::Keyboard6Caret,
+        Key::KEY_7 => KeyboardUsage::Keyboard7Ampersand,
+        Key::KEY_8 => KeyboardUsage::Keyboard8Asterisk,
+        Key::KEY_9 => KeyboardUsage::Keyboard9OpenParens,
+        Key::KEY_0 => KeyboardUsage::Keyboard0CloseParens,
+        Key::KEY_MINUS => KeyboardUsage::KeyboardDashUnderscore,
+        Key::KEY_EQUAL => KeyboardUsage::KeyboardEqualPlus,
+        Key::KEY_BACKSPACE => KeyboardUsage::KeyboardBackspace,
+        Key::KEY_TAB => KeyboardUsage::KeyboardTab,
+        Key::KEY_Q => KeyboardUsage::KeyboardQq,
+        Key::KEY_W => KeyboardUsage::KeyboardWw,
+        Key::KEY_E => KeyboardUsage::KeyboardEe,
+        Key::KEY_R => KeyboardUsage::KeyboardRr,
+        Key::KEY_T => KeyboardUsage::KeyboardTt,
+        Key::KEY_Y => KeyboardUsage::KeyboardYy,
+        Key::KEY_U => KeyboardUsage::KeyboardUu,
+        Key::KEY_I => KeyboardUsage::KeyboardIi,
+        Key::KEY_O => KeyboardUsage::KeyboardOo,
+        Key::KEY_P => KeyboardUsage::KeyboardPp,
+        Key::KEY_LEFTBRACE => KeyboardUsage::KeyboardOpenBracketBrace,
+        Key::KEY_RIGHTBRACE => KeyboardUsage::KeyboardCloseBracketBrace,
+        Key::KEY_ENTER => KeyboardUsage::KeyboardEnter,
+        Key::KEY_LEFTCTRL => KeyboardUsage::KeyboardLeftControl,
+        Key::KEY_A => KeyboardUsage::KeyboardAa,
+        Key::KEY_S => KeyboardUsage::KeyboardSs,
+        Key::KEY_D => KeyboardUsage::KeyboardDd,
+        Key::KEY_F => KeyboardUsage::KeyboardFf,
+        Key::KEY_G => KeyboardUsage::KeyboardGg,
+        Key::KEY_H => KeyboardUsage::KeyboardHh,
+        Key::KEY_J => KeyboardUsage::KeyboardJj,
+        Key::KEY_K => KeyboardUsage::KeyboardKk,
+        Key::KEY_L => KeyboardUsage::KeyboardLl,
+        Key::KEY_SEMICOLON => KeyboardUsage::KeyboardSemiColon,
+        Key::KEY_APOSTROPHE => KeyboardUsage::KeyboardSingleDoubleQuote,
+        Key::KEY_GRAVE => KeyboardUsage::KeyboardBacktickTilde,
+        Key::KEY_LEFTSHIFT => KeyboardUsage::KeypadLeftShift,
+        Key::KEY_BACKSLASH => KeyboardUsage::KeyboardNonUSHash, // UK keymap
+        Key::KEY_Z => KeyboardUsage::KeyboardZz,
+        Key::KEY_X => KeyboardUsage::KeyboardXx,
+        Key::KEY_C => KeyboardUsage::KeyboardCc,
+        Key::KEY_V => KeyboardUsage::KeyboardVv,
+        Key::KEY_B => KeyboardUsage::KeyboardBb,
+        Key::KEY_N => KeyboardUsage::KeyboardNn,
+        Key::KEY_M => KeyboardUsage::KeyboardMm,
+        Key::KEY_COMMA => KeyboardUsage::KeyboardCommaLess,
+        Key::KEY_DOT => KeyboardUsage::KeyboardPeriodGreater,
+        Key::KEY_SLASH => KeyboardUsage::KeyboardSlashQuestion,
+        Key::KEY_RIGHTSHIFT => KeyboardUsage::KeyboardRightShift,
+        Key::KEY_KPASTERISK => KeyboardUsage::KeypadMultiply,
+        Key::KEY_LEFTALT => KeyboardUsage::KeyboardLeftAlt,
+        Key::KEY_SPACE => KeyboardUsage::KeyboardSpacebar,
+        Key::KEY_CAPSLOCK => KeyboardUsage::KeyboardCapsLock,
+        Key::KEY_F1 => KeyboardUsage::KeyboardF1,
+        Key::KEY_F2 => KeyboardUsage::KeyboardF2,
+        Key::KEY_F3 => KeyboardUsage::KeyboardF3,
+        Key::KEY_F4 => KeyboardUsage::KeyboardF4,
+        Key::KEY_F5 => KeyboardUsage::KeyboardF5,
+        Key::KEY_F6 => KeyboardUsage::KeyboardF6,
+        Key::KEY_F7 => KeyboardUsage::KeyboardF7,
+        Key::KEY_F8 => KeyboardUsage::KeyboardF8,
+        Key::KEY_F9 => KeyboardUsage::KeyboardF9,
+        Key::KEY_F10 => KeyboardUsage::KeyboardF10,
+        Key::KEY_NUMLOCK => KeyboardUsage::KeypadNumLock,
+        Key::KEY_SCROLLLOCK => KeyboardUsage::KeyboardScrollLock,
+        Key::KEY_KP7 => KeyboardUsage::Keypad7Home,
+        Key::KEY_KP8 => KeyboardUsage::Keypad8UpArrow,
+        Key::KEY_KP9 => KeyboardUsage::Keypad9PageUp,
+        Key::KEY_KPMINUS => KeyboardUsage::KeypadMinus,
+        Key::KEY_KP4 => KeyboardUsage::Keypad4LeftArrow,
+        Key::KEY_KP5 => KeyboardUsage::Keypad5,
+        Key::KEY_KP6 => KeyboardUsage::Keypad6RightArrow,
+        Key::KEY_KPPLUS => KeyboardUsage::KeypadPlus,
+        Key::KEY_KP1 => KeyboardUsage::Keypad1End,
+        Key::KEY_KP2 => KeyboardUsage::Keypad2DownArrow,
+        Key::KEY_KP3 => KeyboardUsage::Keypad3PageDown,
+        Key::KEY_KP0 => KeyboardUsage::Keypad0Insert,
+        Key::KEY_KPDOT => KeyboardUsage::KeypadPeriodDelete,
+        Key::KEY_ZENKAKUHANKAKU => KeyboardUsage::KeyboardLANG5,
+        Key::KEY_102ND => KeyboardUsage::KeyboardNonUSSlash,
+        Key::KEY_F11 => KeyboardUsage::KeyboardF11,
+        Key::KEY_F12 => KeyboardUsage::KeyboardF12,
+        Key::KEY_RO => KeyboardUsage::KeyboardInternational1,
+        Key::KEY_KATAKANA => KeyboardUsage::KeyboardLANG3,
+        Key::KEY_HIRAGANA => KeyboardUsage::KeyboardLANG4,
+        Key::KEY_HENKAN => KeyboardUsage::KeyboardInternational4,
+        Key::KEY_KATAKANAHIRAGANA => KeyboardUsage::KeyboardInternational2,
+        Key::KEY_MUHENKAN => KeyboardUsage::KeyboardInternational5,
+        Key::KEY_KPJPCOMMA => KeyboardUsage::KeyboardInternational6,
+        Key::KEY_KPENTER => KeyboardUsage::KeypadEnter,
+        Key::KEY_RIGHTCTRL => KeyboardUsage::KeyboardRightControl,
+        Key::KEY_KPSLASH => KeyboardUsage::KeypadDivide,
+        Key::KEY_SYSRQ => KeyboardUsage::KeyboardPrintScreen,
+        Key::KEY_RIGHTALT => KeyboardUsage::KeyboardRightAlt,
+        // Key::KEY_LINEFEED => 101,
+        Key::KEY_HOME => KeyboardUsage::KeyboardHome,
+        Key::KEY_UP => KeyboardUsage::KeyboardUpArrow,
+        Key::KEY_PAGEUP => KeyboardUsage::KeyboardPageUp,
+        Key::KEY_LEFT => KeyboardUsage::KeyboardLeftArrow,
+        Key::KEY_RIGHT => KeyboardUsage::KeyboardRightArrow,
+        Key::KEY_END => KeyboardUsage::KeyboardEnd,
+        Key::KEY_DOWN => KeyboardUsage::KeyboardDownArrow,
+        Key::KEY_PAGEDOWN => KeyboardUsage::KeyboardPageDown,
+        Key::KEY_INSERT => KeyboardUsage::KeyboardInsert,
+        Key::KEY_DELETE => KeyboardUsage::KeyboardDelete,
+        //     Key::KEY_MACRO => 112,
+        // mute and the volume keys go as consumer controls, see consumer_usage
+        Key::KEY_POWER => KeyboardUsage::KeyboardPower, /* SC System Power Down */
+        Key::KEY_KPEQUAL => KeyboardUsage::KeypadEqual,
+        //     Key::KEY_KPPLUSMINUS => 118,
+        Key::KEY_PAUSE => KeyboardUsage::KeyboardPause,
+        //     Key::KEY_SCALE => 120, /* AL Compiz Scale (Expose) */
+        Key::KEY_KPCOMMA => KeyboardUsage::KeypadComma,
+        Key::KEY_HANGEUL => KeyboardUsage::KeyboardLANG1,
+        Key::KEY_HANJA => KeyboardUsage::KeyboardLANG2,
+        Key::KEY_YEN => KeyboardUsage::KeyboardInternational3,
+        Key::KEY_LEFTMETA => KeyboardUsage::KeyboardLeftGUI,
+        Key::KEY_RIGHTMETA => KeyboardUsage::KeyboardRightGUI,
+        Key::KEY_COMPOSE => KeyboardUsage::KeyboardApplication,
+        //     Key::KEY_STOP => 128, /* AC Stop */
+        Key::KEY_AGAIN => KeyboardUsage::KeyboardAgain,
+        //     Key::KEY_PROPS => 130, /* AC Properties */
+        // Key::KEY_UNDO => KeyboardUsage::KeyboardUndo,  /* AC Undo */
+        Key::KEY_FRONT => KeyboardUsage::KeyboardSelect,
+        // Key::KEY_COPY => KeyboardUsage::KeyboardCopy,  /* AC Copy */
+        // Key::KEY_OPEN => 134,  /* AC Open */
+        // A this point I got bored / had a headache...
+        //     Key::KEY_PASTE => 135, /* AC Paste */
+        //     Key::KEY_FIND => 136,  /* AC Search */
+        //     Key::KEY_CUT => 137,   /* AC Cut */
+        //     Key::KEY_HELP => 138,  /* AL Integrated Help Center */
+        //     Key::KEY_MENU => 139,  /* Menu (show menu) */
+        //     Key::KEY_CALC => 140,  /* AL Calculator */
+        //     Key::KEY_SETUP => 141,
+        //     Key::KEY_SLEEP => 142,  /* SC System Sleep */
+        //     Key::KEY_WAKEUP => 143, /* System Wake Up */
+        //     Key::KEY_FILE => 144,   /* AL Local Machine Browser */
+        //     Key::KEY_SENDFILE = 145,
+        //     Key::KEY_DELETEFILE => 146,
+        //     Key::KEY_XFER => 147,
+        //     Key::KEY_PROG1 => 148,
+        //     Key::KEY_PROG2 => 149,
+        //     Key::KEY_WWW = 150, /* AL Internet Browser */
+        //     Key::KEY_MSDOS => 151,
+        //     Key::KEY_COFFEE => 152, /* AL Terminal Lock/Screensaver */
+        //     Key::KEY_DIRECTION => 153,
+        //     Key::KEY_ROTATE_DISPLAY = 153,
+        //     Key::KEY_CYCLEWINDOWS = 154,
+        //     Key::KEY_MAIL = 155,
+        //     Key::KEY_BOOKMARKS = 156, /* AC Bookmarks */
+        //     Key::KEY_COMPUTER = 157,
+        //     Key::KEY_BACK = 158,    /* AC Back */
+        //     Key::KEY_FORWARD = 159, /* AC Forward */
+        //     Key::KEY_CLOSECD = 160,
+        //     Key::KEY_EJECTCD = 161,
+        //     Key::KEY_EJECTCLOSECD = 162,
+        //     Key::KEY_NEXTSONG = 163,
+        //     Key::KEY_PLAYPAUSE = 164,
+        //     Key::KEY_PREVIOUSSONG = 165,
+        //     Key::KEY_STOPCD = 166,
+        //     Key::KEY_RECORD = 167,
+        //     Key::KEY_REWIND = 168,
+        //     Key::KEY_PHONE = 169, /* Media Select Telephone */
+        //     Key::KEY_ISO = 170,
+        //     Key::KEY_CONFIG = 171,   /* AL Consumer Control Configuration */
+        //     Key::KEY_HOMEPAGE = 172, /* AC Home */
+        //     Key::KEY_REFRESH = 173,  /* AC Refresh */
+        //     Key::KEY_EXIT = 174,     /* AC Exit */
+        //     Key::KEY_MOVE = 175,
+        //     Key::KEY_EDIT = 176,
+        //     Key::KEY_SCROLLUP = 177,
+        //     Key::KEY_SCROLLDOWN = 178,
+        //     Key::KEY_KPLEFTPAREN = 179,
+        //     Key::KEY_KPRIGHTPAREN = 180,
+        //     Key::KEY_NEW = 181,  /* AC New */
+        //     Key::KEY_REDO = 182, /* AC Redo/Repeat */
+        Key::KEY_F13 => KeyboardUsage::KeyboardF13,
+        Key::KEY_F14 => KeyboardUsage::KeyboardF14,
+        Key::KEY_F15 => KeyboardUsage::KeyboardF15,
+        Key::KEY_F16 => KeyboardUsage::KeyboardF16,
+        Key::KEY_F17 => KeyboardUsage::KeyboardF17,
+        Key::KEY_F18 => KeyboardUsage::KeyboardF18,
+        Key::KEY_F19 => KeyboardUsage::KeyboardF19,
+        Key::KEY_F20 => KeyboardUsage::KeyboardF20,
+        Key::KEY_F21 => KeyboardUsage::KeyboardF21,
+        Key::KEY_F22 => KeyboardUsage::KeyboardF22,
+        Key::KEY_F23 => KeyboardUsage::KeyboardF23,
+        Key::KEY_F24 => KeyboardUsage::KeyboardF24,
+        //     Key::KEY_PLAYCD = 200,
+        //     Key::KEY_PAUSECD = 201,
+        //     Key::KEY_PROG3 = 202,
+        //     Key::KEY_PROG4 = 203,
+        //     Key::KEY_DASHBOARD = 204, /* AL Dashboard */
+        //     Key::KEY_SUSPEND = 205,
+        //     Key::KEY_CLOSE = 206, /* AC Close */
+        //     Key::KEY_PLAY = 207,
+        //     Key::KEY_FASTFORWARD = 208,
+        //     Key::KEY_BASSBOOST = 209,
+        //     Key::KEY_PRINT = 210, /* AC Print */
+        //     Key::KEY_HP = 211,
+        //     Key::KEY_CAMERA = 212,
+        //     Key::KEY_SOUND = 213,
+        //     Key::KEY_QUESTION = 214,
+        //     Key::KEY_EMAIL = 215,
+        //     Key::KEY_CHAT = 216,
+        //     Key::KEY_SEARCH = 217,
+        //     Key::KEY_CONNECT = 218,
+        //     Key::KEY_FINANCE = 219,
+        //     Key::KEY_SPORT = 220,
+        //     Key::KEY_SHOP = 221,
+        Key::KEY_ALTERASE => KeyboardUsage::KeyboardAlternateErase,
+        //     Key::KEY_CANCEL = 223,
+        //     Key::KEY_BRIGHTNESSDOWN = 224,
+        //     Key::KEY_BRIGHTNESSUP = 225,
+        //     Key::KEY_MEDIA = 226,
+        //     Key::KEY_SWITCHVIDEOMODE = 227,
+        //     Key::KEY_KBDILLUMTOGGLE = 228,
+        //     Key::KEY_KBDILLUMDOWN = 229,
+        //     Key::KEY_KBDILLUMUP = 230,
+        //     Key::KEY_SEND = 231,
+        //     Key::KEY_REPLY = 232,
+        //     Key::KEY_FORWARDMAIL = 233,
+        //     Key::KEY_SAVE = 234,
+        //     Key::KEY_DOCUMENTS = 235,
+        //     Key::KEY_BATTERY = 236,
+        //     Key::KEY_BLUETOOTH = 237,
+        //     Key::KEY_WLAN = 238,
+        //     Key::KEY_UWB = 239,
+        //     Key::KEY_UNKNOWN = 240,
+        //     Key::KEY_VIDEO_NEXT = 241,
+        //     Key::KEY_VIDEO_PREV = 242,
+        //     Key::KEY_BRIGHTNESS_CYCLE = 243,
+        //     Key::KEY_BRIGHTNESS_AUTO = 244,
+        //     Key::KEY_DISPLAY_OFF = 245,
+        //     Key::KEY_WWAN = 246,
+        //     Key::KEY_RFKILL = 247,
+        //     Key::KEY_MICMUTE = 248,
+        //     Key::BTN_0 = 0x100,
+        //     Key::BTN_1 = 0x101,
+        //     Key::BTN_2 = 0x102,
+        //     Key::BTN_3 = 0x103,
+        //     Key::BTN_4 = 0x104,
+        //     Key::BTN_5 = 0x105,
+        //     Key::BTN_6 = 0x106,
+        //     Key::BTN_7 = 0x107,
+        //     Key::BTN_8 = 0x108,
+        //     Key::BTN_9 = 0x109,
+        //     Key::BTN_LEFT = 0x110,
+        //     Key::BTN_RIGHT = 0x111,
+        //     Key::BTN_MIDDLE = 0x112,
+        //     Key::BTN_SIDE = 0x113,
+        //     Key::BTN_EXTRA = 0x114,
+        //     Key::BTN_FORWARD = 0x115,
+        //     Key::BTN_BACK = 0x116,
+        //     Key::BTN_TASK = 0x117,
+        //     Key::BTN_TRIGGER = 0x120,
+        //     Key::BTN_THUMB = 0x121,
+        //     Key::BTN_THUMB2 = 0x122,
+        //     Key::BTN_TOP = 0x123,
+        //     Key::BTN_TOP2 = 0x124,
+        //     Key::BTN_PINKIE = 0x125,
+        //     Key::BTN_BASE = 0x126,
+        //     Key::BTN_BASE2 = 0x127,
+        //     Key::BTN_BASE3 = 0x128,
+        //     Key::BTN_BASE4 = 0x129,
+        //     Key::BTN_BASE5 = 0x12a,
+        //     Key::BTN_BASE6 = 0x12b,
+        //     Key::BTN_DEAD = 0x12f,
+        //     Key::BTN_SOUTH = 0x130,
+        //     Key::BTN_EAST = 0x131,
+        //     Key::BTN_C = 0x132,
+        //     Key::BTN_NORTH = 0x133,
+        //     Key::BTN_WEST = 0x134,
+        //     Key::BTN_Z = 0x135,
+        //     Key::BTN_TL = 0x136,
+        //     Key::BTN_TR = 0x137,
+        //     Key::BTN_TL2 = 0x138,
+        //     Key::BTN_TR2 = 0x139,
+        //     Key::BTN_SELECT = 0x13a,
+        //     Key::BTN_START = 0x13b,
+        //     Key::BTN_MODE = 0x13c,
+        //     Key::BTN_THUMBL = 0x13d,
+        //     Key::BTN_THUMBR = 0x13e,
+        //     Key::BTN_TOOL_PEN = 0x140,
+        //     Key::BTN_TOOL_RUBBER = 0x141,
+        //     Key::BTN_TOOL_BRUSH = 0x142,
+        //     Key::BTN_TOOL_PENCIL = 0x143,
+        //     Key::BTN_TOOL_AIRBRUSH = 0x144,
+        //     Key::BTN_TOOL_FINGER = 0x145,
+        //     Key::BTN_TOOL_MOUSE = 0x146,
+        //     Key::BTN_TOOL_LENS = 0x147,
+        //     Key::BTN_TOOL_QUINTTAP = 0x148, /* Five fingers on trackpad */
+        //     Key::BTN_TOUCH = 0x14a,
+        //     Key::BTN_STYLUS = 0x14b,
+        //     Key::BTN_STYLUS2 = 0x14c,
+        //     Key::BTN_TOOL_DOUBLETAP = 0x14d,
+        //     Key::BTN_TOOL_TRIPLETAP = 0x14e,
+        //     Key::BTN_TOOL_QUADTAP = 0x14f, /* Four fingers on trackpad */
+        //     Key::BTN_GEAR_DOWN = 0x150,
+        //     Key::BTN_GEAR_UP = 0x151,
+        //     Key::KEY_OK = 0x160,
+        //     Key::KEY_SELECT = 0x161,
+        //     Key::KEY_GOTO = 0x162,
+        //     Key::KEY_CLEAR = 0x163,
+        //     Key::KEY_POWER2 = 0x164,
+        //     Key::KEY_OPTION = 0x165,
+        //     Key::KEY_INFO = 0x166, /* AL OEM Features/Tips/Tutorial */
+        //     Key::KEY_TIME = 0x167,
+        //     Key::KEY_VENDOR = 0x168,
+        //     Key::KEY_ARCHIVE = 0x169,
+        //     Key::KEY_PROGRAM = 0x16a, /* Media Select Program Guide */
+        //     Key::KEY_CHANNEL = 0x16b,
+        //     Key::KEY_FAVORITES = 0x16c,
+        //     Key::KEY_EPG = 0x16d,
+        //     Key::KEY_PVR = 0x16e, /* Media Select Home */
+        //     Key::KEY_MHP = 0x16f,
+        //     Key::KEY_LANGUAGE = 0x170,
+        //     Key::KEY_TITLE = 0x171,
+        //     Key::KEY_SUBTITLE = 0x172,
+        //     Key::KEY_ANGLE = 0x173,
+        //     Key::KEY_ZOOM = 0x174,
+        //     Key::KEY_FULL_SCREEN = 0x174,
+        //     Key::KEY_MODE = 0x175,
+        //     Key::KEY_KEYBOARD = 0x176,
+        //     Key::KEY_SCREEN = 0x177,
+        //     Key::KEY_PC = 0x178,   /* Media Select Computer */
+        //     Key::KEY_TV = 0x179,   /* Media Select TV */
+        //     Key::KEY_TV2 = 0x17a,  /* Media Select Cable */
+        //     Key::KEY_VCR = 0x17b,  /* Media Select VCR */
+        //     Key::KEY_VCR2 = 0x17c, /* VCR Plus */
+        //     Key::KEY_SAT = 0x17d,  /* Media Select Satellite */
+        //     Key::KEY_SAT2 = 0x17e,
+        //     Key::KEY_CD = 0x17f,   /* Media Select CD */
+        //     Key::KEY_TAPE = 0x180, /* Media Select Tape */
+        //     Key::KEY_RADIO = 0x181,
+        //     Key::KEY_TUNER = 0x182, /* Media Select Tuner */
+        //     Key::KEY_PLAYER = 0x183,
+        //     Key::KEY_TEXT = 0x184,
+        //     Key::KEY_DVD = 0x185, /* Media Select DVD */
+        //     Key::KEY_AUX = 0x186,
+        //     Key::KEY_MP3 = 0x187,
+        //     Key::KEY_AUDIO = 0x188, /* AL Audio Browser */
+        //     Key::KEY_VIDEO = 0x189, /* AL Movie Browser */
+        //     Key::KEY_DIRECTORY = 0x18a,
+        //     Key::KEY_LIST = 0x18b,
+        //     Key::KEY_MEMO = 0x18c, /* Media Select Messages */
+        //     Key::KEY_CALENDAR = 0x18d,
+        //     Key::KEY_RED = 0x18e,
+        //     Key::KEY_GREEN = 0x18f,
+        //     Key::KEY_YELLOW = 0x190,
+        //     Key::KEY_BLUE = 0x191,
+        //     Key::KEY_CHANNELUP = 0x192,   /* Channel Increment */
+        //     Key::KEY_CHANNELDOWN = 0x193, /* Channel Decrement */
+        //     Key::KEY_FIRST = 0x194,
+        //     Key::KEY_LAST = 0x195, /* Recall Last */
+        //     Key::KEY_AB = 0x196,
+        //     Key::KEY_NEXT = 0x197,
+        //     Key::KEY_RESTART = 0x198,
+        //     Key::KEY_SLOW = 0x199,
+        //     Key::KEY_SHUFFLE = 0x19a,
+        //     Key::KEY_BREAK = 0x19b,
+        //     Key::KEY_PREVIOUS = 0x19c,
+        //     Key::KEY_DIGITS = 0x19d,
+        //     Key::KEY_TEEN = 0x19e,
+        //     Key::KEY_TWEN = 0x19f,
+        //     Key::KEY_VIDEOPHONE = 0x1a0,     /* Media Select Video Phone */
+        //     Key::KEY_GAMES = 0x1a1,          /* Media Select Games */
+        //     Key::KEY_ZOOMIN = 0x1a2,         /* AC Zoom In */
+        //     Key::KEY_ZOOMOUT = 0x1a3,        /* AC Zoom Out */
+        //     Key::KEY_ZOOMRESET = 0x1a4,      /* AC Zoom */
+        //     Key::KEY_WORDPROCESSOR = 0x1a5,  /* AL Word Processor */
+        //     Key::KEY_EDITOR = 0x1a6,         /* AL Text Editor */
+        //     Key::KEY_SPREADSHEET = 0x1a7,    /* AL Spreadsheet */
+        //     Key::KEY_GRAPHICSEDITOR = 0x1a8, /* AL Graphics Editor */
+        //     Key::KEY_PRESENTATION = 0x1a9,   /* AL Presentation App */
+        //     Key::KEY_DATABASE = 0x1aa,       /* AL Database App */
+        //     Key::KEY_NEWS = 0x1ab,           /* AL Newsreader */
+        //     Key::KEY_VOICEMAIL = 0x1ac,      /* AL Voicemail */
+        //     Key::KEY_ADDRESSBOOK = 0x1ad,    /* AL Contacts/Address Book */
+        //     Key::KEY_MESSENGER = 0x1ae,      /* AL Instant Messaging */
+        //     Key::KEY_DISPLAYTOGGLE = 0x1af,  /* Turn display (LCD) on and off */
+        //     Key::KEY_SPELLCHECK = 0x1b0,     /* AL Spell Check */
+        //     Key::KEY_LOGOFF = 0x1b1,         /* AL Logoff */
+        //     Key::KEY_DOLLAR = 0x1b2,
+        //     Key::KEY_EURO = 0x1b3,
+        //     Key::KEY_FRAMEBACK = 0x1b4, /* Consumer - transport controls */
+        //     Key::KEY_FRAMEFORWARD = 0x1b5,
+        //     Key::KEY_CONTEXT_MENU = 0x1b6,   /* GenDesc - system context menu */
+        //     Key::KEY_MEDIA_REPEAT = 0x1b7,   /* Consumer - transport control */
+        //     Key::KEY_10CHANNELSUP = 0x1b8,   /* 10 channels up (10+) */
+        //     Key::KEY_10CHANNELSDOWN = 0x1b9, /* 10 channels down (10-) */
+        //     Key::KEY_IMAGES = 0x1ba,         /* AL Image Browser */
+        //     Key::KEY_DEL_EOL = 0x1c0,
+        //     Key::KEY_DEL_EOS = 0x1c1,
+        //     Key::KEY_INS_LINE = 0x1c2,
+        //     Key::KEY_DEL_LINE = 0x1c3,
+        //     Key::KEY_FN = 0x1d0,
+        //     Key::KEY_FN_ESC = 0x1d1,
+        //     Key::KEY_FN_F1 = 0x1d2,
+        //     Key::KEY_FN_F2 = 0x1d3,
+        //     Key::KEY_FN_F3 = 0x1d4,
+        //     Key::KEY_FN_F4 = 0x1d5,
+        //     Key::KEY_FN_F5 = 0x1d6,
+        //     Key::KEY_FN_F6 = 0x1d7,
+        //     Key::KEY_FN_F7 = 0x1d8,
+        //     Key::KEY_FN_F8 = 0x1d9,
+        //     Key::KEY_FN_F9 = 0x1da,
+        //     Key::KEY_FN_F10 = 0x1db,
+        //     Key::KEY_FN_F11 = 0x1dc,
+        //     Key::KEY_FN_F12 = 0x1dd,
+        //     Key::KEY_FN_1 = 0x1de,
+        //     Key::KEY_FN_2 = 0x1df,
+        //     Key::KEY_FN_D = 0x1e0,
+        //     Key::KEY_FN_E = 0x1e1,
+        //     Key::KEY_FN_F = 0x1e2,
+        //     Key::KEY_FN_S = 0x1e3,
+        //     Key::KEY_FN_B = 0x1e4,
+        //     Key::KEY_BRL_DOT1 = 0x1f1,
+        //     Key::KEY_BRL_DOT2 = 0x1f2,
+        //     Key::KEY_BRL_DOT3 = 0x1f3,
+        //     Key::KEY_BRL_DOT4 = 0x1f4,
+        //     Key::KEY_BRL_DOT5 = 0x1f5,
+        //     Key::KEY_BRL_DOT6 = 0x1f6,
+        //     Key::KEY_BRL_DOT7 = 0x1f7,
+        //     Key::KEY_BRL_DOT8 = 0x1f8,
+        //     Key::KEY_BRL_DOT9 = 0x1f9,
+        //     Key::KEY_BRL_DOT10 = 0x1fa,
+        //     Key::KEY_NUMERIC_0 = 0x200, /* used by phones, remote controls, */
+        //     Key::KEY_NUMERIC_1 = 0x201, /* and other keypads */
+        //     Key::KEY_NUMERIC_2 = 0x202,
+        //     Key::KEY_NUMERIC_3 = 0x203,
+        //     Key::KEY_NUMERIC_4 = 0x204,
+        //     Key::KEY_NUMERIC_5 = 0x205,
+        //     Key::KEY_NUMERIC_6 = 0x206,
+        //     Key::KEY_NUMERIC_7 = 0x207,
+        //     Key::KEY_NUMERIC_8 = 0x208,
+        //     Key::KEY_NUMERIC_9 = 0x209,
+        //     Key::KEY_NUMERIC_STAR = 0x20a,
+        //     Key::KEY_NUMERIC_POUND = 0x20b,
+        //     Key::KEY_NUMERIC_A = 0x20c, /* Phone key A - HUT Telephony 0xb9 */
+        //     Key::KEY_NUMERIC_B = 0x20d,
+        //     Key::KEY_NUMERIC_C = 0x20e,
+        //     Key::KEY_NUMERIC_D = 0x20f,
+        //     Key::KEY_CAMERA_FOCUS = 0x210,
+        //     Key::KEY_WPS_BUTTON = 0x211,      /* WiFi Protected Setup key */
+        //     Key::KEY_TOUCHPAD_TOGGLE = 0x212, /* Request switch touchpad on or off */
+        //     Key::KEY_TOUCHPAD_ON = 0x213,
+        //     Key::KEY_TOUCHPAD_OFF = 0x214,
+        //     Key::KEY_CAMERA_ZOOMIN = 0x215,
+        //     Key::KEY_CAMERA_ZOOMOUT = 0x216,
+        //     Key::KEY_CAMERA_UP = 0x217,
+        //     Key::KEY_CAMERA_DOWN = 0x218,
+        //     Key::KEY_CAMERA_LEFT = 0x219,
+        //     Key::KEY_CAMERA_RIGHT = 0x21a,
+        //     Key::KEY_ATTENDANT_ON = 0x21b,
+        //     Key::KEY_ATTENDANT_OFF = 0x21c,
+        //     Key::KEY_ATTENDANT_TOGGLE = 0x21d, /* Attendant call on or off */
+        //     Key::KEY_LIGHTS_TOGGLE = 0x21e,    /* Reading light on or off */
+        //     Key::BTN_DPAD_UP = 0x220,
+        //     Key::BTN_DPAD_DOWN = 0x221,
+        //     Key::BTN_DPAD_LEFT = 0x222,
+        //     Key::BTN_DPAD_RIGHT = 0x223,
+        //     Key::KEY_ALS_TOGGLE = 0x230,   /* Ambient light sensor */
+        //     Key::KEY_BUTTONCONFIG = 0x240, /* AL Button Configuration */
+        //     Key::KEY_TASKMANAGER = 0x241,  /* AL Task/Project Manager */
+        //     Key::KEY_JOURNAL = 0x242,      /* AL Log/Journal/Timecard */
+        //     Key::KEY_CONTROLPANEL = 0x243, /* AL Control Panel */
+        //     Key::KEY_APPSELECT = 0x244,    /* AL Select Task/Application */
+        //     Key::KEY_SCREENSAVER = 0x245,  /* AL Screen Saver */
+        //     Key::KEY_VOICECOMMAND = 0x246, /* Listening Voice Command */
+        //     Key::KEY_ASSISTANT = 0x247,
+        //     Key::KEY_KBD_LAYOUT_NEXT = 0x248,
+        //     Key::KEY_BRIGHTNESS_MIN = 0x250, /* Set Brightness to Minimum */
+        //     Key::KEY_BRIGHTNESS_MAX = 0x251, /* Set Brightness to Maximum */
+        //     Key::KEY_KBDINPUTASSIST_PREV = 0x260,
+        //     Key::KEY_KBDINPUTASSIST_NEXT = 0x261,
+        //     Key::KEY_KBDINPUTASSIST_PREVGROUP = 0x262,
+        //     Key::KEY_KBDINPUTASSIST_NEXTGROUP = 0x263,
+        //     Key::KEY_KBDINPUTASSIST_ACCEPT = 0x264,
+        //     Key::KEY_KBDINPUTASSIST_CANCEL = 0x265,
+        //     Key::KEY_RIGHT_UP = 0x266,
+        //     Key::KEY_RIGHT_DOWN = 0x267,
+        //     Key::KEY_LEFT_UP = 0x268,
+        //     Key::KEY_LEFT_DOWN = 0x269,
+        //     Key::KEY_ROOT_MENU = 0x26a,
+        //     Key::KEY_MEDIA_TOP_MENU = 0x26b,
+        //     Key::KEY_NUMERIC_11 = 0x26c,
+        //     Key::KEY_NUMERIC_12 = 0x26d,
+        //     Key::KEY_AUDIO_DESC = 0x26e,
+        //     Key::KEY_3D_MODE = 0x26f,
+        //     Key::KEY_NEXT_FAVORITE = 0x270,
+        //     Key::KEY_STOP_RECORD = 0x271,
+        //     Key::KEY_PAUSE_RECORD = 0x272,
+        //     Key::KEY_VOD = 0x273, /* Video on Demand */
+        //     Key::KEY_UNMUTE = 0x274,
+        //     Key::KEY_FASTREVERSE = 0x275,
+        //     Key::KEY_SLOWREVERSE = 0x276,
+        //     Key::KEY_DATA = 0x277,
+        //     Key::KEY_ONSCREEN_KEYBOARD = 0x278,
+        //     Key::KEY_PRIVACY_SCREEN_TOGGLE = 0x279,
+        //     Key::KEY_SELECTIVE_SCREENSHOT = 0x27a,
+        //     Key::BTN_TRIGGER_HAPPY1 = 0x2c0,
+        //     Key::BTN_TRIGGER_HAPPY2 = 0x2c1,
+        //     Key::BTN_TRIGGER_HAPPY3 = 0x2c2,
+        //     Key::BTN_TRIGGER_HAPPY4 = 0x2c3,
+        //     Key::BTN_TRIGGER_HAPPY5 = 0x2c4,
+        //     Key::BTN_TRIGGER_HAPPY6 = 0x2c5,
+        //     Key::BTN_TRIGGER_HAPPY7 = 0x2c6,
+        //     Key::BTN_TRIGGER_HAPPY8 = 0x2c7,
+        //     Key::BTN_TRIGGER_HAPPY9 = 0x2c8,
+        //     Key::BTN_TRIGGER_HAPPY10 = 0x2c9,
+        //     Key::BTN_TRIGGER_HAPPY11 = 0x2ca,
+        //     Key::BTN_TRIGGER_HAPPY12 = 0x2cb,
+        //     Key::BTN_TRIGGER_HAPPY13 = 0x2cc,
+        //     Key::BTN_TRIGGER_HAPPY14 = 0x2cd,
+        //     Key::BTN_TRIGGER_HAPPY15 = 0x2ce,
+        //     Key::BTN_TRIGGER_HAPPY16 = 0x2cf,
+        //     Key::BTN_TRIGGER_HAPPY17 = 0x2d0,
+        //     Key::BTN_TRIGGER_HAPPY18 = 0x2d1,
+        //     Key::BTN_TRIGGER_HAPPY19 = 0x2d2,
+        //     Key::BTN_TRIGGER_HAPPY20 = 0x2d3,
+        //     Key::BTN_TRIGGER_HAPPY21 = 0x2d4,
+        //     Key::BTN_TRIGGER_HAPPY22 = 0x2d5,
+        //     Key::BTN_TRIGGER_HAPPY23 = 0x2d6,
+        //     Key::BTN_TRIGGER_HAPPY24 = 0x2d7,
+        //     Key::BTN_TRIGGER_HAPPY25 = 0x2d8,
+        //     Key::BTN_TRIGGER_HAPPY26 = 0x2d9,
+        //     Key::BTN_TRIGGER_HAPPY27 = 0x2da,
+        //     Key::BTN_TRIGGER_HAPPY28 = 0x2db,
+        //     Key::BTN_TRIGGER_HAPPY29 = 0x2dc,
+        //     Key::BTN_TRIGGER_HAPPY30 = 0x2dd,
+        //     Key::BTN_TRIGGER_HAPPY31 = 0x2de,
+        //     Key::BTN_TRIGGER_HAPPY32 = 0x2df,
+        //     Key::BTN_TRIGGER_HAPPY33 = 0x2e0,
+        //     Key::BTN_TRIGGER_HAPPY34 = 0x2e1,
+        //     Key::BTN_TRIGGER_HAPPY35 = 0x2e2,
+        //     Key::BTN_TRIGGER_HAPPY36 = 0x2e3,
+        //     Key::BTN_TRIGGER_HAPPY37 = 0x2e4,
+        //     Key::BTN_TRIGGER_HAPPY38 = 0x2e5,
+        //     Key::BTN_TRIGGER_HAPPY39 = 0x2e6,
+        //     Key::BTN_TRIGGER_HAPPY40 = 0x2e7,
+        _ => return None,
+    };
+
+    Some(usage_id)
+}
+
+/// The key that types `c` on a US layout, and whether shift needs holding for it.
+pub fn key_for_char(c: char) -> Option<(Key, bool)> {
+    if c.is_ascii_uppercase() {
+        return Some((key_for_char(c.to_ascii_lowercase())?.0, true));
+    }
+    let key = match c {
+        'a' => (Key::KEY_A, false),
+        'b' => (Key::KEY_B, false),
+        'c' => (Key::KEY_C, false),
+        'd' => (Key::KEY_D, false),
+        'e' => (Key::KEY_E, false),
+        'f' => (Key::KEY_F, false),
+        'g' => (Key::KEY_G, false),
+        'h' => (Key::KEY_H, false),
+        'i' => (Key::KEY_I, false),
+        'j' => (Key::KEY_J, false),
+        'k' => (Key::KEY_K, false),
+        'l' => (Key::KEY_L, false),
+        'm' => (Key::KEY_M, false),
+        'n' => (Key::KEY_N, false),
+        'o' => (Key::KEY_O, false),
+        'p' => (Key::KEY_P, false),
+        'q' => (Key::KEY_Q, false),
+        'r' => (Key::KEY_R, false),
+        's' => (Key::KEY_S, false),
+        't' => (Key::KEY_T, false),
+        'u' => (Key::KEY_U, false),
+        'v' => (Key::KEY_V, false),
+        'w' => (Key::KEY_W, false),
+        'x' => (Key::KEY_X, false),
+        'y' => (Key::KEY_Y, false),
+        'z' => (Key::KEY_Z, false),
+        '1' => (Key::KEY_1, false),
+        '!' => (Key::KEY_1, true),
+        '2' => (Key::KEY_2, false),
+        '@' => (Key::KEY_2, true),
+        '3' => (Key::KEY_3, false),
+        '#' => (Key::KEY_3, true),
+        '4' => (Key::KEY_4, false),
+        '$' => (Key::KEY_4, true),
+        '5' => (Key::KEY_5, false),
+        '%' => (Key::KEY_5, true),
+        '6' => (Key::KEY_6, false),
+        '^' => (Key::KEY_6, true),
+        '7' => (Key::KEY_7, false),
+        '&' => (Key::KEY_7, true),
+        '8' => (Key::KEY_8, false),
+        '*' => (Key::KEY_8, true),
+        '9' => (Key::KEY_9, false),
+        '(' => (Key::KEY_9, true),
+        '0' => (Key::KEY_0, false),
+        ')' => (Key::KEY_0, true),
+        ' ' => (Key::KEY_SPACE, false),
+        '\n' => (Key::KEY_ENTER, false),
+        '\t' => (Key::KEY_TAB, false),
+        '-' => (Key::KEY_MINUS, false),
+        '_' => (Key::KEY_MINUS, true),
+        '=' => (Key::KEY_EQUAL, false),
+        '+' => (Key::KEY_EQUAL, true),
+        '[' => (Key::KEY_LEFTBRACE, false),
+        '{' => (Key::KEY_LEFTBRACE, true),
+        ']' => (Key::KEY_RIGHTBRACE, false),
+        '}' => (Key::KEY_RIGHTBRACE, true),
+        '\\' => (Key::KEY_BACKSLASH, false),
+        '|' => (Key::KEY_BACKSLASH, true),
+        ';' => (Key::KEY_SEMICOLON, false),
+        ':' => (Key::KEY_SEMICOLON, true),
+        '\'' => (Key::KEY_APOSTROPHE, false),
+        '"' => (Key::KEY_APOSTROPHE, true),
+        '`' => (Key::KEY_GRAVE, false),
+        '~' => (Key::KEY_GRAVE, true),
+        ',' => (Key::KEY_COMMA, false),
+        '<' => (Key::KEY_COMMA, true),
+        '.' => (Key::KEY_DOT, false),
+        '>' => (Key::KEY_DOT, true),
+        '/' => (Key::KEY_SLASH, false),
+        '?' => (Key::KEY_SLASH, true),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The printable character `key` types on a US layout, the opposite of [`key_for_char`].
+pub fn char_for_key(key: Key, shifted: bool) -> Option<char> {
+    (' '..='~').find(|&c| key_for_char(c) == Some((key, shifted)))
+}
+
+/// The consumer control usage for a media key, which goes to the teensy's consumer
+/// control rather than in a keyboard report, as that's all most targets listen to for them.
+pub fn consumer_usage(key: Key) -> Option<u16> {
+    let usage = match key {
+        Key::KEY_BRIGHTNESSUP => 0x006F,
+        Key::KEY_BRIGHTNESSDOWN => 0x0070,
+        Key::KEY_PLAYCD => 0x00B0,
+        Key::KEY_PAUSECD => 0x00B1,
+        Key::KEY_RECORD => 0x00B2,
+        Key::KEY_FASTFORWARD => 0x00B3,
+        Key::KEY_REWIND => 0x00B4,
+        Key::KEY_NEXTSONG => 0x00B5,
+        Key::KEY_PREVIOUSSONG => 0x00B6,
+        Key::KEY_STOPCD => 0x00B7,
+        Key::KEY_EJECTCD => 0x00B8,
+        Key::KEY_PLAYPAUSE => 0x00CD,
+        Key::KEY_MUTE => 0x00E2,
+        Key::KEY_VOLUMEUP => 0x00E9,
+        Key::KEY_VOLUMEDOWN => 0x00EA,
+        Key::KEY_MAIL => 0x018A,
+        Key::KEY_CALC => 0x0192,
+        Key::KEY_COMPUTER => 0x0194,
+        Key::KEY_WWW => 0x0196,
+        Key::KEY_SEARCH => 0x0221,
+        Key::KEY_HOMEPAGE => 0x0223,
+        Key::KEY_BACK => 0x0224,
+        Key::KEY_FORWARD => 0x0225,
+        Key::KEY_REFRESH => 0x0227,
+        Key::KEY_BOOKMARKS => 0x022A,
+        _ => return None,
+    };
+    Some(usage)
+}
+
+/// Whether `key` is one of the modifiers, which go in the report's modifier byte.
+pub fn is_modifier(key: Key) -> bool {
+    UsbHidModifier::from_key(key).is_some()
+}
+
+/// The keyboard layout the target is set to, which decides what to press to type text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// US QWERTY, which can only type ascii.
+    #[default]
+    Us,
+    /// US International, which types accented letters with a dead key for the accent
+    /// followed by the letter, e.g. ' then e for é.
+    UsIntl,
+    /// German QWERTZ, with its dead ^, ´ and ` keys.
+    De,
+    /// French AZERTY, with its dead ^ and ¨ keys.
+    Fr,
+}
+
+/// A key to press to type something, and which modifiers need holding for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stroke {
+    pub key: Key,
+    pub shift: bool,
+    /// Right alt, which most layouts outside the US type a third character on a key with.
+    pub altgr: bool,
+}
+
+impl Stroke {
+    const fn new(key: Key, shift: bool) -> Self {
+        Self {
+            key,
+            shift,
+            altgr: false,
+        }
+    }
+
+    const fn altgr(key: Key) -> Self {
+        Self {
+            key,
+            shift: false,
+            altgr: true,
+        }
+    }
+
+    /// The keys to hold down together for it, modifiers first.
+    pub fn keys(self) -> Vec<Key> {
+        let mut keys = Vec::with_capacity(3);
+        if self.shift {
+            keys.push(Key::KEY_LEFTSHIFT);
+        }
+        if self.altgr {
+            keys.push(Key::KEY_RIGHTALT);
+        }
+        keys.push(self.key);
+        keys
+    }
+}
+
+impl From<(Key, bool)> for Stroke {
+    fn from((key, shift): (Key, bool)) -> Self {
+        Self::new(key, shift)
+    }
+}
+
+impl Layout {
+    /// The layout for an X keyboard layout name like `us` or `us(intl)`, if it's one of these.
+    pub fn from_xkb(name: &str) -> Option<Self> {
+        match name {
+            "us" => Some(Self::Us),
+            "us(intl)" => Some(Self::UsIntl),
+            "de" => Some(Self::De),
+            "fr" => Some(Self::Fr),
+            _ => None,
+        }
+    }
+
+    /// The keys to press one after another to type `c`, or None if it can't be typed.
+    pub fn strokes_for_char(self, c: char) -> Option<Vec<Stroke>> {
+        let dead_keys = self.dead_keys();
+        if let Some(stroke) = self.stroke_for_char(c) {
+            // the accents are dead keys, so they need a space after to type on their own
+            if dead_keys.iter().any(|&(_, accent)| accent == c) {
+                return Some(vec![stroke, Stroke::new(Key::KEY_SPACE, false)]);
+            }
+            return Some(vec![stroke]);
+        }
+        let (accent, base) = accented(c)?;
+        let &(_, dead_key) = dead_keys.iter().find(|&&(dead, _)| dead == accent)?;
+        Some(vec![
+            self.stroke_for_char(dead_key)?,
+            self.stroke_for_char(base)?,
+        ])
+    }
+
+    /// The key that types `c` by itself, if there is one.
+    fn stroke_for_char(self, c: char) -> Option<Stroke> {
+        let keys = match self {
+            Self::Us | Self::UsIntl => return key_for_char(c).map(Stroke::from),
+            Self::De => DE_KEYS,
+            Self::Fr => FR_KEYS,
+        };
+        // these are the same everywhere
+        match c {
+            ' ' | '\n' | '\t' => return key_for_char(c).map(Stroke::from),
+            '\0' => return None,
+            _ => {}
+        }
+        keys.iter().find_map(
+            |&(key, chars)| match chars.iter().position(|&typed| typed == c)? {
+                0 => Some(Stroke::new(key, false)),
+                1 => Some(Stroke::new(key, true)),
+                _ => Some(Stroke::altgr(key)),
+            },
+        )
+    }
+
+    /// Which accents have a dead key, and the character that key types on its own.
+    fn dead_keys(self) -> &'static [(Accent, char)] {
+        match self {
+            Self::Us => &[],
+            Self::UsIntl => &[
+                (Accent::Acute, '\''),
+                (Accent::Cedilla, '\''),
+                (Accent::Diaeresis, '"'),
+                (Accent::Grave, '`'),
+                (Accent::Tilde, '~'),
+                (Accent::Circumflex, '^'),
+            ],
+            Self::De => &[
+                (Accent::Acute, '´'),
+                (Accent::Grave, '`'),
+                (Accent::Circumflex, '^'),
+            ],
+            Self::Fr => &[(Accent::Circumflex, '^'), (Accent::Diaeresis, '¨')],
+        }
+    }
+}
+
+/// What each key types on a German layout unshifted, shifted and with AltGr, '\0' for
+/// nothing. Keys are named for where they are on a US keyboard.
+const DE_KEYS: &[(Key, [char; 3])] = &[
+    (Key::KEY_GRAVE, ['^', '°', '\0']),
+    (Key::KEY_1, ['1', '!', '\0']),
+    (Key::KEY_2, ['2', '"', '²']),
+    (Key::KEY_3, ['3', '§', '³']),
+    (Key::KEY_4, ['4', '$', '\0']),
+    (Key::KEY_5, ['5', '%', '\0']),
+    (Key::KEY_6, ['6', '&', '\0']),
+    (Key::KEY_7, ['7', '/', '{']),
+    (Key::KEY_8, ['8', '(', '[']),
+    (Key::KEY_9, ['9', ')', ']']),
+    (Key::KEY_0, ['0', '=', '}']),
+    (Key::KEY_MINUS, ['ß', '?', '\\']),
+    (Key::KEY_EQUAL, ['´', '`', '\0']),
+    (Key::KEY_Q, ['q', 'Q', '@']),
+    (Key::KEY_W, ['w', 'W', '\0']),
+    (Key::KEY_E, ['e', 'E', '€']),
+    (Key::KEY_R, ['r', 'R', '\0']),
+    (Key::KEY_T, ['t', 'T', '\0']),
+    (Key::KEY_Y, ['z', 'Z', '\0']),
+    (Key::KEY_U, ['u', 'U', '\0']),
+    (Key::KEY_I, ['i', 'I', '\0']),
+    (Key::KEY_O, ['o', 'O', '\0']),
+    (Key::KEY_P, ['p', 'P', '\0']),
+    (Key::KEY_LEFTBRACE, ['ü', 'Ü', '\0']),
+    (Key::KEY_RIGHTBRACE, ['+', '*', '~']),
+    (Key::KEY_A, ['a', 'A', '\0']),
+    (Key::KEY_S, ['s', 'S', '\0']),
+    (Key::KEY_D, ['d', 'D', '\0']),
+    (Key::KEY_F, ['f', 'F', '\0']),
+    (Key::KEY_G, ['g', 'G', '\0']),
+    (Key::KEY_H, ['h', 'H', '\0']),
+    (Key::KEY_J, ['j', 'J', '\0']),
+    (Key::KEY_K, ['k', 'K', '\0']),
+    (Key::KEY_L, ['l', 'L', '\0']),
+    (Key::KEY_SEMICOLON, ['ö', 'Ö', '\0']),
+    (Key::KEY_APOSTROPHE, ['ä', 'Ä', '\0']),
+    (Key::KEY_BACKSLASH, ['#', '\'', '\0']),
+    (Key::KEY_102ND, ['<', '>', '|']),
+    (Key::KEY_Z, ['y', 'Y', '\0']),
+    (Key::KEY_X, ['x', 'X', '\0']),
+    (Key::KEY_C, ['c', 'C', '\0']),
+    (Key::KEY_V, ['v', 'V', '\0']),
+    (Key::KEY_B, ['b', 'B', '\0']),
+    (Key::KEY_N, ['n', 'N', '\0']),
+    (Key::KEY_M, ['m', 'M', 'µ']),
+    (Key::KEY_COMMA, [',', ';', '\0']),
+    (Key::KEY_DOT, ['.', ':', '\0']),
+    (Key::KEY_SLASH, ['-', '_', '\0']),
+];
+
+/// As [`DE_KEYS`], for a French layout. Its ~ and ` are dead keys on some systems and not
+/// others, so they're left out rather than typed wrong on one or the other.
+const FR_KEYS: &[(Key, [char; 3])] = &[
+    (Key::KEY_GRAVE, ['²', '\0', '\0']),
+    (Key::KEY_1, ['&', '1', '\0']),
+    (Key::KEY_2, ['é', '2', '\0']),
+    (Key::KEY_3, ['"', '3', '#']),
+    (Key::KEY_4, ['\'', '4', '{']),
+    (Key::KEY_5, ['(', '5', '[']),
+    (Key::KEY_6, ['-', '6', '|']),
+    (Key::KEY_7, ['è', '7', '\0']),
+    (Key::KEY_8, ['_', '8', '\\']),
+    (Key::KEY_9, ['ç', '9', '\0']),
+    (Key::KEY_0, ['à', '0', '@']),
+    (Key::KEY_MINUS, [')', '°', ']']),
+    (Key::KEY_EQUAL, ['=', '+', '}']),
+    (Key::KEY_Q, ['a', 'A', '\0']),
+    (Key::KEY_W, ['z', 'Z', '\0']),
+    (Key::KEY_E, ['e', 'E', '€']),
+    (Key::KEY_R, ['r', 'R', '\0']),
+    (Key::KEY_T, ['t', 'T', '\0']),
+    (Key::KEY_Y, ['y', 'Y', '\0']),
+    (Key::KEY_U, ['u', 'U', '\0']),
+    (Key::KEY_I, ['i', 'I', '\0']),
+    (Key::KEY_O, ['o', 'O', '\0']),
+    (Key::KEY_P, ['p', 'P', '\0']),
+    (Key::KEY_LEFTBRACE, ['^', '¨', '\0']),
+    (Key::KEY_RIGHTBRACE, ['$', '£', '¤']),
+    (Key::KEY_A, ['q', 'Q', '\0']),
+    (Key::KEY_S, ['s', 'S', '\0']),
+    (Key::KEY_D, ['d', 'D', '\0']),
+    (Key::KEY_F, ['f', 'F', '\0']),
+    (Key::KEY_G, ['g', 'G', '\0']),
+    (Key::KEY_H, ['h', 'H', '\0']),
+    (Key::KEY_J, ['j', 'J', '\0']),
+    (Key::KEY_K, ['k', 'K', '\0']),
+    (Key::KEY_L, ['l', 'L', '\0']),
+    (Key::KEY_SEMICOLON, ['m', 'M', '\0']),
+    (Key::KEY_APOSTROPHE, ['ù', '%', '\0']),
+    (Key::KEY_BACKSLASH, ['*', 'µ', '\0']),
+    (Key::KEY_102ND, ['<', '>', '\0']),
+    (Key::KEY_Z, ['w', 'W', '\0']),
+    (Key::KEY_X, ['x', 'X', '\0']),
+    (Key::KEY_C, ['c', 'C', '\0']),
+    (Key::KEY_V, ['v', 'V', '\0']),
+    (Key::KEY_B, ['b', 'B', '\0']),
+    (Key::KEY_N, ['n', 'N', '\0']),
+    (Key::KEY_M, [',', '?', '\0']),
+    (Key::KEY_COMMA, [';', '.', '\0']),
+    (Key::KEY_DOT, [':', '/', '\0']),
+    (Key::KEY_SLASH, ['!', '§', '\0']),
+];
+
+/// The accents which some layouts type with a dead key before the letter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Accent {
+    Acute,
+    /// Only US International has it, on the acute dead key.
+    Cedilla,
+    Diaeresis,
+    Grave,
+    Tilde,
+    Circumflex,
+}
+
+/// The accent on `c`, and the letter it's on.
+fn accented(c: char) -> Option<(Accent, char)> {
+    let mut lower = c.to_lowercase();
+    let (Some(lower), None) = (lower.next(), lower.next()) else {
+        return None;
+    };
+    let (accent, base) = match lower {
+        'á' => (Accent::Acute, 'a'),
+        'é' => (Accent::Acute, 'e'),
+        'í' => (Accent::Acute, 'i'),
+        'ó' => (Accent::Acute, 'o'),
+        'ú' => (Accent::Acute, 'u'),
+        'ý' => (Accent::Acute, 'y'),
+        'ç' => (Accent::Cedilla, 'c'),
+        'ä' => (Accent::Diaeresis, 'a'),
+        'ë' => (Accent::Diaeresis, 'e'),
+        'ï' => (Accent::Diaeresis, 'i'),
+        'ö' => (Accent::Diaeresis, 'o'),
+        'ü' => (Accent::Diaeresis, 'u'),
+        'ÿ' => (Accent::Diaeresis, 'y'),
+        'à' => (Accent::Grave, 'a'),
+        'è' => (Accent::Grave, 'e'),
+        'ì' => (Accent::Grave, 'i'),
+        'ò' => (Accent::Grave, 'o'),
+        'ù' => (Accent::Grave, 'u'),
+        'ã' => (Accent::Tilde, 'a'),
+        'ñ' => (Accent::Tilde, 'n'),
+        'õ' => (Accent::Tilde, 'o'),
+        'â' => (Accent::Circumflex, 'a'),
+        'ê' => (Accent::Circumflex, 'e'),
+        'î' => (Accent::Circumflex, 'i'),
+        'ô' => (Accent::Circumflex, 'o'),
+        'û' => (Accent::Circumflex, 'u'),
+        _ => return None,
+    };
+    let base = if c.is_uppercase() {
+        base.to_ascii_uppercase()
+    } else {
+        base
+    };
+    Some((accent, base))
+}
+
+/// The keys for a chord like `ctrl+alt+del` or `shift+F10`, going by common names for
+/// keys, evdev names with or without the `KEY_`, or the character a key types.
+pub fn parse_chord(chord: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    for name in chord.split('+') {
+        if let Some(key) = key_for_name(name) {
+            keys.push(key);
+            continue;
+        }
+        let mut chars = name.chars();
+        match (chars.next().and_then(key_for_char), chars.next()) {
+            (Some((key, shifted)), None) => {
+                if shifted {
+                    keys.push(Key::KEY_LEFTSHIFT);
+                }
+                keys.push(key);
+            }
+            _ => return Err(format!("don't know which key {name:?} is")),
+        }
+    }
+    Ok(keys)
+}
+
+/// The chord a modifier byte and a key's usage make, named so [`parse_chord`] reads it
+/// back, e.g. `leftctrl+leftalt+delete`.
+pub fn chord_name(modifier: u8, usage: u8) -> String {
+    let mut names: Vec<_> = BitFlags::<UsbHidModifier>::from_bits_truncate(modifier)
+        .iter()
+        .map(|modifier| key_name(modifier.key()))
+        .collect();
+    if usage != 0 {
+        let key = (0..0x300)
+            .map(Key::new)
+            .find(|&key| key_to_hid_usage_id(key).is_some_and(|id| id as u8 == usage));
+        names.push(key.map_or_else(|| format!("{usage:#04x}"), key_name));
+    }
+    names.join("+")
+}
+
+/// The evdev name without the `KEY_`, e.g. `delete`.
+fn key_name(key: Key) -> String {
+    let name = format!("{key:?}");
+    name.trim_start_matches("KEY_").to_ascii_lowercase()
+}
+
+fn key_for_name(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" | "lctrl" => Key::KEY_LEFTCTRL,
+        "rctrl" => Key::KEY_RIGHTCTRL,
+        "shift" | "lshift" => Key::KEY_LEFTSHIFT,
+        "rshift" => Key::KEY_RIGHTSHIFT,
+        "alt" | "lalt" => Key::KEY_LEFTALT,
+        "ralt" | "altgr" => Key::KEY_RIGHTALT,
+        "meta" | "super" | "win" | "cmd" | "gui" | "lmeta" => Key::KEY_LEFTMETA,
+        "rmeta" => Key::KEY_RIGHTMETA,
+        "del" => Key::KEY_DELETE,
+        "ins" => Key::KEY_INSERT,
+        "escape" => Key::KEY_ESC,
+        "return" => Key::KEY_ENTER,
+        "pgup" => Key::KEY_PAGEUP,
+        "pgdn" => Key::KEY_PAGEDOWN,
+        "printscreen" | "prtsc" => Key::KEY_SYSRQ,
+        "menu" => Key::KEY_COMPOSE,
+        // the rest of the evdev names are fine as they are, KEY_F10 or f10
+        other => {
+            let upper = other.to_ascii_uppercase();
+            let upper = upper.strip_prefix("KEY_").unwrap_or(&upper);
+            // single letters and digits go through key_for_char instead
+            if upper.len() == 1 {
+                return None;
+            }
+            return format!("KEY_{upper}").parse().ok();
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_modifiers() {
+        let mut keyset = KeySet::new();
+        let presses = [
+            Key::KEY_LEFTCTRL,
+            Key::KEY_LEFTSHIFT,
+            Key::KEY_LEFTALT,
+            Key::KEY_LEFTMETA,
+            Key::KEY_RIGHTCTRL,
+            Key::KEY_RIGHTSHIFT,
+            Key::KEY_RIGHTALT,
+            Key::KEY_RIGHTMETA,
+        ];
+        for (i, key) in presses.into_iter().enumerate() {
+            keyset.press_key(key);
+            assert_eq!(keyset.modifier.bits(), u8::MAX >> (7 - i));
+        }
+
+        for (i, key) in presses.into_iter().rev().enumerate() {
+            keyset.release_key(key);
+            assert_eq!(
+                keyset.modifier.bits(),
+                u8::MAX.checked_shr(i as u32 + 1).unwrap_or(0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_consumer_keys() {
+        assert_eq!(consumer_usage(Key::KEY_VOLUMEUP), Some(0xE9));
+        assert_eq!(consumer_usage(Key::KEY_PLAYPAUSE), Some(0xCD));
+        assert_eq!(consumer_usage(Key::KEY_A), None);
+
+        // they don't go in keyboard reports as well
+        let mut keyset = KeySet::new();
+        keyset.press_key(Key::KEY_VOLUMEUP);
+        assert_eq!(keyset, KeySet::new());
+        assert_eq!(parse_chord("volumeup"), Ok(vec![Key::KEY_VOLUMEUP]));
+    }
+
+    #[test]
+    fn test_press_a_release_a() {
+        let mut keyset = KeySet::new();
+        let key = Key::KEY_A;
+        keyset.press_key(key);
+        assert_eq!(
+            keyset.keys,
+            [KeyboardUsage::KeyboardAa as u8, 0, 0, 0, 0, 0]
+        );
+        keyset.release_key(key);
+        assert_eq!(keyset.keys, [0; 6]);
+    }
+
+    #[test]
+    fn test_press_ab_release_ba() {
+        let mut keyset = KeySet::new();
+        let a = Key::KEY_A;
+        let b = Key::KEY_B;
+        keyset.press_key(a);
+        assert_eq!(
+            keyset.keys,
+            [KeyboardUsage::KeyboardAa as u8, 0, 0, 0, 0, 0]
+        );
+        keyset.press_key(b);
+        assert_eq!(
+            keyset.keys,
+            [
+                KeyboardUsage::KeyboardAa as u8,
+                KeyboardUsage::KeyboardBb as u8,
+                0,
+                0,
+                0,
+                0
+            ]
+        );
+        keyset.release_key(b);
+        assert_eq!(
+            keyset.keys,
+            [KeyboardUsage::KeyboardAa as u8, 0, 0, 0, 0, 0]
+        );
+        keyset.release_key(a);
+        assert_eq!(keyset.keys, [0; 6]);
+    }
+
+    #[test]
+    fn test_press_abcdefg_release_abcdefg() {
+        let mut keyset = KeySet::new();
+        let a = KeyboardUsage::KeyboardAa as u8;
+
+        keyset.press_key(Key::KEY_A);
+        keyset.press_key(Key::KEY_B);
+        keyset.press_key(Key::KEY_C);
+        keyset.press_key(Key::KEY_D);
+        keyset.press_key(Key::KEY_E);
+        keyset.press_key(Key::KEY_F);
+        assert_eq!(keyset.keys, [a, a + 1, a + 2, a + 3, a + 4, a + 5]);
+        keyset.press_key(Key::KEY_G);
+        assert_eq!(keyset.keys, [a, a + 1, a + 2, a + 3, a + 4, a + 5]);
+        keyset.release_key(Key::KEY_G);
+        assert_eq!(keyset.keys, [a, a + 1, a + 2, a + 3, a + 4, a + 5]);
+        keyset.release_key(Key::KEY_F);
+        assert_eq!(keyset.keys, [a + 4, a + 3, a + 2, a + 1, a, 0]);
+        keyset.release_key(Key::KEY_E);
+        keyset.release_key(Key::KEY_D);
+        keyset.release_key(Key::KEY_C);
+        keyset.release_key(Key::KEY_B);
+        keyset.release_key(Key::KEY_A);
+        assert_eq!(keyset.keys, [0; 6]);
+    }
+
+    #[test]
+    fn test_is_pressed() {
+        let mut keyset = KeySet::new();
+        keyset.press_key(Key::KEY_LEFTSHIFT);
+        keyset.press_key(Key::KEY_A);
+        assert!(keyset.is_pressed(Key::KEY_LEFTSHIFT));
+        assert!(keyset.is_pressed(Key::KEY_A));
+        assert!(!keyset.is_pressed(Key::KEY_RIGHTSHIFT));
+        assert!(!keyset.is_pressed(Key::KEY_B));
+        keyset.release_key(Key::KEY_A);
+        assert!(!keyset.is_pressed(Key::KEY_A));
+    }
+
+    #[test]
+    fn test_key_for_char() {
+        assert_eq!(key_for_char('a'), Some((Key::KEY_A, false)));
+        assert_eq!(key_for_char('A'), Some((Key::KEY_A, true)));
+        assert_eq!(key_for_char('?'), Some((Key::KEY_SLASH, true)));
+        assert_eq!(key_for_char('\n'), Some((Key::KEY_ENTER, false)));
+        assert_eq!(key_for_char('é'), None);
+    }
+
+    #[test]
+    fn test_char_for_key() {
+        assert_eq!(char_for_key(Key::KEY_A, false), Some('a'));
+        assert_eq!(char_for_key(Key::KEY_A, true), Some('A'));
+        assert_eq!(char_for_key(Key::KEY_SEMICOLON, true), Some(':'));
+        assert_eq!(char_for_key(Key::KEY_SPACE, false), Some(' '));
+        assert_eq!(char_for_key(Key::KEY_ENTER, false), None);
+        assert_eq!(char_for_key(Key::KEY_LEFTSHIFT, false), None);
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        assert_eq!(Layout::Us.strokes_for_char('é'), None);
+        assert_eq!(
+            Layout::Us.strokes_for_char('\''),
+            Some(vec![Stroke::new(Key::KEY_APOSTROPHE, false)])
+        );
+
+        let intl = Layout::UsIntl;
+        assert_eq!(
+            intl.strokes_for_char('é'),
+            Some(vec![
+                Stroke::new(Key::KEY_APOSTROPHE, false),
+                Stroke::new(Key::KEY_E, false)
+            ])
+        );
+        assert_eq!(
+            intl.strokes_for_char('Ñ'),
+            Some(vec![
+                Stroke::new(Key::KEY_GRAVE, true),
+                Stroke::new(Key::KEY_N, true)
+            ])
+        );
+        assert_eq!(
+            intl.strokes_for_char('ô'),
+            Some(vec![
+                Stroke::new(Key::KEY_6, true),
+                Stroke::new(Key::KEY_O, false)
+            ])
+        );
+        assert_eq!(
+            intl.strokes_for_char('"'),
+            Some(vec![
+                Stroke::new(Key::KEY_APOSTROPHE, true),
+                Stroke::new(Key::KEY_SPACE, false)
+            ])
+        );
+        assert_eq!(
+            intl.strokes_for_char('a'),
+            Some(vec![Stroke::new(Key::KEY_A, false)])
+        );
+        assert_eq!(intl.strokes_for_char('ß'), None);
+
+        assert_eq!(Layout::from_xkb("us(intl)"), Some(Layout::UsIntl));
+        assert_eq!(Layout::from_xkb("us,de"), None);
+    }
+
+    #[test]
+    fn test_altgr_layouts() {
+        let de = Layout::De;
+        assert_eq!(
+            de.strokes_for_char('z'),
+            Some(vec![Stroke::new(Key::KEY_Y, false)])
+        );
+        assert_eq!(
+            de.strokes_for_char('Ä'),
+            Some(vec![Stroke::new(Key::KEY_APOSTROPHE, true)])
+        );
+        assert_eq!(
+            de.strokes_for_char('@'),
+            Some(vec![Stroke::altgr(Key::KEY_Q)])
+        );
+        assert_eq!(
+            Stroke::altgr(Key::KEY_Q).keys(),
+            vec![Key::KEY_RIGHTALT, Key::KEY_Q]
+        );
+        assert_eq!(
+            de.strokes_for_char('é'),
+            Some(vec![
+                Stroke::new(Key::KEY_EQUAL, false),
+                Stroke::new(Key::KEY_E, false)
+            ])
+        );
+        assert_eq!(
+            de.strokes_for_char('^'),
+            Some(vec![
+                Stroke::new(Key::KEY_GRAVE, false),
+                Stroke::new(Key::KEY_SPACE, false)
+            ])
+        );
+        assert_eq!(de.strokes_for_char('ñ'), None);
+        assert_eq!(de.strokes_for_char('\0'), None);
+
+        let fr = Layout::Fr;
+        assert_eq!(
+            fr.strokes_for_char('a'),
+            Some(vec![Stroke::new(Key::KEY_Q, false)])
+        );
+        assert_eq!(
+            fr.strokes_for_char('1'),
+            Some(vec![Stroke::new(Key::KEY_1, true)])
+        );
+        assert_eq!(
+            fr.strokes_for_char('ç'),
+            Some(vec![Stroke::new(Key::KEY_9, false)])
+        );
+        assert_eq!(
+            fr.strokes_for_char('Ê'),
+            Some(vec![
+                Stroke::new(Key::KEY_LEFTBRACE, false),
+                Stroke::new(Key::KEY_E, true)
+            ])
+        );
+        assert_eq!(fr.strokes_for_char('~'), None);
+
+        assert_eq!(Layout::from_xkb("de"), Some(Layout::De));
+        assert_eq!(Layout::from_xkb("fr"), Some(Layout::Fr));
+    }
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!(
+            parse_chord("ctrl+alt+del"),
+            Ok(vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTALT, Key::KEY_DELETE])
+        );
+        assert_eq!(
+            parse_chord("Super+F10"),
+            Ok(vec![Key::KEY_LEFTMETA, Key::KEY_F10])
+        );
+        assert_eq!(
+            parse_chord("rctrl+KEY_PAGEDOWN"),
+            Ok(vec![Key::KEY_RIGHTCTRL, Key::KEY_PAGEDOWN])
+        );
+        assert_eq!(
+            parse_chord("ctrl+C"),
+            Ok(vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_C])
+        );
+        assert_eq!(
+            parse_chord("alt+tab"),
+            Ok(vec![Key::KEY_LEFTALT, Key::KEY_TAB])
+        );
+        assert!(parse_chord("ctrl+frobnicate").is_err());
+        assert!(parse_chord("ctrl+").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use clap::ValueEnum;
+use evdev::{Device, EventType, InputEvent, LedType};
+
+/// An LED on the local keyboard which can show whether we're forwarding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Led {
+    NumLock,
+    CapsLock,
+    ScrollLock,
+    Compose,
+    Kana,
+}
+
+impl Led {
+    fn led_type(self) -> LedType {
+        match self {
+            Self::NumLock => LedType::LED_NUML,
+            Self::CapsLock => LedType::LED_CAPSL,
+            Self::ScrollLock => LedType::LED_SCROLLL,
+            Self::Compose => LedType::LED_COMPOSE,
+            Self::Kana => LedType::LED_KANA,
+        }
+    }
+}
+
+/// Lights an LED while forwarding, putting it back how it was afterwards.
+pub struct CaptureLed {
+    led: LedType,
+    was_lit: bool,
+}
+
+impl CaptureLed {
+    pub fn new(device: &Device, led: Led) -> Self {
+        let led = led.led_type();
+        let was_lit = device.get_led_state().is_ok_and(|leds| leds.contains(led));
+        Self { led, was_lit }
+    }
+
+    pub fn show(&self, device: &mut Device, forwarding: bool) {
+        self.set(device, forwarding);
+    }
+
+    pub fn restore(&self, device: &mut Device) {
+        self.set(device, self.was_lit);
+    }
+
+    // it's only an indicator, not worth stopping over if the keyboard won't have it
+    fn set(&self, device: &mut Device, lit: bool) {
+        let event = InputEvent::new(EventType::LED, self.led.0, lit.into());
+        let _ = device.send_events(&[event]);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Result;
+use evdev::{AbsoluteAxisType, Device, Key, RelativeAxisType};
+use serde::Serialize;
+use tokio_serial::{available_ports, SerialPortType};
+
+use crate::output::Output;
+
+/// An input device we could read events from.
+#[derive(Debug, Serialize)]
+pub struct InputDevice {
+    pub path: PathBuf,
+    /// Symlinks in /dev/input/by-id which survive reboots and replugging, unlike `path`.
+    pub by_id: Vec<PathBuf>,
+    pub name: Option<String>,
+    /// Which of `--keyboard`, `--gamepad` and `--pointer` it looks suitable for.
+    pub capabilities: Vec<&'static str>,
+}
+
+/// A serial port we could talk to the teensy over.
+#[derive(Debug, Serialize)]
+pub struct SerialPort {
+    pub path: String,
+    /// Symlinks in /dev/serial/by-id which survive reboots and replugging, unlike `path`.
+    pub by_id: Vec<PathBuf>,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+/// Every input device that could be a keyboard, gamepad or pointer.
+pub fn input_devices() -> Vec<InputDevice> {
+    let by_id = by_id_links(Path::new("/dev/input/by-id"));
+
+    let mut devices: Vec<_> = evdev::enumerate()
+        .map(|(path, device)| InputDevice {
+            by_id: by_id.get(&path).cloned().unwrap_or_default(),
+            name: device.name().map(str::to_owned),
+            capabilities: capabilities(&device),
+            path,
+        })
+        .filter(|device| !device.capabilities.is_empty())
+        .collect();
+    devices.sort_by(|a, b| a.path.cmp(&b.path));
+    devices
+}
+
+/// Print every input device that could be a keyboard, gamepad or pointer, one per line.
+pub fn list_devices(output: Output) -> Result<()> {
+    for device in input_devices() {
+        match output {
+            Output::Json => println!("{}", serde_json::to_string(&device)?),
+            Output::Human => println!(
+                "{}\t{}\t{}\t{}",
+                device.path.display(),
+                join_paths(&device.by_id),
+                device.capabilities.join(","),
+                device.name.as_deref().unwrap_or("-"),
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Every serial port along with what we know about the USB device behind it.
+pub fn serial_ports() -> Result<Vec<SerialPort>> {
+    let by_id = by_id_links(Path::new("/dev/serial/by-id"));
+
+    let mut ports = available_ports()?;
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+
+    let ports = ports.into_iter().map(|port| {
+        let usb = match port.port_type {
+            SerialPortType::UsbPort(usb) => Some(usb),
+            _ => None,
+        };
+        SerialPort {
+            by_id: by_id
+                .get(Path::new(&port.port_name))
+                .cloned()
+                .unwrap_or_default(),
+            path: port.port_name,
+            vid: usb.as_ref().map(|usb| usb.vid),
+            pid: usb.as_ref().map(|usb| usb.pid),
+            manufacturer: usb.as_ref().and_then(|usb| usb.manufacturer.clone()),
+            product: usb.as_ref().and_then(|usb| usb.product.clone()),
+            serial_number: usb.and_then(|usb| usb.serial_number),
+        }
+    });
+
+    Ok(ports.collect())
+}
+
+/// Print every serial port along with what we know about the USB device behind it.
+pub fn list_ports(output: Output) -> Result<()> {
+    for port in serial_ports()? {
+        match output {
+            Output::Json => println!("{}", serde_json::to_string(&port)?),
+            Output::Human => println!("{}", port.human()),
+        }
+    }
+
+    Ok(())
+}
+
+impl SerialPort {
+    // tab separated so it's easy to pick apart with cut or awk
+    fn human(&self) -> String {
+        let usb_id = match (self.vid, self.pid) {
+            (Some(vid), Some(pid)) => format!("{vid:04x}:{pid:04x}"),
+            _ => "-".to_owned(),
+        };
+        format!(
+            "{}\t{}\t{usb_id}\t{}",
+            self.path,
+            join_paths(&self.by_id),
+            self.product.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// Does this look like something we can forward keypresses from?
+pub fn is_keyboard(device: &Device) -> bool {
+    // if it has an "A" key its probably a keyboard
+    device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(Key::KEY_A))
+}
+
+fn capabilities(device: &Device) -> Vec<&'static str> {
+    let mut capabilities = Vec::new();
+    if is_keyboard(device) {
+        capabilities.push("keyboard");
+    }
+    if device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(Key::BTN_SOUTH))
+    {
+        capabilities.push("gamepad");
+    }
+    let absolute = device
+        .supported_absolute_axes()
+        .is_some_and(|axes| axes.contains(AbsoluteAxisType::ABS_X));
+    let relative = device
+        .supported_relative_axes()
+        .is_some_and(|axes| axes.contains(RelativeAxisType::REL_X));
+    // gamepad sticks are ABS_X too, but they don't have a touch or click to go with them
+    let buttons = device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(Key::BTN_LEFT) || keys.contains(Key::BTN_TOUCH));
+    if (absolute || relative) && buttons {
+        capabilities.push("pointer");
+    }
+    capabilities
+}
+
+// map the device each symlink in a by-id directory points at back to the symlinks
+fn by_id_links(dir: &Path) -> HashMap<PathBuf, Vec<PathBuf>> {
+    let mut links: HashMap<_, Vec<_>> = HashMap::new();
+    // not every machine has these, in which case there's just nothing to show
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return links;
+    };
+    for entry in entries.flatten() {
+        let link = entry.path();
+        if let Ok(target) = link.canonicalize() {
+            links.entry(target).or_default().push(link);
+        }
+    }
+    for links in links.values_mut() {
+        links.sort();
+    }
+    links
+}
+
+fn join_paths(paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return "-".to_owned();
+    }
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_serial_port() {
+        let mut port = SerialPort {
+            path: "/dev/ttyUSB0".to_owned(),
+            by_id: vec![PathBuf::from(
+                "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A10K1234-if00-port0",
+            )],
+            vid: Some(0x0403),
+            pid: Some(0x6001),
+            manufacturer: Some("FTDI".to_owned()),
+            product: Some("FT232R USB UART".to_owned()),
+            serial_number: Some("A10K1234".to_owned()),
+        };
+        assert_eq!(
+            port.human(),
+            concat!(
+                "/dev/ttyUSB0\t",
+                "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A10K1234-if00-port0\t",
+                "0403:6001\t",
+                "FT232R USB UART",
+            )
+        );
+
+        port.by_id.clear();
+        port.vid = None;
+        port.product = None;
+        assert_eq!(port.human(), "/dev/ttyUSB0\t-\t-\t-");
+    }
+}
@@ -0,0 +1,62 @@
+use std::{collections::HashSet, io};
+
+use evdev::{
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+    AttributeSet, EventType, InputEvent, Key,
+};
+
+/// A keyboard made with uinput for passing keys back to this machine rather than forwarding
+/// them, so things like the volume keys keep working while the real keyboard is grabbed.
+pub struct LocalKeys {
+    device: VirtualDevice,
+    // so nothing is left held down on this machine when we stop passing keys back
+    held: HashSet<Key>,
+}
+
+impl LocalKeys {
+    /// Make a keyboard with just `keys` on it, which needs write access to /dev/uinput.
+    pub fn new(keys: impl IntoIterator<Item = Key>) -> io::Result<Self> {
+        let mut set = AttributeSet::<Key>::new();
+        for key in keys {
+            set.insert(key);
+        }
+        let device = VirtualDeviceBuilder::new()?
+            .name("teensy-keyboard-bridge local keys")
+            .with_keys(&set)?
+            .build()?;
+        Ok(Self {
+            device,
+            held: HashSet::new(),
+        })
+    }
+
+    /// Pass on a key event, ignoring releases and repeats of keys it didn't see go down.
+    pub fn key(&mut self, key: Key, value: i32) -> io::Result<()> {
+        // zero is key up, one is key down and two is a key repeat
+        let seen = match value {
+            0 => self.held.remove(&key),
+            1 => {
+                self.held.insert(key);
+                true
+            }
+            _ => self.held.contains(&key),
+        };
+        if !seen {
+            return Ok(());
+        }
+        let event = InputEvent::new(EventType::KEY, key.code(), value);
+        self.device.emit(&[event])
+    }
+
+    pub fn release_all(&mut self) -> io::Result<()> {
+        let events: Vec<_> = self
+            .held
+            .drain()
+            .map(|key| InputEvent::new(EventType::KEY, key.code(), 0))
+            .collect();
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.device.emit(&events)
+    }
+}
@@ -1,124 +1,921 @@
-use std::{collections::HashMap, os::fd::AsRawFd as _, path::PathBuf};
-
-use argh::FromArgs;
-use color_eyre::eyre::{OptionExt, Result};
+mod agent;
+mod barrier;
+mod bridge;
+mod config;
+mod control;
+#[cfg(feature = "screen-edge")]
+mod edge;
+mod gamepad;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "gui")]
+mod gui;
+mod keyset;
+mod led;
+mod list;
+mod local_keys;
+#[cfg(feature = "mdns")]
+mod mdns;
+mod metrics;
+mod mouse_keys;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "notify")]
+mod notify;
+mod oneshot;
+mod output;
+mod payload;
+mod pcap;
+mod pointer;
+#[cfg(feature = "web")]
+mod rest;
+mod rfc2217;
+mod schedule;
+mod secret;
+mod self_test;
+mod send_file;
+mod sender;
+mod settings;
+mod soak;
+mod stress;
+mod terminal;
+#[cfg(feature = "tray")]
+mod tray;
+#[cfg(feature = "web")]
+mod web;
+
+use std::{
+    collections::HashMap,
+    os::fd::{AsRawFd as _, RawFd},
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use bridge::Bridge;
+use clap::{Args, CommandFactory as _, Parser, Subcommand};
+use clap_complete::Shell;
+use color_eyre::eyre::{bail, OptionExt, Result, WrapErr as _};
+use config::Config;
 use dialoguer::FuzzySelect;
-use enumflags2::{bitflags, BitFlag, BitFlags};
-use evdev::{Device, InputEventKind, Key};
+use evdev::Device;
+use gamepad::Gamepad;
+use keyset::Layout;
+use metrics::Metrics;
+use output::{Event, Output};
+#[cfg(feature = "screen-edge")]
+use pointer::Edge;
+use pointer::{Pointer, ScreenSize};
+use rfc2217::Rfc2217;
+use sender::{KeypressSender, Port};
 use termios::{tcsetattr, Termios, TCSANOW};
-use tokio::{io::AsyncWriteExt, select};
-use tokio_serial::{available_ports, SerialPortBuilderExt, SerialPortType, SerialStream};
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+};
+use tokio_serial::{available_ports, FlowControl, SerialPortBuilderExt, SerialPortType};
 use tokio_util::sync::CancellationToken;
-use usbd_hid::descriptor::KeyboardUsage;
 
 /// Send keypresses to the teensy
-#[derive(FromArgs, Debug)]
-struct Args {
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// pop up desktop notifications when the bridge connects, disconnects or hits an error
+    #[cfg(feature = "notify")]
+    #[arg(long, global = true)]
+    notify: bool,
+
+    /// for typing passwords and the like: don't report key events or the target's
+    /// clipboard, show which keys are held or allow --debug-wire or --pcap
+    #[arg(long, global = true)]
+    sensitive: bool,
+
+    /// how to report what's going on, json prints one JSON object per line on stdout
+    #[arg(long, global = true, value_enum, default_value_t = Output::Human)]
+    output: Output,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// forward keypresses, and anything else asked for, to the teensy until stopped
+    Run(Box<RunArgs>),
+    /// connect to a Barrier or InputLeap server as one of its screens and forward what it
+    /// sends to the teensy
+    Barrier(barrier::BarrierArgs),
+    /// send one raw keyboard report, e.g. `send-report --modifier 0x05 --keys 04,05`,
+    /// then an empty one to let go again
+    SendReport(oneshot::SendReportArgs),
+    /// press and release a chord like `press ctrl+alt+del` and exit
+    Press(oneshot::PressArgs),
+    /// type each character by its code point the way the target's OS lets any be typed,
+    /// e.g. `type-unicode --os linux "→ ✓"`, whatever its layout
+    TypeUnicode(oneshot::TypeUnicodeArgs),
+    /// type stdin onto the target, or run the commands in it, one per line: `type TEXT`,
+    /// `press CHORD...` or `sleep MS`
+    Stdin(oneshot::StdinArgs),
+    /// type a secret from the Secret Service or pass onto the target, e.g. to unlock a
+    /// LUKS volume, without it touching the clipboard, logs or disk
+    TypeSecret(secret::TypeSecretArgs),
+    /// copy a file onto a target with no network by typing it as checksummed base64 into
+    /// a decoder typed into its shell
+    SendFile(send_file::SendFileArgs),
+    /// compile a script of `stdin` commands into a payload for the teensy 4.1 to play from
+    /// its SD card by itself, with no client, e.g. `compile-payload setup.txt`
+    CompilePayload(payload::CompilePayloadArgs),
+    /// flood the teensy with key presses and releases at a set rate, checking it keeps up,
+    /// to find how fast the link can go, e.g. `stress --rate 2000 --duration 10`
+    Stress(stress::StressArgs),
+    /// send a repeating pattern of keys for hours, checking nothing goes missing and
+    /// reconnecting if the teensy goes away, then print a summary, e.g. `soak --hours 24`
+    Soak(soak::SoakArgs),
+    /// with the teensy's USB side plugged back into this machine, send it known sequences
+    /// of keys and check they come back out of the keyboard it shows up as
+    SelfTest(self_test::SelfTestArgs),
+    /// put this terminal into raw mode and type what it sends onto the target, escape
+    /// sequences and all, until Ctrl+]
+    Terminal(terminal::TerminalArgs),
+    /// send a command to, or type stdin through, a running bridge's --control socket
+    Control(control::ControlArgs),
+    /// show the settings the teensy keeps in flash, or save or forget one, e.g.
+    /// `settings --save-layout us-intl`
+    Settings(settings::SettingsArgs),
+    /// run on the target, sending its lock LEDs, keyboard layout and clipboard back to
+    /// the client through the teensy's own serial port
+    Agent(agent::AgentArgs),
+    /// serve a page which forwards key presses from any browser to the teensy
+    #[cfg(feature = "web")]
+    Web(web::WebArgs),
+    /// list input devices which could be forwarded, tab separated: path, by-id paths,
+    /// what it could be used as and its name, or one JSON object per device
+    ListDevices,
+    /// list serial ports, tab separated: path, by-id paths, USB vid:pid and product name,
+    /// or one JSON object per port
+    ListPorts,
+    /// list bridges advertised on the network over mDNS, tab separated: name, what to
+    /// pass as --send-to and host name, or one JSON object per bridge
+    #[cfg(feature = "mdns")]
+    ListBridges,
+    /// advertise a network serial server with the teensy on it over mDNS, for ones which
+    /// can't themselves, so `--send-to mdns:NAME` finds it
+    #[cfg(feature = "mdns")]
+    Advertise(mdns::AdvertiseArgs),
+    /// open a window to pick devices and start and stop forwarding from
+    #[cfg(feature = "gui")]
+    Gui,
+    /// print completions for a shell, e.g. `client completions fish > client.fish`
+    Completions { shell: Shell },
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
     /// the keyboard device to read events from, usually a path like /dev/input/event4,
     /// automatically determined if not specified
-    #[argh(option)]
+    #[arg(long)]
     keyboard: Option<PathBuf>,
 
+    /// don't forward a local keyboard, for a bridge only driven through --control or
+    /// --grpc
+    #[arg(long, conflicts_with = "keyboard")]
+    no_keyboard: bool,
+
+    /// a gamepad device to forward as well as the keyboard, usually a path like
+    /// /dev/input/event7, no gamepad is forwarded if not specified
+    #[arg(long)]
+    gamepad: Option<PathBuf>,
+
+    /// a tablet, touchscreen or mouse to forward as an absolute pointer, usually a path
+    /// like /dev/input/event9, nothing is forwarded if not specified
+    #[arg(long)]
+    pointer: Option<PathBuf>,
+
+    /// the size of the target's screen as WIDTHxHEIGHT, used to turn relative mouse
+    /// movements from --pointer and mouse keys into absolute positions
+    #[arg(long, default_value = "1920x1080")]
+    screen: ScreenSize,
+
+    /// forward a mouse given as --pointer as a relative mouse, so the target's own pointer
+    /// speed and acceleration apply and --screen isn't needed, rather than moving an
+    /// absolute pointer around
+    #[arg(long, requires = "pointer")]
+    relative_pointer: bool,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+
+    /// another way to reach the same teensy, e.g. rfc2217://host:port with --send-to a local
+    /// serial port, switched to when the link fails and switched back from when it does too.
+    /// Whatever's held down is sent again after switching
+    #[arg(long)]
+    backup_send_to: Option<String>,
+
+    /// grab the keyboard so keypresses only go to the teensy, hold both Ctrls and Escape
+    /// for two seconds to let go of it again
+    #[arg(long)]
+    grab: bool,
+
+    /// light this LED on the keyboard while forwarding, so it's clear where keypresses
+    /// are going
+    #[arg(long, value_enum)]
+    led: Option<led::Led>,
+
+    /// forward only while the local pointer is off this edge of the screen, going back
+    /// across the opposite edge of the target's screen returns to the local machine,
+    /// needs X11 and a mouse as --pointer, and implies --grab
+    #[cfg(feature = "screen-edge")]
+    #[arg(
+        long,
+        value_enum,
+        requires = "pointer",
+        conflicts_with = "relative_pointer"
+    )]
+    switch_edge: Option<Edge>,
+
+    /// show an icon in the system tray with a menu to pause or stop forwarding
+    #[cfg(feature = "tray")]
+    #[arg(long)]
+    tray: bool,
+
+    /// listen for commands on this unix socket, which can be forwarded over ssh to
+    /// control the bridge and type onto the target from elsewhere with `client control`
+    #[arg(long, requires = "control_token_file")]
+    control: Option<PathBuf>,
+
+    /// a file holding a token which has to be given before the --control socket takes
+    /// any commands, e.g. made with `head -c 32 /dev/urandom | base64 > token`
+    #[arg(long)]
+    control_token_file: Option<PathBuf>,
+
+    /// serve the gRPC API in proto/bridge.proto on this address, e.g. 127.0.0.1:50051,
+    /// calls need `authorization: Bearer <token>` if --control-token-file is given
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc: Option<std::net::SocketAddr>,
+
+    /// serve a JSON API over HTTP on this address, e.g. 127.0.0.1:8081, with GET /status
+    /// and /metrics and POST /pause, /resume, /type, /key and /macro, calls need
+    /// `Authorization: Bearer <token>` with --control-token-file's token
+    #[cfg(feature = "web")]
+    #[arg(long, requires = "control_token_file")]
+    http: Option<std::net::SocketAddr>,
+
+    /// count input events, frames sent and received and errors, but never which keys were
+    /// pressed, printing them on exit and serving them from --http's GET /metrics
+    #[arg(long)]
+    metrics: bool,
+
+    /// with --metrics, send the teensy a probe along with every heartbeat and time its
+    /// reply, for a histogram of round trips over the link
+    #[arg(long, requires = "metrics")]
+    probe: bool,
+
+    /// take commands from and publish status to the MQTT broker at HOST[:PORT], e.g. Home
+    /// Assistant's, anyone who can publish to the --mqtt-topic topics can type onto the target
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "HOST[:PORT]")]
+    mqtt: Option<String>,
+
+    /// what every topic used on the --mqtt broker starts with
+    #[cfg(feature = "mqtt")]
+    #[arg(long, default_value = "teensy-keyboard-bridge")]
+    mqtt_topic: String,
+
+    /// the user to log in to the --mqtt broker as
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt_password_file")]
+    mqtt_user: Option<String>,
+
+    /// a file holding the password for --mqtt-user
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt_user")]
+    mqtt_password_file: Option<PathBuf>,
+
+    /// a TOML config file with key remaps etc., send SIGHUP to reload it while running
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// start off with this profile from the config file, which can also pick the serial port
+    #[arg(long, requires = "config")]
+    profile: Option<String>,
+}
+
+/// How to reach the teensy, shared by everything that forwards to it.
+#[derive(Args, Debug, Clone)]
+struct SerialArgs {
     /// the serial device to send events to, usually a path like /dev/ttyUSB0,
-    /// automatically determined if not specified
-    #[argh(option)]
+    /// automatically determined if not specified. rfc2217://host:port goes to a serial
+    /// port on a network serial server like ser2net instead, tcp://host:port to a teensy on
+    /// the network with the firmware's ethernet feature, and mdns:NAME to the one
+    /// advertised as NAME, or mdns: to pick from those advertised
+    #[arg(long)]
     send_to: Option<String>,
+
+    /// the baud rate to talk to the teensy at, must match what the firmware was built with
+    #[arg(long, default_value_t = shared::DEFAULT_BAUD_RATE)]
+    baud: u32,
+
+    /// once connected at --baud, have the teensy switch the link to this baud rate, up to
+    /// 2000000, to fit in lots of mouse and keyboard traffic. If it can't, or we can't hear
+    /// it there, we carry on at --baud
+    #[arg(long)]
+    switch_baud: Option<u32>,
+
+    /// use RTS/CTS hardware flow control so the teensy can pause us when its queue of
+    /// reports is filling up, the adapter's CTS needs wiring to the teensy's pin 2 for this
+    #[arg(long)]
+    flow_control: bool,
+
+    /// have the teensy let go of everything on the target if it goes this long without
+    /// hearing from us, in milliseconds, e.g. when the serial cable's pulled, or never with
+    /// 0. The bridge sends heartbeats, which get it a second whatever this is
+    #[arg(long, default_value_t = shared::DEFAULT_IDLE_TIMEOUT_MS)]
+    idle_timeout: u32,
+
+    /// have the teensy send the target a keyboard report this many milliseconds apart, up
+    /// to 20, for older machines and KVMs which miss keys at the usual one a millisecond.
+    /// Typing slows down to match. Without it, whatever's saved with
+    /// `settings --save-report-interval`
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(1..=i64::from(shared::MAX_REPORT_INTERVAL_MS))
+    )]
+    report_interval_ms: Option<u8>,
+
+    /// have the teensy send the target each keyboard report as soon as it comes in, rather
+    /// than on its next report interval, cutting up to that much off every keystroke
+    #[arg(long)]
+    immediate_reports: bool,
+
+    /// get keypresses onto the wire as soon as possible by setting ASYNC_LOW_LATENCY on
+    /// the serial port and waiting for every frame to drain, reports drain times on exit
+    #[arg(long)]
+    low_latency: bool,
+
+    /// hexdump every frame sent to and every byte received from the teensy to stderr,
+    /// along with what they decode to
+    #[arg(long)]
+    debug_wire: bool,
+
+    /// write every frame sent to and received from the teensy to this pcapng file, with
+    /// timestamps, for looking at in Wireshark
+    #[arg(long)]
+    pcap: Option<PathBuf>,
+
+    /// go slowly enough for firmware setup screens and bootloaders, and press and release
+    /// each key in one go rather than holding it down, so keys never repeat
+    #[arg(long)]
+    bios: bool,
+
+    /// the keyboard layout the target is set to, for typing text, us-intl can type
+    /// accented letters using dead keys. If not given it's the one saved on the teensy with
+    /// `client settings`, or assumed to be the same as this machine's, going by setxkbmap or
+    /// localectl, then the config's layout, then us
+    #[arg(long, value_enum)]
+    layout: Option<Layout>,
+
+    // the config's layout, for when there's nothing better
+    #[arg(skip)]
+    config_layout: Option<Layout>,
+
+    #[command(flatten)]
+    pacing: sender::Pacing,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Args = argh::from_env();
+    let cli = Cli::parse();
+    #[cfg(feature = "notify")]
+    if cli.notify {
+        notify::enable();
+    }
+    if cli.sensitive {
+        output::set_sensitive();
+    }
+
+    let output = cli.output;
+    let result = match cli.command {
+        Command::Run(args) => run(*args, output).await,
+        Command::Barrier(args) => barrier::run(args, output).await,
+        Command::SendReport(args) => oneshot::send_report(args, output).await,
+        Command::Press(args) => oneshot::press(args, output).await,
+        Command::TypeUnicode(args) => oneshot::type_unicode(args, output).await,
+        Command::Stdin(args) => oneshot::stdin(args, output).await,
+        Command::TypeSecret(args) => secret::type_secret(args, output).await,
+        Command::SendFile(args) => send_file::send_file(args, output).await,
+        Command::CompilePayload(args) => payload::compile_payload(args, output),
+        Command::Stress(args) => stress::stress(args, output).await,
+        Command::Soak(args) => soak::soak(args, output).await,
+        Command::SelfTest(args) => self_test::self_test(args, output).await,
+        Command::Terminal(args) => terminal::run(args, output).await,
+        Command::Settings(args) => settings::settings(args, output).await,
+        #[cfg(feature = "web")]
+        Command::Web(args) => web::run(args, output).await,
+        Command::Control(args) => return control::send(args).await,
+        Command::Agent(args) => return agent::run(args).await,
+        Command::ListDevices => return list::list_devices(output),
+        Command::ListPorts => return list::list_ports(output),
+        #[cfg(feature = "mdns")]
+        Command::ListBridges => return mdns::list_bridges(output),
+        #[cfg(feature = "mdns")]
+        Command::Advertise(args) => mdns::advertise(args, output).await,
+        #[cfg(feature = "gui")]
+        Command::Gui => return gui::run(),
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "client", &mut std::io::stdout());
+            return Ok(());
+        }
+    };
+
+    // people get the full report from color_eyre when we return
+    if let Err(e) = &result {
+        let error = Event::Error {
+            message: format!("{e:#}"),
+        };
+        match output {
+            Output::Json => output.event(&error),
+            #[cfg(feature = "notify")]
+            Output::Human => notify::event(&error),
+            #[cfg(not(feature = "notify"))]
+            Output::Human => {}
+        }
+    }
+    result
+}
 
-    let keyboard = args
-        .keyboard
-        .map_or_else(select_input_device, Ok)
-        .and_then(|path| Ok(Device::open(path)?))?;
+async fn run(args: RunArgs, output: Output) -> Result<()> {
+    #[cfg(feature = "tray")]
+    let tray = args.tray;
+    #[cfg(feature = "screen-edge")]
+    let switch_edge = args.switch_edge;
+    #[cfg(feature = "grpc")]
+    let grpc = args.grpc;
+    #[cfg(feature = "web")]
+    let http = args.http;
+    #[cfg(feature = "mqtt")]
+    let mqtt = match &args.mqtt {
+        Some(address) => {
+            let credentials = match (&args.mqtt_user, &args.mqtt_password_file) {
+                (Some(user), Some(path)) => Some((user.clone(), control::read_token(path)?)),
+                _ => None,
+            };
+            Some(mqtt::Broker::new(address, &args.mqtt_topic, credentials)?)
+        }
+        None => None,
+    };
+    let control_socket = args.control.clone();
+    let control_secret = args
+        .control_token_file
+        .as_deref()
+        .map(control::read_token)
+        .transpose()?;
+    let (mut bridge, mut failover) = connect(args, output).await?;
+    let token = CancellationToken::new();
 
-    let mut sender: KeypressSender = args
-        .send_to
-        .map_or_else(select_serial_port, Ok)
-        .and_then(|port_name| Ok(tokio_serial::new(port_name, 115200).open_native_async()?))?
-        .into();
+    let (status_tx, status) = tokio::sync::watch::channel(bridge::Status::default());
+    let (control, control_rx) = tokio::sync::mpsc::unbounded_channel();
+    bridge = bridge.with_status(status_tx).with_control(control_rx);
+
+    #[cfg(feature = "tray")]
+    if tray {
+        let target = bridge
+            .sender()
+            .port_name()
+            .unwrap_or_else(|| "the teensy".to_owned());
+        tray::spawn(target, status.clone(), control.clone(), token.clone()).await?;
+    }
+    #[cfg(feature = "screen-edge")]
+    if let Some(edge) = switch_edge {
+        edge::spawn(edge, status.clone(), control.clone(), output)?;
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = grpc {
+        let target = bridge.sender().port_name().unwrap_or_default();
+        grpc::serve(
+            addr,
+            control_secret.clone(),
+            target,
+            status.clone(),
+            control.clone(),
+            token.clone(),
+        )?;
+    }
+    #[cfg(feature = "web")]
+    if let (Some(addr), Some(secret)) = (http, &control_secret) {
+        let target = bridge.sender().port_name().unwrap_or_default();
+        rest::serve(
+            addr,
+            secret.clone(),
+            target,
+            bridge.sender().metrics().cloned(),
+            status.clone(),
+            control.clone(),
+            token.clone(),
+        )
+        .await?;
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = mqtt {
+        let target = bridge.sender().port_name().unwrap_or_default();
+        mqtt::spawn(
+            broker,
+            target,
+            status.clone(),
+            control.clone(),
+            token.clone(),
+            output,
+        );
+    }
+    if let (Some(path), Some(secret)) = (control_socket, control_secret) {
+        control::serve(path, secret, status, control, token.clone()).await?;
+    }
 
-    println!("Setup device handle and serial port, disabling terminal echo.");
+    output.status("Setup device handle and serial port, disabling terminal echo.");
     let stdin_fd = std::io::stdin().as_raw_fd();
-    let original_termios = Termios::from_fd(stdin_fd)?;
-    let mut termios = original_termios;
-    termios.c_lflag &= !termios::ECHO;
-    tcsetattr(stdin_fd, TCSANOW, &termios)?;
+    // there's no terminal to stop echoing when running as a daemon
+    let original_termios = Termios::from_fd(stdin_fd).ok();
+    if let Some(mut termios) = original_termios {
+        termios.c_lflag &= !termios::ECHO;
+        tcsetattr(stdin_fd, TCSANOW, &termios)?;
+    }
+
+    let panic_port = install_panic_hook(bridge.sender(), stdin_fd, original_termios)?;
+    bridge = bridge.with_panic_port(panic_port);
 
-    let token = CancellationToken::new();
     let cloned_token = token.clone();
+    let mut sigterm = signal(SignalKind::terminate())?;
     tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.unwrap();
+        select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
         cloned_token.cancel();
     });
+    // `kill -USR1` prints the metrics so far, e.g. before and after changing a setting
+    if let Some(metrics) = bridge.sender().metrics().cloned() {
+        let mut sigusr1 = signal(SignalKind::user_defined1())?;
+        tokio::spawn(async move {
+            while sigusr1.recv().await.is_some() {
+                output.event(&Event::Metrics(&metrics));
+            }
+        });
+    }
 
-    let mut stream = keyboard.into_event_stream()?;
-    let mut keyboard_state = KeySet::new();
-    loop {
-        let event = select! {
-            _ = token.cancelled() => break,
-            event = stream.next_event() => event,
-        }?;
-        let InputEventKind::Key(key) = event.kind() else {
-            continue;
-        };
+    let result = failover.run(&mut bridge, &token).await;
 
-        match event.value() {
-            // zero is key up
-            0 => keyboard_state.release_key(key),
-            // one is key down
-            1 => keyboard_state.press_key(key),
-            // two is key hold just ignore as it doesn't change the state of pressed keys
-            _ => continue,
-        };
+    // however we got here release all keys so nothing is left held down on the target
+    let released = bridge.release_all().await;
+    let locked = bridge.lock_on_exit().await;
+
+    if let Some(stats) = bridge.sender().drain_stats() {
+        output.status(format_args!("Serial latency: {stats}"));
+    }
+    if let Some(metrics) = bridge.sender().metrics() {
+        output.event(&Event::Metrics(metrics));
+    }
+
+    output.status("Stop requested - restoring original terminal properties.");
+    if let Some(original_termios) = original_termios {
+        tcsetattr(stdin_fd, TCSANOW, &original_termios)?;
+    }
+    output.event(&Event::Disconnected);
+
+    result.and(released).and(locked)
+}
 
-        if cfg!(debug_assertions) {
-            eprintln!("{keyboard_state:?}");
+/// Open everything asked for and check the firmware is there, ready to start forwarding.
+async fn connect(args: RunArgs, output: Output) -> Result<(Bridge, Failover)> {
+    let mut config = args
+        .config
+        .as_deref()
+        .map(Config::load)
+        .transpose()?
+        .unwrap_or_default();
+    if let Some(name) = &args.profile {
+        config.use_profile(name)?;
+    }
+
+    let keyboard = if args.no_keyboard {
+        None
+    } else {
+        let path = args.keyboard.map_or_else(select_input_device, Ok)?;
+        Some(Device::open(path)?.into_event_stream()?)
+    };
+
+    let gamepad = args
+        .gamepad
+        .map(|path| {
+            let device = Device::open(path)?;
+            let gamepad = Gamepad::new(&device)?;
+            Ok::<_, color_eyre::Report>((device.into_event_stream()?, gamepad))
+        })
+        .transpose()?;
+
+    let (pointer_events, pointer) = match args.pointer {
+        Some(path) => {
+            let device = Device::open(path)?;
+            let pointer = Pointer::for_device(&device, args.screen)?;
+            (Some(device.into_event_stream()?), pointer)
         }
+        None => (None, Pointer::new(args.screen)),
+    };
 
-        sender.send_state_update(keyboard_state).await?;
+    let bios = args.serial.bios;
+    let (metrics, probe) = (args.metrics, args.probe);
+    let mut serial = args.serial;
+    if serial.send_to.is_none() {
+        serial.send_to = config.profile().and_then(|profile| profile.send_to.clone());
+    }
+    serial.config_layout = config.layout;
+    let backup_send_to = args.backup_send_to.or_else(|| {
+        let profile = config.profile()?;
+        profile.backup_send_to.clone()
+    });
+    let mut sender = open_sender(serial.clone(), output).await?;
+    // come back to the same port rather than asking which again, and carry on with the same
+    // --pcap file
+    serial.send_to = sender.port_name().or(serial.send_to);
+    serial.pcap = None;
+    let mut ports = vec![serial.clone()];
+    ports.extend(backup_send_to.map(|send_to| SerialArgs {
+        send_to: Some(send_to),
+        ..serial
+    }));
+    let failover = Failover { ports, output };
+    if metrics {
+        sender.count_metrics(Arc::new(Metrics::new()));
     }
 
-    // we received Ctrl-C release all keys and exit
-    sender.send_state_update(KeySet::default()).await?;
+    let bridge = Bridge::new(keyboard, sender, config)
+        .with_gamepad(gamepad)
+        .with_pointer(pointer_events, pointer)
+        .with_relative_mouse(args.relative_pointer)
+        .with_output(output)
+        .reload_config_on(signal(SignalKind::hangup())?, args.config)
+        .with_capture_led(args.led)
+        .bios_safe(bios)
+        .with_probes(probe);
+    // the local machine would see everything too otherwise
+    #[cfg(feature = "screen-edge")]
+    let (bridge, grab) = (
+        bridge.with_screen_edge(args.switch_edge),
+        args.grab || args.switch_edge.is_some(),
+    );
+    #[cfg(not(feature = "screen-edge"))]
+    let grab = args.grab;
+    let bridge = bridge.grab(grab)?;
+
+    Ok((bridge, failover))
+}
+
+/// The ways of reaching the teensy, to switch between when the link fails.
+struct Failover {
+    // the one in use first
+    ports: Vec<SerialArgs>,
+    output: Output,
+}
 
-    println!("Stop requested - restoring original terminal properties.");
-    tcsetattr(stdin_fd, TCSANOW, &termios)?;
+impl Failover {
+    /// Run the bridge until it's stopped, switching to the other way of reaching the teensy
+    /// whenever the link fails, if there is one.
+    async fn run(&mut self, bridge: &mut Bridge, token: &CancellationToken) -> Result<()> {
+        loop {
+            let lost = match bridge.run(token).await {
+                Err(e) if self.ports.len() > 1 && bridge.sender().link_failed() => e,
+                result => return result,
+            };
+            if token.is_cancelled() {
+                return Err(lost);
+            }
+            let sender = self.reopen(lost).await?;
+            bridge.fail_over(sender).await?;
+        }
+    }
 
-    Ok(())
+    // the other port, or failing that the one which just went
+    async fn reopen(&mut self, lost: color_eyre::Report) -> Result<KeypressSender> {
+        let name = |serial: &SerialArgs| serial.send_to.clone().unwrap_or_default();
+        self.ports.rotate_left(1);
+        self.output.event(&Event::Error {
+            message: format!(
+                "Lost the teensy on {}, switching to {}: {lost:#}",
+                name(&self.ports[1]),
+                name(&self.ports[0])
+            ),
+        });
+        match open_sender(self.ports[0].clone(), self.output).await {
+            Ok(sender) => Ok(sender),
+            Err(e) => {
+                self.output.event(&Event::Error {
+                    message: format!("Couldn't switch to {}: {e:#}", name(&self.ports[0])),
+                });
+                self.ports.rotate_left(1);
+                open_sender(self.ports[0].clone(), self.output).await
+            }
+        }
+    }
 }
 
-// a wrapper around a SerialStream to make sending keypresses easier
-struct KeypressSender {
-    serial_port: SerialStream,
+/// Open the serial port and check the firmware is on the other end.
+async fn open_sender(args: SerialArgs, output: Output) -> Result<KeypressSender> {
+    // with flow control on writes just block in the kernel while CTS is deasserted,
+    // so the main loop naturally backs off until the firmware catches up
+    let flow_control = if args.flow_control {
+        FlowControl::Hardware
+    } else {
+        FlowControl::None
+    };
+    if args.debug_wire && output::sensitive() {
+        bail!("Not using --debug-wire with --sensitive, every key would end up on stderr");
+    }
+    if args.pcap.is_some() && output::sensitive() {
+        bail!("Not using --pcap with --sensitive, every key would end up on disk");
+    }
+    let port_name = args.send_to.map_or_else(select_serial_port, Ok)?;
+    #[cfg(feature = "mdns")]
+    let port_name = match port_name.strip_prefix("mdns:") {
+        Some(name) => mdns::find(name, output).await?,
+        None => port_name,
+    };
+    #[cfg(not(feature = "mdns"))]
+    if port_name.starts_with("mdns:") {
+        bail!("Finding bridges with --send-to mdns: needs building with --features mdns");
+    }
+    let mut sender: KeypressSender = if let Some(addr) = port_name.strip_prefix("rfc2217://") {
+        Rfc2217::connect(addr, args.baud, args.flow_control)
+            .await?
+            .into()
+    } else if let Some(addr) = port_name.strip_prefix("tcp://") {
+        Port::tcp(addr).await?.into()
+    } else {
+        tokio_serial::new(&port_name, args.baud)
+            .flow_control(flow_control)
+            .open_native_async()?
+            .into()
+    };
+    sender.debug_wire(args.debug_wire);
+    if let Some(path) = &args.pcap {
+        sender.capture_to(path)?;
+    }
+    if args.bios {
+        sender.bios_safe();
+    }
+    if args.low_latency {
+        if let Err(e) = sender.low_latency() {
+            output.event(&Event::Error {
+                message: format!("{e:#}"),
+            });
+        }
+    }
+
+    sender.handshake().await.wrap_err_with(|| {
+        format!(
+            "Handshake failed, is the firmware running and expecting {} baud?",
+            args.baud
+        )
+    })?;
+    match sender.identify().await {
+        Ok((identity, crash_report)) => {
+            output.event(&firmware_event(&identity));
+            sender.set_digitizer(
+                identity
+                    .features
+                    .contains(shared::FirmwareFeature::Digitizer),
+            );
+            if let Some(report) = crash_report {
+                output.event(&Event::Error {
+                    message: format!("The teensy reset after panicking: {report}"),
+                });
+            }
+            if identity.protocol != shared::PROTOCOL_VERSION {
+                bail!(
+                    "The teensy's firmware speaks protocol version {}, but this client speaks {}, \
+                    flash the firmware from the same checkout as the client",
+                    identity.protocol,
+                    shared::PROTOCOL_VERSION
+                );
+            }
+        }
+        // everything from before it could say is protocol version 1
+        Err(e) => output.status(format!("{e:#}")),
+    }
+    let mut baud = args.baud;
+    if let Some(switch_baud) = args.switch_baud {
+        match sender.switch_baud(baud, switch_baud).await {
+            Ok(()) => baud = switch_baud,
+            Err(e) => output.event(&Event::Error {
+                message: format!("Staying at {baud} baud: {e:#}"),
+            }),
+        }
+    }
+    // the last client may have changed it
+    sender.set_idle_timeout(args.idle_timeout).await?;
+    let report_interval_ms = match args.report_interval_ms {
+        Some(ms) => ms,
+        None => settings::saved_report_interval(&mut sender)
+            .await
+            .unwrap_or(shared::DEFAULT_REPORT_INTERVAL_MS),
+    };
+    sender.set_report_interval(report_interval_ms).await?;
+    let mut pacing = args.pacing;
+    // any closer and the firmware's queue just fills up
+    pacing.min_spacing_ms = pacing.min_spacing_ms.max(u64::from(report_interval_ms));
+    sender.set_pacing(pacing);
+    sender.set_immediate_reports(args.immediate_reports).await?;
+    let layout = match args.layout {
+        Some(layout) => Some(layout),
+        None => settings::saved_layout(&mut sender, output).await,
+    };
+    let layout = match layout {
+        Some(layout) => layout,
+        None => local_layout(output)
+            .await
+            .or(args.config_layout)
+            .unwrap_or_default(),
+    };
+    sender.set_layout(layout);
+    output.event(&Event::Connected {
+        port: &port_name,
+        baud,
+    });
+
+    Ok(sender)
 }
 
-impl From<SerialStream> for KeypressSender {
-    fn from(value: SerialStream) -> Self {
-        Self { serial_port: value }
+fn firmware_event(identity: &shared::Identity) -> Event<'static> {
+    let [major, minor, patch] = identity.version;
+    let commit = identity
+        .commit
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let features = identity.features.iter().map(|feature| match feature {
+        shared::FirmwareFeature::Watchdog => "watchdog".to_owned(),
+        shared::FirmwareFeature::FullSpeed => "full-speed".to_owned(),
+        shared::FirmwareFeature::Spi => "spi".to_owned(),
+        shared::FirmwareFeature::I2c => "i2c".to_owned(),
+        shared::FirmwareFeature::Can => "can".to_owned(),
+        shared::FirmwareFeature::Ethernet => "ethernet".to_owned(),
+        shared::FirmwareFeature::SdPayload => "sd-payload".to_owned(),
+        shared::FirmwareFeature::LockLeds => "lock-leds".to_owned(),
+        shared::FirmwareFeature::Digitizer => "digitizer".to_owned(),
+        shared::FirmwareFeature::KeyMatrix => "key-matrix".to_owned(),
+    });
+    Event::Firmware {
+        version: format!("{major}.{minor}.{patch}"),
+        commit: (identity.commit != [0; 4]).then_some(commit),
+        dirty: identity.dirty,
+        protocol: identity.protocol,
+        features: features.collect(),
+        serial_number: (identity.unique_id != 0).then(|| format!("{:016X}", identity.unique_id)),
     }
 }
 
-impl KeypressSender {
-    async fn send_state_update(&mut self, new_state: KeySet) -> Result<()> {
-        let report = shared::WhyNoDeriveDeserializeManSadFaceHere::from(new_state);
-        let mut buf = [0; 32];
-        let to_send = postcard::to_slice_cobs(&report, &mut buf)?;
-        self.serial_port.write_all(to_send).await?;
-        Ok(())
+/// The layout this machine's keyboard is set to, if there's a table for it, on the basis
+/// that the target's most likely set up the same.
+async fn local_layout(output: Output) -> Option<Layout> {
+    let name = agent::layout().await?;
+    let layout = Layout::from_xkb(&name);
+    match layout {
+        Some(_) => output.status(format!("Typing text for this machine's {name} layout.")),
+        None => output.status(format!("There's no table for typing in the {name} layout.")),
     }
+    layout
+}
+
+/// Best effort attempt to release all keys and restore the terminal if we panic,
+/// as the async serial port can't be used from inside the panic hook.
+fn install_panic_hook(
+    sender: &KeypressSender,
+    stdin_fd: RawFd,
+    original_termios: Option<Termios>,
+) -> Result<sender::PanicPort> {
+    use std::io::Write as _;
+
+    let serial_port: sender::PanicPort = Arc::new(Mutex::new(Some(sender.dup_port()?)));
+    let release_all = sender::panic_frames()?;
+
+    let hook_port = serial_port.clone();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // we're already panicking, nothing useful to do if these fail
+        let port = hook_port.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(port) = &*port {
+            let _ = (&*port).write_all(&release_all);
+        }
+        if let Some(original_termios) = &original_termios {
+            let _ = tcsetattr(stdin_fd, TCSANOW, original_termios);
+        }
+        default_hook(info);
+    }));
+
+    Ok(serial_port)
 }
 
 fn select_input_device() -> Result<PathBuf> {
     let mut keyboards = HashMap::new();
     for (path, device) in evdev::enumerate() {
-        // if it has an "A" key its probably a keyboard
-        let supported = device
-            .supported_keys()
-            .map_or(false, |keys| keys.contains(Key::KEY_A));
-        if !supported {
+        if !list::is_keyboard(&device) {
             continue;
         }
 
@@ -166,748 +963,3 @@ fn select_serial_port() -> Result<String> {
 
     Ok(port?.port_name)
 }
-
-#[rustfmt::skip]
-#[bitflags]
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum UsbHidModifier {
-    LeftControl  = 0b0000_0001,
-    LeftShift    = 0b0000_0010,
-    LeftAlt      = 0b0000_0100,
-    LeftMeta     = 0b0000_1000,
-    RightControl = 0b0001_0000,
-    RightShift   = 0b0010_0000,
-    RightAlt     = 0b0100_0000,
-    RightMeta    = 0b1000_0000,
-}
-
-impl UsbHidModifier {
-    fn from_key(key: Key) -> Option<Self> {
-        match key {
-            Key::KEY_LEFTCTRL => Some(Self::LeftControl),
-            Key::KEY_RIGHTCTRL => Some(Self::RightControl),
-            Key::KEY_LEFTSHIFT => Some(Self::LeftShift),
-            Key::KEY_RIGHTSHIFT => Some(Self::RightShift),
-            Key::KEY_LEFTALT => Some(Self::LeftAlt),
-            Key::KEY_RIGHTALT => Some(Self::RightAlt),
-            Key::KEY_LEFTMETA => Some(Self::LeftMeta),
-            Key::KEY_RIGHTMETA => Some(Self::RightMeta),
-            _ => None,
-        }
-    }
-}
-
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-struct KeySet {
-    modifier: BitFlags<UsbHidModifier>,
-    keys: [u8; 6],
-}
-
-impl From<KeySet> for shared::WhyNoDeriveDeserializeManSadFaceHere {
-    fn from(value: KeySet) -> Self {
-        shared::WhyNoDeriveDeserializeManSadFaceHere {
-            modifier: value.modifier.bits(),
-            keys: value.keys,
-        }
-    }
-}
-
-impl KeySet {
-    fn new() -> Self {
-        Self {
-            modifier: UsbHidModifier::empty(),
-            keys: [0; 6],
-        }
-    }
-
-    fn press_key(&mut self, key: Key) {
-        if let Some(modifier) = UsbHidModifier::from_key(key) {
-            self.modifier.set(modifier, true);
-        } else if let Some(usage_id) = key_to_hid_usage_id(key) {
-            let usage_id = usage_id as u8;
-            if self.keys.contains(&usage_id) {
-                return;
-            }
-
-            if let Some(slot) = self.keys.iter_mut().find(|id| **id == 0) {
-                *slot = usage_id;
-            }
-        }
-    }
-
-    fn release_key(&mut self, key: Key) {
-        if let Some(modifier) = UsbHidModifier::from_key(key) {
-            self.modifier.set(modifier, false);
-        } else if let Some(usage_id) = key_to_hid_usage_id(key) {
-            let usage_id = usage_id as u8;
-            if !self.keys.contains(&usage_id) {
-                return;
-            }
-
-            if let Some(slot) = self.keys.iter_mut().find(|id| **id == usage_id) {
-                *slot = 0;
-            }
-
-            self.keys.sort_by(|a, b| b.cmp(a));
-        }
-    }
-}
-
-fn key_to_hid_usage_id(key: Key) -> Option<KeyboardUsage> {
-    let usage_id = match key {
-        Key::KEY_ESC => KeyboardUsage::KeyboardEscape,
-        Key::KEY_1 => KeyboardUsage::Keyboard1Exclamation,
-        Key::KEY_2 => KeyboardUsage::Keyboard2At,
-        Key::KEY_3 => KeyboardUsage::Keyboard3Hash,
-        Key::KEY_4 => KeyboardUsage::Keyboard4Dollar,
-        Key::KEY_5 => KeyboardUsage::Keyboard5Percent,
-        Key::KEY_6 => KeyboardUsage::Keyboard6Caret,
-        Key::KEY_7 => KeyboardUsage::Keyboard7Ampersand,
-        Key::KEY_8 => KeyboardUsage::Keyboard8Asterisk,
-        Key::KEY_9 => KeyboardUsage::Keyboard9OpenParens,
-        Key::KEY_0 => KeyboardUsage::Keyboard0CloseParens,
-        Key::KEY_MINUS => KeyboardUsage::KeyboardDashUnderscore,
-        Key::KEY_EQUAL => KeyboardUsage::KeyboardEqualPlus,
-        Key::KEY_BACKSPACE => KeyboardUsage::KeyboardBackspace,
-        Key::KEY_TAB => KeyboardUsage::KeyboardTab,
-        Key::KEY_Q => KeyboardUsage::KeyboardQq,
-        Key::KEY_W => KeyboardUsage::KeyboardWw,
-        Key::KEY_E => KeyboardUsage::KeyboardEe,
-        Key::KEY_R => KeyboardUsage::KeyboardRr,
-        Key::KEY_T => KeyboardUsage::KeyboardTt,
-        Key::KEY_Y => KeyboardUsage::KeyboardYy,
-        Key::KEY_U => KeyboardUsage::KeyboardUu,
-        Key::KEY_I => KeyboardUsage::KeyboardIi,
-        Key::KEY_O => KeyboardUsage::KeyboardOo,
-        Key::KEY_P => KeyboardUsage::KeyboardPp,
-        Key::KEY_LEFTBRACE => KeyboardUsage::KeyboardOpenBracketBrace,
-        Key::KEY_RIGHTBRACE => KeyboardUsage::KeyboardCloseBracketBrace,
-        Key::KEY_ENTER => KeyboardUsage::KeyboardEnter,
-        Key::KEY_LEFTCTRL => KeyboardUsage::KeyboardLeftControl,
-        Key::KEY_A => KeyboardUsage::KeyboardAa,
-        Key::KEY_S => KeyboardUsage::KeyboardSs,
-        Key::KEY_D => KeyboardUsage::KeyboardDd,
-        Key::KEY_F => KeyboardUsage::KeyboardFf,
-        Key::KEY_G => KeyboardUsage::KeyboardGg,
-        Key::KEY_H => KeyboardUsage::KeyboardHh,
-        Key::KEY_J => KeyboardUsage::KeyboardJj,
-        Key::KEY_K => KeyboardUsage::KeyboardKk,
-        Key::KEY_L => KeyboardUsage::KeyboardLl,
-        Key::KEY_SEMICOLON => KeyboardUsage::KeyboardSemiColon,
-        Key::KEY_APOSTROPHE => KeyboardUsage::KeyboardSingleDoubleQuote,
-        Key::KEY_GRAVE => KeyboardUsage::KeyboardBacktickTilde,
-        Key::KEY_LEFTSHIFT => KeyboardUsage::KeypadLeftShift,
-        Key::KEY_BACKSLASH => KeyboardUsage::KeyboardNonUSHash, // UK keymap
-        Key::KEY_Z => KeyboardUsage::KeyboardZz,
-        Key::KEY_X => KeyboardUsage::KeyboardXx,
-        Key::KEY_C => KeyboardUsage::KeyboardCc,
-        Key::KEY_V => KeyboardUsage::KeyboardVv,
-        Key::KEY_B => KeyboardUsage::KeyboardBb,
-        Key::KEY_N => KeyboardUsage::KeyboardNn,
-        Key::KEY_M => KeyboardUsage::KeyboardMm,
-        Key::KEY_COMMA => KeyboardUsage::KeyboardCommaLess,
-        Key::KEY_DOT => KeyboardUsage::KeyboardPeriodGreater,
-        Key::KEY_SLASH => KeyboardUsage::KeyboardSlashQuestion,
-        Key::KEY_RIGHTSHIFT => KeyboardUsage::KeyboardRightShift,
-        Key::KEY_KPASTERISK => KeyboardUsage::KeypadMultiply,
-        Key::KEY_LEFTALT => KeyboardUsage::KeyboardLeftAlt,
-        Key::KEY_SPACE => KeyboardUsage::KeyboardSpacebar,
-        Key::KEY_CAPSLOCK => KeyboardUsage::KeyboardCapsLock,
-        Key::KEY_F1 => KeyboardUsage::KeyboardF1,
-        Key::KEY_F2 => KeyboardUsage::KeyboardF2,
-        Key::KEY_F3 => KeyboardUsage::KeyboardF3,
-        Key::KEY_F4 => KeyboardUsage::KeyboardF4,
-        Key::KEY_F5 => KeyboardUsage::KeyboardF5,
-        Key::KEY_F6 => KeyboardUsage::KeyboardF6,
-        Key::KEY_F7 => KeyboardUsage::KeyboardF7,
-        Key::KEY_F8 => KeyboardUsage::KeyboardF8,
-        Key::KEY_F9 => KeyboardUsage::KeyboardF9,
-        Key::KEY_F10 => KeyboardUsage::KeyboardF10,
-        Key::KEY_NUMLOCK => KeyboardUsage::KeypadNumLock,
-        Key::KEY_SCROLLLOCK => KeyboardUsage::KeyboardScrollLock,
-        Key::KEY_KP7 => KeyboardUsage::Keypad7Home,
-        Key::KEY_KP8 => KeyboardUsage::Keypad8UpArrow,
-        Key::KEY_KP9 => KeyboardUsage::Keypad9PageUp,
-        Key::KEY_KPMINUS => KeyboardUsage::KeypadMinus,
-        Key::KEY_KP4 => KeyboardUsage::Keypad4LeftArrow,
-        Key::KEY_KP5 => KeyboardUsage::Keypad5,
-        Key::KEY_KP6 => KeyboardUsage::Keypad6RightArrow,
-        Key::KEY_KPPLUS => KeyboardUsage::KeypadPlus,
-        Key::KEY_KP1 => KeyboardUsage::Keypad1End,
-        Key::KEY_KP2 => KeyboardUsage::Keypad2DownArrow,
-        Key::KEY_KP3 => KeyboardUsage::Keypad3PageDown,
-        Key::KEY_KP0 => KeyboardUsage::Keypad0Insert,
-        Key::KEY_KPDOT => KeyboardUsage::KeypadPeriodDelete,
-        Key::KEY_ZENKAKUHANKAKU => KeyboardUsage::KeyboardLANG5,
-        Key::KEY_102ND => KeyboardUsage::KeyboardNonUSSlash,
-        Key::KEY_F11 => KeyboardUsage::KeyboardF11,
-        Key::KEY_F12 => KeyboardUsage::KeyboardF12,
-        Key::KEY_RO => KeyboardUsage::KeyboardInternational1,
-        Key::KEY_KATAKANA => KeyboardUsage::KeyboardLANG3,
-        Key::KEY_HIRAGANA => KeyboardUsage::KeyboardLANG4,
-        Key::KEY_HENKAN => KeyboardUsage::KeyboardInternational4,
-        Key::KEY_KATAKANAHIRAGANA => KeyboardUsage::KeyboardInternational2,
-        Key::KEY_MUHENKAN => KeyboardUsage::KeyboardInternational5,
-        Key::KEY_KPJPCOMMA => KeyboardUsage::KeyboardInternational6,
-        Key::KEY_KPENTER => KeyboardUsage::KeypadEnter,
-        Key::KEY_RIGHTCTRL => KeyboardUsage::KeyboardRightControl,
-        Key::KEY_KPSLASH => KeyboardUsage::KeypadDivide,
-        Key::KEY_SYSRQ => KeyboardUsage::KeyboardPrintScreen,
-        Key::KEY_RIGHTALT => KeyboardUsage::KeyboardRightAlt,
-        // Key::KEY_LINEFEED => 101,
-        Key::KEY_HOME => KeyboardUsage::KeyboardHome,
-        Key::KEY_UP => KeyboardUsage::KeyboardUpArrow,
-        Key::KEY_PAGEUP => KeyboardUsage::KeyboardPageUp,
-        Key::KEY_LEFT => KeyboardUsage::KeyboardLeftArrow,
-        Key::KEY_RIGHT => KeyboardUsage::KeyboardRightArrow,
-        Key::KEY_END => KeyboardUsage::KeyboardEnd,
-        Key::KEY_DOWN => KeyboardUsage::KeyboardDownArrow,
-        Key::KEY_PAGEDOWN => KeyboardUsage::KeyboardPageDown,
-        Key::KEY_INSERT => KeyboardUsage::KeyboardInsert,
-        Key::KEY_DELETE => KeyboardUsage::KeyboardDelete,
-        //     Key::KEY_MACRO => 112,
-        Key::KEY_MUTE => KeyboardUsage::KeyboardMute,
-        Key::KEY_VOLUMEDOWN => KeyboardUsage::KeyboardVolumeDown,
-        Key::KEY_VOLUMEUP => KeyboardUsage::KeyboardVolumeUp,
-        Key::KEY_POWER => KeyboardUsage::KeyboardPower, /* SC System Power Down */
-        Key::KEY_KPEQUAL => KeyboardUsage::KeypadEqual,
-        //     Key::KEY_KPPLUSMINUS => 118,
-        Key::KEY_PAUSE => KeyboardUsage::KeyboardPause,
-        //     Key::KEY_SCALE => 120, /* AL Compiz Scale (Expose) */
-        Key::KEY_KPCOMMA => KeyboardUsage::KeypadComma,
-        Key::KEY_HANGEUL => KeyboardUsage::KeyboardLANG1,
-        Key::KEY_HANJA => KeyboardUsage::KeyboardLANG2,
-        Key::KEY_YEN => KeyboardUsage::KeyboardInternational3,
-        Key::KEY_LEFTMETA => KeyboardUsage::KeyboardLeftGUI,
-        Key::KEY_RIGHTMETA => KeyboardUsage::KeyboardRightGUI,
-        Key::KEY_COMPOSE => KeyboardUsage::KeyboardApplication,
-        //     Key::KEY_STOP => 128, /* AC Stop */
-        Key::KEY_AGAIN => KeyboardUsage::KeyboardAgain,
-        //     Key::KEY_PROPS => 130, /* AC Properties */
-        // Key::KEY_UNDO => KeyboardUsage::KeyboardUndo,  /* AC Undo */
-        Key::KEY_FRONT => KeyboardUsage::KeyboardSelect,
-        // Key::KEY_COPY => KeyboardUsage::KeyboardCopy,  /* AC Copy */
-        // Key::KEY_OPEN => 134,  /* AC Open */
-        // A this point I got bored / had a headache...
-        //     Key::KEY_PASTE => 135, /* AC Paste */
-        //     Key::KEY_FIND => 136,  /* AC Search */
-        //     Key::KEY_CUT => 137,   /* AC Cut */
-        //     Key::KEY_HELP => 138,  /* AL Integrated Help Center */
-        //     Key::KEY_MENU => 139,  /* Menu (show menu) */
-        //     Key::KEY_CALC => 140,  /* AL Calculator */
-        //     Key::KEY_SETUP => 141,
-        //     Key::KEY_SLEEP => 142,  /* SC System Sleep */
-        //     Key::KEY_WAKEUP => 143, /* System Wake Up */
-        //     Key::KEY_FILE => 144,   /* AL Local Machine Browser */
-        //     Key::KEY_SENDFILE = 145,
-        //     Key::KEY_DELETEFILE => 146,
-        //     Key::KEY_XFER => 147,
-        //     Key::KEY_PROG1 => 148,
-        //     Key::KEY_PROG2 => 149,
-        //     Key::KEY_WWW = 150, /* AL Internet Browser */
-        //     Key::KEY_MSDOS => 151,
-        //     Key::KEY_COFFEE => 152, /* AL Terminal Lock/Screensaver */
-        //     Key::KEY_DIRECTION => 153,
-        //     Key::KEY_ROTATE_DISPLAY = 153,
-        //     Key::KEY_CYCLEWINDOWS = 154,
-        //     Key::KEY_MAIL = 155,
-        //     Key::KEY_BOOKMARKS = 156, /* AC Bookmarks */
-        //     Key::KEY_COMPUTER = 157,
-        //     Key::KEY_BACK = 158,    /* AC Back */
-        //     Key::KEY_FORWARD = 159, /* AC Forward */
-        //     Key::KEY_CLOSECD = 160,
-        //     Key::KEY_EJECTCD = 161,
-        //     Key::KEY_EJECTCLOSECD = 162,
-        //     Key::KEY_NEXTSONG = 163,
-        //     Key::KEY_PLAYPAUSE = 164,
-        //     Key::KEY_PREVIOUSSONG = 165,
-        //     Key::KEY_STOPCD = 166,
-        //     Key::KEY_RECORD = 167,
-        //     Key::KEY_REWIND = 168,
-        //     Key::KEY_PHONE = 169, /* Media Select Telephone */
-        //     Key::KEY_ISO = 170,
-        //     Key::KEY_CONFIG = 171,   /* AL Consumer Control Configuration */
-        //     Key::KEY_HOMEPAGE = 172, /* AC Home */
-        //     Key::KEY_REFRESH = 173,  /* AC Refresh */
-        //     Key::KEY_EXIT = 174,     /* AC Exit */
-        //     Key::KEY_MOVE = 175,
-        //     Key::KEY_EDIT = 176,
-        //     Key::KEY_SCROLLUP = 177,
-        //     Key::KEY_SCROLLDOWN = 178,
-        //     Key::KEY_KPLEFTPAREN = 179,
-        //     Key::KEY_KPRIGHTPAREN = 180,
-        //     Key::KEY_NEW = 181,  /* AC New */
-        //     Key::KEY_REDO = 182, /* AC Redo/Repeat */
-        Key::KEY_F13 => KeyboardUsage::KeyboardF13,
-        Key::KEY_F14 => KeyboardUsage::KeyboardF14,
-        Key::KEY_F15 => KeyboardUsage::KeyboardF15,
-        Key::KEY_F16 => KeyboardUsage::KeyboardF16,
-        Key::KEY_F17 => KeyboardUsage::KeyboardF17,
-        Key::KEY_F18 => KeyboardUsage::KeyboardF18,
-        Key::KEY_F19 => KeyboardUsage::KeyboardF19,
-        Key::KEY_F20 => KeyboardUsage::KeyboardF20,
-        Key::KEY_F21 => KeyboardUsage::KeyboardF21,
-        Key::KEY_F22 => KeyboardUsage::KeyboardF22,
-        Key::KEY_F23 => KeyboardUsage::KeyboardF23,
-        Key::KEY_F24 => KeyboardUsage::KeyboardF24,
-        //     Key::KEY_PLAYCD = 200,
-        //     Key::KEY_PAUSECD = 201,
-        //     Key::KEY_PROG3 = 202,
-        //     Key::KEY_PROG4 = 203,
-        //     Key::KEY_DASHBOARD = 204, /* AL Dashboard */
-        //     Key::KEY_SUSPEND = 205,
-        //     Key::KEY_CLOSE = 206, /* AC Close */
-        //     Key::KEY_PLAY = 207,
-        //     Key::KEY_FASTFORWARD = 208,
-        //     Key::KEY_BASSBOOST = 209,
-        //     Key::KEY_PRINT = 210, /* AC Print */
-        //     Key::KEY_HP = 211,
-        //     Key::KEY_CAMERA = 212,
-        //     Key::KEY_SOUND = 213,
-        //     Key::KEY_QUESTION = 214,
-        //     Key::KEY_EMAIL = 215,
-        //     Key::KEY_CHAT = 216,
-        //     Key::KEY_SEARCH = 217,
-        //     Key::KEY_CONNECT = 218,
-        //     Key::KEY_FINANCE = 219,
-        //     Key::KEY_SPORT = 220,
-        //     Key::KEY_SHOP = 221,
-        Key::KEY_ALTERASE => KeyboardUsage::KeyboardAlternateErase,
-        //     Key::KEY_CANCEL = 223,
-        //     Key::KEY_BRIGHTNESSDOWN = 224,
-        //     Key::KEY_BRIGHTNESSUP = 225,
-        //     Key::KEY_MEDIA = 226,
-        //     Key::KEY_SWITCHVIDEOMODE = 227,
-        //     Key::KEY_KBDILLUMTOGGLE = 228,
-        //     Key::KEY_KBDILLUMDOWN = 229,
-        //     Key::KEY_KBDILLUMUP = 230,
-        //     Key::KEY_SEND = 231,
-        //     Key::KEY_REPLY = 232,
-        //     Key::KEY_FORWARDMAIL = 233,
-        //     Key::KEY_SAVE = 234,
-        //     Key::KEY_DOCUMENTS = 235,
-        //     Key::KEY_BATTERY = 236,
-        //     Key::KEY_BLUETOOTH = 237,
-        //     Key::KEY_WLAN = 238,
-        //     Key::KEY_UWB = 239,
-        //     Key::KEY_UNKNOWN = 240,
-        //     Key::KEY_VIDEO_NEXT = 241,
-        //     Key::KEY_VIDEO_PREV = 242,
-        //     Key::KEY_BRIGHTNESS_CYCLE = 243,
-        //     Key::KEY_BRIGHTNESS_AUTO = 244,
-        //     Key::KEY_DISPLAY_OFF = 245,
-        //     Key::KEY_WWAN = 246,
-        //     Key::KEY_RFKILL = 247,
-        //     Key::KEY_MICMUTE = 248,
-        //     Key::BTN_0 = 0x100,
-        //     Key::BTN_1 = 0x101,
-        //     Key::BTN_2 = 0x102,
-        //     Key::BTN_3 = 0x103,
-        //     Key::BTN_4 = 0x104,
-        //     Key::BTN_5 = 0x105,
-        //     Key::BTN_6 = 0x106,
-        //     Key::BTN_7 = 0x107,
-        //     Key::BTN_8 = 0x108,
-        //     Key::BTN_9 = 0x109,
-        //     Key::BTN_LEFT = 0x110,
-        //     Key::BTN_RIGHT = 0x111,
-        //     Key::BTN_MIDDLE = 0x112,
-        //     Key::BTN_SIDE = 0x113,
-        //     Key::BTN_EXTRA = 0x114,
-        //     Key::BTN_FORWARD = 0x115,
-        //     Key::BTN_BACK = 0x116,
-        //     Key::BTN_TASK = 0x117,
-        //     Key::BTN_TRIGGER = 0x120,
-        //     Key::BTN_THUMB = 0x121,
-        //     Key::BTN_THUMB2 = 0x122,
-        //     Key::BTN_TOP = 0x123,
-        //     Key::BTN_TOP2 = 0x124,
-        //     Key::BTN_PINKIE = 0x125,
-        //     Key::BTN_BASE = 0x126,
-        //     Key::BTN_BASE2 = 0x127,
-        //     Key::BTN_BASE3 = 0x128,
-        //     Key::BTN_BASE4 = 0x129,
-        //     Key::BTN_BASE5 = 0x12a,
-        //     Key::BTN_BASE6 = 0x12b,
-        //     Key::BTN_DEAD = 0x12f,
-        //     Key::BTN_SOUTH = 0x130,
-        //     Key::BTN_EAST = 0x131,
-        //     Key::BTN_C = 0x132,
-        //     Key::BTN_NORTH = 0x133,
-        //     Key::BTN_WEST = 0x134,
-        //     Key::BTN_Z = 0x135,
-        //     Key::BTN_TL = 0x136,
-        //     Key::BTN_TR = 0x137,
-        //     Key::BTN_TL2 = 0x138,
-        //     Key::BTN_TR2 = 0x139,
-        //     Key::BTN_SELECT = 0x13a,
-        //     Key::BTN_START = 0x13b,
-        //     Key::BTN_MODE = 0x13c,
-        //     Key::BTN_THUMBL = 0x13d,
-        //     Key::BTN_THUMBR = 0x13e,
-        //     Key::BTN_TOOL_PEN = 0x140,
-        //     Key::BTN_TOOL_RUBBER = 0x141,
-        //     Key::BTN_TOOL_BRUSH = 0x142,
-        //     Key::BTN_TOOL_PENCIL = 0x143,
-        //     Key::BTN_TOOL_AIRBRUSH = 0x144,
-        //     Key::BTN_TOOL_FINGER = 0x145,
-        //     Key::BTN_TOOL_MOUSE = 0x146,
-        //     Key::BTN_TOOL_LENS = 0x147,
-        //     Key::BTN_TOOL_QUINTTAP = 0x148, /* Five fingers on trackpad */
-        //     Key::BTN_TOUCH = 0x14a,
-        //     Key::BTN_STYLUS = 0x14b,
-        //     Key::BTN_STYLUS2 = 0x14c,
-        //     Key::BTN_TOOL_DOUBLETAP = 0x14d,
-        //     Key::BTN_TOOL_TRIPLETAP = 0x14e,
-        //     Key::BTN_TOOL_QUADTAP = 0x14f, /* Four fingers on trackpad */
-        //     Key::BTN_GEAR_DOWN = 0x150,
-        //     Key::BTN_GEAR_UP = 0x151,
-        //     Key::KEY_OK = 0x160,
-        //     Key::KEY_SELECT = 0x161,
-        //     Key::KEY_GOTO = 0x162,
-        //     Key::KEY_CLEAR = 0x163,
-        //     Key::KEY_POWER2 = 0x164,
-        //     Key::KEY_OPTION = 0x165,
-        //     Key::KEY_INFO = 0x166, /* AL OEM Features/Tips/Tutorial */
-        //     Key::KEY_TIME = 0x167,
-        //     Key::KEY_VENDOR = 0x168,
-        //     Key::KEY_ARCHIVE = 0x169,
-        //     Key::KEY_PROGRAM = 0x16a, /* Media Select Program Guide */
-        //     Key::KEY_CHANNEL = 0x16b,
-        //     Key::KEY_FAVORITES = 0x16c,
-        //     Key::KEY_EPG = 0x16d,
-        //     Key::KEY_PVR = 0x16e, /* Media Select Home */
-        //     Key::KEY_MHP = 0x16f,
-        //     Key::KEY_LANGUAGE = 0x170,
-        //     Key::KEY_TITLE = 0x171,
-        //     Key::KEY_SUBTITLE = 0x172,
-        //     Key::KEY_ANGLE = 0x173,
-        //     Key::KEY_ZOOM = 0x174,
-        //     Key::KEY_FULL_SCREEN = 0x174,
-        //     Key::KEY_MODE = 0x175,
-        //     Key::KEY_KEYBOARD = 0x176,
-        //     Key::KEY_SCREEN = 0x177,
-        //     Key::KEY_PC = 0x178,   /* Media Select Computer */
-        //     Key::KEY_TV = 0x179,   /* Media Select TV */
-        //     Key::KEY_TV2 = 0x17a,  /* Media Select Cable */
-        //     Key::KEY_VCR = 0x17b,  /* Media Select VCR */
-        //     Key::KEY_VCR2 = 0x17c, /* VCR Plus */
-        //     Key::KEY_SAT = 0x17d,  /* Media Select Satellite */
-        //     Key::KEY_SAT2 = 0x17e,
-        //     Key::KEY_CD = 0x17f,   /* Media Select CD */
-        //     Key::KEY_TAPE = 0x180, /* Media Select Tape */
-        //     Key::KEY_RADIO = 0x181,
-        //     Key::KEY_TUNER = 0x182, /* Media Select Tuner */
-        //     Key::KEY_PLAYER = 0x183,
-        //     Key::KEY_TEXT = 0x184,
-        //     Key::KEY_DVD = 0x185, /* Media Select DVD */
-        //     Key::KEY_AUX = 0x186,
-        //     Key::KEY_MP3 = 0x187,
-        //     Key::KEY_AUDIO = 0x188, /* AL Audio Browser */
-        //     Key::KEY_VIDEO = 0x189, /* AL Movie Browser */
-        //     Key::KEY_DIRECTORY = 0x18a,
-        //     Key::KEY_LIST = 0x18b,
-        //     Key::KEY_MEMO = 0x18c, /* Media Select Messages */
-        //     Key::KEY_CALENDAR = 0x18d,
-        //     Key::KEY_RED = 0x18e,
-        //     Key::KEY_GREEN = 0x18f,
-        //     Key::KEY_YELLOW = 0x190,
-        //     Key::KEY_BLUE = 0x191,
-        //     Key::KEY_CHANNELUP = 0x192,   /* Channel Increment */
-        //     Key::KEY_CHANNELDOWN = 0x193, /* Channel Decrement */
-        //     Key::KEY_FIRST = 0x194,
-        //     Key::KEY_LAST = 0x195, /* Recall Last */
-        //     Key::KEY_AB = 0x196,
-        //     Key::KEY_NEXT = 0x197,
-        //     Key::KEY_RESTART = 0x198,
-        //     Key::KEY_SLOW = 0x199,
-        //     Key::KEY_SHUFFLE = 0x19a,
-        //     Key::KEY_BREAK = 0x19b,
-        //     Key::KEY_PREVIOUS = 0x19c,
-        //     Key::KEY_DIGITS = 0x19d,
-        //     Key::KEY_TEEN = 0x19e,
-        //     Key::KEY_TWEN = 0x19f,
-        //     Key::KEY_VIDEOPHONE = 0x1a0,     /* Media Select Video Phone */
-        //     Key::KEY_GAMES = 0x1a1,          /* Media Select Games */
-        //     Key::KEY_ZOOMIN = 0x1a2,         /* AC Zoom In */
-        //     Key::KEY_ZOOMOUT = 0x1a3,        /* AC Zoom Out */
-        //     Key::KEY_ZOOMRESET = 0x1a4,      /* AC Zoom */
-        //     Key::KEY_WORDPROCESSOR = 0x1a5,  /* AL Word Processor */
-        //     Key::KEY_EDITOR = 0x1a6,         /* AL Text Editor */
-        //     Key::KEY_SPREADSHEET = 0x1a7,    /* AL Spreadsheet */
-        //     Key::KEY_GRAPHICSEDITOR = 0x1a8, /* AL Graphics Editor */
-        //     Key::KEY_PRESENTATION = 0x1a9,   /* AL Presentation App */
-        //     Key::KEY_DATABASE = 0x1aa,       /* AL Database App */
-        //     Key::KEY_NEWS = 0x1ab,           /* AL Newsreader */
-        //     Key::KEY_VOICEMAIL = 0x1ac,      /* AL Voicemail */
-        //     Key::KEY_ADDRESSBOOK = 0x1ad,    /* AL Contacts/Address Book */
-        //     Key::KEY_MESSENGER = 0x1ae,      /* AL Instant Messaging */
-        //     Key::KEY_DISPLAYTOGGLE = 0x1af,  /* Turn display (LCD) on and off */
-        //     Key::KEY_SPELLCHECK = 0x1b0,     /* AL Spell Check */
-        //     Key::KEY_LOGOFF = 0x1b1,         /* AL Logoff */
-        //     Key::KEY_DOLLAR = 0x1b2,
-        //     Key::KEY_EURO = 0x1b3,
-        //     Key::KEY_FRAMEBACK = 0x1b4, /* Consumer - transport controls */
-        //     Key::KEY_FRAMEFORWARD = 0x1b5,
-        //     Key::KEY_CONTEXT_MENU = 0x1b6,   /* GenDesc - system context menu */
-        //     Key::KEY_MEDIA_REPEAT = 0x1b7,   /* Consumer - transport control */
-        //     Key::KEY_10CHANNELSUP = 0x1b8,   /* 10 channels up (10+) */
-        //     Key::KEY_10CHANNELSDOWN = 0x1b9, /* 10 channels down (10-) */
-        //     Key::KEY_IMAGES = 0x1ba,         /* AL Image Browser */
-        //     Key::KEY_DEL_EOL = 0x1c0,
-        //     Key::KEY_DEL_EOS = 0x1c1,
-        //     Key::KEY_INS_LINE = 0x1c2,
-        //     Key::KEY_DEL_LINE = 0x1c3,
-        //     Key::KEY_FN = 0x1d0,
-        //     Key::KEY_FN_ESC = 0x1d1,
-        //     Key::KEY_FN_F1 = 0x1d2,
-        //     Key::KEY_FN_F2 = 0x1d3,
-        //     Key::KEY_FN_F3 = 0x1d4,
-        //     Key::KEY_FN_F4 = 0x1d5,
-        //     Key::KEY_FN_F5 = 0x1d6,
-        //     Key::KEY_FN_F6 = 0x1d7,
-        //     Key::KEY_FN_F7 = 0x1d8,
-        //     Key::KEY_FN_F8 = 0x1d9,
-        //     Key::KEY_FN_F9 = 0x1da,
-        //     Key::KEY_FN_F10 = 0x1db,
-        //     Key::KEY_FN_F11 = 0x1dc,
-        //     Key::KEY_FN_F12 = 0x1dd,
-        //     Key::KEY_FN_1 = 0x1de,
-        //     Key::KEY_FN_2 = 0x1df,
-        //     Key::KEY_FN_D = 0x1e0,
-        //     Key::KEY_FN_E = 0x1e1,
-        //     Key::KEY_FN_F = 0x1e2,
-        //     Key::KEY_FN_S = 0x1e3,
-        //     Key::KEY_FN_B = 0x1e4,
-        //     Key::KEY_BRL_DOT1 = 0x1f1,
-        //     Key::KEY_BRL_DOT2 = 0x1f2,
-        //     Key::KEY_BRL_DOT3 = 0x1f3,
-        //     Key::KEY_BRL_DOT4 = 0x1f4,
-        //     Key::KEY_BRL_DOT5 = 0x1f5,
-        //     Key::KEY_BRL_DOT6 = 0x1f6,
-        //     Key::KEY_BRL_DOT7 = 0x1f7,
-        //     Key::KEY_BRL_DOT8 = 0x1f8,
-        //     Key::KEY_BRL_DOT9 = 0x1f9,
-        //     Key::KEY_BRL_DOT10 = 0x1fa,
-        //     Key::KEY_NUMERIC_0 = 0x200, /* used by phones, remote controls, */
-        //     Key::KEY_NUMERIC_1 = 0x201, /* and other keypads */
-        //     Key::KEY_NUMERIC_2 = 0x202,
-        //     Key::KEY_NUMERIC_3 = 0x203,
-        //     Key::KEY_NUMERIC_4 = 0x204,
-        //     Key::KEY_NUMERIC_5 = 0x205,
-        //     Key::KEY_NUMERIC_6 = 0x206,
-        //     Key::KEY_NUMERIC_7 = 0x207,
-        //     Key::KEY_NUMERIC_8 = 0x208,
-        //     Key::KEY_NUMERIC_9 = 0x209,
-        //     Key::KEY_NUMERIC_STAR = 0x20a,
-        //     Key::KEY_NUMERIC_POUND = 0x20b,
-        //     Key::KEY_NUMERIC_A = 0x20c, /* Phone key A - HUT Telephony 0xb9 */
-        //     Key::KEY_NUMERIC_B = 0x20d,
-        //     Key::KEY_NUMERIC_C = 0x20e,
-        //     Key::KEY_NUMERIC_D = 0x20f,
-        //     Key::KEY_CAMERA_FOCUS = 0x210,
-        //     Key::KEY_WPS_BUTTON = 0x211,      /* WiFi Protected Setup key */
-        //     Key::KEY_TOUCHPAD_TOGGLE = 0x212, /* Request switch touchpad on or off */
-        //     Key::KEY_TOUCHPAD_ON = 0x213,
-        //     Key::KEY_TOUCHPAD_OFF = 0x214,
-        //     Key::KEY_CAMERA_ZOOMIN = 0x215,
-        //     Key::KEY_CAMERA_ZOOMOUT = 0x216,
-        //     Key::KEY_CAMERA_UP = 0x217,
-        //     Key::KEY_CAMERA_DOWN = 0x218,
-        //     Key::KEY_CAMERA_LEFT = 0x219,
-        //     Key::KEY_CAMERA_RIGHT = 0x21a,
-        //     Key::KEY_ATTENDANT_ON = 0x21b,
-        //     Key::KEY_ATTENDANT_OFF = 0x21c,
-        //     Key::KEY_ATTENDANT_TOGGLE = 0x21d, /* Attendant call on or off */
-        //     Key::KEY_LIGHTS_TOGGLE = 0x21e,    /* Reading light on or off */
-        //     Key::BTN_DPAD_UP = 0x220,
-        //     Key::BTN_DPAD_DOWN = 0x221,
-        //     Key::BTN_DPAD_LEFT = 0x222,
-        //     Key::BTN_DPAD_RIGHT = 0x223,
-        //     Key::KEY_ALS_TOGGLE = 0x230,   /* Ambient light sensor */
-        //     Key::KEY_BUTTONCONFIG = 0x240, /* AL Button Configuration */
-        //     Key::KEY_TASKMANAGER = 0x241,  /* AL Task/Project Manager */
-        //     Key::KEY_JOURNAL = 0x242,      /* AL Log/Journal/Timecard */
-        //     Key::KEY_CONTROLPANEL = 0x243, /* AL Control Panel */
-        //     Key::KEY_APPSELECT = 0x244,    /* AL Select Task/Application */
-        //     Key::KEY_SCREENSAVER = 0x245,  /* AL Screen Saver */
-        //     Key::KEY_VOICECOMMAND = 0x246, /* Listening Voice Command */
-        //     Key::KEY_ASSISTANT = 0x247,
-        //     Key::KEY_KBD_LAYOUT_NEXT = 0x248,
-        //     Key::KEY_BRIGHTNESS_MIN = 0x250, /* Set Brightness to Minimum */
-        //     Key::KEY_BRIGHTNESS_MAX = 0x251, /* Set Brightness to Maximum */
-        //     Key::KEY_KBDINPUTASSIST_PREV = 0x260,
-        //     Key::KEY_KBDINPUTASSIST_NEXT = 0x261,
-        //     Key::KEY_KBDINPUTASSIST_PREVGROUP = 0x262,
-        //     Key::KEY_KBDINPUTASSIST_NEXTGROUP = 0x263,
-        //     Key::KEY_KBDINPUTASSIST_ACCEPT = 0x264,
-        //     Key::KEY_KBDINPUTASSIST_CANCEL = 0x265,
-        //     Key::KEY_RIGHT_UP = 0x266,
-        //     Key::KEY_RIGHT_DOWN = 0x267,
-        //     Key::KEY_LEFT_UP = 0x268,
-        //     Key::KEY_LEFT_DOWN = 0x269,
-        //     Key::KEY_ROOT_MENU = 0x26a,
-        //     Key::KEY_MEDIA_TOP_MENU = 0x26b,
-        //     Key::KEY_NUMERIC_11 = 0x26c,
-        //     Key::KEY_NUMERIC_12 = 0x26d,
-        //     Key::KEY_AUDIO_DESC = 0x26e,
-        //     Key::KEY_3D_MODE = 0x26f,
-        //     Key::KEY_NEXT_FAVORITE = 0x270,
-        //     Key::KEY_STOP_RECORD = 0x271,
-        //     Key::KEY_PAUSE_RECORD = 0x272,
-        //     Key::KEY_VOD = 0x273, /* Video on Demand */
-        //     Key::KEY_UNMUTE = 0x274,
-        //     Key::KEY_FASTREVERSE = 0x275,
-        //     Key::KEY_SLOWREVERSE = 0x276,
-        //     Key::KEY_DATA = 0x277,
-        //     Key::KEY_ONSCREEN_KEYBOARD = 0x278,
-        //     Key::KEY_PRIVACY_SCREEN_TOGGLE = 0x279,
-        //     Key::KEY_SELECTIVE_SCREENSHOT = 0x27a,
-        //     Key::BTN_TRIGGER_HAPPY1 = 0x2c0,
-        //     Key::BTN_TRIGGER_HAPPY2 = 0x2c1,
-        //     Key::BTN_TRIGGER_HAPPY3 = 0x2c2,
-        //     Key::BTN_TRIGGER_HAPPY4 = 0x2c3,
-        //     Key::BTN_TRIGGER_HAPPY5 = 0x2c4,
-        //     Key::BTN_TRIGGER_HAPPY6 = 0x2c5,
-        //     Key::BTN_TRIGGER_HAPPY7 = 0x2c6,
-        //     Key::BTN_TRIGGER_HAPPY8 = 0x2c7,
-        //     Key::BTN_TRIGGER_HAPPY9 = 0x2c8,
-        //     Key::BTN_TRIGGER_HAPPY10 = 0x2c9,
-        //     Key::BTN_TRIGGER_HAPPY11 = 0x2ca,
-        //     Key::BTN_TRIGGER_HAPPY12 = 0x2cb,
-        //     Key::BTN_TRIGGER_HAPPY13 = 0x2cc,
-        //     Key::BTN_TRIGGER_HAPPY14 = 0x2cd,
-        //     Key::BTN_TRIGGER_HAPPY15 = 0x2ce,
-        //     Key::BTN_TRIGGER_HAPPY16 = 0x2cf,
-        //     Key::BTN_TRIGGER_HAPPY17 = 0x2d0,
-        //     Key::BTN_TRIGGER_HAPPY18 = 0x2d1,
-        //     Key::BTN_TRIGGER_HAPPY19 = 0x2d2,
-        //     Key::BTN_TRIGGER_HAPPY20 = 0x2d3,
-        //     Key::BTN_TRIGGER_HAPPY21 = 0x2d4,
-        //     Key::BTN_TRIGGER_HAPPY22 = 0x2d5,
-        //     Key::BTN_TRIGGER_HAPPY23 = 0x2d6,
-        //     Key::BTN_TRIGGER_HAPPY24 = 0x2d7,
-        //     Key::BTN_TRIGGER_HAPPY25 = 0x2d8,
-        //     Key::BTN_TRIGGER_HAPPY26 = 0x2d9,
-        //     Key::BTN_TRIGGER_HAPPY27 = 0x2da,
-        //     Key::BTN_TRIGGER_HAPPY28 = 0x2db,
-        //     Key::BTN_TRIGGER_HAPPY29 = 0x2dc,
-        //     Key::BTN_TRIGGER_HAPPY30 = 0x2dd,
-        //     Key::BTN_TRIGGER_HAPPY31 = 0x2de,
-        //     Key::BTN_TRIGGER_HAPPY32 = 0x2df,
-        //     Key::BTN_TRIGGER_HAPPY33 = 0x2e0,
-        //     Key::BTN_TRIGGER_HAPPY34 = 0x2e1,
-        //     Key::BTN_TRIGGER_HAPPY35 = 0x2e2,
-        //     Key::BTN_TRIGGER_HAPPY36 = 0x2e3,
-        //     Key::BTN_TRIGGER_HAPPY37 = 0x2e4,
-        //     Key::BTN_TRIGGER_HAPPY38 = 0x2e5,
-        //     Key::BTN_TRIGGER_HAPPY39 = 0x2e6,
-        //     Key::BTN_TRIGGER_HAPPY40 = 0x2e7,
-        _ => return None,
-    };
-
-    Some(usage_id)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_standard_modifiers() {
-        let mut keyset = KeySet::new();
-        let presses = [
-            Key::KEY_LEFTCTRL,
-            Key::KEY_LEFTSHIFT,
-            Key::KEY_LEFTALT,
-            Key::KEY_LEFTMETA,
-            Key::KEY_RIGHTCTRL,
-            Key::KEY_RIGHTSHIFT,
-            Key::KEY_RIGHTALT,
-            Key::KEY_RIGHTMETA,
-        ];
-        for (i, key) in presses.into_iter().enumerate() {
-            keyset.press_key(key);
-            assert_eq!(keyset.modifier.bits(), u8::MAX >> (7 - i));
-        }
-
-        for (i, key) in presses.into_iter().rev().enumerate() {
-            keyset.release_key(key);
-            assert_eq!(
-                keyset.modifier.bits(),
-                u8::MAX.checked_shr(i as u32 + 1).unwrap_or(0)
-            );
-        }
-    }
-
-    #[test]
-    fn test_press_a_release_a() {
-        let mut keyset = KeySet::new();
-        let key = Key::KEY_A;
-        keyset.press_key(key);
-        assert_eq!(
-            keyset.keys,
-            [KeyboardUsage::KeyboardAa as u8, 0, 0, 0, 0, 0]
-        );
-        keyset.release_key(key);
-        assert_eq!(keyset.keys, [0; 6]);
-    }
-
-    #[test]
-    fn test_press_ab_release_ba() {
-        let mut keyset = KeySet::new();
-        let a = Key::KEY_A;
-        let b = Key::KEY_B;
-        keyset.press_key(a);
-        assert_eq!(
-            keyset.keys,
-            [KeyboardUsage::KeyboardAa as u8, 0, 0, 0, 0, 0]
-        );
-        keyset.press_key(b);
-        assert_eq!(
-            keyset.keys,
-            [
-                KeyboardUsage::KeyboardAa as u8,
-                KeyboardUsage::KeyboardBb as u8,
-                0,
-                0,
-                0,
-                0
-            ]
-        );
-        keyset.release_key(b);
-        assert_eq!(
-            keyset.keys,
-            [KeyboardUsage::KeyboardAa as u8, 0, 0, 0, 0, 0]
-        );
-        keyset.release_key(a);
-        assert_eq!(keyset.keys, [0; 6]);
-    }
-
-    #[test]
-    fn test_press_abcdefg_release_abcdefg() {
-        let mut keyset = KeySet::new();
-        let a = KeyboardUsage::KeyboardAa as u8;
-
-        keyset.press_key(Key::KEY_A);
-        keyset.press_key(Key::KEY_B);
-        keyset.press_key(Key::KEY_C);
-        keyset.press_key(Key::KEY_D);
-        keyset.press_key(Key::KEY_E);
-        keyset.press_key(Key::KEY_F);
-        assert_eq!(keyset.keys, [a, a + 1, a + 2, a + 3, a + 4, a + 5]);
-        keyset.press_key(Key::KEY_G);
-        assert_eq!(keyset.keys, [a, a + 1, a + 2, a + 3, a + 4, a + 5]);
-        keyset.release_key(Key::KEY_G);
-        assert_eq!(keyset.keys, [a, a + 1, a + 2, a + 3, a + 4, a + 5]);
-        keyset.release_key(Key::KEY_F);
-        assert_eq!(keyset.keys, [a + 4, a + 3, a + 2, a + 1, a, 0]);
-        keyset.release_key(Key::KEY_E);
-        keyset.release_key(Key::KEY_D);
-        keyset.release_key(Key::KEY_C);
-        keyset.release_key(Key::KEY_B);
-        keyset.release_key(Key::KEY_A);
-        assert_eq!(keyset.keys, [0; 6]);
-    }
-}
@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use color_eyre::eyre::{bail, OptionExt as _, Result, WrapErr as _};
+use dialoguer::FuzzySelect;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+
+use crate::output::Output;
+
+/// What bridges are advertised as, a network serial server with the teensy on one of its
+/// ports speaking RFC2217.
+pub const SERVICE_TYPE: &str = "_teensy-bridge._tcp.local.";
+/// How long to wait for bridges to answer, mDNS responders wait up to half a second to
+/// answer a query and can be slower on a busy network.
+const BROWSE_TIME: Duration = Duration::from_secs(2);
+
+/// A bridge found on the network.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkBridge {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl NetworkBridge {
+    /// What to pass as `--send-to` to reach it, by address as not every machine can resolve
+    /// .local names.
+    pub fn send_to(&self) -> String {
+        // IPv4 first, a link-local IPv6 address is no use without its interface
+        let address = self
+            .addresses
+            .iter()
+            .min_by_key(|address| address.is_ipv6());
+        match address {
+            Some(&address) => format!("rfc2217://{}", SocketAddr::new(address, self.port)),
+            None => format!(
+                "rfc2217://{}:{}",
+                self.host.trim_end_matches('.'),
+                self.port
+            ),
+        }
+    }
+
+    // tab separated so it's easy to pick apart with cut or awk
+    fn human(&self) -> String {
+        format!("{}\t{}\t{}", self.name, self.send_to(), self.host)
+    }
+}
+
+/// Every bridge which answers within a couple of seconds, sorted by name. This blocks
+/// while it waits for them.
+pub fn browse() -> Result<Vec<NetworkBridge>> {
+    let daemon = ServiceDaemon::new().wrap_err("Failed to start looking for bridges")?;
+    let events = daemon.browse(SERVICE_TYPE)?;
+    let deadline = Instant::now() + BROWSE_TIME;
+    // a bridge answers once for each interface it's on, only keep one of it
+    let mut bridges = BTreeMap::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        let bridge = NetworkBridge {
+            name: instance_name(info.get_fullname()).to_owned(),
+            host: info.get_hostname().to_owned(),
+            port: info.get_port(),
+            addresses: info.get_addresses().iter().copied().collect(),
+        };
+        bridges.insert(bridge.name.clone(), bridge);
+    }
+    let _ = daemon.shutdown();
+    Ok(bridges.into_values().collect())
+}
+
+/// Where to find the bridge called `name`, or the one picked from those on the network if
+/// `name` is empty, as a `--send-to` rfc2217:// URL.
+pub async fn find(name: &str, output: Output) -> Result<String> {
+    let bridges = tokio::task::spawn_blocking(browse).await??;
+    let bridge = if name.is_empty() {
+        select_bridge(bridges)?
+    } else {
+        let found = bridges.into_iter().find(|bridge| bridge.name == name);
+        found.ok_or_eyre(format!("No bridge called {name} answered over mDNS"))?
+    };
+    output.status(format!("Found {} at {}", bridge.name, bridge.send_to()));
+    Ok(bridge.send_to())
+}
+
+fn select_bridge(bridges: Vec<NetworkBridge>) -> Result<NetworkBridge> {
+    if bridges.len() <= 1 {
+        return bridges
+            .into_iter()
+            .next()
+            .ok_or_eyre("No bridges answered over mDNS");
+    }
+    let names: Vec<_> = bridges.iter().map(NetworkBridge::human).collect();
+    let selection = FuzzySelect::new()
+        .with_prompt("Which bridge should I send events to?")
+        .items(&names)
+        .interact()
+        .expect("Rude :(");
+    bridges
+        .into_iter()
+        .nth(selection)
+        .ok_or_eyre("Selected bridge has fled the country?")
+}
+
+/// Print every bridge advertised on the network, one per line.
+pub fn list_bridges(output: Output) -> Result<()> {
+    for bridge in browse()? {
+        match output {
+            Output::Json => println!("{}", serde_json::to_string(&bridge)?),
+            Output::Human => println!("{}", bridge.human()),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub struct AdvertiseArgs {
+    /// what to call the bridge, e.g. the machine it's plugged into, for --send-to mdns:NAME
+    name: String,
+
+    /// the TCP port the network serial server has the teensy on, speaking RFC2217
+    #[arg(long)]
+    port: u16,
+
+    /// the network serial server's address, if it isn't this machine, e.g. a terminal
+    /// server which can't advertise itself
+    #[arg(long)]
+    address: Option<IpAddr>,
+}
+
+/// Advertise a bridge on the network until Ctrl+C, for network serial servers which can't
+/// do it themselves.
+pub async fn advertise(args: AdvertiseArgs, output: Output) -> Result<()> {
+    if args.name.contains('.') {
+        bail!("Bridge names can't have dots in them");
+    }
+    let (host, addresses) = match args.address {
+        Some(address) => (format!("{}.local.", args.name), address.to_string()),
+        None => {
+            let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")?;
+            (format!("{}.local.", hostname.trim()), String::new())
+        }
+    };
+    let info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &args.name,
+        &host,
+        addresses.as_str(),
+        args.port,
+        None::<std::collections::HashMap<String, String>>,
+    )?;
+    // without an address given, advertise whichever this machine has
+    let info = match args.address {
+        Some(_) => info,
+        None => info.enable_addr_auto(),
+    };
+    let fullname = info.get_fullname().to_owned();
+
+    let daemon = ServiceDaemon::new().wrap_err("Failed to start advertising")?;
+    daemon.register(info)?;
+    output.status(format!(
+        "Advertising {} on port {} until Ctrl+C",
+        args.name, args.port
+    ));
+    tokio::signal::ctrl_c().await?;
+    // say goodbye so it's gone from everyone's lists straight away
+    if let Ok(unregistered) = daemon.unregister(&fullname) {
+        let _ = tokio::task::spawn_blocking(move || unregistered.recv_timeout(BROWSE_TIME)).await;
+    }
+    let _ = daemon.shutdown();
+    Ok(())
+}
+
+/// The instance's name, from its full name with the service type on the end.
+fn instance_name(fullname: &str) -> &str {
+    let service = format!(".{SERVICE_TYPE}");
+    fullname.strip_suffix(&service).unwrap_or(fullname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_to() {
+        assert_eq!(instance_name("rack3._teensy-bridge._tcp.local."), "rack3");
+
+        let mut bridge = NetworkBridge {
+            name: "rack3".to_owned(),
+            host: "ser2net.local.".to_owned(),
+            port: 4001,
+            addresses: vec!["fe80::1".parse().unwrap(), "10.0.0.7".parse().unwrap()],
+        };
+        assert_eq!(bridge.send_to(), "rfc2217://10.0.0.7:4001");
+        bridge.addresses.remove(1);
+        assert_eq!(bridge.send_to(), "rfc2217://[fe80::1]:4001");
+        bridge.addresses.clear();
+        assert_eq!(bridge.send_to(), "rfc2217://ser2net.local:4001");
+    }
+}
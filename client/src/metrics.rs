@@ -0,0 +1,464 @@
+use std::{
+    fmt::{self, Write as _},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{ser::SerializeMap as _, Serialize, Serializer};
+
+/// The name and help text of each of the counters in [`Metrics`].
+const COUNTERS: [(&str, &str); 11] = [
+    ("key_events", "Key events read from the keyboard"),
+    ("pointer_events", "Events read from the pointer"),
+    ("gamepad_events", "Events read from the gamepad"),
+    ("frames_sent", "Frames sent to the teensy"),
+    ("bytes_sent", "Bytes sent to the teensy"),
+    ("write_errors", "Failed writes to the serial port"),
+    (
+        "frames_resent",
+        "Frames sent to the teensy again after it said one didn't arrive intact",
+    ),
+    ("frames_received", "Frames received from the teensy"),
+    ("bad_frames", "Frames from the teensy which didn't decode"),
+    (
+        "reports_dropped",
+        "Keyboard reports the teensy dropped with its queue full",
+    ),
+    ("devices_lost", "Input devices which went away"),
+];
+
+/// The same for the counts the teensy keeps, see [`shared::Telemetry`].
+const TEENSY_COUNTERS: [(&str, &str); 4] = [
+    (
+        "reports_dropped",
+        "Keyboard reports the teensy dropped with its queue full",
+    ),
+    ("bad_frames", "Frames the teensy couldn't decode"),
+    (
+        "uart_errors",
+        "Times the teensy's UART lost or garbled bytes",
+    ),
+    (
+        "usb_errors",
+        "Keyboard reports the target didn't take in time",
+    ),
+];
+
+/// The same for the latency histograms.
+const HISTOGRAMS: [(&str, &str); 2] = [
+    (
+        "capture_to_write",
+        "From an input event to its frame being written",
+    ),
+    ("round_trip", "From sending a --probe to the teensy's reply"),
+];
+
+/// Upper bounds of the latency histograms' buckets in microseconds, doubling each time,
+/// with anything slower going in one more bucket on the end.
+const BUCKETS_US: [u64; 12] = [
+    100, 200, 400, 800, 1_600, 3_200, 6_400, 12_800, 25_600, 51_200, 102_400, 204_800,
+];
+
+/// Counts of what the bridge has been up to, for keeping an eye on one left running.
+/// Unlike `--output json` nothing here says which keys were pressed or what was typed, so
+/// it's fine to leave on where other people use the bridge.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    pub key_events: Counter,
+    pub pointer_events: Counter,
+    pub gamepad_events: Counter,
+    pub frames_sent: Counter,
+    pub bytes_sent: Counter,
+    /// Writes to the serial port which failed or timed out.
+    pub write_errors: Counter,
+    /// Frames sent again after the firmware NAKed one, also counted in `frames_sent`.
+    pub frames_resent: Counter,
+    pub frames_received: Counter,
+    /// Frames from the firmware which didn't decode.
+    pub bad_frames: Counter,
+    /// Keyboard reports the firmware said it had no room for.
+    pub reports_dropped: Counter,
+    pub devices_lost: Counter,
+    /// From the timestamp the kernel gave an input event to the frame it caused being
+    /// written, for the events which caused one.
+    pub capture_to_write: Histogram,
+    /// From sending a `--probe` to the teensy's reply, there and back over the link.
+    pub round_trip: Histogram,
+    /// What the teensy last said about how it's getting on, if it's been asked.
+    teensy: Mutex<Option<shared::Telemetry>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Latencies sorted into [`BUCKETS_US`], for seeing how they're spread out rather than just
+/// their average.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [Counter; BUCKETS_US.len() + 1],
+    count: Counter,
+    sum_us: Counter,
+    // for the standard deviation, which is what's given as the jitter
+    sum_squares_us: Counter,
+}
+
+impl Histogram {
+    pub fn record(&self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = BUCKETS_US.iter().position(|&bound| us <= bound);
+        self.buckets[bucket.unwrap_or(BUCKETS_US.len())].inc();
+        self.count.inc();
+        self.sum_us.add(us);
+        self.sum_squares_us.add(us.saturating_mul(us));
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.get()
+    }
+
+    fn mean_us(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            count => self.sum_us.get() as f64 / count as f64,
+        }
+    }
+
+    /// The standard deviation.
+    fn jitter_us(&self) -> f64 {
+        let count = self.count();
+        if count == 0 {
+            return 0.0;
+        }
+        let mean = self.mean_us();
+        let variance = self.sum_squares_us.get() as f64 / count as f64 - mean * mean;
+        variance.max(0.0).sqrt()
+    }
+
+    /// The upper bound of the bucket the `q`th quantile is in, None if it's slower than
+    /// all of them.
+    fn quantile_us(&self, q: f64) -> Option<u64> {
+        let rank = (q * self.count() as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (&bound, bucket) in BUCKETS_US.iter().zip(&self.buckets) {
+            seen += bucket.get();
+            if seen >= rank {
+                return Some(bound);
+            }
+        }
+        None
+    }
+
+    fn prometheus(&self, text: &mut String, name: &str, help: &str) {
+        let name = format!("bridge_{name}_seconds");
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} histogram");
+        let mut seen = 0;
+        for (bound, bucket) in BUCKETS_US.iter().zip(&self.buckets) {
+            seen += bucket.get();
+            let le = *bound as f64 / 1e6;
+            let _ = writeln!(text, "{name}_bucket{{le=\"{le}\"}} {seen}");
+        }
+        let count = self.count();
+        let sum = self.sum_us.get() as f64 / 1e6;
+        let _ = writeln!(text, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(text, "{name}_sum {sum}");
+        let _ = writeln!(text, "{name}_count {count}");
+    }
+}
+
+impl Serialize for Histogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(6))?;
+        map.serialize_entry("count", &self.count())?;
+        map.serialize_entry("mean_us", &self.mean_us())?;
+        map.serialize_entry("jitter_us", &self.jitter_us())?;
+        for (name, q) in [("p50_us", 0.5), ("p90_us", 0.9), ("p99_us", 0.99)] {
+            map.serialize_entry(name, &self.quantile_us(q))?;
+        }
+        map.end()
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quantile = |q| match self.quantile_us(q) {
+            Some(us) => format!("under {:.1}ms", us as f64 / 1e3),
+            None => format!(
+                "over {:.1}ms",
+                BUCKETS_US[BUCKETS_US.len() - 1] as f64 / 1e3
+            ),
+        };
+        write!(
+            f,
+            "{} times, {:.2}ms on average with {:.2}ms of jitter, half {} and 99% {}",
+            self.count(),
+            self.mean_us() / 1e3,
+            self.jitter_us() / 1e3,
+            quantile(0.5),
+            quantile(0.99)
+        )
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            key_events: Counter::default(),
+            pointer_events: Counter::default(),
+            gamepad_events: Counter::default(),
+            frames_sent: Counter::default(),
+            bytes_sent: Counter::default(),
+            write_errors: Counter::default(),
+            frames_resent: Counter::default(),
+            frames_received: Counter::default(),
+            bad_frames: Counter::default(),
+            reports_dropped: Counter::default(),
+            devices_lost: Counter::default(),
+            capture_to_write: Histogram::default(),
+            round_trip: Histogram::default(),
+            teensy: Mutex::default(),
+        }
+    }
+
+    pub fn teensy_reported(&self, telemetry: shared::Telemetry) {
+        *self.teensy.lock().unwrap() = Some(telemetry);
+    }
+
+    fn teensy(&self) -> Option<shared::Telemetry> {
+        *self.teensy.lock().unwrap()
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // in the same order as COUNTERS
+    fn counts(&self) -> [u64; COUNTERS.len()] {
+        [
+            self.key_events.get(),
+            self.pointer_events.get(),
+            self.gamepad_events.get(),
+            self.frames_sent.get(),
+            self.bytes_sent.get(),
+            self.write_errors.get(),
+            self.frames_resent.get(),
+            self.frames_received.get(),
+            self.bad_frames.get(),
+            self.reports_dropped.get(),
+            self.devices_lost.get(),
+        ]
+    }
+
+    // in the same order as TEENSY_COUNTERS
+    fn teensy_counts(teensy: &shared::Telemetry) -> [u64; TEENSY_COUNTERS.len()] {
+        [
+            teensy.reports_dropped.into(),
+            teensy.bad_frames.into(),
+            teensy.uart_errors.into(),
+            teensy.usb_errors.into(),
+        ]
+    }
+
+    // in the same order as HISTOGRAMS
+    fn histograms(&self) -> [&Histogram; HISTOGRAMS.len()] {
+        [&self.capture_to_write, &self.round_trip]
+    }
+
+    /// Everything in Prometheus' text format, for scraping.
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        let uptime = self.uptime().as_secs_f64();
+        let _ = writeln!(text, "# HELP bridge_uptime_seconds How long it's been up");
+        let _ = writeln!(text, "# TYPE bridge_uptime_seconds gauge");
+        let _ = writeln!(text, "bridge_uptime_seconds {uptime:.3}");
+        for ((name, help), count) in COUNTERS.iter().zip(self.counts()) {
+            let _ = writeln!(text, "# HELP bridge_{name}_total {help}");
+            let _ = writeln!(text, "# TYPE bridge_{name}_total counter");
+            let _ = writeln!(text, "bridge_{name}_total {count}");
+        }
+        if let Some(teensy) = self.teensy() {
+            let name = "bridge_teensy_queued_reports";
+            let _ = writeln!(
+                text,
+                "# HELP {name} Keyboard reports waiting in the teensy's queue"
+            );
+            let _ = writeln!(text, "# TYPE {name} gauge");
+            let _ = writeln!(text, "{name} {}", teensy.queued_reports);
+            let counts = Self::teensy_counts(&teensy);
+            for ((name, help), count) in TEENSY_COUNTERS.iter().zip(counts) {
+                let _ = writeln!(text, "# HELP bridge_teensy_{name}_total {help}");
+                let _ = writeln!(text, "# TYPE bridge_teensy_{name}_total counter");
+                let _ = writeln!(text, "bridge_teensy_{name}_total {count}");
+            }
+        }
+        for ((name, help), histogram) in HISTOGRAMS.iter().zip(self.histograms()) {
+            histogram.prometheus(&mut text, name, help);
+        }
+        text
+    }
+}
+
+impl Serialize for Metrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = COUNTERS.len() + HISTOGRAMS.len() + 2;
+        let mut map = serializer.serialize_map(Some(len))?;
+        map.serialize_entry("uptime_secs", &self.uptime().as_secs())?;
+        for ((name, _), count) in COUNTERS.iter().zip(self.counts()) {
+            map.serialize_entry(name, &count)?;
+        }
+        for ((name, _), histogram) in HISTOGRAMS.iter().zip(self.histograms()) {
+            map.serialize_entry(name, histogram)?;
+        }
+        map.serialize_entry("teensy", &self.teensy())?;
+        map.end()
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sent = self.frames_sent.get();
+        let received = self.frames_received.get();
+        writeln!(
+            f,
+            "In {}s: {} key, {} pointer and {} gamepad events",
+            self.uptime().as_secs(),
+            self.key_events.get(),
+            self.pointer_events.get(),
+            self.gamepad_events.get()
+        )?;
+        writeln!(
+            f,
+            "Sent {sent} frames ({} bytes, {} again), {} writes failed ({:.2}%)",
+            self.bytes_sent.get(),
+            self.frames_resent.get(),
+            self.write_errors.get(),
+            percent(self.write_errors.get(), sent + self.write_errors.get())
+        )?;
+        writeln!(
+            f,
+            "Received {received} frames, {} bad ({:.2}%), the teensy dropped {} reports",
+            self.bad_frames.get(),
+            percent(self.bad_frames.get(), received + self.bad_frames.get()),
+            self.reports_dropped.get()
+        )?;
+        write!(f, "Lost a device {} times", self.devices_lost.get())?;
+        if let Some(teensy) = self.teensy() {
+            write!(
+                f,
+                "\nThe teensy has {} reports queued, and since it started has dropped {}, had {} \
+                bad frames, {} UART errors and {} reports the target didn't take",
+                teensy.queued_reports,
+                teensy.reports_dropped,
+                teensy.bad_frames,
+                teensy.uart_errors,
+                teensy.usb_errors
+            )?;
+        }
+        let labels = ["Capture to write took", "Probes took"];
+        for (label, histogram) in labels.into_iter().zip(self.histograms()) {
+            if histogram.count() > 0 {
+                write!(f, "\n{label} {histogram}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / whole as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        metrics.frames_sent.add(99);
+        metrics.write_errors.inc();
+        metrics.key_events.add(4);
+        metrics.reports_dropped.add(2);
+
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE bridge_frames_sent_total counter\n"));
+        assert!(text.contains("\nbridge_frames_sent_total 99\n"));
+        assert!(text.contains("\nbridge_bad_frames_total 0\n"));
+
+        let summary = metrics.to_string();
+        assert!(summary.contains("4 key, 0 pointer and 0 gamepad events"));
+        assert!(summary.contains("1 writes failed (1.00%)"));
+        assert!(summary.contains("the teensy dropped 2 reports"));
+
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["key_events"], 4);
+        assert_eq!(json["write_errors"], 1);
+        assert_eq!(json["teensy"], serde_json::Value::Null);
+        assert!(!text.contains("teensy_queued_reports"));
+
+        metrics.teensy_reported(shared::Telemetry {
+            queued_reports: 3,
+            bad_frames: 7,
+            ..Default::default()
+        });
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE bridge_teensy_queued_reports gauge\n"));
+        assert!(text.contains("\nbridge_teensy_queued_reports 3\n"));
+        assert!(text.contains("\nbridge_teensy_bad_frames_total 7\n"));
+        let summary = metrics.to_string();
+        assert!(summary.contains("The teensy has 3 reports queued"));
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["teensy"]["bad_frames"], 7);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let metrics = Metrics::new();
+        let histogram = &metrics.capture_to_write;
+        for ms in [1, 1, 1, 2, 300] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.quantile_us(0.5), Some(1_600));
+        assert_eq!(histogram.quantile_us(0.8), Some(3_200));
+        assert_eq!(histogram.quantile_us(0.99), None);
+        assert_eq!(histogram.mean_us(), 61_000.0);
+
+        let text = metrics.prometheus();
+        assert!(text.contains("\nbridge_capture_to_write_seconds_bucket{le=\"0.0016\"} 3\n"));
+        assert!(text.contains("\nbridge_capture_to_write_seconds_bucket{le=\"+Inf\"} 5\n"));
+        assert!(text.contains("\nbridge_capture_to_write_seconds_sum 0.305\n"));
+        assert!(text.contains("\nbridge_round_trip_seconds_count 0\n"));
+
+        let summary = metrics.to_string();
+        assert!(summary.contains("Capture to write took 5 times, 61.00ms on average"));
+        assert!(summary.contains("half under 1.6ms and 99% over 204.8ms"));
+        assert!(!summary.contains("Probes"));
+
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["capture_to_write"]["p90_us"], serde_json::Value::Null);
+        assert_eq!(json["round_trip"]["count"], 0);
+    }
+}
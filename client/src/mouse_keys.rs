@@ -0,0 +1,79 @@
+use evdev::Key;
+
+use crate::pointer::{self, Pointer};
+
+/// Pressing this switches the keyboard between typing and moving the pointer.
+pub const TOGGLE: Key = Key::KEY_SCROLLLOCK;
+
+/// How far one press, or one key repeat, moves the pointer across the pretend screen.
+const STEP: i32 = 16;
+
+/// Drive `pointer` with a key event, returning false if the key isn't one of ours and
+/// should be typed as usual.
+///
+/// The arrows and IJKL move, U, O and M are the left, right and middle buttons, and
+/// Y and H or Page Up and Page Down scroll.
+pub fn handle_key(pointer: &mut Pointer, key: Key, value: i32) -> bool {
+    // zero is key up, one is key down and two is a key repeat
+    let (pressed, repeat) = (value != 0, value == 2);
+
+    let (dx, dy) = match key {
+        Key::KEY_UP | Key::KEY_I => (0, -STEP),
+        Key::KEY_DOWN | Key::KEY_K => (0, STEP),
+        Key::KEY_LEFT | Key::KEY_J => (-STEP, 0),
+        Key::KEY_RIGHT | Key::KEY_L => (STEP, 0),
+        Key::KEY_U => return set_button(pointer, pointer::LEFT, pressed, repeat),
+        Key::KEY_O => return set_button(pointer, pointer::RIGHT, pressed, repeat),
+        Key::KEY_M => return set_button(pointer, pointer::MIDDLE, pressed, repeat),
+        Key::KEY_Y | Key::KEY_PAGEUP => {
+            if pressed {
+                pointer.scroll(1);
+            }
+            return true;
+        }
+        Key::KEY_H | Key::KEY_PAGEDOWN => {
+            if pressed {
+                pointer.scroll(-1);
+            }
+            return true;
+        }
+        _ => return false,
+    };
+
+    if pressed {
+        pointer.move_by(dx, dy);
+    }
+    true
+}
+
+fn set_button(pointer: &mut Pointer, mask: u8, pressed: bool, repeat: bool) -> bool {
+    if !repeat {
+        pointer.set_button(mask, pressed);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointer::ScreenSize;
+
+    #[test]
+    fn test_mouse_keys() {
+        let mut pointer = Pointer::new(ScreenSize::default());
+
+        assert!(handle_key(&mut pointer, Key::KEY_L, 1));
+        assert!(handle_key(&mut pointer, Key::KEY_L, 2));
+        assert!(handle_key(&mut pointer, Key::KEY_U, 1));
+        let state = pointer.take_update().unwrap();
+        assert!(state.x > 0);
+        assert_eq!(state.buttons, pointer::LEFT);
+
+        assert!(handle_key(&mut pointer, Key::KEY_U, 0));
+        assert_eq!(pointer.take_update().unwrap().buttons, 0);
+
+        // anything else is still typed
+        assert!(!handle_key(&mut pointer, Key::KEY_A, 1));
+        assert_eq!(pointer.take_update(), None);
+    }
+}
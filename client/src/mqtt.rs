@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, Result};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, Publish, QoS};
+use serde::Serialize;
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    bridge::{Control, Status},
+    keyset,
+    output::Output,
+};
+
+const DEFAULT_PORT: u16 = 1883;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// How long to wait before trying the broker again after losing it.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// The topics under the prefix which are subscribed to.
+const COMMANDS: [&str; 4] = ["type", "press", "macro", "paused/set"];
+
+/// Where to find the broker and what to call things on it.
+#[derive(Debug, Clone)]
+pub struct Broker {
+    pub host: String,
+    pub port: u16,
+    /// Every topic used starts with this, e.g. `teensy-keyboard-bridge/type`.
+    pub prefix: String,
+    pub credentials: Option<(String, String)>,
+}
+
+impl Broker {
+    /// `host` or `host:port`, as given to `--mqtt`.
+    pub fn new(address: &str, prefix: &str, credentials: Option<(String, String)>) -> Result<Self> {
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host, port),
+                Err(_) => bail!("{port:?} isn't a port number in --mqtt {address}"),
+            },
+            None => (address, DEFAULT_PORT),
+        };
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() || prefix.contains(['+', '#']) {
+            bail!("{prefix:?} can't be used as an MQTT topic prefix");
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            prefix: prefix.to_owned(),
+            credentials,
+        })
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{name}", self.prefix)
+    }
+}
+
+/// What's published to `<prefix>/status`. The held keys are left out on purpose, they'd
+/// put everything typed on the broker.
+#[derive(Debug, PartialEq, Serialize)]
+struct StatusMessage<'a> {
+    paused: bool,
+    target: &'a str,
+    profile: Option<&'a str>,
+}
+
+/// Take commands from `<prefix>/type`, `<prefix>/press`, `<prefix>/macro` and
+/// `<prefix>/paused/set`, and keep `<prefix>/status` and `<prefix>/availability` up to
+/// date, until `token` is cancelled. Losing the broker only loses the commands, it's
+/// tried again every few seconds.
+pub fn spawn(
+    broker: Broker,
+    target: String,
+    mut status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+    token: CancellationToken,
+    output: Output,
+) {
+    let mut options = MqttOptions::new(
+        format!("teensy-keyboard-bridge-{}", std::process::id()),
+        &broker.host,
+        broker.port,
+    );
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(
+        broker.topic("availability"),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some((user, password)) = &broker.credentials {
+        options.set_credentials(user, password);
+    }
+    // the client only queues requests, the event loop has to keep being polled to send them
+    let (client, mut events) = AsyncClient::new(options, 16);
+
+    tokio::spawn(async move {
+        let publish_status = |status: &Status| {
+            let message = StatusMessage {
+                paused: status.paused,
+                target: &target,
+                profile: status.profile.as_deref(),
+            };
+            let payload = serde_json::to_vec(&message).unwrap_or_default();
+            let _ = client.try_publish(broker.topic("status"), QoS::AtLeastOnce, true, payload);
+        };
+        let mut last = None;
+        let mut connected = false;
+        loop {
+            select! {
+                _ = token.cancelled() => break,
+                changed = status.changed(), if connected => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let current = status.borrow_and_update().clone();
+                    // held keys change with every key press, don't republish for those
+                    let shown = (current.paused, current.profile.clone());
+                    if last.as_ref() != Some(&shown) {
+                        publish_status(&current);
+                        last = Some(shown);
+                    }
+                }
+                event = events.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        output.status(format!("Connected to the MQTT broker on {}", broker.host));
+                        connected = true;
+                        // at most once, as typing something twice is worse than not at all
+                        for name in COMMANDS {
+                            let _ = client.try_subscribe(broker.topic(name), QoS::AtMostOnce);
+                        }
+                        let _ = client.try_publish(
+                            broker.topic("availability"),
+                            QoS::AtLeastOnce,
+                            true,
+                            "online",
+                        );
+                        let current = status.borrow_and_update().clone();
+                        publish_status(&current);
+                        last = Some((current.paused, current.profile));
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        match command(&broker.prefix, &publish) {
+                            Ok(command) => {
+                                if control.send(command).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => output.status(format!("Ignoring an MQTT command: {e}")),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if connected {
+                            output.status(format!("Lost the MQTT broker: {e}"));
+                        }
+                        connected = false;
+                        select! {
+                            _ = token.cancelled() => break,
+                            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                        }
+                    }
+                },
+            }
+        }
+        // the will only goes out if the connection drops, so say it ourselves
+        if connected {
+            let _ = client.try_publish(
+                broker.topic("availability"),
+                QoS::AtLeastOnce,
+                true,
+                "offline",
+            );
+            let _ = client.try_disconnect();
+            while let Ok(event) = events.poll().await {
+                if let Event::Outgoing(rumqttc::Outgoing::Disconnect) = event {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// What to do for a message on one of the [`COMMANDS`] topics.
+fn command(prefix: &str, publish: &Publish) -> Result<Control, String> {
+    let name = publish
+        .topic
+        .strip_prefix(prefix)
+        .and_then(|topic| topic.strip_prefix('/'))
+        .unwrap_or(&publish.topic);
+    // a command left retained would otherwise be run again every time we connect
+    if publish.retain {
+        return Err(format!(
+            "not running the retained message on {}",
+            publish.topic
+        ));
+    }
+    let Ok(payload) = std::str::from_utf8(&publish.payload) else {
+        return Err(format!("the payload on {} isn't text", publish.topic));
+    };
+    let command = match name {
+        "type" => Control::Type(payload.to_owned()),
+        "press" => Control::Press(keyset::parse_chord(payload.trim())?),
+        "macro" => Control::RunMacro(payload.trim().to_owned()),
+        "paused/set" => match payload.trim() {
+            "ON" | "on" | "true" | "1" => Control::SetPaused(true),
+            "OFF" | "off" | "false" | "0" => Control::SetPaused(false),
+            _ => return Err(format!("{payload:?} isn't ON or OFF")),
+        },
+        _ => return Err(format!("nothing is done for {}", publish.topic)),
+    };
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use evdev::Key;
+
+    use super::*;
+
+    fn publish(topic: &str, payload: &str) -> Publish {
+        Publish::new(topic, QoS::AtLeastOnce, payload)
+    }
+
+    #[test]
+    fn test_command() {
+        let prefix = "htpc";
+        assert_eq!(
+            command(prefix, &publish("htpc/type", "hello\n")),
+            Ok(Control::Type("hello\n".to_owned()))
+        );
+        assert_eq!(
+            command(prefix, &publish("htpc/press", "ctrl+alt+del")),
+            Ok(Control::Press(vec![
+                Key::KEY_LEFTCTRL,
+                Key::KEY_LEFTALT,
+                Key::KEY_DELETE
+            ]))
+        );
+        assert_eq!(
+            command(prefix, &publish("htpc/macro", "wake")),
+            Ok(Control::RunMacro("wake".to_owned()))
+        );
+        assert_eq!(
+            command(prefix, &publish("htpc/paused/set", "ON")),
+            Ok(Control::SetPaused(true))
+        );
+        assert!(command(prefix, &publish("htpc/paused/set", "maybe")).is_err());
+        assert!(command(prefix, &publish("htpc/press", "ctrl+frobnicate")).is_err());
+        assert!(command(prefix, &publish("htpc/status", "{}")).is_err());
+
+        let mut retained = publish("htpc/type", "hello\n");
+        retained.retain = true;
+        assert!(command(prefix, &retained).is_err());
+    }
+
+    #[test]
+    fn test_broker() {
+        let broker = Broker::new("hass.local", "htpc/", None).unwrap();
+        assert_eq!((broker.host.as_str(), broker.port), ("hass.local", 1883));
+        assert_eq!(broker.topic("type"), "htpc/type");
+
+        let broker = Broker::new("10.0.0.2:8883", "htpc", None).unwrap();
+        assert_eq!((broker.host.as_str(), broker.port), ("10.0.0.2", 8883));
+
+        assert!(Broker::new("hass.local:mqtt", "htpc", None).is_err());
+        assert!(Broker::new("hass.local", "htpc/#", None).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use notify_rust::{Notification, Urgency};
+
+use crate::output::Event;
+
+/// Set by --notify, everything goes through [`crate::output::Output::event`] which
+/// doesn't know about the command line.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Pop up a desktop notification for anything that means the bridge has come or gone.
+pub fn event(event: &Event) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let (summary, body, urgency) = match event {
+        Event::Connected { port, baud } => (
+            "Bridge connected",
+            format!("Forwarding to {port} at {baud} baud"),
+            Urgency::Low,
+        ),
+        Event::Disconnected => (
+            "Bridge disconnected",
+            "Keypresses are no longer going to the target".to_owned(),
+            Urgency::Normal,
+        ),
+        Event::Error { message } => ("Bridge error", message.clone(), Urgency::Critical),
+        _ => return,
+    };
+
+    // a missing notification daemon is no reason to stop forwarding
+    let _ = Notification::new()
+        .appname("Teensy keyboard bridge")
+        .summary(summary)
+        .body(&body)
+        .icon("input-keyboard")
+        .urgency(urgency)
+        .show();
+}
@@ -0,0 +1,331 @@
+use std::{io::IsTerminal as _, num::ParseIntError, time::Duration};
+
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr as _};
+use evdev::Key;
+use tokio::io::{AsyncBufReadExt as _, BufReader};
+
+use crate::{
+    control,
+    keyset::{self, KeySet},
+    output::{Event, Output},
+    SerialArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct SendReportArgs {
+    /// the modifier byte in hex, one bit per modifier from left ctrl (0x01) to right
+    /// meta (0x80)
+    #[arg(long, value_parser = parse_byte, default_value = "0")]
+    modifier: u8,
+
+    /// up to six HID usage codes in hex, comma separated, e.g. 04,05 for a and b
+    #[arg(long, value_parser = parse_byte, value_delimiter = ',')]
+    keys: Vec<u8>,
+
+    /// how long to leave the report before sending an empty one
+    #[arg(long, default_value_t = 50)]
+    hold_ms: u64,
+
+    /// leave the report in place rather than sending an empty one after --hold-ms, and
+    /// don't have the teensy let go of it after --idle-timeout
+    #[arg(long)]
+    keep: bool,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct PressArgs {
+    /// chords to press and release one after another, keys joined with +, e.g. ctrl+alt+del,
+    /// going by common names like ctrl, super, del and pgup, evdev names like KEY_F10 or
+    /// f10, or the character a key types
+    #[arg(required = true, value_parser = keyset::parse_chord)]
+    chords: Vec<Vec<Key>>,
+
+    /// hold each chord down for this many milliseconds, timed by the teensy rather than by
+    /// when the frames reach it
+    #[arg(long)]
+    hold_ms: Option<u16>,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Press and release each chord in turn, for scripts which don't want the whole bridge.
+pub async fn press(args: PressArgs, output: Output) -> Result<()> {
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    for chord in &args.chords {
+        let Some(hold_ms) = args.hold_ms else {
+            sender.tap(chord).await?;
+            continue;
+        };
+        let mut pressed = KeySet::new();
+        for &key in chord {
+            pressed.press_key(key);
+        }
+        sender
+            .send_scheduled(&[(pressed, hold_ms), (KeySet::new(), 0)])
+            .await?;
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub struct TypeUnicodeArgs {
+    /// the text to type
+    text: String,
+
+    /// which OS the target runs, which decides how
+    #[arg(long, value_enum)]
+    os: TargetOs,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// The ways [`shared::TargetOs`] has of typing a code point.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TargetOs {
+    /// Ctrl+Shift+U then the hex, for GTK, Qt and IBus
+    Linux,
+    /// Alt with keypad + then the hex, which needs EnableHexNumpad set in the registry
+    Windows,
+    /// the hex then Alt+X, in Word and WordPad
+    WindowsAltX,
+    /// Option with the hex, with the Unicode Hex Input source selected
+    Macos,
+}
+
+impl From<TargetOs> for shared::TargetOs {
+    fn from(os: TargetOs) -> Self {
+        match os {
+            TargetOs::Linux => Self::Linux,
+            TargetOs::Windows => Self::Windows,
+            TargetOs::WindowsAltX => Self::WindowsAltX,
+            TargetOs::Macos => Self::MacOs,
+        }
+    }
+}
+
+/// Type each character of the text by its code point, so it comes out whatever the
+/// target's layout.
+pub async fn type_unicode(args: TypeUnicodeArgs, output: Output) -> Result<()> {
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    for c in args.text.chars() {
+        sender.type_codepoint(c, args.os.into()).await?;
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub struct StdinArgs {
+    /// type everything read as text rather than reading commands
+    #[arg(long)]
+    raw: bool,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// One line of commands on stdin, or in a file replayed by a hotkey.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// `type <text>`, with `\n`, `\t` and `\\` escapes.
+    Type(String),
+    /// `press <chord>...`, pressing and releasing each chord in turn.
+    Press(Vec<Vec<Key>>),
+    /// `sleep <ms>`
+    Sleep(Duration),
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let command = match command {
+            "type" => Self::Type(control::unescape(rest)),
+            "press" => Self::Press(
+                rest.split_whitespace()
+                    .map(keyset::parse_chord)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| eyre!(e))?,
+            ),
+            "sleep" => Self::Sleep(Duration::from_millis(rest.trim().parse()?)),
+            _ => bail!("unknown command {command:?}"),
+        };
+        Ok(command)
+    }
+
+    /// Every command in `script`, skipping blank lines and `#` comments.
+    pub fn parse_script(script: &str) -> Result<Vec<Self>> {
+        let lines = script.lines().map(str::trim_start).enumerate();
+        lines
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| Self::parse(line).wrap_err_with(|| format!("On line {}", i + 1)))
+            .collect()
+    }
+}
+
+/// Drive the target from a pipe, either typing stdin as it is or running a command from
+/// each line of it, until it's closed.
+pub async fn stdin(args: StdinArgs, output: Output) -> Result<()> {
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    if std::io::stdin().is_terminal() {
+        output.status("Reading from stdin, press Ctrl+D to stop.");
+    }
+
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let mut number = 0;
+    while let Some(line) = stdin.next_line().await? {
+        number += 1;
+        if args.raw {
+            sender.type_text(&line).await?;
+            sender.type_text("\n").await?;
+            continue;
+        }
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match Command::parse(line).wrap_err_with(|| format!("On line {number} of stdin"))? {
+            Command::Type(text) => sender.type_text(&text).await?,
+            Command::Press(chords) => {
+                for chord in &chords {
+                    sender.tap(chord).await?;
+                }
+            }
+            Command::Sleep(duration) => sender.wait(duration).await,
+        }
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+/// Send exactly the keyboard report asked for, for anything the key mapping can't express.
+pub async fn send_report(args: SendReportArgs, output: Output) -> Result<()> {
+    if args.keys.len() > 6 {
+        bail!("A report only has room for 6 keys, not {}", args.keys.len());
+    }
+    let mut keys = [0; 6];
+    keys[..args.keys.len()].copy_from_slice(&args.keys);
+    let report = shared::WhyNoDeriveDeserializeManSadFaceHere {
+        modifier: args.modifier,
+        keys,
+    };
+
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    if args.keep {
+        sender.set_idle_timeout(0).await?;
+    }
+    sender
+        .send_message(shared::HostMessage::Report(report))
+        .await?;
+    if !args.keep {
+        tokio::time::sleep(Duration::from_millis(args.hold_ms)).await;
+        sender.send_state_update(KeySet::new()).await?;
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+/// A byte in hex, with or without a leading 0x.
+fn parse_byte(s: &str) -> Result<u8, ParseIntError> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u8::from_str_radix(digits, 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            Command::parse("type hello\\tworld\\n").unwrap(),
+            Command::Type("hello\tworld\n".to_owned())
+        );
+        assert_eq!(
+            Command::parse("press super+r enter").unwrap(),
+            Command::Press(vec![
+                vec![Key::KEY_LEFTMETA, Key::KEY_R],
+                vec![Key::KEY_ENTER]
+            ])
+        );
+        assert_eq!(
+            Command::parse("sleep 500").unwrap(),
+            Command::Sleep(Duration::from_millis(500))
+        );
+        assert!(Command::parse("press ctrl+frobnicate").is_err());
+        assert!(Command::parse("sleep soon").is_err());
+        assert!(Command::parse("reboot").is_err());
+
+        let script = "# log in\ntype root\\n\n\n  sleep 500\n";
+        assert_eq!(
+            Command::parse_script(script).unwrap(),
+            [
+                Command::Type("root\n".to_owned()),
+                Command::Sleep(Duration::from_millis(500))
+            ]
+        );
+        let error = Command::parse_script("type a\nreboot").unwrap_err();
+        assert_eq!(error.to_string(), "On line 2");
+    }
+
+    #[test]
+    fn test_parse_byte() {
+        assert_eq!(parse_byte("0x05"), Ok(0x05));
+        assert_eq!(parse_byte("e0"), Ok(0xe0));
+        assert_eq!(parse_byte("0XFF"), Ok(0xff));
+        assert!(parse_byte("0x100").is_err());
+        assert!(parse_byte("").is_err());
+    }
+
+    #[test]
+    fn test_codepoint_reports() {
+        let chords = |os: TargetOs, c| {
+            shared::TargetOs::from(os)
+                .codepoint_reports(c)
+                .map(|report| (report.modifier, report.keys[0]))
+                .collect::<Vec<_>>()
+        };
+        // ctrl+shift+u, e, 9, space
+        assert_eq!(
+            chords(TargetOs::Linux, 'é'),
+            [
+                (0x03, 0x18),
+                (0, 0),
+                (0, 0x08),
+                (0, 0),
+                (0, 0x26),
+                (0, 0),
+                (0, 0x2C),
+                (0, 0)
+            ]
+        );
+        // alt, keypad +, 2, 0, a, c on the keypad where they're digits
+        assert_eq!(
+            chords(TargetOs::Windows, '€')[..4],
+            [(0x04, 0), (0x04, 0x57), (0x04, 0), (0x04, 0x5A)]
+        );
+        assert_eq!(chords(TargetOs::Windows, '€')[5], (0x04, 0x62));
+        assert_eq!(
+            chords(TargetOs::WindowsAltX, '\0'),
+            [(0, 0x27), (0, 0), (0x04, 0x1B), (0, 0)]
+        );
+        // a surrogate pair, d83d de00, with option held throughout
+        let smiley = chords(TargetOs::Macos, '😀');
+        assert_eq!(smiley.len(), shared::MAX_CODEPOINT_REPORTS);
+        assert_eq!(smiley[1..3], [(0x04, 0x07), (0x04, 0)]);
+        assert_eq!(smiley.last(), Some(&(0, 0)));
+    }
+}
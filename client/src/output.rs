@@ -0,0 +1,262 @@
+use std::{
+    fmt,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+use evdev::Key;
+use serde::Serialize;
+
+use crate::{metrics::Metrics, soak::SoakReport};
+
+/// Set by --sensitive, for when what's typed is nobody else's business.
+static SENSITIVE: AtomicBool = AtomicBool::new(false);
+
+/// Stop anything recording or showing what's typed or copied on the target: key events
+/// and the target's clipboard aren't reported, held keys aren't shown in status and
+/// `--debug-wire` and `--pcap` are refused.
+pub fn set_sensitive() {
+    SENSITIVE.store(true, Ordering::Relaxed);
+}
+
+pub fn sensitive() -> bool {
+    SENSITIVE.load(Ordering::Relaxed)
+}
+
+/// How the client tells the outside world what it's up to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Messages for a person watching the terminal.
+    #[default]
+    Human,
+    /// One JSON object per line on stdout, everything else goes to stderr.
+    Json,
+}
+
+/// Something happening that a script wrapping the client might care about.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Connected {
+        port: &'a str,
+        baud: u32,
+    },
+    Key {
+        key: Key,
+        pressed: bool,
+    },
+    ConfigReloaded {
+        path: &'a Path,
+    },
+    ProfileChanged {
+        name: &'a str,
+    },
+    Paused,
+    Resumed,
+    Locked,
+    MouseKeys {
+        enabled: bool,
+    },
+    DeviceLost {
+        device: &'a str,
+        reason: String,
+    },
+    DeviceFound {
+        device: &'a str,
+    },
+    /// From the agent on the target, see `client agent`.
+    TargetLeds {
+        num_lock: bool,
+        caps_lock: bool,
+        scroll_lock: bool,
+    },
+    TargetLayout {
+        layout: &'a str,
+    },
+    TargetClipboard {
+        text: String,
+    },
+    /// The teensy had no room for some keyboard reports, so typing's slowed down.
+    Overflow {
+        dropped: u8,
+    },
+    /// How `client stress` got on at one rate.
+    Stress {
+        rate: u32,
+        achieved: f64,
+        probes_sent: u32,
+        probes_answered: u32,
+        worst_round_trip_ms: f64,
+        sustained: bool,
+    },
+    /// What `client soak` has seen so far, now and then and when it stops.
+    Soak(&'a SoakReport),
+    /// What `--metrics` has counted, when the bridge stops.
+    Metrics(&'a Metrics),
+    /// Which firmware the teensy's running, once connected.
+    Firmware {
+        version: String,
+        /// None if it wasn't built from a git checkout.
+        commit: Option<String>,
+        dirty: bool,
+        protocol: u16,
+        features: Vec<String>,
+        /// The teensy's USB serial number, None if the board doesn't have one.
+        serial_number: Option<String>,
+    },
+    /// What the teensy has saved for a setting, see `client settings`.
+    Setting {
+        name: &'a str,
+        value: Option<String>,
+    },
+    Disconnected,
+    Error {
+        message: String,
+    },
+}
+
+impl Output {
+    pub fn event(self, event: &Event) {
+        if sensitive() && matches!(event, Event::Key { .. } | Event::TargetClipboard { .. }) {
+            return;
+        }
+        #[cfg(feature = "notify")]
+        crate::notify::event(event);
+
+        match self {
+            Self::Json => match serde_json::to_string(event) {
+                Ok(line) => println!("{line}"),
+                Err(e) => eprintln!("Failed to serialise {event:?}: {e}"),
+            },
+            Self::Human => match event {
+                Event::Connected { port, baud } => {
+                    println!("Connected to {port} at {baud} baud.");
+                }
+                Event::ConfigReloaded { path } => {
+                    println!("Reloaded config from {}", path.display());
+                }
+                Event::ProfileChanged { name } => println!("Switched to the {name} profile."),
+                Event::Paused => {
+                    println!("Paused forwarding, hold the emergency chord to resume.");
+                }
+                Event::Resumed => println!("Resuming forwarding."),
+                Event::Locked => println!("Pressed the lock chord on the target."),
+                Event::MouseKeys { enabled: true } => {
+                    println!("Mouse keys on - press Scroll Lock to go back to typing.");
+                }
+                Event::MouseKeys { enabled: false } => println!("Mouse keys off."),
+                Event::DeviceLost { device, reason } => {
+                    println!("Lost {device} ({reason}), waiting for it to come back.");
+                }
+                Event::DeviceFound { device } => println!("Found {device} again."),
+                Event::TargetLeds {
+                    num_lock,
+                    caps_lock,
+                    scroll_lock,
+                } => {
+                    let on_off = |on: &bool| if *on { "on" } else { "off" };
+                    println!(
+                        "The target has num lock {}, caps lock {} and scroll lock {}.",
+                        on_off(num_lock),
+                        on_off(caps_lock),
+                        on_off(scroll_lock)
+                    );
+                }
+                Event::TargetLayout { layout } => {
+                    println!("The target is using the {layout} keyboard layout.");
+                }
+                // what's on it is only for scripts asking for --output json
+                Event::TargetClipboard { text } => {
+                    println!("The target's clipboard changed ({} bytes).", text.len());
+                }
+                Event::Error { message } => eprintln!("{message}"),
+                Event::Overflow { dropped } => eprintln!(
+                    "The teensy couldn't keep up and dropped {dropped} reports, slowing down."
+                ),
+                Event::Stress {
+                    rate,
+                    achieved,
+                    probes_sent,
+                    probes_answered,
+                    worst_round_trip_ms,
+                    sustained,
+                } => {
+                    let verdict = if *sustained {
+                        "kept up"
+                    } else {
+                        "couldn't keep up"
+                    };
+                    println!(
+                        "At {rate} a second: sent {achieved:.1} a second, {probes_answered} of \
+                        {probes_sent} probes answered, the slowest in {worst_round_trip_ms:.1}ms, \
+                        {verdict}."
+                    );
+                }
+                Event::Soak(report) => println!("{report}"),
+                Event::Metrics(metrics) => println!("{metrics}"),
+                Event::Firmware {
+                    version,
+                    commit,
+                    dirty,
+                    protocol,
+                    features,
+                    serial_number,
+                } => {
+                    let commit = match commit {
+                        Some(commit) if *dirty => format!(", commit {commit} with changes"),
+                        Some(commit) => format!(", commit {commit}"),
+                        None => String::new(),
+                    };
+                    let features = match features.as_slice() {
+                        [] => "nothing optional".to_owned(),
+                        features => features.join(", "),
+                    };
+                    let serial_number = match serial_number {
+                        Some(serial_number) => format!(", serial number {serial_number},"),
+                        None => String::new(),
+                    };
+                    println!(
+                        "The teensy{serial_number} is running firmware {version}{commit}, \
+                        protocol version {protocol}, with {features}."
+                    );
+                }
+                Event::Setting { name, value } => match value {
+                    Some(value) => println!("{name}: {value}"),
+                    None => println!("{name}: not saved"),
+                },
+                // too noisy for a person, and color_eyre reports why we stopped
+                Event::Key { .. } | Event::Disconnected => {}
+            },
+        }
+    }
+
+    /// Anything which isn't an event, kept off stdout when that's reserved for JSON.
+    pub fn status(self, message: impl fmt::Display) {
+        match self {
+            Self::Human => println!("{message}"),
+            Self::Json => eprintln!("{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_events() {
+        let key = Event::Key {
+            key: Key::KEY_A,
+            pressed: true,
+        };
+        assert_eq!(
+            serde_json::to_string(&key).unwrap(),
+            r#"{"event":"key","key":"KEY_A","pressed":true}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Disconnected).unwrap(),
+            r#"{"event":"disconnected"}"#
+        );
+    }
+}
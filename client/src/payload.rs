@@ -0,0 +1,125 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Args;
+use color_eyre::eyre::{OptionExt as _, Result, WrapErr as _};
+use shared::payload::{self, REPORT_LEN};
+
+use crate::{
+    keyset::{KeySet, Layout},
+    oneshot::Command,
+    output::Output,
+};
+
+#[derive(Args, Debug)]
+pub struct CompilePayloadArgs {
+    /// the commands to play, one per line as for `stdin`: `type TEXT`, `press CHORD...` or
+    /// `sleep MS`
+    script: PathBuf,
+
+    /// where to write the payload, which goes in the root of the teensy's SD card
+    #[arg(long, short, default_value = payload::FILE_NAME)]
+    out: PathBuf,
+
+    /// the keyboard layout the target is set to, for typing text
+    #[arg(long, value_enum, default_value_t)]
+    layout: Layout,
+
+    /// how many milliseconds apart the teensy sends the target each report, up to 20, for
+    /// older machines and KVMs which miss keys at the usual one a millisecond
+    #[arg(
+        long,
+        default_value_t = shared::DEFAULT_REPORT_INTERVAL_MS,
+        value_parser = clap::value_parser!(u8).range(1..=i64::from(shared::MAX_REPORT_INTERVAL_MS))
+    )]
+    report_interval_ms: u8,
+}
+
+/// Turn a script into a payload for the firmware to play from its SD card by itself.
+pub fn compile_payload(args: CompilePayloadArgs, output: Output) -> Result<()> {
+    let script = std::fs::read_to_string(&args.script)
+        .wrap_err_with(|| format!("Couldn't read {}", args.script.display()))?;
+    let commands = Command::parse_script(&script)
+        .wrap_err_with(|| format!("Couldn't parse {}", args.script.display()))?;
+    let payload = compile(&commands, args.layout, args.report_interval_ms)?;
+    std::fs::write(&args.out, &payload)
+        .wrap_err_with(|| format!("Couldn't write {}", args.out.display()))?;
+
+    let reports = (payload.len() - payload::HEADER_LEN) / REPORT_LEN;
+    let playing = Duration::from_millis(reports as u64 * u64::from(args.report_interval_ms));
+    output.status(format!(
+        "Wrote {reports} reports to {}, which take {playing:.1?} to play",
+        args.out.display()
+    ));
+    Ok(())
+}
+
+/// The payload for `commands`, each report going `interval_ms` after the last. Every key is
+/// let go of before the next is pressed, and at the end.
+fn compile(commands: &[Command], layout: Layout, interval_ms: u8) -> Result<Vec<u8>> {
+    let mut reports = Vec::new();
+    for command in commands {
+        match command {
+            Command::Type(text) => {
+                for c in text.chars() {
+                    let strokes = layout
+                        .strokes_for_char(c)
+                        .ok_or_eyre(format!("{c:?} can't be typed on a {layout:?} layout"))?;
+                    for stroke in strokes {
+                        let mut pressed = KeySet::new();
+                        for key in stroke.keys() {
+                            pressed.press_key(key);
+                        }
+                        reports.extend([pressed, KeySet::new()]);
+                    }
+                }
+            }
+            Command::Press(chords) => {
+                for chord in chords {
+                    let mut pressed = KeySet::new();
+                    for &key in chord {
+                        pressed.press_key(key);
+                    }
+                    reports.extend([pressed, KeySet::new()]);
+                }
+            }
+            Command::Sleep(duration) => {
+                let count = duration.as_millis().div_ceil(u128::from(interval_ms));
+                reports.extend((0..count).map(|_| KeySet::new()));
+            }
+        }
+    }
+
+    let mut payload = payload::header(interval_ms).to_vec();
+    for report in reports {
+        payload.extend(payload::encode_report(report.into()));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let commands = Command::parse_script("type A\npress ctrl+c\nsleep 10").unwrap();
+        let payload = compile(&commands, Layout::Us, 4).unwrap();
+        let (header, reports) = payload.split_at(payload::HEADER_LEN);
+        assert_eq!(payload::parse_header(header.try_into().unwrap()), Some(4));
+
+        let reports: Vec<_> = reports
+            .chunks(REPORT_LEN)
+            .map(|report| payload::decode_report(report.try_into().unwrap()))
+            .map(|report| (report.modifier, report.keys[0]))
+            .collect();
+        let mut expected = vec![(0x02, 0x04), (0, 0), (0x01, 0x06), (0, 0)];
+        // 10ms of nothing, rounded up to 3 reports
+        expected.extend([(0, 0); 3]);
+        assert_eq!(reports, expected);
+
+        let commands = Command::parse_script("type é").unwrap();
+        let error = compile(&commands, Layout::Us, 1).unwrap_err();
+        assert_eq!(error.to_string(), "'é' can't be typed on a Us layout");
+        assert!(compile(&commands, Layout::UsIntl, 1).is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+/// LINKTYPE_USER0, the first of the link types set aside for private use, which Wireshark
+/// can be told to decode with a dissector of your own under DLT_User.
+const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Which way a frame went, as far as the client is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the teensy to the client.
+    Inbound,
+    /// From the client to the teensy.
+    Outbound,
+}
+
+/// Writes frames to a pcapng file as they go over the serial link, one packet per COBS
+/// frame with its trailing zero, timestamped to the microsecond.
+pub struct Capture<W: Write> {
+    out: W,
+}
+
+impl Capture<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> Capture<W> {
+    /// Start a capture with a section header and the one interface, the serial link.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        // version 1.0
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // section length, not known up front
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut out, SECTION_HEADER, &body)?;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // no limit on how much of each packet is kept
+        body.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut body, OPT_IF_NAME, b"teensy serial link");
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        write_block(&mut out, INTERFACE_DESCRIPTION, &body)?;
+        out.flush()?;
+
+        Ok(Self { out })
+    }
+
+    pub fn frame(&mut self, direction: Direction, frame: &[u8]) -> io::Result<()> {
+        self.frame_at(SystemTime::now(), direction, frame)
+    }
+
+    fn frame_at(&mut self, at: SystemTime, direction: Direction, frame: &[u8]) -> io::Result<()> {
+        let micros = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let len = u32::try_from(frame.len()).unwrap_or(u32::MAX);
+
+        let mut body = Vec::new();
+        // interface 0, the only one
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        // captured and original length, always all of it
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(frame);
+        pad(&mut body);
+        let flags: u32 = match direction {
+            Direction::Inbound => 1,
+            Direction::Outbound => 2,
+        };
+        push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_ENDOFOPT, &[]);
+        write_block(&mut self.out, ENHANCED_PACKET, &body)?;
+        // so a capture is still useful when the client is killed
+        self.out.flush()
+    }
+}
+
+/// A block is its type and length, then the body, then the length again.
+fn write_block(out: &mut impl Write, kind: u32, body: &[u8]) -> io::Result<()> {
+    let len = (body.len() + 12) as u32;
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&len.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+// everything in pcapng is padded out to four bytes
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut capture = Capture::new(Vec::new()).unwrap();
+        let at = SystemTime::UNIX_EPOCH + Duration::from_micros(0x1_0000_0002);
+        capture
+            .frame_at(at, Direction::Outbound, &[0x03, 0x01, 0x02, 0x00, 0xff])
+            .unwrap();
+        let out = capture.out;
+
+        // every block starts and ends with its length, and they follow on from each other
+        let mut blocks = Vec::new();
+        let mut at = 0;
+        while at < out.len() {
+            let len = u32_at(&out, at + 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(&out, at + len - 4) as usize, len);
+            blocks.push((u32_at(&out, at), &out[at + 8..at + len - 4]));
+            at += len;
+        }
+        assert_eq!(at, out.len());
+
+        let kinds: Vec<_> = blocks.iter().map(|&(kind, _)| kind).collect();
+        assert_eq!(
+            kinds,
+            [SECTION_HEADER, INTERFACE_DESCRIPTION, ENHANCED_PACKET]
+        );
+        assert_eq!(u32_at(blocks[0].1, 0), BYTE_ORDER_MAGIC);
+        assert_eq!(u32_at(blocks[1].1, 0), u32::from(LINKTYPE_USER0));
+
+        let packet = blocks[2].1;
+        // timestamp high then low, both lengths then the frame padded to eight bytes
+        assert_eq!(u32_at(packet, 4), 1);
+        assert_eq!(u32_at(packet, 8), 2);
+        assert_eq!(u32_at(packet, 12), 5);
+        assert_eq!(u32_at(packet, 16), 5);
+        assert_eq!(&packet[20..28], &[0x03, 0x01, 0x02, 0x00, 0xff, 0, 0, 0]);
+        // the flags option says it was outbound
+        assert_eq!(&packet[28..32], &[2, 0, 4, 0]);
+        assert_eq!(u32_at(packet, 32), 2);
+    }
+}
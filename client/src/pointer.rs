@@ -0,0 +1,618 @@
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Report, Result};
+use evdev::{
+    AbsoluteAxisType, Device, InputEvent, InputEventKind, Key, RelativeAxisType, Synchronization,
+};
+use serde::Deserialize;
+
+/// The bits of [`shared::AbsolutePointerState::buttons`] for each button.
+pub const LEFT: u8 = 0b001;
+pub const RIGHT: u8 = 0b010;
+pub const MIDDLE: u8 = 0b100;
+/// And the two extra buttons only the relative mouse has, see [`shared::MouseState`].
+const BACK: u8 = 0b1000;
+const FORWARD: u8 = 0b10000;
+
+/// How far a mouse moves up or down while scrolling for each click of the wheel.
+const SCROLL_STEP: f64 = 15.0;
+
+/// The size of the screen a relative mouse is moved around, in mouse units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for ScreenSize {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| eyre!("Expected a size like 1920x1080"))?;
+        let size = Self {
+            width: width.parse()?,
+            height: height.parse()?,
+        };
+        if size.width == 0 || size.height == 0 {
+            return Err(eyre!("The screen needs to be bigger than nothing"));
+        }
+        Ok(size)
+    }
+}
+
+impl Default for ScreenSize {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+        }
+    }
+}
+
+/// A side of the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    /// The side across the screen from this one.
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Top => Self::Bottom,
+            Self::Bottom => Self::Top,
+        }
+    }
+}
+
+/// How movements from a relative mouse are scaled, as the target's own settings can't
+/// be relied on for a pointer it thinks is a tablet.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Motion {
+    /// Multiplies every movement, 2.0 moves twice as far.
+    pub sensitivity: f64,
+    /// How much more quick movements are scaled up than slow ones, 0.0 scales them all
+    /// the same and 0.05 is gentle.
+    pub acceleration: f64,
+    /// While this key or button is held, moving the mouse up and down scrolls instead,
+    /// like middle button scrolling on a trackpoint. It isn't forwarded itself.
+    pub scroll_key: Option<Key>,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Self {
+            sensitivity: 1.0,
+            acceleration: 0.0,
+            scroll_key: None,
+        }
+    }
+}
+
+impl Motion {
+    /// How far to go for a movement of (dx, dy) in one report from the mouse.
+    fn scale(&self, dx: i32, dy: i32) -> (f64, f64) {
+        let (dx, dy) = (f64::from(dx), f64::from(dy));
+        let gain = self.sensitivity * (1.0 + self.acceleration * dx.hypot(dy));
+        (dx * gain, dy * gain)
+    }
+}
+
+/// Turns evdev events from a tablet (or a mouse moving over a pretend screen) into
+/// the absolute positions we send to the firmware.
+pub struct Pointer {
+    state: shared::AbsolutePointerState,
+    // the (min, max) range of each absolute axis, indexed by the axis code
+    ranges: Vec<(i32, i32)>,
+    screen: ScreenSize,
+    // where a relative mouse has got to on the pretend screen
+    position: (i32, i32),
+    // which way the last movement on each axis tried to go past the edge, if it did
+    pushed: (i32, i32),
+    motion: Motion,
+    // relative movement in the report the mouse is still sending
+    moved: (i32, i32),
+    // the fractions of a unit left over from scaling, so slow movements aren't lost
+    remainder: (f64, f64),
+    // whether the scroll key is held, and how far towards the next click we've gone
+    scrolling: bool,
+    scrolled: f64,
+    changed: bool,
+}
+
+impl Pointer {
+    /// A pointer only moved by relative movements across the pretend screen.
+    pub fn new(screen: ScreenSize) -> Self {
+        Self {
+            state: shared::AbsolutePointerState::default(),
+            ranges: Vec::new(),
+            screen,
+            position: (0, 0),
+            pushed: (0, 0),
+            motion: Motion::default(),
+            moved: (0, 0),
+            remainder: (0.0, 0.0),
+            scrolling: false,
+            scrolled: 0.0,
+            changed: false,
+        }
+    }
+
+    /// Scale relative movements from the device with `motion` from now on.
+    pub fn set_motion(&mut self, motion: Motion) {
+        self.motion = motion;
+        self.remainder = (0.0, 0.0);
+        self.set_scrolling(false);
+    }
+
+    pub fn is_scroll_key(&self, key: Key) -> bool {
+        self.motion.scroll_key == Some(key)
+    }
+
+    /// Turn vertical movement into scrolling, while the scroll key is held.
+    pub fn set_scrolling(&mut self, scrolling: bool) {
+        self.scrolling = scrolling;
+        self.scrolled = 0.0;
+    }
+
+    /// A pointer following `device`, whose absolute axes cover the whole screen.
+    pub fn for_device(device: &Device, screen: ScreenSize) -> Result<Self> {
+        let ranges = device
+            .get_abs_state()?
+            .iter()
+            .map(|info| (info.minimum, info.maximum))
+            .collect();
+
+        Ok(Self {
+            ranges,
+            ..Self::new(screen)
+        })
+    }
+
+    /// Update the state from an event, returning the new state once the device has
+    /// finished sending a batch of changes.
+    pub fn handle_event(&mut self, event: InputEvent) -> Option<shared::AbsolutePointerState> {
+        match event.kind() {
+            InputEventKind::Key(key) if self.is_scroll_key(key) => {
+                self.set_scrolling(event.value() != 0);
+            }
+            InputEventKind::Key(key) => {
+                let mask = match key {
+                    Key::BTN_LEFT | Key::BTN_TOUCH => LEFT,
+                    Key::BTN_RIGHT | Key::BTN_STYLUS => RIGHT,
+                    Key::BTN_MIDDLE | Key::BTN_STYLUS2 => MIDDLE,
+                    _ => return None,
+                };
+                self.set_button(mask, event.value() != 0);
+            }
+            InputEventKind::AbsAxis(axis @ AbsoluteAxisType::ABS_X) => {
+                let x = self.scale_absolute(axis, event.value());
+                self.set_position(x, self.state.y);
+            }
+            InputEventKind::AbsAxis(axis @ AbsoluteAxisType::ABS_Y) => {
+                let y = self.scale_absolute(axis, event.value());
+                self.set_position(self.state.x, y);
+            }
+            InputEventKind::RelAxis(RelativeAxisType::REL_X) => {
+                self.moved.0 = self.moved.0.saturating_add(event.value());
+            }
+            InputEventKind::RelAxis(RelativeAxisType::REL_Y) => {
+                self.moved.1 = self.moved.1.saturating_add(event.value());
+            }
+            InputEventKind::RelAxis(RelativeAxisType::REL_WHEEL) => self.scroll(event.value()),
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                self.apply_motion();
+                return self.take_update();
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Press or release the buttons in `mask`.
+    pub fn set_button(&mut self, mask: u8, pressed: bool) {
+        let buttons = if pressed {
+            self.state.buttons | mask
+        } else {
+            self.state.buttons & !mask
+        };
+        self.changed |= buttons != self.state.buttons;
+        self.state.buttons = buttons;
+    }
+
+    /// Move across the pretend screen, stopping at its edges.
+    pub fn move_by(&mut self, dx: i32, dy: i32) {
+        let (width, height) = (max(self.screen.width), max(self.screen.height));
+        if dx != 0 {
+            let x = self.position.0.saturating_add(dx);
+            self.position.0 = x.clamp(0, width);
+            self.pushed.0 = (x - self.position.0).signum();
+        }
+        if dy != 0 {
+            let y = self.position.1.saturating_add(dy);
+            self.position.1 = y.clamp(0, height);
+            self.pushed.1 = (y - self.position.1).signum();
+        }
+        let x = scale(self.position.0, 0, width);
+        let y = scale(self.position.1, 0, height);
+        self.set_position(x, y);
+    }
+
+    /// Move to a spot on the pretend screen, stopping at its edges.
+    pub fn move_to(&mut self, x: i32, y: i32) {
+        let (width, height) = (max(self.screen.width), max(self.screen.height));
+        self.position = (x.clamp(0, width), y.clamp(0, height));
+        self.move_by(0, 0);
+    }
+
+    /// Has the last movement tried to carry on off `edge` of the pretend screen?
+    pub fn pushed_past(&self, edge: Edge) -> bool {
+        match edge {
+            Edge::Left => self.pushed.0 < 0,
+            Edge::Right => self.pushed.0 > 0,
+            Edge::Top => self.pushed.1 < 0,
+            Edge::Bottom => self.pushed.1 > 0,
+        }
+    }
+
+    /// Jump to `edge` of the pretend screen, `along` of the way along it from the top or
+    /// left.
+    pub fn enter_at(&mut self, edge: Edge, along: f64) {
+        let (width, height) = (max(self.screen.width), max(self.screen.height));
+        let along = along.clamp(0.0, 1.0);
+        let (x, y) = match edge {
+            Edge::Left => (0, (f64::from(height) * along) as i32),
+            Edge::Right => (width, (f64::from(height) * along) as i32),
+            Edge::Top => ((f64::from(width) * along) as i32, 0),
+            Edge::Bottom => ((f64::from(width) * along) as i32, height),
+        };
+        self.pushed = (0, 0);
+        self.move_to(x, y);
+    }
+
+    /// How far along `edge` of the pretend screen the pointer is, from the top or left, the
+    /// other way round to [`Self::enter_at`].
+    pub fn along(&self, edge: Edge) -> f64 {
+        let (width, height) = (max(self.screen.width), max(self.screen.height));
+        match edge {
+            Edge::Left | Edge::Right => f64::from(self.position.1) / f64::from(height.max(1)),
+            Edge::Top | Edge::Bottom => f64::from(self.position.0) / f64::from(width.max(1)),
+        }
+    }
+
+    /// Scroll by some clicks of the wheel, positive is away from the user.
+    pub fn scroll(&mut self, clicks: i32) {
+        let wheel = i32::from(self.state.wheel)
+            .saturating_add(clicks)
+            .clamp(-127, 127);
+        self.changed |= wheel != i32::from(self.state.wheel);
+        self.state.wheel = wheel as i8;
+    }
+
+    /// The state to send if anything has changed since we last sent one.
+    pub fn take_update(&mut self) -> Option<shared::AbsolutePointerState> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        let state = self.state;
+        // the wheel is relative, once it's sent that scroll has happened
+        self.state.wheel = 0;
+        Some(state)
+    }
+
+    /// Where the pointer is and which buttons are held, without any scrolling still to go.
+    pub fn state(&self) -> shared::AbsolutePointerState {
+        shared::AbsolutePointerState {
+            wheel: 0,
+            ..self.state
+        }
+    }
+
+    /// Let go of all the buttons but leave the pointer where it is.
+    pub fn release_buttons(&mut self) -> shared::AbsolutePointerState {
+        self.state.buttons = 0;
+        self.state.wheel = 0;
+        self.state
+    }
+
+    // acceleration goes by the whole of each report, not each axis on its own
+    fn apply_motion(&mut self) {
+        let (dx, dy) = std::mem::take(&mut self.moved);
+        if (dx, dy) == (0, 0) {
+            return;
+        }
+        if self.scrolling {
+            // moving up scrolls up, which is away from the user
+            self.scrolled -= f64::from(dy) * self.motion.sensitivity;
+            let clicks = (self.scrolled / SCROLL_STEP).trunc();
+            self.scrolled -= clicks * SCROLL_STEP;
+            self.scroll(clicks as i32);
+            return;
+        }
+        let (dx, dy) = self.motion.scale(dx, dy);
+        let (dx, dy) = (dx + self.remainder.0, dy + self.remainder.1);
+        self.remainder = (dx.fract(), dy.fract());
+        self.move_by(dx.trunc() as i32, dy.trunc() as i32);
+    }
+
+    fn set_position(&mut self, x: u16, y: u16) {
+        self.changed |= (x, y) != (self.state.x, self.state.y);
+        self.state.x = x;
+        self.state.y = y;
+    }
+
+    fn scale_absolute(&self, axis: AbsoluteAxisType, value: i32) -> u16 {
+        match self.ranges.get(axis.0 as usize) {
+            Some(&(min, max)) => scale(value, min, max),
+            None => 0,
+        }
+    }
+}
+
+/// Turns evdev events from a mouse into relative movements for the firmware's mouse, so the
+/// target's own pointer speed and acceleration apply and there's no screen size to know.
+#[derive(Debug, Default)]
+pub struct RelativeMouse {
+    state: shared::MouseState,
+    changed: bool,
+}
+
+impl RelativeMouse {
+    /// Update the state from an event, returning the movement since the last one once the
+    /// device has finished sending a batch of changes.
+    pub fn handle_event(&mut self, event: InputEvent) -> Option<shared::MouseState> {
+        match event.kind() {
+            InputEventKind::Key(key) => {
+                let mask = match key {
+                    Key::BTN_LEFT => LEFT,
+                    Key::BTN_RIGHT => RIGHT,
+                    Key::BTN_MIDDLE => MIDDLE,
+                    Key::BTN_SIDE | Key::BTN_BACK => BACK,
+                    Key::BTN_EXTRA | Key::BTN_FORWARD => FORWARD,
+                    _ => return None,
+                };
+                let buttons = if event.value() != 0 {
+                    self.state.buttons | mask
+                } else {
+                    self.state.buttons & !mask
+                };
+                self.changed |= buttons != self.state.buttons;
+                self.state.buttons = buttons;
+            }
+            InputEventKind::RelAxis(axis) => {
+                let moved = match axis {
+                    RelativeAxisType::REL_X => &mut self.state.x,
+                    RelativeAxisType::REL_Y => &mut self.state.y,
+                    RelativeAxisType::REL_WHEEL => {
+                        let clicks = event.value().clamp(-127, 127) as i8;
+                        self.state.wheel = self.state.wheel.saturating_add(clicks);
+                        self.changed |= clicks != 0;
+                        return None;
+                    }
+                    _ => return None,
+                };
+                let value = event.value().clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+                *moved = moved.saturating_add(value);
+                self.changed |= value != 0;
+            }
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) if self.changed => {
+                self.changed = false;
+                let state = self.state;
+                // the firmware adds each movement on, so once it's sent it's been made
+                self.state = shared::MouseState {
+                    buttons: state.buttons,
+                    ..Default::default()
+                };
+                return Some(state);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Which buttons are held, without any movement.
+    pub fn state(&self) -> shared::MouseState {
+        shared::MouseState {
+            buttons: self.state.buttons,
+            ..Default::default()
+        }
+    }
+
+    /// Let go of all the buttons and forget any movement not yet sent.
+    pub fn release_buttons(&mut self) -> shared::MouseState {
+        *self = Self::default();
+        self.state
+    }
+}
+
+// the largest position on one axis of the pretend screen
+fn max(pixels: u32) -> i32 {
+    pixels.saturating_sub(1).try_into().unwrap_or(i32::MAX)
+}
+
+// map min..=max onto the firmware's 0..=ABSOLUTE_POINTER_MAX
+fn scale(value: i32, min: i32, max: i32) -> u16 {
+    if max <= min {
+        return 0;
+    }
+
+    let value = i64::from(value.clamp(min, max));
+    let (min, max) = (i64::from(min), i64::from(max));
+    ((value - min) * i64::from(shared::ABSOLUTE_POINTER_MAX) / (max - min)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_screen_size() {
+        let size: ScreenSize = "1920x1080".parse().unwrap();
+        assert_eq!(
+            size,
+            ScreenSize {
+                width: 1920,
+                height: 1080
+            }
+        );
+        assert!("1920".parse::<ScreenSize>().is_err());
+        assert!("0x1080".parse::<ScreenSize>().is_err());
+    }
+
+    #[test]
+    fn test_relative_motion_is_clamped_to_the_screen() {
+        let mut pointer = Pointer::new(ScreenSize {
+            width: 101,
+            height: 11,
+        });
+        let rel_x = |value| InputEvent::new(evdev::EventType::RELATIVE, 0, value);
+        let rel_y = |value| InputEvent::new(evdev::EventType::RELATIVE, 1, value);
+        let sync = InputEvent::new(evdev::EventType::SYNCHRONIZATION, 0, 0);
+
+        pointer.handle_event(rel_x(50));
+        pointer.handle_event(rel_y(1000));
+        let state = pointer.handle_event(sync).unwrap();
+        assert_eq!(state.x, shared::ABSOLUTE_POINTER_MAX / 2);
+        assert_eq!(state.y, shared::ABSOLUTE_POINTER_MAX);
+
+        pointer.handle_event(rel_x(-1000));
+        let state = pointer.handle_event(sync).unwrap();
+        assert_eq!(state.x, 0);
+    }
+
+    #[test]
+    fn test_motion_scaling() {
+        let mut pointer = Pointer::new(ScreenSize {
+            width: 1001,
+            height: 1001,
+        });
+        let rel_x = |value| InputEvent::new(evdev::EventType::RELATIVE, 0, value);
+        let sync = InputEvent::new(evdev::EventType::SYNCHRONIZATION, 0, 0);
+
+        pointer.set_motion(Motion {
+            sensitivity: 0.5,
+            ..Motion::default()
+        });
+        // half a unit isn't enough to move, but two halves are
+        pointer.handle_event(rel_x(1));
+        assert_eq!(pointer.handle_event(sync), None);
+        pointer.handle_event(rel_x(1));
+        assert_eq!(pointer.handle_event(sync).unwrap().x, scale(1, 0, 1000));
+
+        pointer.set_motion(Motion {
+            sensitivity: 1.0,
+            acceleration: 0.1,
+            ..Motion::default()
+        });
+        pointer.move_to(0, 0);
+        pointer.handle_event(rel_x(10));
+        // 10 at twice the speed
+        assert_eq!(pointer.handle_event(sync).unwrap().x, scale(20, 0, 1000));
+    }
+
+    #[test]
+    fn test_scroll_key() {
+        let mut pointer = Pointer::new(ScreenSize::default());
+        pointer.set_motion(Motion {
+            scroll_key: Some(Key::BTN_MIDDLE),
+            ..Motion::default()
+        });
+        let rel_y = |value| InputEvent::new(evdev::EventType::RELATIVE, 1, value);
+        let middle = |value| InputEvent::new(evdev::EventType::KEY, Key::BTN_MIDDLE.code(), value);
+        let sync = InputEvent::new(evdev::EventType::SYNCHRONIZATION, 0, 0);
+
+        pointer.handle_event(middle(1));
+        pointer.handle_event(rel_y(-20));
+        let state = pointer.handle_event(sync).unwrap();
+        // the button isn't pressed and the pointer doesn't move
+        assert_eq!((state.buttons, state.x, state.y, state.wheel), (0, 0, 0, 1));
+        // the 5 left over carries on to the next click
+        pointer.handle_event(rel_y(-10));
+        assert_eq!(pointer.handle_event(sync).unwrap().wheel, 1);
+
+        // back to moving, already at the top so going up does nothing
+        pointer.handle_event(middle(0));
+        pointer.handle_event(rel_y(-20));
+        assert_eq!(pointer.handle_event(sync), None);
+        pointer.handle_event(rel_y(20));
+        assert_ne!(pointer.handle_event(sync).unwrap().y, 0);
+    }
+
+    #[test]
+    fn test_pushing_past_the_edge() {
+        let mut pointer = Pointer::new(ScreenSize {
+            width: 101,
+            height: 101,
+        });
+        pointer.enter_at(Edge::Left, 0.5);
+        let state = pointer.take_update().unwrap();
+        assert_eq!((state.x, state.y), (0, shared::ABSOLUTE_POINTER_MAX / 2));
+        assert!(!pointer.pushed_past(Edge::Left));
+
+        pointer.move_by(-1, 0);
+        assert!(pointer.pushed_past(Edge::Left));
+        // moving up and down along the edge doesn't count as coming away from it
+        pointer.move_by(0, 5);
+        assert!(pointer.pushed_past(Edge::Left));
+        pointer.move_by(1, 0);
+        assert!(!pointer.pushed_past(Edge::Left));
+
+        pointer.move_by(1000, 0);
+        assert!(pointer.pushed_past(Edge::Right));
+        assert!(!pointer.pushed_past(Edge::Top));
+        assert_eq!(pointer.along(Edge::Right), 0.55);
+        assert_eq!(pointer.along(Edge::Bottom), 1.0);
+    }
+
+    #[test]
+    fn test_relative_mouse() {
+        let mut mouse = RelativeMouse::default();
+        let rel = |axis, value| InputEvent::new(evdev::EventType::RELATIVE, axis, value);
+        let side = |value| InputEvent::new(evdev::EventType::KEY, Key::BTN_SIDE.code(), value);
+        let sync = InputEvent::new(evdev::EventType::SYNCHRONIZATION, 0, 0);
+
+        mouse.handle_event(rel(0, 3));
+        mouse.handle_event(rel(1, -100_000));
+        mouse.handle_event(rel(8, 1));
+        mouse.handle_event(side(1));
+        let state = mouse.handle_event(sync).unwrap();
+        assert_eq!(
+            (state.buttons, state.x, state.y, state.wheel),
+            (BACK, 3, i16::MIN, 1)
+        );
+
+        // movement isn't sent twice, but the button stays held
+        assert_eq!(mouse.handle_event(sync), None);
+        mouse.handle_event(rel(0, -1));
+        let state = mouse.handle_event(sync).unwrap();
+        assert_eq!(
+            (state.buttons, state.x, state.y, state.wheel),
+            (BACK, -1, 0, 0)
+        );
+        assert_eq!(mouse.release_buttons(), shared::MouseState::default());
+    }
+
+    #[test]
+    fn test_wheel_is_only_sent_once() {
+        let mut pointer = Pointer::new(ScreenSize::default());
+        pointer.scroll(2);
+        assert_eq!(pointer.take_update().unwrap().wheel, 2);
+        assert_eq!(pointer.take_update(), None);
+
+        pointer.set_button(LEFT, true);
+        let state = pointer.take_update().unwrap();
+        assert_eq!((state.buttons, state.wheel), (LEFT, 0));
+    }
+}
@@ -0,0 +1,267 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use color_eyre::eyre::{Result, WrapErr as _};
+use evdev::Key;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    bridge::{Control, Status},
+    control, keyset,
+    metrics::Metrics,
+};
+
+/// How long to wait for the bridge to pause or resume before giving up on it.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Reply<T> = Result<T, (StatusCode, String)>;
+
+struct Api {
+    secret: String,
+    target: String,
+    // only with --metrics
+    metrics: Option<Arc<Metrics>>,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct StatusReply {
+    paused: bool,
+    held_keys: Vec<Key>,
+    target: String,
+    profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TypeRequest {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct KeyRequest {
+    /// e.g. `ctrl+alt+del`, as for `client press`
+    chord: String,
+}
+
+#[derive(Deserialize)]
+struct MacroRequest {
+    name: String,
+}
+
+/// Serve a JSON API over HTTP until `token` is cancelled, for driving the bridge from
+/// anything that can make a request. Every request needs `Authorization: Bearer <secret>`,
+/// even on localhost, as any page open in a browser there can make them.
+pub async fn serve(
+    addr: SocketAddr,
+    secret: String,
+    target: String,
+    metrics: Option<Arc<Metrics>>,
+    status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+    token: CancellationToken,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("Failed to listen on {addr}"))?;
+
+    let api = Arc::new(Api {
+        secret,
+        target,
+        metrics,
+        status,
+        control,
+    });
+    let app = Router::new()
+        .route("/status", get(status_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/type", post(type_text))
+        .route("/key", post(press_key))
+        .route("/macro", post(run_macro))
+        .layer(middleware::from_fn_with_state(api.clone(), check_secret))
+        .with_state(api);
+    let server = axum::serve(listener, app).with_graceful_shutdown(token.cancelled_owned());
+    tokio::spawn(async move {
+        // nothing to be done about it going wrong, the bridge carries on without it
+        let _ = server.await;
+    });
+
+    Ok(())
+}
+
+async fn check_secret(State(api): State<Arc<Api>>, request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if !control::same_secret(given, &api.secret) {
+        return (StatusCode::UNAUTHORIZED, "wrong token").into_response();
+    }
+    next.run(request).await
+}
+
+impl Api {
+    fn status(&self) -> StatusReply {
+        let status = self.status.borrow();
+        StatusReply {
+            paused: status.paused,
+            held_keys: status.held_keys.clone(),
+            target: self.target.clone(),
+            profile: status.profile.clone(),
+        }
+    }
+
+    fn send(&self, control: Control) -> Reply<()> {
+        self.control
+            .send(control)
+            .map_err(|_| error(StatusCode::SERVICE_UNAVAILABLE, "the bridge is stopping"))
+    }
+
+    fn check_forwarding(&self) -> Reply<()> {
+        if self.status.borrow().paused {
+            return Err(error(StatusCode::CONFLICT, "forwarding is paused"));
+        }
+        Ok(())
+    }
+
+    async fn set_paused(&self, paused: bool) -> Reply<Json<StatusReply>> {
+        self.send(Control::SetPaused(paused))?;
+        let mut status = self.status.clone();
+        tokio::time::timeout(SETTLE_TIMEOUT, status.wait_for(|s| s.paused == paused))
+            .await
+            .map_err(|_| {
+                error(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "the bridge didn't answer in time",
+                )
+            })?
+            .map_err(|_| error(StatusCode::SERVICE_UNAVAILABLE, "the bridge is stopping"))?;
+        Ok(Json(self.status()))
+    }
+}
+
+fn error(code: StatusCode, message: &str) -> (StatusCode, String) {
+    (code, message.to_owned())
+}
+
+async fn status_handler(State(api): State<Arc<Api>>) -> Json<StatusReply> {
+    Json(api.status())
+}
+
+/// In Prometheus' text format.
+async fn metrics_handler(State(api): State<Arc<Api>>) -> Reply<String> {
+    match &api.metrics {
+        Some(metrics) => Ok(metrics.prometheus()),
+        None => Err(error(
+            StatusCode::NOT_FOUND,
+            "start the bridge with --metrics",
+        )),
+    }
+}
+
+async fn pause(State(api): State<Arc<Api>>) -> Reply<Json<StatusReply>> {
+    api.set_paused(true).await
+}
+
+async fn resume(State(api): State<Arc<Api>>) -> Reply<Json<StatusReply>> {
+    api.set_paused(false).await
+}
+
+async fn type_text(State(api): State<Arc<Api>>, Json(request): Json<TypeRequest>) -> Reply<()> {
+    api.check_forwarding()?;
+    api.send(Control::Type(request.text))
+}
+
+async fn press_key(State(api): State<Arc<Api>>, Json(request): Json<KeyRequest>) -> Reply<()> {
+    let chord = keyset::parse_chord(&request.chord).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    api.check_forwarding()?;
+    api.send(Control::Press(chord))
+}
+
+async fn run_macro(State(api): State<Arc<Api>>, Json(request): Json<MacroRequest>) -> Reply<()> {
+    api.check_forwarding()?;
+    api.send(Control::RunMacro(request.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api() -> (
+        Arc<Api>,
+        watch::Sender<Status>,
+        mpsc::UnboundedReceiver<Control>,
+    ) {
+        let (status_tx, status) = watch::channel(Status::default());
+        let (control, control_rx) = mpsc::unbounded_channel();
+        let api = Api {
+            secret: "secret".to_owned(),
+            target: "/dev/ttyUSB0".to_owned(),
+            metrics: None,
+            status,
+            control,
+        };
+        (Arc::new(api), status_tx, control_rx)
+    }
+
+    #[tokio::test]
+    async fn test_pause_waits_for_the_bridge() {
+        let (api, status_tx, mut control_rx) = api();
+        // stand in for the bridge doing what it's told
+        tokio::spawn(async move {
+            while let Some(control) = control_rx.recv().await {
+                if let Control::SetPaused(paused) = control {
+                    status_tx.send_modify(|status| status.paused = paused);
+                }
+            }
+        });
+
+        let Json(status) = pause(State(api.clone())).await.unwrap();
+        assert!(status.paused);
+        assert_eq!(status.target, "/dev/ttyUSB0");
+        let request = TypeRequest {
+            text: "hello".to_owned(),
+        };
+        let (code, _) = type_text(State(api), Json(request)).await.unwrap_err();
+        assert_eq!(code, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_press_key() {
+        let (api, _status_tx, mut control_rx) = api();
+        let request = KeyRequest {
+            chord: "ctrl+alt+del".to_owned(),
+        };
+        press_key(State(api.clone()), Json(request)).await.unwrap();
+        assert_eq!(
+            control_rx.recv().await,
+            Some(Control::Press(vec![
+                Key::KEY_LEFTCTRL,
+                Key::KEY_LEFTALT,
+                Key::KEY_DELETE
+            ]))
+        );
+
+        let request = KeyRequest {
+            chord: "ctrl+frobnicate".to_owned(),
+        };
+        let (code, _) = press_key(State(api), Json(request)).await.unwrap_err();
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+    }
+}
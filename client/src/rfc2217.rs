@@ -0,0 +1,330 @@
+use std::{collections::HashSet, time::Duration};
+
+use color_eyre::eyre::{bail, eyre, Result, WrapErr as _};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+};
+use zeroize::Zeroizing;
+
+/// How long the server has to agree to RFC2217 and set the baud rate.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+// telnet commands, RFC 854
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// telnet options, RFC 856, RFC 858 and RFC 2217
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// COM-PORT-OPTION subnegotiations, the server answers each with the same plus 100
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const SERVER_OFFSET: u8 = 100;
+
+/// A serial port on a network serial server like ser2net or a terminal server, spoken to
+/// over telnet with the RFC2217 COM-PORT-OPTION so the baud rate and flow control are set
+/// on the server's port.
+pub struct Rfc2217 {
+    stream: TcpStream,
+    url: String,
+    telnet: Telnet,
+    // bytes from the serial port which haven't been read yet
+    data: Vec<u8>,
+}
+
+impl Rfc2217 {
+    /// Connect to `addr`, a host and port, and set the server's serial port to `baud`,
+    /// eight bits, no parity and one stop bit.
+    pub async fn connect(addr: &str, baud: u32, flow_control: bool) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .wrap_err_with(|| format!("Failed to connect to {addr}"))?;
+        // frames are small and want to go straight away, not wait for more to join them
+        stream.set_nodelay(true)?;
+        let mut port = Self {
+            stream,
+            url: format!("rfc2217://{addr}"),
+            telnet: Telnet::default(),
+            data: Vec::new(),
+        };
+
+        for option in [BINARY, SUPPRESS_GO_AHEAD] {
+            port.telnet.negotiate(WILL, option);
+            port.telnet.negotiate(DO, option);
+        }
+        port.telnet.negotiate(WILL, COM_PORT_OPTION);
+        port.write_all(&[]).await?;
+        port.negotiated(|telnet| telnet.com_port.is_some())
+            .await
+            .wrap_err("The server didn't answer the telnet negotiation")?;
+        if port.telnet.com_port == Some(false) {
+            bail!("{addr} doesn't support RFC2217, is it a raw TCP port?");
+        }
+
+        let control = if flow_control { 3 } else { 1 };
+        let settings = [
+            (SET_DATASIZE, 8),
+            (SET_PARITY, 1),
+            (SET_STOPSIZE, 1),
+            (SET_CONTROL, control),
+        ];
+        for (command, value) in settings {
+            port.telnet
+                .replies
+                .extend(subnegotiation(command, &[value]));
+        }
+        port.set_baud(baud).await?;
+        Ok(port)
+    }
+
+    /// Set the server's port to `baud`, waiting for it to say it has.
+    pub async fn set_baud(&mut self, baud: u32) -> Result<()> {
+        self.telnet.baud = None;
+        let set_baud = subnegotiation(SET_BAUDRATE, &baud.to_be_bytes());
+        self.telnet.replies.extend(set_baud);
+        self.write_all(&[]).await?;
+        self.negotiated(|telnet| telnet.baud.is_some())
+            .await
+            .wrap_err("The server didn't answer setting the baud rate")?;
+        match self.telnet.baud {
+            Some(set) if set != baud => {
+                bail!("{} set its port to {set} baud, not {baud}", self.url)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read until `done` says the server has answered, keeping any data which turns up.
+    async fn negotiated(&mut self, done: impl Fn(&Telnet) -> bool) -> Result<()> {
+        tokio::time::timeout(NEGOTIATION_TIMEOUT, async {
+            while !done(&self.telnet) {
+                self.receive().await?;
+                // answer whatever the server asked of us in the meantime
+                self.write_all(&[]).await?;
+            }
+            Ok::<_, color_eyre::Report>(())
+        })
+        .await
+        .map_err(|_| eyre!("Timed out after {NEGOTIATION_TIMEOUT:?}"))?
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Write `bytes` to the server's serial port, along with any answers to the server's
+    /// negotiations.
+    pub async fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        // frames can be the keys of a password, so this is wiped like the frames themselves
+        let mut out = Zeroizing::new(std::mem::take(&mut self.telnet.replies));
+        escape(bytes, &mut out);
+        if out.is_empty() {
+            return Ok(());
+        }
+        self.stream.write_all(&out).await
+    }
+
+    /// Read what's come from the server's serial port into `buf`, or 0 if it hung up.
+    /// This is cancel safe, nothing is lost if it's dropped while waiting.
+    pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.data.is_empty() {
+            if !self.receive().await? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data.drain(..len);
+        Ok(len)
+    }
+
+    // false when the server hangs up
+    async fn receive(&mut self) -> std::io::Result<bool> {
+        let mut chunk = [0; 64];
+        let read = self.stream.read(&mut chunk).await?;
+        self.telnet.decode(&chunk[..read], &mut self.data);
+        Ok(read > 0)
+    }
+}
+
+/// Where we are in the telnet stream from the server.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    // the option following a WILL, WONT, DO or DONT
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+#[derive(Debug, Default)]
+struct Telnet {
+    state: State,
+    // negotiations we've sent, so the same one never goes twice and we can't end up in a
+    // loop with the server
+    sent: HashSet<[u8; 2]>,
+    // what's to go to the server before the next data
+    replies: Vec<u8>,
+    // the subnegotiation being read
+    sub: Vec<u8>,
+    // whether the server agreed to COM-PORT-OPTION, once it's said
+    com_port: Option<bool>,
+    // the baud rate the server says its port is at, once it's said
+    baud: Option<u32>,
+}
+
+impl Telnet {
+    /// Take the telnet commands out of `input`, leaving what came from the serial port in
+    /// `data`.
+    fn decode(&mut self, input: &[u8], data: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(byte);
+                    State::Data
+                }
+                // a doubled IAC is a 0xff in the data
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Negotiate(byte),
+                (State::Iac, SB) => {
+                    self.sub.clear();
+                    State::Sub
+                }
+                // nothing else means anything to a serial port
+                (State::Iac, _) => State::Data,
+                (State::Negotiate(command), option) => {
+                    self.answer(command, option);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => {
+                    self.sub.push(byte);
+                    State::Sub
+                }
+                (State::SubIac, IAC) => {
+                    self.sub.push(IAC);
+                    State::Sub
+                }
+                (State::SubIac, _) => {
+                    self.subnegotiated();
+                    State::Data
+                }
+            }
+        }
+    }
+
+    /// Go along with the options we want and turn down everything else.
+    fn answer(&mut self, command: u8, option: u8) {
+        match (command, option) {
+            (DO, COM_PORT_OPTION) => self.com_port = Some(true),
+            (DONT, COM_PORT_OPTION) => self.com_port = Some(false),
+            _ => {}
+        }
+        let reply = match (command, option) {
+            (DO, BINARY | SUPPRESS_GO_AHEAD | COM_PORT_OPTION) => WILL,
+            (WILL, BINARY | SUPPRESS_GO_AHEAD) => DO,
+            (DO | DONT, _) => WONT,
+            _ => DONT,
+        };
+        self.negotiate(reply, option);
+    }
+
+    fn negotiate(&mut self, command: u8, option: u8) {
+        if self.sent.insert([command, option]) {
+            self.replies.extend([IAC, command, option]);
+        }
+    }
+
+    fn subnegotiated(&mut self) {
+        if let [COM_PORT_OPTION, command, ref value @ ..] = self.sub[..] {
+            if command == SET_BAUDRATE + SERVER_OFFSET {
+                if let Ok(&baud) = value.try_into() {
+                    self.baud = Some(u32::from_be_bytes(baud));
+                }
+            }
+        }
+    }
+}
+
+/// Double every IAC in `bytes` so the server doesn't take it for a command.
+fn escape(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        out.push(byte);
+        if byte == IAC {
+            out.push(IAC);
+        }
+    }
+}
+
+fn subnegotiation(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![IAC, SB, COM_PORT_OPTION, command];
+    escape(value, &mut out);
+    out.extend([IAC, SE]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut out = Vec::new();
+        escape(&[0x01, 0xff, 0x00], &mut out);
+        assert_eq!(out, [0x01, 0xff, 0xff, 0x00]);
+
+        let baud = subnegotiation(SET_BAUDRATE, &115_200u32.to_be_bytes());
+        assert_eq!(baud, [IAC, SB, 44, 1, 0x00, 0x01, 0xc2, 0x00, IAC, SE]);
+        // a baud rate with an 0xff in it is escaped too
+        let odd = subnegotiation(SET_BAUDRATE, &0xffu32.to_be_bytes());
+        assert_eq!(odd, [IAC, SB, 44, 1, 0, 0, 0, IAC, IAC, IAC, SE]);
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut telnet = Telnet::default();
+        let mut data = Vec::new();
+        telnet.negotiate(WILL, COM_PORT_OPTION);
+        telnet.replies.clear();
+
+        // the server agrees, asks for something we don't do and sends an escaped 0xff
+        telnet.decode(&[IAC, DO, 44, IAC, DO, 24, 0x03, IAC, IAC, 0x00], &mut data);
+        assert_eq!(data, [0x03, 0xff, 0x00]);
+        assert_eq!(telnet.com_port, Some(true));
+        // agreeing to what we asked for goes unanswered, the rest is turned down
+        assert_eq!(telnet.replies, [IAC, WONT, 24]);
+
+        // a baud rate answer split across reads
+        telnet.decode(&[IAC, SB, 44, 101, 0x00, 0x01], &mut data);
+        assert_eq!(telnet.baud, None);
+        telnet.decode(&[0xc2, 0x00, IAC, SE, 0x05], &mut data);
+        assert_eq!(telnet.baud, Some(115_200));
+        assert_eq!(data, [0x03, 0xff, 0x00, 0x05]);
+
+        // asking again doesn't get a second answer
+        telnet.replies.clear();
+        telnet.decode(&[IAC, DO, 24], &mut data);
+        assert!(telnet.replies.is_empty());
+    }
+}
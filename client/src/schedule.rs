@@ -0,0 +1,325 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::bridge::Control;
+
+/// How far ahead to look for a time a cron expression matches, past this it never does,
+/// e.g. `0 0 30 2 *`.
+const CRON_SEARCH_LIMIT_SECS: i64 = 5 * 366 * 24 * 60 * 60;
+
+/// Something to do on the target at set times, from `[[schedule]]` in the config, e.g.
+/// `{ cron = "0 3 * * *", macro = "accept" }` or `{ every_secs = 300, text = " " }`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawJob")]
+pub struct Job {
+    pub when: When,
+    /// Either [`Control::RunMacro`] or [`Control::Type`].
+    pub action: Control,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum When {
+    Cron(Cron),
+    Every(Duration),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawJob {
+    cron: Option<Cron>,
+    every_secs: Option<u64>,
+    #[serde(rename = "macro")]
+    run_macro: Option<String>,
+    text: Option<String>,
+}
+
+impl TryFrom<RawJob> for Job {
+    type Error = String;
+
+    fn try_from(job: RawJob) -> Result<Self, Self::Error> {
+        let when = match (job.cron, job.every_secs) {
+            (Some(cron), None) => When::Cron(cron),
+            (None, Some(0)) => return Err("every_secs has to be more than 0".to_owned()),
+            (None, Some(secs)) => When::Every(Duration::from_secs(secs)),
+            _ => return Err("a scheduled job needs one of cron or every_secs".to_owned()),
+        };
+        let action = match (job.run_macro, job.text) {
+            (Some(name), None) => Control::RunMacro(name),
+            (None, Some(text)) => Control::Type(text),
+            _ => return Err("a scheduled job needs one of macro or text".to_owned()),
+        };
+        Ok(Self { when, action })
+    }
+}
+
+/// A standard five field cron expression, `minute hour day-of-month month day-of-week`, in
+/// local time. Each field is `*` or a comma separated list of numbers and `a-b` ranges,
+/// any of which can be followed by a `/step`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cron {
+    // one bit per allowed value
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // as usual for cron, if both days and weekdays are restricted either matching will do
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("expected 5 fields in {s:?}, not {}", fields.len()));
+        };
+        let mut weekdays_bits = parse_field(weekdays, 0, 7)?;
+        // both 0 and 7 are sunday
+        if weekdays_bits & (1 << 7) != 0 {
+            weekdays_bits |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse().map_err(|_| format!("bad step in {part:?}"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let parse = |n: &str| {
+            n.parse::<u32>()
+                .ok()
+                .filter(|n| (min..=max).contains(n))
+                .ok_or_else(|| format!("{n:?} isn't a number from {min} to {max}"))
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(range)?, parse(range)?),
+        };
+        if step == 0 || start > end {
+            return Err(format!("{part:?} doesn't cover anything"));
+        }
+        for n in (start..=end).step_by(step) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    fn matches(&self, tm: &libc::tm) -> bool {
+        self.matches_hour(tm) && has(self.minutes, tm.tm_min)
+    }
+
+    /// Whether any minute of the hour `tm` is in could match.
+    fn matches_hour(&self, tm: &libc::tm) -> bool {
+        let day = has(self.days, tm.tm_mday);
+        let weekday = has(self.weekdays, tm.tm_wday);
+        let day = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        day && has(self.months, tm.tm_mon + 1) && has(self.hours, tm.tm_hour)
+    }
+
+    /// The first whole minute after `after`, in seconds since the epoch, this matches.
+    fn next_after(&self, after: i64) -> Option<i64> {
+        let mut time = after - after.rem_euclid(60) + 60;
+        while time - after < CRON_SEARCH_LIMIT_SECS {
+            let tm = local_time(time);
+            if self.matches(&tm) {
+                return Some(time);
+            }
+            // skip the rest of the hour if none of it will do
+            time += if self.matches_hour(&tm) {
+                60
+            } else {
+                60 * i64::from(60 - tm.tm_min)
+            };
+        }
+        None
+    }
+}
+
+fn has(bits: u64, n: libc::c_int) -> bool {
+    bits & (1 << n) != 0
+}
+
+fn local_time(secs: i64) -> libc::tm {
+    // SAFETY: tm is plain old data so all zeroes is fine, and localtime_r only writes to it
+    unsafe {
+        let mut tm = std::mem::zeroed();
+        libc::localtime_r(&(secs as libc::time_t), &mut tm);
+        tm
+    }
+}
+
+/// Keeps track of when each of the config's scheduled jobs is next due.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    // None for a cron job which will never run again
+    jobs: Vec<(Job, Option<Instant>)>,
+}
+
+impl Scheduler {
+    pub fn new(jobs: &[Job]) -> Self {
+        let jobs = jobs
+            .iter()
+            .map(|job| (job.clone(), next_run(&job.when, None)));
+        Self {
+            jobs: jobs.collect(),
+        }
+    }
+
+    /// Wait until the next job is due and say what to do for it, or forever if there's
+    /// nothing scheduled. Cancel safe.
+    pub async fn next(&mut self) -> Control {
+        let due = self.jobs.iter_mut().filter_map(|(job, at)| {
+            let when = (*at)?;
+            Some((job, at, when))
+        });
+        let Some((job, at, when)) = due.min_by_key(|(_, _, when)| *when) else {
+            return std::future::pending().await;
+        };
+        tokio::time::sleep_until(when).await;
+        *at = next_run(&job.when, Some(when));
+        job.action.clone()
+    }
+}
+
+/// When a job is next due, given when it was `last` due if it's been run before.
+fn next_run(when: &When, last: Option<Instant>) -> Option<Instant> {
+    match when {
+        When::Every(every) => Some(last.unwrap_or_else(Instant::now) + *every),
+        When::Cron(cron) => {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()?;
+            let mut from = now.as_secs() as i64;
+            // timers can go off a touch before the wall clock gets to the minute they were
+            // set for, so look from well past it to not run twice in the same minute
+            if last.is_some() {
+                from += 30;
+            }
+            let next = Duration::from_secs(cron.next_after(from)? as u64);
+            Some(Instant::now() + next.checked_sub(now)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tm(minute: i32, hour: i32, day: i32, month: i32, weekday: i32) -> libc::tm {
+        // SAFETY: tm is plain old data
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_min = minute;
+        tm.tm_hour = hour;
+        tm.tm_mday = day;
+        tm.tm_mon = month - 1;
+        tm.tm_wday = weekday;
+        tm
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        let nightly: Cron = "30 3 * * *".parse().unwrap();
+        assert!(nightly.matches(&tm(30, 3, 14, 6, 2)));
+        assert!(!nightly.matches(&tm(31, 3, 14, 6, 2)));
+
+        let weekdays: Cron = "*/15 9-17 * * 1-5".parse().unwrap();
+        assert!(weekdays.matches(&tm(45, 17, 1, 1, 5)));
+        assert!(!weekdays.matches(&tm(50, 12, 1, 1, 3)));
+        assert!(!weekdays.matches(&tm(0, 12, 1, 1, 0)));
+
+        // either the day of the month or the weekday will do when both are given
+        let either: Cron = "0 0 1,15 * 7".parse().unwrap();
+        assert!(either.matches(&tm(0, 0, 15, 2, 3)));
+        assert!(either.matches(&tm(0, 0, 9, 2, 0)));
+        assert!(!either.matches(&tm(0, 0, 9, 2, 1)));
+
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        let every_minute: Cron = "* * * * *".parse().unwrap();
+        assert_eq!(every_minute.next_after(1_000_000_000), Some(1_000_000_020));
+        assert_eq!(every_minute.next_after(1_000_000_020), Some(1_000_000_080));
+
+        let never: Cron = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(never.next_after(1_000_000_000), None);
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        #[derive(Deserialize)]
+        struct Jobs {
+            schedule: Vec<Job>,
+        }
+        let jobs: Jobs = toml::from_str(
+            r#"
+            [[schedule]]
+            cron = "0 3 * * *"
+            macro = "accept"
+
+            [[schedule]]
+            every_secs = 300
+            text = " "
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            jobs.schedule[0].action,
+            Control::RunMacro("accept".to_owned())
+        );
+        assert_eq!(
+            jobs.schedule[1],
+            Job {
+                when: When::Every(Duration::from_secs(300)),
+                action: Control::Type(" ".to_owned()),
+            }
+        );
+
+        let both = "schedule = [{ every_secs = 1, text = \"a\", macro = \"b\" }]";
+        assert!(toml::from_str::<Jobs>(both).is_err());
+        let neither = "schedule = [{ text = \"a\" }]";
+        assert!(toml::from_str::<Jobs>(neither).is_err());
+    }
+}
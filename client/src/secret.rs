@@ -0,0 +1,132 @@
+use std::process::Stdio;
+
+use clap::Args;
+use color_eyre::eyre::{bail, Result, WrapErr as _};
+use evdev::Key;
+use tokio::process::Command;
+use zeroize::Zeroizing;
+
+use crate::{
+    output::{Event, Output},
+    SerialArgs,
+};
+
+/// The Secret Service attribute secrets are looked up by, store one with e.g.
+/// `secret-tool store --label "LUKS passphrase" teensy-keyboard-bridge luks`
+#[cfg(feature = "secret-service")]
+const ATTRIBUTE: &str = "teensy-keyboard-bridge";
+
+#[derive(Args, Debug)]
+pub struct TypeSecretArgs {
+    /// the secret to type, the value of its teensy-keyboard-bridge attribute in the Secret
+    /// Service, or its path in the password store with --pass
+    name: String,
+
+    /// get the secret from the first line of `pass show NAME` instead
+    #[arg(long, required = cfg!(not(feature = "secret-service")))]
+    pass: bool,
+
+    /// press enter after typing it
+    #[arg(long)]
+    enter: bool,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Type a secret onto the target straight from where it's kept, without it going
+/// anywhere else on the way, e.g. a LUKS passphrase for a headless machine.
+pub async fn type_secret(args: TypeSecretArgs, output: Output) -> Result<()> {
+    if args.serial.debug_wire {
+        bail!("Not typing a secret with --debug-wire, every key would end up on stderr");
+    }
+    if args.serial.pcap.is_some() {
+        bail!("Not typing a secret with --pcap, every key would end up on disk");
+    }
+    // fetched before connecting so a locked keyring can take its time asking to be unlocked
+    let secret = if args.pass {
+        from_pass(&args.name).await?
+    } else {
+        from_secret_service(&args.name).await?
+    };
+
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    sender.type_text(&secret).await?;
+    if args.enter {
+        sender.tap(&[Key::KEY_ENTER]).await?;
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+/// The first line of `pass show name`, as everything after it is notes and such.
+async fn from_pass(name: &str) -> Result<Zeroizing<String>> {
+    let out = Command::new("pass")
+        .args(["show", name])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .await
+        .wrap_err("Failed to run pass, is it installed?")?;
+    let stdout = Zeroizing::new(out.stdout);
+    if !out.status.success() {
+        bail!("pass show {name} failed with {}", out.status);
+    }
+    secret_line(&stdout)
+}
+
+#[cfg(feature = "secret-service")]
+async fn from_secret_service(name: &str) -> Result<Zeroizing<String>> {
+    let keyring = oo7::Keyring::new()
+        .await
+        .wrap_err("Failed to connect to the Secret Service")?;
+    let items = keyring
+        .search_items(&vec![(ATTRIBUTE, name)])
+        .await
+        .wrap_err("Failed to search the Secret Service")?;
+    let [item] = items.as_slice() else {
+        bail!(
+            "Expected one secret with {ATTRIBUTE}={name:?} in the Secret Service, found {}",
+            items.len()
+        );
+    };
+    if item.is_locked().await? {
+        item.unlock()
+            .await
+            .wrap_err("Failed to unlock the secret")?;
+    }
+    // oo7 zeroizes its secrets when they're dropped
+    let secret = item.secret().await?;
+    secret_line(&secret)
+}
+
+#[cfg(not(feature = "secret-service"))]
+async fn from_secret_service(_name: &str) -> Result<Zeroizing<String>> {
+    unreachable!("--pass is required without the secret-service feature")
+}
+
+/// The secret's first line, which is all of it unless it's come from a password store.
+fn secret_line(bytes: &[u8]) -> Result<Zeroizing<String>> {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        bail!("The secret isn't text");
+    };
+    let line = text.lines().next().unwrap_or_default();
+    Ok(Zeroizing::new(line.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_line() {
+        assert_eq!(secret_line(b"hunter2").unwrap().as_str(), "hunter2");
+        assert_eq!(
+            secret_line(b"hunter2\nuser: root\n").unwrap().as_str(),
+            "hunter2"
+        );
+        assert_eq!(secret_line(b"").unwrap().as_str(), "");
+        assert!(secret_line(&[0xff, 0xfe]).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use clap::Args;
+use color_eyre::eyre::{bail, OptionExt as _, Result, WrapErr as _};
+use evdev::{Device, EventStream, EventType, Key};
+
+use crate::{
+    keyset::KeySet,
+    list,
+    output::{Event, Output},
+    sender::KeypressSender,
+    SerialArgs,
+};
+
+/// How long each report has to come back out of the teensy's USB side.
+const REPORT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Keys to press and release one at a time, a few from each part of the keyboard.
+const SINGLE_KEYS: [Key; 24] = [
+    Key::KEY_A,
+    Key::KEY_Z,
+    Key::KEY_1,
+    Key::KEY_0,
+    Key::KEY_MINUS,
+    Key::KEY_SLASH,
+    Key::KEY_SPACE,
+    Key::KEY_ENTER,
+    Key::KEY_ESC,
+    Key::KEY_BACKSPACE,
+    Key::KEY_TAB,
+    Key::KEY_F1,
+    Key::KEY_F12,
+    Key::KEY_UP,
+    Key::KEY_PAGEDOWN,
+    Key::KEY_KP5,
+    Key::KEY_LEFTCTRL,
+    Key::KEY_LEFTSHIFT,
+    Key::KEY_LEFTALT,
+    Key::KEY_LEFTMETA,
+    Key::KEY_RIGHTCTRL,
+    Key::KEY_RIGHTSHIFT,
+    Key::KEY_RIGHTALT,
+    Key::KEY_RIGHTMETA,
+];
+
+#[derive(Args, Debug)]
+pub struct SelfTestArgs {
+    /// the teensy's keyboard on this machine, found by its USB vendor and product IDs if
+    /// not given
+    #[arg(long)]
+    device: Option<PathBuf>,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Some keys to hold down in turn, and what to call them.
+struct Sequence {
+    name: String,
+    /// What's held after each report, ending with nothing.
+    steps: Vec<Vec<Key>>,
+}
+
+fn sequences() -> Vec<Sequence> {
+    let mut sequences: Vec<_> = SINGLE_KEYS
+        .iter()
+        .map(|&key| Sequence {
+            name: format!("{key:?}"),
+            steps: vec![vec![key], vec![]],
+        })
+        .collect();
+    let (ctrl, shift) = (Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT);
+    sequences.push(Sequence {
+        name: "ctrl+shift+t, letting go all at once".to_owned(),
+        steps: vec![
+            vec![ctrl],
+            vec![ctrl, shift],
+            vec![ctrl, shift, Key::KEY_T],
+            vec![],
+        ],
+    });
+    let six = [
+        Key::KEY_A,
+        Key::KEY_S,
+        Key::KEY_D,
+        Key::KEY_F,
+        Key::KEY_J,
+        Key::KEY_K,
+    ];
+    sequences.push(Sequence {
+        name: "six keys at once".to_owned(),
+        steps: vec![six.to_vec(), six[3..].to_vec(), vec![]],
+    });
+    sequences
+}
+
+/// With the teensy's USB side plugged back into this machine, send it known sequences of
+/// reports and check the same keys come out of the keyboard it shows up as, testing the
+/// whole way through the firmware. The keyboard is grabbed so nothing gets typed here.
+pub async fn self_test(args: SelfTestArgs, output: Output) -> Result<()> {
+    let path = match args.device {
+        Some(path) => path,
+        None => find_teensy_keyboard()?,
+    };
+    let mut device =
+        Device::open(&path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    device.grab()?;
+    let mut keyboard = device.into_event_stream()?;
+    output.status(format!("Testing through {}", path.display()));
+
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    // start with nothing held, and forget anything which came before
+    sender.send_state_update(KeySet::new()).await?;
+    while next_frame(&mut keyboard).await?.is_some() {}
+
+    let sequences = sequences();
+    let mut failed = 0;
+    for sequence in &sequences {
+        match run_sequence(&mut sender, &mut keyboard, sequence).await? {
+            Ok(()) => output.status(format!("ok   {}", sequence.name)),
+            Err(why) => {
+                failed += 1;
+                output.event(&Event::Error {
+                    message: format!("FAIL {}: {why}", sequence.name),
+                });
+                sender.send_state_update(KeySet::new()).await?;
+                while next_frame(&mut keyboard).await?.is_some() {}
+            }
+        }
+    }
+    output.event(&Event::Disconnected);
+    if failed > 0 {
+        bail!(
+            "{failed} of {} sequences didn't come back as sent",
+            sequences.len()
+        );
+    }
+    output.status(format!(
+        "All {} sequences came back as sent",
+        sequences.len()
+    ));
+    Ok(())
+}
+
+fn find_teensy_keyboard() -> Result<PathBuf> {
+    let mut devices = evdev::enumerate();
+    let found = devices.find(|(_, device)| {
+        let id = device.input_id();
+        id.vendor() == shared::USB_VID
+            && id.product() == shared::USB_PID
+            && list::is_keyboard(device)
+    });
+    let (path, _) = found.ok_or_eyre(
+        "Couldn't find the teensy's keyboard, is its USB port plugged into this machine? \
+        If so pass it with --device",
+    )?;
+    Ok(path)
+}
+
+/// Send each step of `sequence`, checking what comes back, or why it didn't.
+async fn run_sequence(
+    sender: &mut KeypressSender,
+    keyboard: &mut EventStream,
+    sequence: &Sequence,
+) -> Result<Result<(), String>> {
+    let mut held: &[Key] = &[];
+    for step in &sequence.steps {
+        let mut state = KeySet::new();
+        for &key in step {
+            state.press_key(key);
+        }
+        sender.send_state_update(state).await?;
+        let expected = changes(held, step);
+        held = step;
+        let Some(mut got) = next_frame(keyboard).await? else {
+            return Ok(Err(format!("nothing came back for {expected:?}")));
+        };
+        sort(&mut got);
+        if got != expected {
+            return Ok(Err(format!("sent {expected:?} but got {got:?}")));
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// The keys which go down and come up going from holding `before` to holding `after`,
+/// in a set order.
+fn changes(before: &[Key], after: &[Key]) -> Vec<(Key, bool)> {
+    let (before, after): (HashSet<_>, HashSet<_>) =
+        (before.iter().collect(), after.iter().collect());
+    let released = before.difference(&after).map(|&&key| (key, false));
+    let pressed = after.difference(&before).map(|&&key| (key, true));
+    let mut changes: Vec<_> = released.chain(pressed).collect();
+    sort(&mut changes);
+    changes
+}
+
+fn sort(changes: &mut [(Key, bool)]) {
+    changes.sort_by_key(|&(key, pressed)| (key.code(), pressed));
+}
+
+/// The key presses and releases up to the next sync, one report's worth, or None if
+/// nothing turns up in time.
+async fn next_frame(keyboard: &mut EventStream) -> Result<Option<Vec<(Key, bool)>>> {
+    let mut frame = Vec::new();
+    loop {
+        let event = match tokio::time::timeout(REPORT_TIMEOUT, keyboard.next_event()).await {
+            Ok(event) => event?,
+            Err(_) => return Ok(None),
+        };
+        match event.event_type() {
+            EventType::SYNCHRONIZATION if !frame.is_empty() => return Ok(Some(frame)),
+            // two is the kernel repeating a held key, which says nothing about the teensy
+            EventType::KEY if event.value() != 2 => {
+                frame.push((Key::new(event.code()), event.value() == 1));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let (ctrl, a, b) = (Key::KEY_LEFTCTRL, Key::KEY_A, Key::KEY_B);
+        assert_eq!(changes(&[], &[b, ctrl]), [(ctrl, true), (b, true)]);
+        assert_eq!(changes(&[ctrl, a], &[b, ctrl]), [(a, false), (b, true)]);
+        assert_eq!(changes(&[a], &[a]), []);
+
+        // every sequence ends up letting go of everything
+        for sequence in sequences() {
+            assert_eq!(sequence.steps.last(), Some(&vec![]), "{}", sequence.name);
+        }
+    }
+}
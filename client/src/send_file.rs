@@ -0,0 +1,269 @@
+use std::{path::PathBuf, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{bail, OptionExt as _, Result, WrapErr as _};
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    output::{Event, Output},
+    SerialArgs,
+};
+
+/// The POSIX shell decoder, typed as one line. It checks each chunk against its sum as it
+/// comes in and keeps the good ones in `NAME.parts/`, so sending the bad or missing ones
+/// again later fills in the gaps, then puts the file together once it has them all.
+const SH_DECODER: &str = concat!(
+    "command -v sha256sum >/dev/null || sha256sum() { shasum -a 256; }; ",
+    "tkb_recv() { d=\"$1.parts\"; mkdir -p \"$d\" || return; ",
+    "while read -r n s b; do [ \"$n\" = end ] && break; ",
+    "printf '%s' \"$b\" | base64 -d > \"$d/$n.tmp\" 2>/dev/null; ",
+    "if [ \"$(sha256sum < \"$d/$n.tmp\" | cut -c1-16)\" = \"$s\" ]; ",
+    "then mv \"$d/$n.tmp\" \"$d/$n\"; else rm -f \"$d/$n.tmp\"; echo \"BAD $n\"; fi; done; ",
+    "i=0; m=; while [ $i -lt $2 ]; do [ -f \"$d/$i\" ] || m=\"$m $i\"; i=$((i+1)); done; ",
+    "[ -n \"$m\" ] && { echo \"MISSING$m\"; return 1; }; ",
+    "i=0; : > \"$1\"; while [ $i -lt $2 ]; do cat \"$d/$i\" >> \"$1\"; i=$((i+1)); done; ",
+    "if [ \"$(sha256sum < \"$1\" | cut -c1-64)\" = \"$3\" ]; ",
+    "then rm -r \"$d\"; echo \"OK $1\"; else echo \"BAD FILE\"; return 1; fi; }",
+);
+
+/// The same again for PowerShell.
+const POWERSHELL_DECODER: &str = concat!(
+    "function tkb_sum([byte[]]$x){-join([Security.Cryptography.SHA256]::Create()",
+    ".ComputeHash($x)|%{$_.ToString('x2')})}; ",
+    "function tkb_recv($f,$n,$h){$f=[IO.Path]::Combine($PWD.ProviderPath,$f);$d=\"$f.parts\";",
+    "$null=New-Item -ItemType Directory -Force $d;",
+    "while(($l=Read-Host) -ne 'end'){$i,$s,$b=$l.Split(' ');",
+    "try{$x=[Convert]::FromBase64String(\"$b\")}catch{$x=$null};",
+    "if($null -ne $x -and (tkb_sum $x).Substring(0,16) -eq $s)",
+    "{[IO.File]::WriteAllBytes(\"$d\\$i\",$x)}else{\"BAD $i\"}};",
+    "$m=@(0..($n-1)|?{!(Test-Path \"$d\\$_\")});if($m){\"MISSING $m\";return};",
+    "$o=[IO.File]::Create($f);",
+    "0..($n-1)|%{$x=[IO.File]::ReadAllBytes(\"$d\\$_\");$o.Write($x,0,$x.Length)};$o.Close();",
+    "if((tkb_sum ([IO.File]::ReadAllBytes($f))) -eq $h){Remove-Item -Recurse $d;\"OK $f\"}",
+    "else{\"BAD FILE\"}}",
+);
+
+/// How many hex digits of each chunk's SHA-256 go with it, plenty to catch a dropped key.
+const CHUNK_SUM_LEN: usize = 16;
+
+/// The shell open on the target, which the decoder has to be written for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    /// sh, bash, zsh and the like, needing base64 and sha256sum or shasum.
+    #[default]
+    Sh,
+    /// Windows PowerShell or PowerShell 7.
+    Powershell,
+}
+
+impl Shell {
+    fn decoder(self) -> &'static str {
+        match self {
+            Self::Sh => SH_DECODER,
+            Self::Powershell => POWERSHELL_DECODER,
+        }
+    }
+
+    // both take single quoted strings as they are, apart from the quotes themselves
+    fn quote(self, s: &str) -> String {
+        let quote = match self {
+            Self::Sh => r"'\''",
+            Self::Powershell => "''",
+        };
+        format!("'{}'", s.replace('\'', quote))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SendFileArgs {
+    /// the file to send
+    path: PathBuf,
+
+    /// what to call the file on the target, relative to the shell's working directory,
+    /// the same name as here if not given
+    #[arg(long)]
+    name: Option<String>,
+
+    /// the shell waiting at a prompt on the target, which the decoder is typed for
+    #[arg(long, value_enum, default_value_t = Shell::Sh)]
+    shell: Shell,
+
+    /// how many bytes of the file go on each checksummed line, which is all typed again
+    /// if any of it goes wrong
+    #[arg(long, default_value_t = 512, value_parser = clap::value_parser!(u16).range(1..=2048))]
+    chunk_size: u16,
+
+    /// how long to give the target to check and save each line before typing the next
+    #[arg(long, default_value_t = 100)]
+    chunk_delay_ms: u64,
+
+    /// skip the chunks before this one, to carry on after being cut off part way
+    #[arg(long, default_value_t = 0)]
+    from: usize,
+
+    /// only send these chunks, e.g. the ones the decoder said were BAD or MISSING
+    #[arg(long, value_delimiter = ',', conflicts_with = "from")]
+    only: Vec<usize>,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Copy a file onto a target with no other way in by typing it as base64 into a decoder
+/// typed into the target's shell. Each chunk carries a checksum so the decoder can say
+/// which ones to send again with `--only`, and the whole file is checked at the end.
+pub async fn send_file(args: SendFileArgs, output: Output) -> Result<()> {
+    let data = std::fs::read(&args.path)
+        .wrap_err_with(|| format!("Failed to read {}", args.path.display()))?;
+    let name = match args.name {
+        Some(name) => name,
+        None => {
+            let name = args
+                .path
+                .file_name()
+                .ok_or_eyre("The path isn't to a file")?;
+            name.to_string_lossy().into_owned()
+        }
+    };
+    let transfer = Transfer::new(&data, usize::from(args.chunk_size));
+    let count = transfer.lines.len();
+    if let Some(&chunk) = args.only.iter().find(|&&chunk| chunk >= count) {
+        bail!("There are only {count} chunks, there's no chunk {chunk}");
+    }
+    let to_send: Vec<usize> = if args.only.is_empty() {
+        (args.from..count).collect()
+    } else {
+        args.only.clone()
+    };
+
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    sender.type_text(args.shell.decoder()).await?;
+    sender.type_text("\n").await?;
+    let quoted = args.shell.quote(&name);
+    let start = format!("tkb_recv {quoted} {count} {}\n", transfer.sum);
+    sender.type_text(&start).await?;
+
+    let chunk_delay = Duration::from_millis(args.chunk_delay_ms);
+    for (sent, &chunk) in to_send.iter().enumerate() {
+        sender
+            .type_text(&transfer.lines[chunk])
+            .await
+            .wrap_err_with(|| {
+                format!("Stopped part way through chunk {chunk}, carry on with --from {chunk}")
+            })?;
+        sender.wait(chunk_delay).await;
+        output.status(format!(
+            "Sent chunk {chunk} ({} of {})",
+            sent + 1,
+            to_send.len()
+        ));
+    }
+    sender.type_text("end\n").await?;
+    output.status(format!(
+        "Done, the target prints OK once it has all {count} chunks of {name}, \
+        otherwise send the ones it lists again with --only"
+    ));
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+/// What gets typed for a file.
+struct Transfer {
+    /// One line per chunk, `index sum base64`, always at least one even for an empty file.
+    lines: Vec<String>,
+    /// The SHA-256 of the whole file in hex.
+    sum: String,
+}
+
+impl Transfer {
+    fn new(data: &[u8], chunk_size: usize) -> Self {
+        let mut chunks: Vec<_> = data.chunks(chunk_size).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let lines = chunks.iter().enumerate().map(|(i, chunk)| {
+            let mut sum = hex_sum(chunk);
+            sum.truncate(CHUNK_SUM_LEN);
+            format!("{i} {sum} {}\n", BASE64.encode(chunk))
+        });
+        Self {
+            lines: lines.collect(),
+            sum: hex_sum(data),
+        }
+    }
+}
+
+fn hex_sum(data: &[u8]) -> String {
+    let sum = Sha256::digest(data);
+    sum.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write as _,
+        process::{Command, Stdio},
+    };
+
+    use super::*;
+
+    // what the target's shell does with what we type, minus the typing, with the lines
+    // after the command as what it reads
+    fn run_sh(dir: &std::path::Path, command: &str, lines: &[&str]) -> String {
+        let mut sh = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{SH_DECODER}\n{command}"))
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = sh.stdin.take().unwrap();
+        stdin.write_all(lines.concat().as_bytes()).unwrap();
+        drop(stdin);
+        let out = sh.wait_with_output().unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_sh_decoder() {
+        let dir = std::env::temp_dir().join(format!("send-file-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let transfer = Transfer::new(&data, 300);
+        let lines = &transfer.lines;
+        assert_eq!(lines.len(), 4);
+        let command = format!("tkb_recv 'got it' 4 {}", transfer.sum);
+
+        // the second chunk has a key dropped, and the last never arrives
+        let mut dropped = lines[1].clone();
+        dropped.remove(dropped.len() - 10);
+        let out = run_sh(&dir, &command, &[&lines[0], &dropped, &lines[2], "end\n"]);
+        assert_eq!(out, "BAD 1\nMISSING 1 3\n");
+
+        let out = run_sh(&dir, &command, &[&lines[1], &lines[3], "end\n"]);
+        assert_eq!(out, "OK got it\n");
+        assert_eq!(std::fs::read(dir.join("got it")).unwrap(), data);
+        assert!(!dir.join("got it.parts").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_transfer() {
+        let transfer = Transfer::new(b"", 512);
+        assert_eq!(transfer.lines, ["0 e3b0c44298fc1c14 \n"]);
+        assert_eq!(
+            transfer.sum,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let transfer = Transfer::new(b"hello", 3);
+        assert_eq!(transfer.lines.len(), 2);
+        assert!(transfer.lines[1].ends_with(" bG8=\n"));
+
+        assert_eq!(Shell::Sh.quote("it's"), r"'it'\''s'");
+        assert_eq!(Shell::Powershell.quote("it's"), "'it''s'");
+    }
+}
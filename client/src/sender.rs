@@ -0,0 +1,1492 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Write as _},
+    fs::File,
+    hash::{BuildHasher as _, RandomState},
+    io::BufWriter,
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::Args;
+use color_eyre::eyre::{bail, eyre, Result, WrapErr as _};
+use evdev::Key;
+use shared::link;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+    time::{Interval, MissedTickBehavior},
+};
+use tokio_serial::{SerialPort as _, SerialStream};
+use zeroize::{Zeroize as _, Zeroizing};
+
+use crate::{
+    keyset::{self, KeySet, Layout},
+    metrics::Metrics,
+    pcap::{Capture, Direction},
+    rfc2217::Rfc2217,
+};
+
+/// How long to hold the line in a break before the hello, many character times even at the
+/// slowest baud rate.
+const BREAK_DURATION: Duration = Duration::from_millis(10);
+/// How long to wait for the firmware to answer our hello before giving up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// And to save a setting, erasing flash can take a few hundred milliseconds.
+const SETTING_TIMEOUT: Duration = Duration::from_secs(2);
+/// A write taking this long means the link is wedged, better to bail out than risk
+/// sitting on a grabbed keyboard forever.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to leave each report when typing text unless told otherwise, the firmware
+/// only queues so many.
+const TYPING_DELAY: Duration = Duration::from_millis(5);
+/// With --bios, the least time to leave between reports and to hold each key down for.
+/// Firmware setup screens and bootloaders can poll the keyboard far less often than an OS.
+const BIOS_REPORT_INTERVAL: Duration = Duration::from_millis(20);
+const BIOS_TYPING_DELAY: Duration = Duration::from_millis(30);
+/// How much more to leave between reports when typing each time the firmware says its
+/// queue overflowed, up to the most, and how long it goes without before that's halved.
+const OVERFLOW_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_OVERFLOW_BACKOFF: Duration = Duration::from_millis(10);
+const BACKOFF_HALF_LIFE: Duration = Duration::from_secs(5);
+/// How many frames the firmware hasn't acknowledged yet are kept, to send again if it says
+/// one of them didn't arrive intact.
+const RESEND_HISTORY: usize = 64;
+/// How long the firmware has to acknowledge a frame before it's taken as lost, and sent
+/// again along with the next one.
+const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+/// How many messages from the firmware are kept for [`KeypressSender::recv_message`] before
+/// the oldest are dropped, for commands which never ask for them.
+const MAX_UNREAD: usize = 64;
+
+/// Where the teensy is, a local tty, a serial port on a network serial server, or a teensy
+/// on the network itself with the firmware's ethernet feature.
+pub enum Port {
+    Serial(SerialStream),
+    Rfc2217(Rfc2217),
+    /// The frames as they are over TCP, and the tcp:// URL it was reached at.
+    Tcp(TcpStream, String),
+}
+
+impl Port {
+    /// Connect to a teensy listening on `addr`, a host and port.
+    pub async fn tcp(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .wrap_err_with(|| format!("Failed to connect to {addr}"))?;
+        // frames are small and want to go straight away, not wait for more to join them
+        stream.set_nodelay(true)?;
+        Ok(Self::Tcp(stream, format!("tcp://{addr}")))
+    }
+
+    async fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Serial(port) => port.write_all(bytes).await,
+            Self::Rfc2217(port) => port.write_all(bytes).await,
+            Self::Tcp(stream, _) => stream.write_all(bytes).await,
+        }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Serial(port) => port.read(buf).await,
+            Self::Rfc2217(port) => port.read(buf).await,
+            Self::Tcp(stream, _) => stream.read(buf).await,
+        }
+    }
+
+    fn name(&self) -> Option<String> {
+        match self {
+            Self::Serial(port) => port.name(),
+            Self::Rfc2217(port) => Some(port.url().to_owned()),
+            Self::Tcp(_, url) => Some(url.clone()),
+        }
+    }
+
+    async fn set_baud(&mut self, baud: u32) -> Result<()> {
+        match self {
+            Self::Serial(port) => Ok(port.set_baud_rate(baud)?),
+            Self::Rfc2217(port) => port.set_baud(baud).await,
+            // the firmware never agrees to switch over the network, so this isn't reached
+            Self::Tcp(..) => Ok(()),
+        }
+    }
+
+    /// Hold a local serial port's line in a break for a moment, which the firmware takes as
+    /// a new client starting afresh, so anything left half-sent from before is thrown away.
+    /// There's no need over the network, where a new connection does the same.
+    async fn send_break(&mut self) -> Result<()> {
+        let Self::Serial(port) = self else {
+            return Ok(());
+        };
+        port.set_break()?;
+        tokio::time::sleep(BREAK_DURATION).await;
+        port.clear_break()?;
+        Ok(())
+    }
+
+    // the tty, which has to be local
+    fn tty(&self) -> Option<RawFd> {
+        match self {
+            Self::Serial(port) => Some(port.as_raw_fd()),
+            Self::Rfc2217(_) | Self::Tcp(..) => None,
+        }
+    }
+}
+
+impl AsRawFd for Port {
+    // for the socket this skips the telnet escaping, which the frames the panic hook sends
+    // don't need
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Serial(port) => port.as_raw_fd(),
+            Self::Rfc2217(port) => port.stream().as_raw_fd(),
+            Self::Tcp(stream, _) => stream.as_raw_fd(),
+        }
+    }
+}
+
+// a wrapper around a serial port to make sending keypresses easier
+pub struct KeypressSender {
+    serial_port: Port,
+    // bytes received from the firmware which don't make up a whole frame yet
+    rx_buf: Vec<u8>,
+    // when we started dumping frames for --debug-wire, timestamps are relative to this
+    debug_wire: Option<Instant>,
+    // set by --pcap, where every frame is written for Wireshark
+    capture: Option<Capture<BufWriter<File>>>,
+    // set by --low-latency, how long frames have taken to leave the tty
+    drain_stats: Option<DrainStats>,
+    // set by --metrics, shared with whatever's reporting them
+    metrics: Option<Arc<Metrics>>,
+    // how long to leave each report when typing
+    typing_delay: Duration,
+    pacing: Pacing,
+    // set by the profile, the least time between reports and the extra time to leave after
+    // one with particular keys held
+    report_interval: Duration,
+    key_delays: HashMap<Key, Duration>,
+    // when the next report can go, going by the above
+    next_report: Option<tokio::time::Instant>,
+    // set by --bios, going slowly whatever else says otherwise
+    bios: bool,
+    // what the target's keyboard layout is, for typing text
+    layout: Layout,
+    // set when the firmware shows up as a digitizer, which the pointer goes to instead
+    digitizer: bool,
+    // when we last sent a report while typing, for --min-spacing-ms and --max-rate
+    last_typed: Option<tokio::time::Instant>,
+    // added to the spacing between reports when typing, once the firmware can't keep up
+    backoff: Backoff,
+    // set once a write or read fails, or the port closes
+    link_failed: bool,
+    // the next frame's sequence number
+    next_seq: u8,
+    // the frames the firmware hasn't acknowledged yet, oldest first
+    sent: VecDeque<SentFrame>,
+    // set by a NAK, the first frame to send again before the next new one
+    resend_from: Option<u8>,
+    // messages from the firmware waiting for recv_message
+    received: VecDeque<shared::DeviceMessage>,
+    // what was last sent for each device, to send again if the firmware might not have it
+    held: Held,
+    // set by an overflow, to send what's held again before the next new frame
+    resend_held: bool,
+}
+
+struct SentFrame {
+    seq: u8,
+    // when it was last written
+    at: Instant,
+    frame: Zeroizing<Vec<u8>>,
+}
+
+/// How much longer to leave between reports while typing as the firmware's queue has
+/// overflowed, which wears off once it stops.
+#[derive(Debug, Default)]
+struct Backoff {
+    extra: Duration,
+    // when it last overflowed, or was halved since
+    since: Option<Instant>,
+}
+
+impl Backoff {
+    fn overflowed(&mut self, now: Instant) {
+        self.extra = (self.at(now) + OVERFLOW_BACKOFF).min(MAX_OVERFLOW_BACKOFF);
+        self.since = Some(now);
+    }
+
+    /// How much longer to leave `now`.
+    fn at(&mut self, now: Instant) -> Duration {
+        while let Some(since) = self.since {
+            if now.saturating_duration_since(since) < BACKOFF_HALF_LIFE {
+                break;
+            }
+            self.extra /= 2;
+            if self.extra < OVERFLOW_BACKOFF {
+                self.extra = Duration::ZERO;
+            }
+            self.since = (!self.extra.is_zero()).then_some(since + BACKOFF_HALF_LIFE);
+        }
+        self.extra
+    }
+}
+
+/// The last state sent to each of the firmware's devices, with any movement taken out, so
+/// it can be sent again once frames are lost past sending them again.
+#[derive(Debug, Default)]
+struct Held {
+    keyboard: Option<shared::WhyNoDeriveDeserializeManSadFaceHere>,
+    consumer: Option<u16>,
+    gamepad: Option<shared::GamepadState>,
+    pointer: Option<shared::AbsolutePointerState>,
+    digitizer: Option<shared::DigitizerState>,
+    mouse: Option<shared::MouseState>,
+}
+
+impl Held {
+    fn record(&mut self, message: &shared::HostMessage) {
+        match *message {
+            shared::HostMessage::Report(report) => self.keyboard = Some(report),
+            shared::HostMessage::Reports { count, reports } => {
+                let count = usize::from(count).min(reports.len());
+                if let Some(&report) = reports[..count].last() {
+                    self.keyboard = Some(report);
+                }
+            }
+            shared::HostMessage::Scheduled { count, reports } => {
+                let count = usize::from(count).min(reports.len());
+                if let Some(scheduled) = reports[..count].last() {
+                    self.keyboard = Some(scheduled.report);
+                }
+            }
+            // it ends with everything let go of
+            shared::HostMessage::TypeCodepoint { .. } => {
+                self.keyboard = Some(KeySet::new().into());
+            }
+            shared::HostMessage::Consumer(usage) => self.consumer = Some(usage),
+            shared::HostMessage::Gamepad(state) => self.gamepad = Some(state),
+            shared::HostMessage::AbsolutePointer(state) => {
+                self.pointer = Some(shared::AbsolutePointerState { wheel: 0, ..state });
+            }
+            shared::HostMessage::Digitizer(state) => self.digitizer = Some(state),
+            shared::HostMessage::Mouse(state) => {
+                self.mouse = Some(shared::MouseState {
+                    buttons: state.buttons,
+                    ..Default::default()
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// A message for each device that's been sent anything, putting it back how it was.
+    fn messages(&self) -> impl Iterator<Item = shared::HostMessage> {
+        [
+            self.keyboard.map(shared::HostMessage::Report),
+            self.consumer.map(shared::HostMessage::Consumer),
+            self.gamepad.map(shared::HostMessage::Gamepad),
+            self.pointer.map(shared::HostMessage::AbsolutePointer),
+            self.digitizer.map(shared::HostMessage::Digitizer),
+            self.mouse.map(shared::HostMessage::Mouse),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+impl From<SerialStream> for KeypressSender {
+    fn from(value: SerialStream) -> Self {
+        Port::Serial(value).into()
+    }
+}
+
+impl From<Rfc2217> for KeypressSender {
+    fn from(value: Rfc2217) -> Self {
+        Port::Rfc2217(value).into()
+    }
+}
+
+impl From<Port> for KeypressSender {
+    fn from(value: Port) -> Self {
+        Self {
+            serial_port: value,
+            rx_buf: Vec::new(),
+            debug_wire: None,
+            capture: None,
+            drain_stats: None,
+            metrics: None,
+            typing_delay: TYPING_DELAY,
+            pacing: Pacing::default(),
+            layout: Layout::default(),
+            digitizer: false,
+            bios: false,
+            report_interval: Duration::ZERO,
+            key_delays: HashMap::new(),
+            next_report: None,
+            last_typed: None,
+            backoff: Backoff::default(),
+            link_failed: false,
+            next_seq: 0,
+            sent: VecDeque::new(),
+            resend_from: None,
+            received: VecDeque::new(),
+            held: Held::default(),
+            resend_held: false,
+        }
+    }
+}
+
+impl KeypressSender {
+    /// Hexdump every frame sent and every byte received to stderr.
+    pub fn debug_wire(&mut self, enabled: bool) {
+        self.debug_wire = enabled.then(Instant::now);
+    }
+
+    /// Write every frame sent and received to a new pcapng file at `path`.
+    pub fn capture_to(&mut self, path: &Path) -> Result<()> {
+        let capture = Capture::create(path)
+            .wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    /// Get bytes onto the wire as soon as they're written, rather than letting the
+    /// USB-serial adapter sit on them for its latency timer (16ms for FTDI chips).
+    ///
+    /// This only fails if the driver doesn't support ASYNC_LOW_LATENCY, in which case
+    /// we still drain the tty after every frame.
+    pub fn low_latency(&mut self) -> Result<()> {
+        let Some(tty) = self.serial_port.tty() else {
+            bail!("--low-latency only works with a local serial port");
+        };
+        self.drain_stats = Some(DrainStats::default());
+        set_async_low_latency(tty).wrap_err("Failed to set ASYNC_LOW_LATENCY on the serial port")
+    }
+
+    /// How long frames have been taking to leave the tty, if we've been measuring.
+    pub fn drain_stats(&self) -> Option<&DrainStats> {
+        self.drain_stats.as_ref()
+    }
+
+    /// Count frames and errors in `metrics` from now on.
+    pub fn count_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Leave each report for `delay` when typing, or the default if None.
+    pub fn set_typing_delay(&mut self, delay: Option<Duration>) {
+        self.typing_delay = delay.unwrap_or(TYPING_DELAY);
+    }
+
+    /// Leave at least `interval` between reports, and `key_delays` more after one with
+    /// those keys held, for targets which drop keys sent too close together.
+    pub fn set_report_delays(&mut self, interval: Duration, key_delays: HashMap<Key, Duration>) {
+        self.report_interval = interval;
+        self.key_delays = key_delays;
+    }
+
+    /// Go slowly enough for firmware setup screens and bootloaders, whatever the pacing and
+    /// profile say.
+    pub fn bios_safe(&mut self) {
+        self.bios = true;
+    }
+
+    /// Type text for a target set to `layout`.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// Send the pointer as the firmware's digitizer, for firmware built with it.
+    pub fn set_digitizer(&mut self, digitizer: bool) {
+        self.digitizer = digitizer;
+    }
+
+    /// Go at this pace when typing and waiting between steps.
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
+
+    /// Wait for `duration` between steps of something being played back, sped up or
+    /// slowed down with --speed.
+    pub async fn wait(&self, duration: Duration) {
+        tokio::time::sleep(self.pacing.scale(duration)).await;
+    }
+
+    /// Keep counting metrics and writing the --pcap file where `old` left off, when this is
+    /// taking over from it.
+    pub fn take_over(&mut self, mut old: KeypressSender) {
+        self.metrics = old.metrics.take();
+        self.capture = old.capture.take();
+    }
+
+    /// Whether the link to the teensy has failed, rather than something else going wrong.
+    pub fn link_failed(&self) -> bool {
+        self.link_failed
+    }
+
+    /// The path of the serial port we're sending to, if it has one.
+    pub fn port_name(&self) -> Option<String> {
+        self.serial_port.name()
+    }
+
+    pub async fn send_state_update(&mut self, new_state: KeySet) -> Result<()> {
+        if let Some(next) = self.next_report {
+            tokio::time::sleep_until(next).await;
+        }
+        let report = shared::WhyNoDeriveDeserializeManSadFaceHere::from(new_state);
+        self.send_message(shared::HostMessage::Report(report))
+            .await?;
+
+        let held = self.key_delays.iter();
+        let held = held.filter(|(&key, _)| new_state.is_pressed(key));
+        let mut interval = self.report_interval;
+        if self.bios {
+            interval = interval.max(BIOS_REPORT_INTERVAL);
+        }
+        let wait = interval + held.map(|(_, &delay)| delay).max().unwrap_or_default();
+        self.next_report = (!wait.is_zero()).then(|| tokio::time::Instant::now() + wait);
+        Ok(())
+    }
+
+    /// Type some text in the target's layout, skipping anything untypeable, leaving nothing
+    /// held down afterwards. Dead keys get a report of their own before the letter.
+    pub async fn type_text(&mut self, text: &str) -> Result<()> {
+        if self.pacing.pack_reports && !self.bios {
+            let mut reports = Vec::new();
+            for c in text.chars() {
+                for stroke in self.layout.strokes_for_char(c).unwrap_or_default() {
+                    let mut pressed = KeySet::new();
+                    for key in stroke.keys() {
+                        pressed.press_key(key);
+                    }
+                    reports.extend([pressed, KeySet::new()]);
+                }
+            }
+            return self.send_packed(&reports).await;
+        }
+        for c in text.chars() {
+            for stroke in self.layout.strokes_for_char(c).unwrap_or_default() {
+                self.tap(&stroke.keys()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Press `keys` together then let go of them all.
+    pub async fn tap(&mut self, keys: &[Key]) -> Result<()> {
+        self.tap_on(KeySet::new(), keys).await
+    }
+
+    /// Press `keys` on top of what's already `held`, then go back to just `held`. A media
+    /// key among them is pressed and released on the consumer control alongside.
+    pub async fn tap_on(&mut self, held: KeySet, keys: &[Key]) -> Result<()> {
+        let mut pressed = held;
+        for &key in keys {
+            pressed.press_key(key);
+        }
+        // setup screens only take a boot keyboard, which has no media keys
+        let consumer = keys
+            .iter()
+            .find_map(|&key| keyset::consumer_usage(key))
+            .filter(|_| !self.bios);
+        for (state, usage) in [(pressed, consumer), (held, consumer.map(|_| 0))] {
+            if let Some(last) = self.last_typed {
+                tokio::time::sleep_until(
+                    last + self.pacing.spacing() + self.backoff.at(Instant::now()),
+                )
+                .await;
+            }
+            self.send_state_update(state).await?;
+            if let Some(usage) = usage {
+                self.send_consumer(usage).await?;
+            }
+            self.last_typed = Some(tokio::time::Instant::now());
+            let mut delay = self.pacing.scale(self.typing_delay);
+            if self.bios {
+                delay = delay.max(BIOS_TYPING_DELAY);
+            }
+            tokio::time::sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Send `reports` in as few frames as they'll fit in, for the firmware to hand to the
+    /// target one after another, keeping to the pacing between each of them.
+    pub async fn send_packed(&mut self, reports: &[KeySet]) -> Result<()> {
+        let spacing = self.pacing.spacing() + self.backoff.at(Instant::now());
+        for chunk in reports.chunks(shared::MAX_PACKED_REPORTS) {
+            if let Some(last) = self.last_typed {
+                tokio::time::sleep_until(last + spacing).await;
+            }
+            self.send_message(pack(chunk)).await?;
+            // the firmware takes a report off its queue each time the target polls it
+            let played = spacing * (chunk.len() as u32 - 1);
+            self.last_typed = Some(tokio::time::Instant::now() + played);
+        }
+        Ok(())
+    }
+
+    /// Have the firmware hand the target each of `reports` in turn, each held for its number
+    /// of milliseconds, timed on the teensy so the link's jitter doesn't come into it.
+    pub async fn send_scheduled(&mut self, reports: &[(KeySet, u16)]) -> Result<()> {
+        let spacing = self.pacing.spacing() + self.backoff.at(Instant::now());
+        for chunk in reports.chunks(shared::MAX_SCHEDULED_REPORTS) {
+            if let Some(last) = self.last_typed {
+                tokio::time::sleep_until(last + spacing).await;
+            }
+            let released = shared::ScheduledReport {
+                report: KeySet::new().into(),
+                hold_ms: 0,
+            };
+            let mut scheduled = [released; shared::MAX_SCHEDULED_REPORTS];
+            for (slot, &(report, hold_ms)) in scheduled.iter_mut().zip(chunk) {
+                *slot = shared::ScheduledReport {
+                    report: report.into(),
+                    hold_ms,
+                };
+            }
+            self.send_message(shared::HostMessage::Scheduled {
+                count: chunk.len() as u8,
+                reports: scheduled,
+            })
+            .await?;
+            let held: u64 = chunk.iter().map(|&(_, hold_ms)| u64::from(hold_ms)).sum();
+            self.last_typed = Some(tokio::time::Instant::now() + Duration::from_millis(held));
+        }
+        Ok(())
+    }
+
+    /// Have the firmware type `c` by its code point the way `os` lets it be, which gets
+    /// characters the target's layout hasn't got a key for across.
+    pub async fn type_codepoint(&mut self, c: char, os: shared::TargetOs) -> Result<()> {
+        let spacing = self.pacing.spacing() + self.backoff.at(Instant::now());
+        if let Some(last) = self.last_typed {
+            tokio::time::sleep_until(last + spacing).await;
+        }
+        self.send_message(shared::HostMessage::TypeCodepoint { codepoint: c, os })
+            .await?;
+        let played = spacing * (os.codepoint_reports(c).count() as u32 - 1);
+        self.last_typed = Some(tokio::time::Instant::now() + played);
+        Ok(())
+    }
+
+    /// Let the firmware know we're still here, see [`heartbeat_interval`] for how often.
+    pub async fn send_heartbeat(&mut self) -> Result<()> {
+        self.send_message(shared::HostMessage::Heartbeat).await
+    }
+
+    /// Have the firmware press `chord` if it stops hearing from us, or nothing if None.
+    pub async fn set_lock_chord(&mut self, chord: Option<&[Key]>) -> Result<()> {
+        let chord = chord.map(|chord| {
+            let mut keys = KeySet::new();
+            for &key in chord {
+                keys.press_key(key);
+            }
+            keys.into()
+        });
+        self.send_message(shared::HostMessage::LockChord(chord))
+            .await
+    }
+
+    /// Have the firmware let go of everything after `ms` without a frame, or never with 0.
+    pub async fn set_idle_timeout(&mut self, ms: u32) -> Result<()> {
+        self.send_message(shared::HostMessage::IdleTimeout(ms))
+            .await
+    }
+
+    /// Have the firmware send the target a keyboard report every `ms` milliseconds.
+    pub async fn set_report_interval(&mut self, ms: u8) -> Result<()> {
+        self.send_message(shared::HostMessage::ReportInterval(ms))
+            .await
+    }
+
+    /// Have the firmware send the target each keyboard report as soon as it can, rather than
+    /// on its next report interval.
+    pub async fn set_immediate_reports(&mut self, immediate: bool) -> Result<()> {
+        self.send_message(shared::HostMessage::ImmediateReports(immediate))
+            .await
+    }
+
+    /// Hold down a consumer control usage, see [`keyset::consumer_usage`], or let go with 0.
+    pub async fn send_consumer(&mut self, usage: u16) -> Result<()> {
+        self.send_message(shared::HostMessage::Consumer(usage))
+            .await
+    }
+
+    pub async fn send_gamepad_update(&mut self, new_state: shared::GamepadState) -> Result<()> {
+        self.send_message(shared::HostMessage::Gamepad(new_state))
+            .await
+    }
+
+    pub async fn send_pointer_update(
+        &mut self,
+        new_state: shared::AbsolutePointerState,
+    ) -> Result<()> {
+        let message = if self.digitizer {
+            shared::HostMessage::Digitizer(new_state.into())
+        } else {
+            shared::HostMessage::AbsolutePointer(new_state)
+        };
+        self.send_message(message).await
+    }
+
+    pub async fn send_mouse_update(&mut self, new_state: shared::MouseState) -> Result<()> {
+        self.send_message(shared::HostMessage::Mouse(new_state))
+            .await
+    }
+
+    pub async fn send_message(&mut self, message: shared::HostMessage) -> Result<()> {
+        self.read_waiting().await?;
+        let lost = self
+            .sent
+            .front()
+            .filter(|sent| sent.at.elapsed() > ACK_TIMEOUT);
+        if let Some(from) = self.resend_from.take().or(lost.map(|sent| sent.seq)) {
+            self.resend(from).await?;
+        }
+        if std::mem::take(&mut self.resend_held) {
+            self.send_held().await?;
+        }
+        self.send_frame(&message).await
+    }
+
+    /// Send where everything's at now, in case the firmware lost a release.
+    async fn send_held(&mut self) -> Result<()> {
+        for message in self.held.messages().collect::<Vec<_>>() {
+            self.send_frame(&message).await?;
+        }
+        Ok(())
+    }
+
+    /// Send everything again from the frame numbered `from`, which the firmware says
+    /// didn't arrive intact or hasn't acknowledged, as it's dropped everything since.
+    async fn resend(&mut self, from: u8) -> Result<()> {
+        if from == self.next_seq {
+            // it's missing nothing we've sent
+            return Ok(());
+        }
+        let Some(start) = self.sent.iter().position(|sent| sent.seq == from) else {
+            // too long ago to still have, so what's missing is lost, but a hello gets the
+            // firmware listening to us again, and then where everything's at now, in case
+            // a release was among it
+            let nonce = fresh_nonce();
+            self.send_frame(&shared::HostMessage::Hello { nonce })
+                .await?;
+            return self.send_held().await;
+        };
+        for i in start..self.sent.len() {
+            let sent = &mut self.sent[i];
+            sent.at = Instant::now();
+            let (seq, frame) = (sent.seq, sent.frame.clone());
+            self.dump("->", &frame, Some(&format_args!("again, frame {seq}")));
+            self.capture(Direction::Outbound, &frame);
+            if let Some(metrics) = &self.metrics {
+                metrics.frames_resent.inc();
+            }
+            self.write_frame(&frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Forget the frames up to and including `seq`, which the firmware has acted on.
+    fn acked(&mut self, seq: u8) {
+        // sequence numbers wrap, but there are never more than half of them to go through
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| sent.seq.wrapping_sub(seq) as i8 <= 0)
+        {
+            self.sent.pop_front();
+        }
+    }
+
+    async fn send_frame(&mut self, message: &shared::HostMessage) -> Result<()> {
+        // frames can be the keys of a password, so nothing is left lying around in memory
+        let mut buf = Zeroizing::new([0; shared::MAX_FRAME_LEN]);
+        let seq = self.next_seq;
+        let to_send = link::encode_host_frame(seq, message, &mut *buf)?;
+        self.next_seq = seq.wrapping_add(1);
+        self.held.record(message);
+        if self.sent.len() == RESEND_HISTORY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(SentFrame {
+            seq,
+            at: Instant::now(),
+            frame: Zeroizing::new(to_send.to_vec()),
+        });
+        self.dump("->", to_send, Some(message));
+        self.capture(Direction::Outbound, to_send);
+        self.write_frame(to_send).await
+    }
+
+    async fn write_frame(&mut self, to_send: &[u8]) -> Result<()> {
+        let started = Instant::now();
+        let written =
+            tokio::time::timeout(WRITE_TIMEOUT, self.serial_port.write_all(to_send)).await;
+        let ok = matches!(written, Ok(Ok(())));
+        self.link_failed |= !ok;
+        if let Some(metrics) = &self.metrics {
+            if ok {
+                metrics.frames_sent.inc();
+                metrics.bytes_sent.add(to_send.len() as u64);
+            } else {
+                metrics.write_errors.inc();
+            }
+        }
+        written.map_err(|_| eyre!("Timed out writing to the serial port"))??;
+
+        if let (Some(stats), Some(fd)) = (&mut self.drain_stats, self.serial_port.tty()) {
+            // tcdrain blocks until the kernel has handed every byte to the hardware,
+            // so it goes on a blocking thread where a wedged link can't hang us
+            let drained = tokio::time::timeout(
+                WRITE_TIMEOUT,
+                tokio::task::spawn_blocking(move || termios::tcdrain(fd)),
+            )
+            .await;
+            self.link_failed |= !matches!(drained, Ok(Ok(Ok(()))));
+            drained.map_err(|_| eyre!("Timed out draining the serial port"))???;
+            stats.record(started.elapsed());
+        }
+        Ok(())
+    }
+
+    pub async fn recv_message(&mut self) -> Result<shared::DeviceMessage> {
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(message);
+            }
+            self.read_chunk().await?;
+        }
+    }
+
+    /// Take in whatever the firmware's sent without waiting for more, so its ACKs and NAKs
+    /// are seen by commands which never call [`Self::recv_message`] too.
+    async fn read_waiting(&mut self) -> Result<()> {
+        while let Ok(read) = tokio::time::timeout(Duration::ZERO, self.read_chunk()).await {
+            read?;
+        }
+        Ok(())
+    }
+
+    // this is cancel safe, nothing is lost if it's dropped while waiting
+    async fn read_chunk(&mut self) -> Result<()> {
+        let mut chunk = Zeroizing::new([0; 64]);
+        let read = self.serial_port.read(&mut *chunk).await;
+        self.link_failed |= !matches!(read, Ok(1..));
+        let read = read?;
+        if read == 0 {
+            bail!("Serial port closed");
+        }
+        self.dump("<-", &chunk[..read], None);
+        self.rx_buf.extend_from_slice(&chunk[..read]);
+        self.take_frames();
+        Ok(())
+    }
+
+    /// Decode every whole frame received so far, dealing with those about the link itself
+    /// and keeping the rest for [`Self::recv_message`].
+    fn take_frames(&mut self) {
+        while let Some(end) = self.rx_buf.iter().position(|&byte| byte == 0) {
+            let mut frame: Zeroizing<Vec<u8>> = Zeroizing::new(self.rx_buf.drain(..=end).collect());
+            self.capture(Direction::Inbound, &frame);
+            // the rest of the frame moved down, wipe where it was
+            self.rx_buf.spare_capacity_mut().zeroize();
+            // anything which doesn't decode is line noise, skip it and wait for the next frame
+            let message = match postcard::from_bytes_cobs(&mut frame) {
+                Ok(message) => message,
+                Err(e) => {
+                    self.dump("<=", &[], Some(&e));
+                    if let Some(metrics) = &self.metrics {
+                        metrics.bad_frames.inc();
+                    }
+                    continue;
+                }
+            };
+            self.dump("<=", &[], Some(&message));
+            if let Some(metrics) = &self.metrics {
+                metrics.frames_received.inc();
+            }
+            match message {
+                shared::DeviceMessage::Overflow { dropped } => self.overflowed(dropped),
+                // sent again along with the next frame, as recv_message may be cancelled
+                shared::DeviceMessage::Nak { expected } => {
+                    self.acked(expected.wrapping_sub(1));
+                    self.resend_from = Some(expected);
+                    continue;
+                }
+                shared::DeviceMessage::Ack { seq } => {
+                    self.acked(seq);
+                    continue;
+                }
+                _ => {}
+            }
+            if self.received.len() == MAX_UNREAD {
+                self.received.pop_front();
+            }
+            self.received.push_back(message);
+        }
+    }
+
+    /// Go slower when typing for a while, as the firmware had to drop `dropped` reports, and
+    /// say again what's held with the next frame as a release may have been among them.
+    fn overflowed(&mut self, dropped: u8) {
+        self.backoff.overflowed(Instant::now());
+        self.resend_held = true;
+        if let Some(metrics) = &self.metrics {
+            metrics.reports_dropped.add(dropped.into());
+        }
+    }
+
+    /// Have the firmware switch the link from `from` baud to `to` and follow it there, or
+    /// stay at `from` if either end can't.
+    pub async fn switch_baud(&mut self, from: u32, to: u32) -> Result<()> {
+        self.send_message(shared::HostMessage::SetBaud(to)).await?;
+        let answer = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            loop {
+                if let shared::DeviceMessage::Baud(baud) = self.recv_message().await? {
+                    return Ok::<_, color_eyre::Report>(baud);
+                }
+            }
+        })
+        .await
+        .map_err(|_| eyre!("The teensy didn't answer, is its firmware too old?"))??;
+        if answer != to {
+            bail!("The teensy can't do {to} baud");
+        }
+
+        self.serial_port.set_baud(to).await?;
+        if let Err(e) = self.handshake().await {
+            // the firmware goes back too once it hasn't heard anything for long enough
+            let gone_back = Duration::from_millis(shared::BAUD_CONFIRM_MS.into());
+            tokio::time::sleep(gone_back).await;
+            self.serial_port.set_baud(from).await?;
+            // whatever came in at the wrong rate is junk
+            self.rx_buf.zeroize();
+            return Err(e.wrap_err(format!("No answer at {to} baud")));
+        }
+        Ok(())
+    }
+
+    /// Ask the firmware how it's getting on, which it answers with a
+    /// [`shared::DeviceMessage::Telemetry`].
+    pub async fn request_telemetry(&mut self) -> Result<()> {
+        self.send_message(shared::HostMessage::GetTelemetry).await
+    }
+
+    /// Which firmware the teensy is running, and what it panicked with before it last reset
+    /// if it did.
+    pub async fn identify(&mut self) -> Result<(shared::Identity, Option<String>)> {
+        self.send_message(shared::HostMessage::Identify).await?;
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            let mut crash_report: Option<Vec<u8>> = None;
+            loop {
+                match self.recv_message().await? {
+                    shared::DeviceMessage::Identity(identity) => {
+                        let crash_report =
+                            crash_report.map(|report| String::from_utf8_lossy(&report).into());
+                        return Ok((identity, crash_report));
+                    }
+                    shared::DeviceMessage::Crashed { text, first, .. } => {
+                        if first {
+                            crash_report = Some(Vec::new());
+                        }
+                        if let Some(report) = &mut crash_report {
+                            report.extend_from_slice(text.as_bytes());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| eyre!("The teensy didn't say which firmware it's running, it must be old"))?
+    }
+
+    /// What the firmware has saved in flash for `key`, or None if it hasn't.
+    pub async fn get_setting(
+        &mut self,
+        key: shared::SettingKey,
+    ) -> Result<Option<shared::Setting>> {
+        self.send_message(shared::HostMessage::GetSetting(key))
+            .await?;
+        self.setting_answer(key, HANDSHAKE_TIMEOUT).await
+    }
+
+    /// Have the firmware save `value` for `key` in flash, or forget it with None.
+    pub async fn save_setting(
+        &mut self,
+        key: shared::SettingKey,
+        value: Option<shared::Setting>,
+    ) -> Result<()> {
+        let message = match value {
+            Some(setting) => shared::HostMessage::SetSetting(setting),
+            None => shared::HostMessage::ForgetSetting(key),
+        };
+        self.send_message(message).await?;
+        // it answers with what it has either way
+        if self.setting_answer(key, SETTING_TIMEOUT).await? != value {
+            bail!("The teensy couldn't write to its flash");
+        }
+        Ok(())
+    }
+
+    async fn setting_answer(
+        &mut self,
+        key: shared::SettingKey,
+        timeout: Duration,
+    ) -> Result<Option<shared::Setting>> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let shared::DeviceMessage::Setting(answered, value) = self.recv_message().await?
+                {
+                    if answered == key {
+                        return Ok(value);
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| eyre!("The teensy didn't answer, is its firmware too old?"))?
+    }
+
+    // check the firmware is alive and that we're speaking at the same baud rate
+    pub async fn handshake(&mut self) -> Result<()> {
+        let nonce = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .subsec_nanos();
+        self.serial_port.send_break().await?;
+        self.send_message(shared::HostMessage::Hello { nonce })
+            .await?;
+
+        let reply = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            loop {
+                let message = self.recv_message().await?;
+                let shared::DeviceMessage::HelloAck { nonce: echoed } = message else {
+                    continue;
+                };
+                if echoed == nonce {
+                    return Ok(());
+                }
+            }
+        })
+        .await;
+
+        match reply {
+            Ok(result) => result,
+            Err(_) => bail!(
+                "No handshake reply after {HANDSHAKE_TIMEOUT:?}, is the firmware flashed from the \
+                same version as this?"
+            ),
+        }
+    }
+
+    fn capture(&mut self, direction: Direction, frame: &[u8]) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        // a full disk is no reason to stop forwarding, just to stop capturing
+        if let Err(e) = capture.frame(direction, frame) {
+            eprintln!("Stopped writing to the --pcap file: {e}");
+            self.capture = None;
+        }
+    }
+
+    // `->` is a frame we sent, `<-` raw bytes as they arrive and `<=` what they decoded to
+    fn dump(&self, direction: &str, bytes: &[u8], decoded: Option<&dyn std::fmt::Debug>) {
+        let Some(start) = self.debug_wire else {
+            return;
+        };
+        let mut line = format!("[{:12.6}] {direction}", start.elapsed().as_secs_f64());
+        if !bytes.is_empty() {
+            let _ = write!(line, " {}", hexdump(bytes));
+        }
+        if let Some(decoded) = decoded {
+            let _ = write!(line, " {decoded:?}");
+        }
+        eprintln!("{line}");
+    }
+}
+
+impl AsRawFd for KeypressSender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.serial_port.as_raw_fd()
+    }
+}
+
+impl KeypressSender {
+    /// A duplicate of the port's fd, which stays open whatever happens to the sender.
+    pub fn dup_port(&self) -> Result<File> {
+        // SAFETY: the serial port outlives this borrow, and we only use it to dup the fd
+        let fd = unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) };
+        Ok(File::from(fd.try_clone_to_owned()?))
+    }
+}
+
+/// How fast to go when typing text, running macros and playing things back, so the
+/// firmware's queue of reports never overflows.
+#[derive(Args, Debug, Clone, Copy, PartialEq)]
+pub struct Pacing {
+    /// type and play back this many times faster, or slower if it's less than 1
+    #[arg(long, default_value_t = 1.0, value_parser = parse_positive)]
+    pub speed: f64,
+
+    /// never send more than this many reports a second when typing
+    #[arg(long, value_parser = parse_positive)]
+    pub max_rate: Option<f64>,
+
+    /// leave at least this long between reports when typing, the firmware sends the target
+    /// one report every --report-interval-ms
+    #[arg(long, default_value_t = 1)]
+    pub min_spacing_ms: u64,
+
+    /// when typing text, pack the reports for a couple of characters into each frame for
+    /// the firmware to send on one after another, cutting the frames sent, though each
+    /// key is then only held down for one report
+    #[arg(long)]
+    pub pack_reports: bool,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            max_rate: None,
+            min_spacing_ms: 1,
+            pack_reports: false,
+        }
+    }
+}
+
+impl Pacing {
+    /// The least time between one report and the next.
+    fn spacing(&self) -> Duration {
+        let spacing = Duration::from_millis(self.min_spacing_ms);
+        match self.max_rate {
+            Some(rate) => spacing.max(Duration::from_secs_f64(1.0 / rate)),
+            None => spacing,
+        }
+    }
+
+    fn scale(&self, duration: Duration) -> Duration {
+        duration.div_f64(self.speed)
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err("expected a number bigger than 0".to_owned()),
+    }
+}
+
+/// Up to [`shared::MAX_PACKED_REPORTS`] reports in one message.
+fn pack(reports: &[KeySet]) -> shared::HostMessage {
+    let mut packed = [KeySet::new().into(); shared::MAX_PACKED_REPORTS];
+    for (slot, &report) in packed.iter_mut().zip(reports) {
+        *slot = report.into();
+    }
+    shared::HostMessage::Reports {
+        count: reports.len().min(shared::MAX_PACKED_REPORTS) as u8,
+        reports: packed,
+    }
+}
+
+/// A nonce for a hello that isn't the last one's, which the firmware would take for it
+/// being sent again and drop.
+fn fresh_nonce() -> u32 {
+    // random keys for each thread, and a different one for each call
+    RandomState::new().hash_one(()) as u32
+}
+
+/// Where the panic hook sends [`panic_frames`], swapped for the new port's when the bridge
+/// fails over.
+pub type PanicPort = Arc<Mutex<Option<File>>>;
+
+/// What the panic hook sends: a hello, which the firmware accepts whatever sequence number
+/// it was expecting, then a report with every key released.
+pub fn panic_frames() -> Result<Vec<u8>> {
+    loop {
+        let mut frames = Vec::new();
+        let messages = [
+            shared::HostMessage::Hello {
+                nonce: fresh_nonce(),
+            },
+            shared::HostMessage::Report(KeySet::new().into()),
+        ];
+        for (seq, message) in (0..).zip(&messages) {
+            let mut buf = [0; shared::MAX_FRAME_LEN];
+            frames.extend_from_slice(link::encode_host_frame(seq, message, &mut buf)?);
+        }
+        // the hook writes straight to the fd, past rfc2217's escaping, so find a nonce that
+        // doesn't need any
+        if !frames.contains(&0xFF) {
+            return Ok(frames);
+        }
+    }
+}
+
+/// Ticks whenever it's time for [`KeypressSender::send_heartbeat`], starting straight away.
+/// Once the firmware has had one it lets go of every key if they stop coming.
+pub fn heartbeat_interval() -> Interval {
+    let period = Duration::from_millis(shared::HEARTBEAT_INTERVAL_MS.into());
+    let mut interval = tokio::time::interval(period);
+    // a burst of catch up heartbeats after a long write wouldn't tell the firmware anything
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+/// Write-to-drain times of the frames sent with `--low-latency`.
+#[derive(Debug, Default)]
+pub struct DrainStats {
+    frames: u32,
+    total: Duration,
+    worst: Duration,
+}
+
+impl DrainStats {
+    fn record(&mut self, took: Duration) {
+        self.frames += 1;
+        self.total += took;
+        self.worst = self.worst.max(took);
+    }
+}
+
+impl fmt::Display for DrainStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.frames == 0 {
+            return write!(f, "no frames sent");
+        }
+        write!(
+            f,
+            "{} frames took {:?} on average to drain, {:?} at worst",
+            self.frames,
+            self.total / self.frames,
+            self.worst
+        )
+    }
+}
+
+// struct serial_struct from linux/serial.h, which libc doesn't have
+#[repr(C)]
+struct SerialStruct {
+    kind: libc::c_int,
+    line: libc::c_int,
+    port: libc::c_uint,
+    irq: libc::c_int,
+    flags: libc::c_int,
+    xmit_fifo_size: libc::c_int,
+    custom_divisor: libc::c_int,
+    baud_base: libc::c_int,
+    close_delay: libc::c_ushort,
+    io_type: libc::c_char,
+    reserved_char: [libc::c_char; 1],
+    hub6: libc::c_int,
+    closing_wait: libc::c_ushort,
+    closing_wait2: libc::c_ushort,
+    iomem_base: *mut libc::c_uchar,
+    iomem_reg_shift: libc::c_ushort,
+    port_high: libc::c_uint,
+    iomap_base: libc::c_ulong,
+}
+
+const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+fn set_async_low_latency(fd: RawFd) -> std::io::Result<()> {
+    // SAFETY: serial_struct is plain old data so all zeroes is fine, and the ioctls
+    // only read and write within it
+    unsafe {
+        let mut serial: SerialStruct = std::mem::zeroed();
+        if libc::ioctl(fd, libc::TIOCGSERIAL, &mut serial) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        serial.flags |= ASYNC_LOW_LATENCY;
+        if libc::ioctl(fd, libc::TIOCSSERIAL, &serial) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn hexdump(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for byte in bytes {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tcp_port() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut port = Port::tcp(&addr).await.unwrap();
+        let (mut teensy, _) = listener.accept().await.unwrap();
+        assert_eq!(port.name(), Some(format!("tcp://{addr}")));
+        assert!(port.tty().is_none());
+
+        // no telnet escaping, unlike rfc2217
+        port.write_all(&[0xFF, 1, 0]).await.unwrap();
+        let mut buf = [0; 3];
+        teensy.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [0xFF, 1, 0]);
+    }
+
+    /// A sender talking to a stand in for the teensy over TCP.
+    async fn tcp_sender() -> (KeypressSender, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let sender = KeypressSender::from(Port::tcp(&addr).await.unwrap());
+        let (teensy, _) = listener.accept().await.unwrap();
+        (sender, teensy)
+    }
+
+    /// The next `count` messages the teensy's been sent.
+    async fn received(teensy: &mut TcpStream, count: usize) -> Vec<shared::HostMessage> {
+        let mut reader = link::FrameReader::default();
+        let mut decoded = Vec::new();
+        while decoded.len() < count {
+            let mut chunk = [0; 64];
+            let read = teensy.read(&mut chunk).await.unwrap();
+            decoded.extend(
+                chunk[..read]
+                    .iter()
+                    .filter_map(|&byte| reader.push_host(byte))
+                    .map(|frame| frame.unwrap().message.unwrap()),
+            );
+        }
+        decoded
+    }
+
+    #[tokio::test]
+    async fn test_overflow_resends_held() {
+        let (mut sender, mut teensy) = tcp_sender().await;
+        let mut pressed = KeySet::new();
+        pressed.press_key(Key::KEY_A);
+        sender.send_state_update(pressed).await.unwrap();
+        let mut buf = [0; shared::MAX_FRAME_LEN];
+        let overflow = shared::DeviceMessage::Overflow { dropped: 1 };
+        let frame = postcard::to_slice_cobs(&overflow, &mut buf).unwrap();
+        sender.rx_buf.extend_from_slice(frame);
+        sender.take_frames();
+        sender.send_heartbeat().await.unwrap();
+
+        let decoded = received(&mut teensy, 3).await;
+        // the A that might have been dropped is held again before the heartbeat
+        for message in &decoded[..2] {
+            let shared::HostMessage::Report(report) = message else {
+                panic!("should be the keyboard, not {message:?}");
+            };
+            assert_eq!(report.keys[0], 0x04);
+        }
+        assert!(matches!(decoded[2], shared::HostMessage::Heartbeat));
+    }
+
+    #[tokio::test]
+    async fn test_bios_drops_consumer() {
+        let (mut sender, mut teensy) = tcp_sender().await;
+        sender.bios_safe();
+        sender
+            .tap_on(KeySet::new(), &[Key::KEY_PLAYCD])
+            .await
+            .unwrap();
+        sender.send_heartbeat().await.unwrap();
+
+        // only the keyboard's press and release, with nothing for the consumer control
+        let decoded = received(&mut teensy, 3).await;
+        assert!(matches!(decoded[0], shared::HostMessage::Report(_)));
+        assert!(matches!(decoded[1], shared::HostMessage::Report(_)));
+        assert!(matches!(decoded[2], shared::HostMessage::Heartbeat));
+    }
+
+    #[test]
+    fn test_drain_stats() {
+        let mut stats = DrainStats::default();
+        assert_eq!(stats.to_string(), "no frames sent");
+        stats.record(Duration::from_millis(1));
+        stats.record(Duration::from_millis(3));
+        assert_eq!(
+            stats.to_string(),
+            "2 frames took 2ms on average to drain, 3ms at worst"
+        );
+    }
+
+    #[test]
+    fn test_pacing() {
+        let pacing = Pacing::default();
+        assert_eq!(pacing.spacing(), Duration::from_millis(1));
+        assert_eq!(pacing.scale(TYPING_DELAY), TYPING_DELAY);
+
+        let pacing = Pacing {
+            speed: 2.0,
+            max_rate: Some(100.0),
+            min_spacing_ms: 1,
+            pack_reports: false,
+        };
+        assert_eq!(pacing.spacing(), Duration::from_millis(10));
+        assert_eq!(
+            pacing.scale(Duration::from_millis(500)),
+            Duration::from_millis(250)
+        );
+
+        assert!(parse_positive("0").is_err());
+        assert!(parse_positive("inf").is_err());
+        assert_eq!(parse_positive("0.5"), Ok(0.5));
+    }
+
+    #[test]
+    fn test_pack() {
+        let mut pressed = KeySet::new();
+        pressed.press_key(Key::KEY_LEFTSHIFT);
+        pressed.press_key(Key::KEY_A);
+        let reports = [pressed, KeySet::new(), pressed, KeySet::new(), pressed];
+        let message = pack(&reports);
+        let shared::HostMessage::Reports { count, reports } = message else {
+            panic!("should be packed");
+        };
+        assert_eq!(count, 4);
+        assert_eq!(reports[0].modifier, 0x02);
+        assert_eq!(reports[0].keys[0], 0x04);
+        assert_eq!(reports[1].modifier, 0);
+
+        // a full one still fits in a frame, whatever keys are in it
+        let mut six = KeySet::new();
+        for key in [
+            Key::KEY_Z,
+            Key::KEY_X,
+            Key::KEY_C,
+            Key::KEY_V,
+            Key::KEY_B,
+            Key::KEY_N,
+        ] {
+            six.press_key(key);
+        }
+        for modifier in [Key::KEY_LEFTCTRL, Key::KEY_RIGHTMETA] {
+            six.press_key(modifier);
+        }
+        let mut buf = [0; shared::MAX_FRAME_LEN];
+        assert!(link::encode_host_frame(u8::MAX, &pack(&[six; 4]), &mut buf).is_ok());
+
+        // as does a full schedule with the longest holds
+        let step = shared::ScheduledReport {
+            report: six.into(),
+            hold_ms: u16::MAX,
+        };
+        let scheduled = shared::HostMessage::Scheduled {
+            count: shared::MAX_SCHEDULED_REPORTS as u8,
+            reports: [step; shared::MAX_SCHEDULED_REPORTS],
+        };
+        assert!(link::encode_host_frame(u8::MAX, &scheduled, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn test_held() {
+        let mut held = Held::default();
+        assert_eq!(held.messages().count(), 0);
+
+        let mut pressed = KeySet::new();
+        pressed.press_key(Key::KEY_A);
+        held.record(&pack(&[KeySet::new(), pressed]));
+        held.record(&shared::HostMessage::Mouse(shared::MouseState {
+            buttons: 1,
+            x: 100,
+            y: -20,
+            wheel: 3,
+        }));
+        held.record(&shared::HostMessage::Heartbeat);
+        let messages: Vec<_> = held.messages().collect();
+        assert_eq!(messages.len(), 2);
+        // the last of a pack is what's held
+        let shared::HostMessage::Report(report) = messages[0] else {
+            panic!("should be the keyboard first");
+        };
+        assert_eq!(report.keys[0], 0x04);
+        // the buttons stay held, but the mouse doesn't move again
+        let shared::HostMessage::Mouse(mouse) = messages[1] else {
+            panic!("should be the mouse");
+        };
+        assert_eq!(
+            mouse,
+            shared::MouseState {
+                buttons: 1,
+                ..Default::default()
+            }
+        );
+
+        held.record(&shared::HostMessage::Report(KeySet::new().into()));
+        let Some(shared::HostMessage::Report(report)) = held.messages().next() else {
+            panic!("should be the keyboard first");
+        };
+        assert_eq!(report.keys, [0; 6]);
+    }
+
+    #[test]
+    fn test_panic_frames() {
+        let frames = panic_frames().unwrap();
+        // the hook skips rfc2217's escaping, which would double these
+        assert!(!frames.contains(&0xFF));
+
+        let mut reader = link::FrameReader::default();
+        let decoded: Vec<_> = frames
+            .iter()
+            .filter_map(|&byte| reader.push_host(byte))
+            .map(Result::unwrap)
+            .collect();
+        assert!(matches!(
+            decoded[0].message,
+            Some(shared::HostMessage::Hello { .. })
+        ));
+        assert!(matches!(
+            decoded[1].message,
+            Some(shared::HostMessage::Report(_))
+        ));
+        assert_eq!(decoded[1].seq, decoded[0].seq.wrapping_add(1));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let start = Instant::now();
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.at(start), Duration::ZERO);
+        for _ in 0..20 {
+            backoff.overflowed(start);
+        }
+        assert_eq!(backoff.at(start), MAX_OVERFLOW_BACKOFF);
+
+        // it wears off, a half at a time
+        assert_eq!(
+            backoff.at(start + BACKOFF_HALF_LIFE),
+            MAX_OVERFLOW_BACKOFF / 2
+        );
+        assert_eq!(
+            backoff.at(start + BACKOFF_HALF_LIFE * 2),
+            MAX_OVERFLOW_BACKOFF / 4
+        );
+        // and another overflow starts it waiting again from there
+        let later = start + BACKOFF_HALF_LIFE * 2 + Duration::from_secs(1);
+        backoff.overflowed(later);
+        let extra = MAX_OVERFLOW_BACKOFF / 4 + OVERFLOW_BACKOFF;
+        assert_eq!(backoff.at(later + BACKOFF_HALF_LIFE / 2), extra);
+        assert_eq!(backoff.at(later + BACKOFF_HALF_LIFE * 20), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(hexdump(&[]), "");
+        assert_eq!(hexdump(&[0x01, 0xab, 0x00]), "01 ab 00");
+    }
+}
@@ -0,0 +1,198 @@
+use clap::{Args, ValueEnum as _};
+use color_eyre::eyre::Result;
+use shared::{AgentText, ButtonMacro, Setting, SettingKey};
+
+use crate::{
+    keyset::{self, KeySet, Layout},
+    output::{Event, Output},
+    sender::KeypressSender,
+    SerialArgs,
+};
+
+#[derive(Args, Debug)]
+pub struct SettingsArgs {
+    /// save the keyboard layout the target is set to on the teensy, which every client
+    /// then types text for unless it's given --layout
+    #[arg(long, value_enum)]
+    save_layout: Option<Layout>,
+
+    /// forget the saved layout, so clients go back to guessing
+    #[arg(long, conflicts_with = "save_layout")]
+    forget_layout: bool,
+
+    /// save what the button on pin 4 types on the target, up to 12 chords separated by
+    /// spaces, each any modifiers and one other key, e.g. "ctrl+alt+del" or
+    /// "scrolllock scrolllock" for a KVM's menu
+    #[arg(long, value_parser = parse_button_macro)]
+    save_button_macro: Option<ButtonMacro>,
+
+    /// forget the button's macro, so it does nothing
+    #[arg(long, conflicts_with = "save_button_macro")]
+    forget_button_macro: bool,
+
+    /// save how many milliseconds apart the teensy sends the target keyboard reports, up
+    /// to 20, which it starts with from then on and clients use unless given
+    /// --report-interval-ms
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(1..=i64::from(shared::MAX_REPORT_INTERVAL_MS))
+    )]
+    save_report_interval: Option<u8>,
+
+    /// forget the saved report interval, so it's back to one a millisecond
+    #[arg(long, conflicts_with = "save_report_interval")]
+    forget_report_interval: bool,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Save or forget any settings asked to, then show everything the teensy has saved.
+pub async fn settings(args: SettingsArgs, output: Output) -> Result<()> {
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    if let Some(layout) = args.save_layout {
+        let setting = layout_setting(layout);
+        sender.save_setting(setting.key(), Some(setting)).await?;
+    }
+    if args.forget_layout {
+        sender.save_setting(SettingKey::Layout, None).await?;
+    }
+    if let Some(button_macro) = args.save_button_macro {
+        let setting = Setting::ButtonMacro(button_macro);
+        sender.save_setting(setting.key(), Some(setting)).await?;
+    }
+    if args.forget_button_macro {
+        sender.save_setting(SettingKey::ButtonMacro, None).await?;
+    }
+    if let Some(ms) = args.save_report_interval {
+        let setting = Setting::ReportInterval(ms);
+        sender.save_setting(setting.key(), Some(setting)).await?;
+    }
+    if args.forget_report_interval {
+        sender
+            .save_setting(SettingKey::ReportInterval, None)
+            .await?;
+    }
+
+    for key in SettingKey::ALL {
+        let value = sender.get_setting(key).await?;
+        output.event(&Event::Setting {
+            name: name(key),
+            value: value.map(describe),
+        });
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+/// The layout saved on the teensy, if there is one this client has a table for, and the
+/// firmware's new enough to have settings.
+pub async fn saved_layout(sender: &mut KeypressSender, output: Output) -> Option<Layout> {
+    let setting = sender.get_setting(SettingKey::Layout).await.ok()??;
+    let name = describe(setting);
+    let layout = Layout::from_str(&name, false).ok();
+    match layout {
+        Some(_) => output.status(format!("Typing text for the teensy's saved {name} layout.")),
+        None => output.status(format!("There's no table for typing in the {name} layout.")),
+    }
+    layout
+}
+
+/// The report interval saved on the teensy, if the firmware's new enough to have settings
+/// and one's been saved.
+pub async fn saved_report_interval(sender: &mut KeypressSender) -> Option<u8> {
+    let setting = sender
+        .get_setting(SettingKey::ReportInterval)
+        .await
+        .ok()??;
+    match setting {
+        Setting::ReportInterval(ms) => Some(ms),
+        _ => None,
+    }
+}
+
+fn layout_setting(layout: Layout) -> Setting {
+    let value = layout.to_possible_value().expect("no layouts are skipped");
+    Setting::Layout(AgentText::new(value.get_name().as_bytes()))
+}
+
+fn name(key: SettingKey) -> &'static str {
+    match key {
+        SettingKey::Layout => "layout",
+        SettingKey::ButtonMacro => "button-macro",
+        SettingKey::ReportInterval => "report-interval",
+    }
+}
+
+fn describe(setting: Setting) -> String {
+    match setting {
+        Setting::Layout(name) => String::from_utf8_lossy(name.as_bytes()).into_owned(),
+        Setting::ButtonMacro(button_macro) => {
+            let chords = button_macro.chords().iter();
+            let names: Vec<_> = chords
+                .map(|&(modifier, usage)| keyset::chord_name(modifier, usage))
+                .collect();
+            names.join(" ")
+        }
+        Setting::ReportInterval(ms) => format!("{ms}ms"),
+    }
+}
+
+fn parse_button_macro(chords: &str) -> Result<ButtonMacro, String> {
+    let chords = chords
+        .split_whitespace()
+        .map(|chord| {
+            let mut pressed = KeySet::new();
+            for key in keyset::parse_chord(chord)? {
+                pressed.press_key(key);
+            }
+            match shared::WhyNoDeriveDeserializeManSadFaceHere::from(pressed) {
+                report @ shared::WhyNoDeriveDeserializeManSadFaceHere {
+                    keys: [key, 0, 0, 0, 0, 0],
+                    ..
+                } => Ok((report.modifier, key)),
+                _ => Err(format!("{chord:?} has more than one key besides modifiers")),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if chords.is_empty() || chords.len() > shared::BUTTON_MACRO_LEN {
+        return Err(format!(
+            "a macro has 1 to {} chords, not {}",
+            shared::BUTTON_MACRO_LEN,
+            chords.len()
+        ));
+    }
+    Ok(ButtonMacro::new(&chords))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_setting() {
+        for &layout in Layout::value_variants() {
+            let name = describe(layout_setting(layout));
+            assert_eq!(Layout::from_str(&name, false), Ok(layout));
+        }
+        assert_eq!(describe(layout_setting(Layout::UsIntl)), "us-intl");
+    }
+
+    #[test]
+    fn test_button_macro() {
+        let button_macro = parse_button_macro("ctrl+alt+del  scrolllock scrolllock").unwrap();
+        assert_eq!(button_macro.chords(), [(0x05, 0x4c), (0, 0x47), (0, 0x47)]);
+        let described = describe(Setting::ButtonMacro(button_macro));
+        assert_eq!(described, "leftctrl+leftalt+delete scrolllock scrolllock");
+        assert_eq!(parse_button_macro(&described), Ok(button_macro));
+
+        assert_eq!(
+            parse_button_macro("shift+A ctrl").unwrap().chords(),
+            [(0x02, 0x04), (0x01, 0)]
+        );
+        assert!(parse_button_macro("ctrl+a+b").is_err());
+        assert!(parse_button_macro("").is_err());
+        assert!(parse_button_macro(&"a ".repeat(13)).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use std::{fmt, time::Duration};
+
+use clap::Args;
+use color_eyre::eyre::{bail, Result};
+use evdev::Key;
+use serde::Serialize;
+use tokio::{select, time::Instant};
+
+use crate::{
+    agent,
+    keyset::KeySet,
+    output::{Event, Output},
+    sender::{self, KeypressSender},
+    SerialArgs,
+};
+
+/// The keys pressed and released in turn, which nothing much uses.
+const PATTERN: [Key; 12] = [
+    Key::KEY_F13,
+    Key::KEY_F14,
+    Key::KEY_F15,
+    Key::KEY_F16,
+    Key::KEY_F17,
+    Key::KEY_F18,
+    Key::KEY_F19,
+    Key::KEY_F20,
+    Key::KEY_F21,
+    Key::KEY_F22,
+    Key::KEY_F23,
+    Key::KEY_F24,
+];
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long the agent has to see scroll lock change, it only looks every 500ms by default.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Args, Debug)]
+pub struct SoakArgs {
+    /// how many hours to run for, Ctrl+C stops early and still prints the summary
+    #[arg(long, default_value_t = 8)]
+    hours: u64,
+
+    /// how many key events to send a second, each one a press or a release
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    rate: u32,
+
+    /// with `client agent` running on the target, tap scroll lock this often and check
+    /// the agent sees it change, to catch presses and releases going missing
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    check_every_secs: u64,
+
+    /// how often to print the summary so far
+    #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    report_every_mins: u64,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// What a soak test has seen so far.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct SoakReport {
+    pub elapsed_secs: u64,
+    pub events_sent: u64,
+    pub probes_sent: u64,
+    /// Probes answered within [`PROBE_TIMEOUT`].
+    pub probes_answered: u64,
+    pub worst_round_trip_ms: f64,
+    /// Scroll lock taps the agent was asked to see, none without the agent.
+    pub lock_checks: u64,
+    /// Taps the agent didn't see, where a press or a release went missing.
+    pub lock_checks_failed: u64,
+    /// Keyboard reports the teensy had no room for.
+    pub reports_dropped: u64,
+    /// How many times the serial port had to be opened again.
+    pub reconnects: u64,
+    pub downtime_secs: u64,
+}
+
+impl SoakReport {
+    /// Whether anything went wrong, for the exit status.
+    pub fn problems(&self) -> bool {
+        self.probes_answered < self.probes_sent
+            || self.lock_checks_failed > 0
+            || self.reports_dropped > 0
+            || self.reconnects > 0
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hours = self.elapsed_secs as f64 / 3600.0;
+        writeln!(
+            f,
+            "In {hours:.2} hours: sent {} key events",
+            self.events_sent
+        )?;
+        writeln!(
+            f,
+            "{} of {} probes answered, the slowest in {:.1}ms",
+            self.probes_answered, self.probes_sent, self.worst_round_trip_ms
+        )?;
+        if self.lock_checks == 0 {
+            writeln!(f, "No lock checks, the agent isn't running on the target")?;
+        } else {
+            writeln!(
+                f,
+                "{} of {} lock checks failed",
+                self.lock_checks_failed, self.lock_checks
+            )?;
+        }
+        if self.reports_dropped > 0 {
+            writeln!(f, "The teensy dropped {} reports", self.reports_dropped)?;
+        }
+        write!(
+            f,
+            "Reconnected {} times, down for {}s in all",
+            self.reconnects, self.downtime_secs
+        )
+    }
+}
+
+/// Send a repeating pattern of key presses and releases for hours on end, checking the
+/// teensy keeps answering, that key presses and releases arrive when the agent is there to
+/// see them, and opening the serial port again if it goes away, then sum it all up.
+pub async fn soak(args: SoakArgs, output: Output) -> Result<()> {
+    let mut serial = args.serial;
+    let mut sender = crate::open_sender(serial.clone(), output).await?;
+    // come back to the same port, rather than asking which again
+    serial.send_to = sender.port_name().or(serial.send_to);
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.hours * 3600);
+    let mut soak = Soak {
+        started,
+        period: Duration::from_secs(1) / args.rate,
+        check_every: Duration::from_secs(args.check_every_secs),
+        report_every: Duration::from_secs(args.report_every_mins * 60),
+        output,
+        sent: 0,
+        probe: None,
+        check: None,
+        agent: agent::Listener::default(),
+        report: SoakReport::default(),
+    };
+    let mut stopped = false;
+    while !stopped {
+        let ran = soak.run(&mut sender, deadline).await;
+        let e = match ran {
+            Ok(()) => break,
+            Err(e) => e,
+        };
+        output.event(&Event::Error {
+            message: format!("Lost the teensy: {e:#}"),
+        });
+        let lost = Instant::now();
+        loop {
+            if Instant::now() >= deadline {
+                stopped = true;
+                break;
+            }
+            select! {
+                _ = tokio::signal::ctrl_c() => {
+                    stopped = true;
+                    break;
+                }
+                _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+            }
+            if let Ok(reopened) = crate::open_sender(serial.clone(), output).await {
+                sender = reopened;
+                soak.report.reconnects += 1;
+                break;
+            }
+        }
+        soak.report.downtime_secs += lost.elapsed().as_secs();
+        // anything in flight went with the old port, and counts as lost
+        soak.probe = None;
+        soak.check = None;
+    }
+
+    // whatever happened, leave nothing held down
+    let _ = sender.send_state_update(KeySet::new()).await;
+    // and anything still in flight hasn't had its chance
+    if soak
+        .probe
+        .is_some_and(|(_, at)| at.elapsed() < PROBE_TIMEOUT)
+    {
+        soak.report.probes_sent -= 1;
+    }
+    if soak
+        .check
+        .is_some_and(|(_, at)| at.elapsed() < CHECK_TIMEOUT)
+    {
+        soak.report.lock_checks -= 1;
+    }
+    soak.report.elapsed_secs = started.elapsed().as_secs();
+    output.event(&Event::Soak(&soak.report));
+    output.event(&Event::Disconnected);
+    if soak.report.problems() {
+        bail!("The soak test saw problems, see the summary above");
+    }
+    Ok(())
+}
+
+struct Soak {
+    started: Instant,
+    period: Duration,
+    check_every: Duration,
+    report_every: Duration,
+    output: Output,
+    // how many events of the pattern have gone out
+    sent: u64,
+    // the nonce of the probe in flight and when it went
+    probe: Option<(u64, Instant)>,
+    // what scroll lock should change to and when it was tapped, until the agent sees it
+    check: Option<(bool, Instant)>,
+    agent: agent::Listener,
+    report: SoakReport,
+}
+
+impl Soak {
+    /// Carry on until `deadline` or Ctrl+C, or until the link fails.
+    async fn run(&mut self, sender: &mut KeypressSender, deadline: Instant) -> Result<()> {
+        let mut events = tokio::time::interval(self.period);
+        let mut heartbeat = sender::heartbeat_interval();
+        let mut probes = tokio::time::interval(PROBE_INTERVAL);
+        let mut checks = tokio::time::interval(self.check_every);
+        let mut reports = tokio::time::interval(self.report_every);
+        // the first tick is straight away, and there's nothing to say yet
+        reports.tick().await;
+        loop {
+            select! {
+                _ = tokio::time::sleep_until(deadline) => return Ok(()),
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+                _ = events.tick() => {
+                    sender.send_state_update(pattern(self.sent)).await?;
+                    self.sent += 1;
+                    self.report.events_sent += 1;
+                }
+                _ = heartbeat.tick() => sender.send_heartbeat().await?,
+                _ = probes.tick() => self.send_probe(sender).await?,
+                _ = checks.tick() => self.start_check(sender).await?,
+                message = sender.recv_message() => self.handle_message(message?),
+                _ = reports.tick() => {
+                    self.report.elapsed_secs = self.started.elapsed().as_secs();
+                    self.output.event(&Event::Soak(&self.report));
+                }
+            }
+        }
+    }
+
+    async fn send_probe(&mut self, sender: &mut KeypressSender) -> Result<()> {
+        if self
+            .probe
+            .is_some_and(|(_, at)| at.elapsed() < PROBE_TIMEOUT)
+        {
+            return Ok(());
+        }
+        let nonce = self.report.probes_sent;
+        self.report.probes_sent += 1;
+        self.probe = Some((nonce, Instant::now()));
+        // the nonce only has to tell this probe from the last few
+        let nonce = nonce as u32;
+        sender
+            .send_message(shared::HostMessage::Probe { nonce })
+            .await
+    }
+
+    /// Tap scroll lock, if the agent has told us whether it's on so we know what to expect.
+    async fn start_check(&mut self, sender: &mut KeypressSender) -> Result<()> {
+        if let Some((_, at)) = self.check {
+            if at.elapsed() < CHECK_TIMEOUT {
+                return Ok(());
+            }
+            self.check = None;
+            self.report.lock_checks_failed += 1;
+            self.output.event(&Event::Error {
+                message: "The agent didn't see scroll lock change".to_owned(),
+            });
+        }
+        let Some(leds) = self.agent.report().leds else {
+            return Ok(());
+        };
+        let scroll_lock = leds & shared::LED_SCROLL_LOCK != 0;
+        // on top of whatever the pattern is holding down
+        let held = pattern(self.sent.wrapping_sub(1));
+        sender.tap_on(held, &[Key::KEY_SCROLLLOCK]).await?;
+        self.report.lock_checks += 1;
+        self.check = Some((!scroll_lock, Instant::now()));
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: shared::DeviceMessage) {
+        match message {
+            shared::DeviceMessage::HelloAck { nonce } => {
+                let Some((sent, at)) = self.probe else {
+                    return;
+                };
+                if sent as u32 == nonce && at.elapsed() < PROBE_TIMEOUT {
+                    self.probe = None;
+                    self.report.probes_answered += 1;
+                    let ms = at.elapsed().as_secs_f64() * 1e3;
+                    self.report.worst_round_trip_ms = self.report.worst_round_trip_ms.max(ms);
+                }
+            }
+            shared::DeviceMessage::Baud(_)
+            | shared::DeviceMessage::Setting(..)
+            | shared::DeviceMessage::Identity(_)
+            | shared::DeviceMessage::Telemetry(_)
+            | shared::DeviceMessage::Nak { .. }
+            | shared::DeviceMessage::Ack { .. }
+            | shared::DeviceMessage::Crashed { .. } => {}
+            shared::DeviceMessage::Overflow { dropped } => {
+                self.report.reports_dropped += u64::from(dropped);
+            }
+            shared::DeviceMessage::Agent(message) => {
+                self.agent.handle(message);
+                let (Some((expected, _)), Some(leds)) = (self.check, self.agent.report().leds)
+                else {
+                    return;
+                };
+                if (leds & shared::LED_SCROLL_LOCK != 0) == expected {
+                    self.check = None;
+                }
+            }
+        }
+    }
+}
+
+/// What's held down after event `i` of the pattern, each key pressed then released in turn.
+fn pattern(i: u64) -> KeySet {
+    let mut keys = KeySet::new();
+    if i.is_multiple_of(2) {
+        keys.press_key(PATTERN[(i / 2) as usize % PATTERN.len()]);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        assert!(pattern(0).is_pressed(Key::KEY_F13));
+        assert_eq!(pattern(1), KeySet::new());
+        assert!(pattern(2).is_pressed(Key::KEY_F14));
+        assert!(pattern(24).is_pressed(Key::KEY_F13));
+        // the one before the first is a release, so nothing is held
+        assert_eq!(pattern(u64::MAX), KeySet::new());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = SoakReport {
+            elapsed_secs: 7200,
+            events_sent: 144_000,
+            probes_sent: 7200,
+            probes_answered: 7200,
+            worst_round_trip_ms: 4.3,
+            ..SoakReport::default()
+        };
+        assert!(!report.problems());
+        let summary = report.to_string();
+        assert!(summary.contains("In 2.00 hours: sent 144000 key events"));
+        assert!(summary.contains("the slowest in 4.3ms"));
+        assert!(summary.contains("No lock checks"));
+
+        report.lock_checks = 120;
+        report.lock_checks_failed = 1;
+        assert!(report.problems());
+        assert!(report.to_string().contains("1 of 120 lock checks failed"));
+
+        report.lock_checks_failed = 0;
+        report.reports_dropped = 3;
+        assert!(report.problems());
+        assert!(report.to_string().contains("The teensy dropped 3 reports"));
+    }
+}
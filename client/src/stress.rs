@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use tokio::{
+    select,
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    keyset::{self, KeySet},
+    output::{Event, Output},
+    sender::{self, KeypressSender},
+    SerialArgs,
+};
+
+/// How often to send the teensy a probe while flooding it, to see whether it's keeping up.
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+/// A probe which takes longer than this to answer counts as lost, it's stuck in a queue.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+/// The share of the events asked for which has to go out for a rate to count as sustained.
+const SUSTAINED: f64 = 0.99;
+
+#[derive(Args, Debug)]
+pub struct StressArgs {
+    /// how many key events to send a second, each one a press or a release
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    rate: u32,
+
+    /// how many seconds to keep it up for
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    duration: u64,
+
+    /// after --duration at --rate, double the rate and go again until the link can't keep
+    /// up, to find the fastest it can
+    #[arg(long)]
+    ramp: bool,
+
+    /// the key or chord to press and release over and over, best something which does
+    /// nothing on the target
+    #[arg(long, default_value = "f24")]
+    key: String,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// How one rate went.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StressResult {
+    pub rate: u32,
+    /// Key events a second which actually went out.
+    pub achieved: f64,
+    pub probes_sent: u32,
+    pub probes_answered: u32,
+    /// The longest a probe took to be answered.
+    pub worst_round_trip: Duration,
+    /// Whether a write failed or timed out or the port closed, which ends the run.
+    pub link_failed: bool,
+}
+
+impl StressResult {
+    /// Whether the client got everything out and the teensy answered every probe in time.
+    pub fn sustained(&self) -> bool {
+        self.achieved >= f64::from(self.rate) * SUSTAINED
+            && self.probes_answered == self.probes_sent
+            && !self.link_failed
+    }
+
+    fn event(&self) -> Event<'static> {
+        Event::Stress {
+            rate: self.rate,
+            achieved: self.achieved,
+            probes_sent: self.probes_sent,
+            probes_answered: self.probes_answered,
+            worst_round_trip_ms: self.worst_round_trip.as_secs_f64() * 1e3,
+            sustained: self.sustained(),
+        }
+    }
+}
+
+/// Flood the teensy with key presses and releases, checking it keeps up by timing probes
+/// sent in between, to find how fast the link and firmware can go with these settings.
+/// The firmware doesn't say when it drops something, so a probe which goes unanswered,
+/// or answered late, stands in for its queues overflowing.
+pub async fn stress(args: StressArgs, output: Output) -> Result<()> {
+    let chord = keyset::parse_chord(&args.key).map_err(|e| eyre!("Bad --key: {e}"))?;
+    let mut pressed = KeySet::new();
+    for key in chord {
+        pressed.press_key(key);
+    }
+    let duration = Duration::from_secs(args.duration);
+
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    let mut fastest = None;
+    let mut rate = args.rate;
+    loop {
+        output.status(format!(
+            "Sending {rate} key events a second for {duration:?}"
+        ));
+        let result = run_at(&mut sender, pressed, rate, duration).await;
+        // whatever happened, leave nothing held down
+        let released = sender.send_state_update(KeySet::new()).await;
+        output.event(&result.event());
+        if result.link_failed || released.is_err() || !result.sustained() {
+            break;
+        }
+        fastest = Some(rate);
+        match rate.checked_mul(2).filter(|_| args.ramp) {
+            Some(doubled) => rate = doubled,
+            None => break,
+        }
+    }
+
+    match fastest {
+        Some(rate) => output.status(format!("The link kept up with {rate} key events a second")),
+        None => output.status(format!(
+            "The link couldn't keep up with {} a second",
+            args.rate
+        )),
+    }
+    output.event(&Event::Disconnected);
+    Ok(())
+}
+
+async fn run_at(
+    sender: &mut KeypressSender,
+    pressed: KeySet,
+    rate: u32,
+    duration: Duration,
+) -> StressResult {
+    let mut result = StressResult {
+        rate,
+        ..StressResult::default()
+    };
+    let period = Duration::from_secs(1) / rate;
+    let mut events = tokio::time::interval(period);
+    // behind schedule is what we're here to find out, so carry on as fast as we can
+    events.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let mut probes = tokio::time::interval(PROBE_INTERVAL);
+    let mut heartbeat = sender::heartbeat_interval();
+    // the nonce of the probe in flight, and when it went
+    let mut probe: Option<(u32, Instant)> = None;
+
+    let started = Instant::now();
+    let deadline = started + duration;
+    let mut sent = 0u64;
+    loop {
+        let carried_on = select! {
+            _ = tokio::time::sleep_until(deadline) => break,
+            _ = events.tick() => {
+                let state = if sent.is_multiple_of(2) { pressed } else { KeySet::new() };
+                sent += 1;
+                sender.send_state_update(state).await
+            }
+            _ = heartbeat.tick() => sender.send_heartbeat().await,
+            _ = probes.tick() => {
+                if let Some((_, at)) = probe {
+                    if at.elapsed() > PROBE_TIMEOUT {
+                        probe = None;
+                    }
+                }
+                if probe.is_some() {
+                    continue;
+                }
+                let nonce = result.probes_sent;
+                result.probes_sent += 1;
+                probe = Some((nonce, Instant::now()));
+                sender.send_message(shared::HostMessage::Probe { nonce }).await
+            }
+            message = sender.recv_message() => message.map(|message| {
+                let shared::DeviceMessage::HelloAck { nonce } = message else {
+                    return;
+                };
+                if let Some((_, at)) = probe.filter(|&(probe_nonce, _)| probe_nonce == nonce) {
+                    result.probes_answered += 1;
+                    result.worst_round_trip = result.worst_round_trip.max(at.elapsed());
+                    probe = None;
+                }
+            }),
+        };
+        if carried_on.is_err() {
+            result.link_failed = true;
+            break;
+        }
+    }
+    // one still in flight at the end hasn't had its chance
+    if let Some((_, at)) = probe {
+        if at.elapsed() <= PROBE_TIMEOUT {
+            result.probes_sent -= 1;
+        }
+    }
+    result.achieved = sent as f64 / started.elapsed().as_secs_f64();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sustained() {
+        let result = StressResult {
+            rate: 1000,
+            achieved: 995.0,
+            probes_sent: 10,
+            probes_answered: 10,
+            ..StressResult::default()
+        };
+        assert!(result.sustained());
+        let slow = StressResult {
+            achieved: 900.0,
+            ..result
+        };
+        assert!(!slow.sustained());
+        let lost = StressResult {
+            probes_answered: 9,
+            ..result
+        };
+        assert!(!lost.sustained());
+    }
+}
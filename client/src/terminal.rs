@@ -0,0 +1,297 @@
+use std::{
+    os::fd::{AsRawFd as _, RawFd},
+    time::Duration,
+};
+
+use clap::Args;
+use color_eyre::eyre::{Result, WrapErr as _};
+use evdev::Key;
+use termios::{cfmakeraw, tcsetattr, Termios, TCSANOW};
+use tokio::{
+    io::AsyncReadExt as _,
+    select,
+    signal::unix::{signal, SignalKind},
+};
+
+use crate::{
+    keyset,
+    output::{Event, Output},
+    SerialArgs,
+};
+
+/// Ctrl+], the same as telnet, as every other control character goes to the target.
+const EXIT_BYTE: u8 = 0x1d;
+
+/// An escape on its own is the Escape key, rather than the start of a sequence, if nothing
+/// follows it for this long.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Args, Debug)]
+pub struct TerminalArgs {
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// Put the terminal into raw mode and type whatever it sends onto the target, for when
+/// there's a terminal but no keyboard device to read, e.g. over ssh.
+pub async fn run(args: TerminalArgs, output: Output) -> Result<()> {
+    let mut sender = crate::open_sender(args.serial, output).await?;
+    output.status("Forwarding this terminal, press Ctrl+] to stop.");
+
+    let _raw = RawMode::enter(std::io::stdin().as_raw_fd())
+        .wrap_err("Failed to put the terminal into raw mode, is stdin a terminal?")?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut stdin = tokio::io::stdin();
+    let mut parser = Parser::default();
+    let mut buf = [0; 64];
+    'forward: loop {
+        // reading stdin is cancel safe, tokio holds on to anything already read
+        let read = select! {
+            _ = sigterm.recv() => break,
+            read = stdin.read(&mut buf) => read?,
+            _ = tokio::time::sleep(ESCAPE_TIMEOUT), if parser.is_pending() => {
+                if let Some(chord) = parser.flush() {
+                    sender.tap(&chord).await?;
+                }
+                continue;
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        for &byte in &buf[..read] {
+            if byte == EXIT_BYTE {
+                break 'forward;
+            }
+            if let Some(chord) = parser.feed(byte) {
+                sender.tap(&chord).await?;
+            }
+        }
+    }
+    output.event(&Event::Disconnected);
+
+    Ok(())
+}
+
+/// Puts the terminal back how it was when dropped.
+struct RawMode {
+    fd: RawFd,
+    original: Termios,
+}
+
+impl RawMode {
+    fn enter(fd: RawFd) -> std::io::Result<Self> {
+        let original = Termios::from_fd(fd)?;
+        let mut raw = original;
+        cfmakeraw(&mut raw);
+        tcsetattr(fd, TCSANOW, &raw)?;
+        Ok(Self { fd, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // nothing to be done if we can't, the shell's `reset` will have to do
+        let _ = tcsetattr(self.fd, TCSANOW, &self.original);
+    }
+}
+
+/// Turns the bytes a terminal sends, escape sequences and all, back into key chords.
+#[derive(Debug, Default)]
+struct Parser {
+    state: State,
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Ground,
+    /// Just had an escape.
+    Escape,
+    /// In an `ESC [` sequence, with the parameter bytes so far.
+    Csi(Vec<u8>),
+    /// Had `ESC O`, which one more byte finishes.
+    Ss3,
+}
+
+impl Parser {
+    /// Whether there's an escape which could still be the start of a sequence.
+    fn is_pending(&self) -> bool {
+        matches!(self.state, State::Escape)
+    }
+
+    /// Give up waiting for the rest of a sequence, an escape on its own is the Escape key.
+    fn flush(&mut self) -> Option<Vec<Key>> {
+        match std::mem::take(&mut self.state) {
+            State::Escape => Some(vec![Key::KEY_ESC]),
+            _ => None,
+        }
+    }
+
+    /// The chord `byte` finishes, if any. Anything which doesn't mean a key is dropped.
+    fn feed(&mut self, byte: u8) -> Option<Vec<Key>> {
+        match std::mem::take(&mut self.state) {
+            State::Ground if byte == 0x1b => {
+                self.state = State::Escape;
+                None
+            }
+            State::Ground => chord_for_byte(byte),
+            State::Escape => match byte {
+                b'[' => {
+                    self.state = State::Csi(Vec::new());
+                    None
+                }
+                b'O' => {
+                    self.state = State::Ss3;
+                    None
+                }
+                // escape pressed twice
+                0x1b => {
+                    self.state = State::Escape;
+                    Some(vec![Key::KEY_ESC])
+                }
+                // terminals send alt+x as escape then x
+                _ => {
+                    let mut chord = chord_for_byte(byte)?;
+                    chord.insert(0, Key::KEY_LEFTALT);
+                    Some(chord)
+                }
+            },
+            State::Csi(mut params) => match byte {
+                0x20..=0x3f => {
+                    params.push(byte);
+                    self.state = State::Csi(params);
+                    None
+                }
+                _ => csi_chord(&params, byte),
+            },
+            State::Ss3 => csi_chord(&[], byte),
+        }
+    }
+}
+
+/// The chord for a byte outside of an escape sequence.
+fn chord_for_byte(byte: u8) -> Option<Vec<Key>> {
+    let chord = match byte {
+        b'\r' | b'\n' => vec![Key::KEY_ENTER],
+        b'\t' => vec![Key::KEY_TAB],
+        0x7f | 0x08 => vec![Key::KEY_BACKSPACE],
+        0x00 => vec![Key::KEY_LEFTCTRL, Key::KEY_SPACE],
+        0x01..=0x1a => {
+            let (key, _) = keyset::key_for_char(char::from(b'a' + byte - 1))?;
+            vec![Key::KEY_LEFTCTRL, key]
+        }
+        // anything past ascii is part of a character we couldn't type anyway
+        _ if !byte.is_ascii() => return None,
+        _ => match keyset::key_for_char(char::from(byte))? {
+            (key, true) => vec![Key::KEY_LEFTSHIFT, key],
+            (key, false) => vec![key],
+        },
+    };
+    Some(chord)
+}
+
+/// The chord for a CSI or SS3 sequence ending in `last`, e.g. `ESC [ 1 ; 5 C` for ctrl+right.
+fn csi_chord(params: &[u8], last: u8) -> Option<Vec<Key>> {
+    let params = std::str::from_utf8(params).ok()?;
+    let mut params = params.split(';').map(|param| param.parse::<u8>().ok());
+    let first = params.next().flatten();
+    // one more than a bitmask of shift, alt, ctrl and meta
+    let modifiers = params.next().flatten().unwrap_or(1).saturating_sub(1);
+
+    let key = match (last, first) {
+        (b'A', _) => Key::KEY_UP,
+        (b'B', _) => Key::KEY_DOWN,
+        (b'C', _) => Key::KEY_RIGHT,
+        (b'D', _) => Key::KEY_LEFT,
+        (b'H', _) => Key::KEY_HOME,
+        (b'F', _) => Key::KEY_END,
+        (b'P', _) => Key::KEY_F1,
+        (b'Q', _) => Key::KEY_F2,
+        (b'R', _) => Key::KEY_F3,
+        (b'S', _) => Key::KEY_F4,
+        (b'Z', _) => return Some(vec![Key::KEY_LEFTSHIFT, Key::KEY_TAB]),
+        (b'~', Some(1 | 7)) => Key::KEY_HOME,
+        (b'~', Some(2)) => Key::KEY_INSERT,
+        (b'~', Some(3)) => Key::KEY_DELETE,
+        (b'~', Some(4 | 8)) => Key::KEY_END,
+        (b'~', Some(5)) => Key::KEY_PAGEUP,
+        (b'~', Some(6)) => Key::KEY_PAGEDOWN,
+        (b'~', Some(11)) => Key::KEY_F1,
+        (b'~', Some(12)) => Key::KEY_F2,
+        (b'~', Some(13)) => Key::KEY_F3,
+        (b'~', Some(14)) => Key::KEY_F4,
+        (b'~', Some(15)) => Key::KEY_F5,
+        (b'~', Some(17)) => Key::KEY_F6,
+        (b'~', Some(18)) => Key::KEY_F7,
+        (b'~', Some(19)) => Key::KEY_F8,
+        (b'~', Some(20)) => Key::KEY_F9,
+        (b'~', Some(21)) => Key::KEY_F10,
+        (b'~', Some(23)) => Key::KEY_F11,
+        (b'~', Some(24)) => Key::KEY_F12,
+        _ => return None,
+    };
+
+    let mut chord = Vec::new();
+    for (bit, modifier) in [
+        Key::KEY_LEFTSHIFT,
+        Key::KEY_LEFTALT,
+        Key::KEY_LEFTCTRL,
+        Key::KEY_LEFTMETA,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if modifiers & (1 << bit) != 0 {
+            chord.push(modifier);
+        }
+    }
+    chord.push(key);
+    Some(chord)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_all(parser: &mut Parser, bytes: &[u8]) -> Vec<Vec<Key>> {
+        bytes.iter().filter_map(|&byte| parser.feed(byte)).collect()
+    }
+
+    #[test]
+    fn test_plain_bytes() {
+        let mut parser = Parser::default();
+        assert_eq!(
+            feed_all(&mut parser, b"aB\r\x03\x7f"),
+            [
+                vec![Key::KEY_A],
+                vec![Key::KEY_LEFTSHIFT, Key::KEY_B],
+                vec![Key::KEY_ENTER],
+                vec![Key::KEY_LEFTCTRL, Key::KEY_C],
+                vec![Key::KEY_BACKSPACE],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let mut parser = Parser::default();
+        assert_eq!(
+            feed_all(&mut parser, b"\x1b[A\x1b[1;5C\x1b[3~\x1bOP\x1b[15;2~\x1bx"),
+            [
+                vec![Key::KEY_UP],
+                vec![Key::KEY_LEFTCTRL, Key::KEY_RIGHT],
+                vec![Key::KEY_DELETE],
+                vec![Key::KEY_F1],
+                vec![Key::KEY_LEFTSHIFT, Key::KEY_F5],
+                vec![Key::KEY_LEFTALT, Key::KEY_X],
+            ]
+        );
+
+        // a lone escape only counts once we've given up waiting for more
+        assert_eq!(parser.feed(0x1b), None);
+        assert!(parser.is_pending());
+        assert_eq!(parser.flush(), Some(vec![Key::KEY_ESC]));
+        assert!(!parser.is_pending());
+    }
+}
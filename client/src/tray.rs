@@ -0,0 +1,119 @@
+use color_eyre::eyre::{Result, WrapErr as _};
+use ksni::{menu::StandardItem, MenuItem, TrayMethods as _};
+use tokio::sync::{mpsc, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::bridge::{Control, Status};
+
+/// A StatusNotifierItem showing whether we're forwarding, with a menu to pause or stop.
+struct Tray {
+    target: String,
+    paused: bool,
+    control: mpsc::UnboundedSender<Control>,
+    token: CancellationToken,
+}
+
+impl ksni::Tray for Tray {
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").into()
+    }
+
+    fn title(&self) -> String {
+        "Teensy keyboard bridge".into()
+    }
+
+    fn icon_name(&self) -> String {
+        if self.paused {
+            "input-keyboard-symbolic".into()
+        } else {
+            "input-keyboard".into()
+        }
+    }
+
+    fn status(&self) -> ksni::Status {
+        if self.paused {
+            ksni::Status::Passive
+        } else {
+            ksni::Status::Active
+        }
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        let state = if self.paused { "Paused" } else { "Forwarding" };
+        ksni::ToolTip {
+            title: self.title(),
+            description: format!("{state} to {}", self.target),
+            ..Default::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        vec![
+            StandardItem {
+                label: format!("Sending to {}", self.target),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: if self.paused { "Resume" } else { "Pause" }.into(),
+                activate: Box::new(|tray: &mut Self| {
+                    // the bridge only goes away when we're stopping anyway
+                    let _ = tray.control.send(Control::TogglePause);
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Stop".into(),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|tray: &mut Self| tray.token.cancel()),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+/// Put an icon in the system tray which follows `status` until `token` is cancelled.
+pub async fn spawn(
+    target: String,
+    mut status: watch::Receiver<Status>,
+    control: mpsc::UnboundedSender<Control>,
+    token: CancellationToken,
+) -> Result<()> {
+    let tray = Tray {
+        target,
+        paused: status.borrow().paused,
+        control,
+        token: token.clone(),
+    };
+    let handle = tray
+        .spawn()
+        .await
+        .wrap_err("Failed to create the tray icon, is there a StatusNotifierItem host running?")?;
+
+    tokio::spawn(async move {
+        let mut paused = status.borrow().paused;
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                changed = status.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+            }
+            // the held keys change all the time but we don't show them, don't bother dbus
+            let now_paused = status.borrow_and_update().paused;
+            if now_paused != paused {
+                paused = now_paused;
+                handle.update(|tray| tray.paused = paused).await;
+            }
+        }
+        handle.shutdown().await;
+    });
+
+    Ok(())
+}
@@ -0,0 +1,387 @@
+use std::{collections::HashMap, io::Read as _, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{
+        header::{HOST, ORIGIN},
+        HeaderMap, StatusCode,
+    },
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use clap::Args;
+use color_eyre::eyre::{Result, WrapErr as _};
+use evdev::Key;
+use serde::Deserialize;
+use tokio::{
+    net::TcpListener,
+    select,
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    control,
+    keyset::{self, KeySet},
+    output::{Event, Output},
+    sender::{self, KeypressSender},
+    SerialArgs,
+};
+
+/// The page that captures key presses in the browser.
+const PAGE: &str = include_str!("web.html");
+
+#[derive(Args, Debug)]
+pub struct WebArgs {
+    /// the address to serve the page on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// a file holding a token which has to be in the page's address as ?token=..., a
+    /// random one is made up and printed if not given
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    serial: SerialArgs,
+}
+
+/// What the page sends over the websocket.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// A key going up or down, `code` being the browser's name for the physical key.
+    Key { code: String, down: bool },
+    /// Some text to type all at once.
+    Text { text: String },
+    /// Let go of everything, as the page won't see keys go up any more.
+    Release,
+}
+
+#[derive(Clone)]
+struct Shared {
+    secret: Arc<str>,
+    messages: mpsc::UnboundedSender<Message>,
+}
+
+/// Serve a page which forwards the browser's key presses to the teensy.
+pub async fn run(args: WebArgs, output: Output) -> Result<()> {
+    // any page open in the browser can reach localhost, so there's always a token
+    let (secret, query) = match &args.token_file {
+        Some(path) => (control::read_token(path)?, "?token=...".to_owned()),
+        None => {
+            let secret = random_token()?;
+            let query = format!("?token={secret}");
+            (secret, query)
+        }
+    };
+    let mut sender = crate::open_sender(args.serial, output).await?;
+
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .wrap_err_with(|| format!("Failed to listen on {}", args.listen))?;
+    output.status(format_args!(
+        "Open http://{}/{query} in a browser.",
+        args.listen
+    ));
+
+    let (messages, mut rx) = mpsc::unbounded_channel();
+    let app = router(Shared {
+        secret: Arc::from(secret),
+        messages,
+    });
+
+    let token = CancellationToken::new();
+    let cloned_token = token.clone();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+        cloned_token.cancel();
+    });
+
+    let mut keyboard = KeySet::new();
+    let result = select! {
+        served = axum::serve(listener, app) => served.wrap_err("The web server stopped"),
+        forwarded = forward(&mut rx, &mut sender, &mut keyboard, output, &token) => forwarded,
+    };
+
+    // however we got here release all keys so nothing is left held down on the target
+    let released = sender.send_state_update(KeySet::new()).await;
+    output.event(&Event::Disconnected);
+
+    result.and(released)
+}
+
+fn router(shared: Shared) -> Router {
+    Router::new()
+        .route("/", get(|| async { Html(PAGE) }))
+        .route("/ws", get(websocket))
+        .with_state(shared)
+}
+
+async fn forward(
+    rx: &mut mpsc::UnboundedReceiver<Message>,
+    sender: &mut KeypressSender,
+    keyboard: &mut KeySet,
+    output: Output,
+    token: &CancellationToken,
+) -> Result<()> {
+    let mut heartbeat = sender::heartbeat_interval();
+    loop {
+        // only ever stop between messages so we never leave half a frame on the wire
+        let message = select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = heartbeat.tick() => {
+                sender.send_heartbeat().await?;
+                continue;
+            }
+            Some(message) = rx.recv() => message,
+        };
+        match message {
+            Message::Key { code, down } => {
+                let Some(key) = key_for_code(&code) else {
+                    continue;
+                };
+                if down {
+                    keyboard.press_key(key);
+                } else {
+                    keyboard.release_key(key);
+                }
+                sender.send_state_update(*keyboard).await?;
+                output.event(&Event::Key { key, pressed: down });
+            }
+            Message::Text { text } => {
+                sender.type_text(&text).await?;
+                sender.send_state_update(*keyboard).await?;
+            }
+            Message::Release => {
+                *keyboard = KeySet::new();
+                sender.send_state_update(*keyboard).await?;
+            }
+        }
+    }
+}
+
+async fn websocket(
+    upgrade: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+    State(shared): State<Shared>,
+) -> Response {
+    // browsers let any page open a websocket anywhere, but always say which page it was
+    if !same_origin(&headers) {
+        return (StatusCode::FORBIDDEN, "wrong origin").into_response();
+    }
+    let given = query.get("token").map_or("", String::as_str);
+    if !control::same_secret(given, &shared.secret) {
+        return (StatusCode::UNAUTHORIZED, "wrong token").into_response();
+    }
+    upgrade.on_upgrade(|socket| relay(socket, shared.messages))
+}
+
+async fn relay(mut socket: WebSocket, messages: mpsc::UnboundedSender<Message>) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let WsMessage::Text(text) = message else {
+            continue;
+        };
+        // a page that isn't ours sending nonsense isn't worth dropping the socket over
+        if let Ok(message) = serde_json::from_str(text.as_str()) {
+            let _ = messages.send(message);
+        }
+    }
+    // the page going away mustn't leave keys held down on the target
+    let _ = messages.send(Message::Release);
+}
+
+/// Whether the request came from a page served from where it was sent, or not from a
+/// browser at all as there's no `Origin`.
+fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(ORIGIN) else {
+        return true;
+    };
+    let host = headers.get(HOST).and_then(|host| host.to_str().ok());
+    let origin = origin.to_str().ok().and_then(|origin| {
+        origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"))
+    });
+    matches!((origin, host), (Some(origin), Some(host)) if origin.eq_ignore_ascii_case(host))
+}
+
+/// A token to put in the page's address, for when there's no --token-file.
+fn random_token() -> Result<String> {
+    let mut bytes = [0; 24];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+        .wrap_err("Failed to make up a token")?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// The key for a `KeyboardEvent.code`, which names the physical key whatever the layout.
+fn key_for_code(code: &str) -> Option<Key> {
+    if let Some(letter) = code.strip_prefix("Key") {
+        let c = letter.chars().next()?;
+        return keyset::key_for_char(c.to_ascii_lowercase()).map(|(key, _)| key);
+    }
+    if let Some(digit) = code.strip_prefix("Digit") {
+        let c = digit.chars().next()?;
+        return keyset::key_for_char(c).map(|(key, _)| key);
+    }
+    let key = match code {
+        "Escape" => Key::KEY_ESC,
+        "Minus" => Key::KEY_MINUS,
+        "Equal" => Key::KEY_EQUAL,
+        "Backspace" => Key::KEY_BACKSPACE,
+        "Tab" => Key::KEY_TAB,
+        "BracketLeft" => Key::KEY_LEFTBRACE,
+        "BracketRight" => Key::KEY_RIGHTBRACE,
+        "Enter" => Key::KEY_ENTER,
+        "Semicolon" => Key::KEY_SEMICOLON,
+        "Quote" => Key::KEY_APOSTROPHE,
+        "Backquote" => Key::KEY_GRAVE,
+        "Backslash" => Key::KEY_BACKSLASH,
+        "IntlBackslash" => Key::KEY_102ND,
+        "Comma" => Key::KEY_COMMA,
+        "Period" => Key::KEY_DOT,
+        "Slash" => Key::KEY_SLASH,
+        "Space" => Key::KEY_SPACE,
+        "CapsLock" => Key::KEY_CAPSLOCK,
+        "F1" => Key::KEY_F1,
+        "F2" => Key::KEY_F2,
+        "F3" => Key::KEY_F3,
+        "F4" => Key::KEY_F4,
+        "F5" => Key::KEY_F5,
+        "F6" => Key::KEY_F6,
+        "F7" => Key::KEY_F7,
+        "F8" => Key::KEY_F8,
+        "F9" => Key::KEY_F9,
+        "F10" => Key::KEY_F10,
+        "F11" => Key::KEY_F11,
+        "F12" => Key::KEY_F12,
+        "PrintScreen" => Key::KEY_SYSRQ,
+        "ScrollLock" => Key::KEY_SCROLLLOCK,
+        "Pause" => Key::KEY_PAUSE,
+        "Insert" => Key::KEY_INSERT,
+        "Home" => Key::KEY_HOME,
+        "PageUp" => Key::KEY_PAGEUP,
+        "Delete" => Key::KEY_DELETE,
+        "End" => Key::KEY_END,
+        "PageDown" => Key::KEY_PAGEDOWN,
+        "ArrowRight" => Key::KEY_RIGHT,
+        "ArrowLeft" => Key::KEY_LEFT,
+        "ArrowDown" => Key::KEY_DOWN,
+        "ArrowUp" => Key::KEY_UP,
+        "NumLock" => Key::KEY_NUMLOCK,
+        "NumpadDivide" => Key::KEY_KPSLASH,
+        "NumpadMultiply" => Key::KEY_KPASTERISK,
+        "NumpadSubtract" => Key::KEY_KPMINUS,
+        "NumpadAdd" => Key::KEY_KPPLUS,
+        "NumpadEnter" => Key::KEY_KPENTER,
+        "NumpadDecimal" => Key::KEY_KPDOT,
+        "NumpadEqual" => Key::KEY_KPEQUAL,
+        "Numpad0" => Key::KEY_KP0,
+        "Numpad1" => Key::KEY_KP1,
+        "Numpad2" => Key::KEY_KP2,
+        "Numpad3" => Key::KEY_KP3,
+        "Numpad4" => Key::KEY_KP4,
+        "Numpad5" => Key::KEY_KP5,
+        "Numpad6" => Key::KEY_KP6,
+        "Numpad7" => Key::KEY_KP7,
+        "Numpad8" => Key::KEY_KP8,
+        "Numpad9" => Key::KEY_KP9,
+        "ContextMenu" => Key::KEY_COMPOSE,
+        "ControlLeft" => Key::KEY_LEFTCTRL,
+        "ShiftLeft" => Key::KEY_LEFTSHIFT,
+        "AltLeft" => Key::KEY_LEFTALT,
+        "MetaLeft" => Key::KEY_LEFTMETA,
+        "ControlRight" => Key::KEY_RIGHTCTRL,
+        "ShiftRight" => Key::KEY_RIGHTSHIFT,
+        "AltRight" => Key::KEY_RIGHTALT,
+        "MetaRight" => Key::KEY_RIGHTMETA,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_messages() {
+        let message: Message =
+            serde_json::from_str(r#"{"type":"key","code":"KeyA","down":true}"#).unwrap();
+        assert_eq!(
+            message,
+            Message::Key {
+                code: "KeyA".to_owned(),
+                down: true
+            }
+        );
+        let message: Message = serde_json::from_str(r#"{"type":"release"}"#).unwrap();
+        assert_eq!(message, Message::Release);
+    }
+
+    /// The status line of the answer to a websocket upgrade from a page at `origin`, or
+    /// the server's own page.
+    async fn upgrade_status(origin: Option<&str>) -> String {
+        let (messages, _rx) = mpsc::unbounded_channel();
+        let app = router(Shared {
+            secret: Arc::from("secret"),
+            messages,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let origin = origin.map_or_else(|| format!("http://{addr}"), str::to_owned);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET /ws?token=secret HTTP/1.1\r\n\
+             Host: {addr}\r\n\
+             Origin: {origin}\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut reply = BufReader::new(stream);
+        let mut status = String::new();
+        reply.read_line(&mut status).await.unwrap();
+        status.trim_end().to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_foreign_origin() {
+        assert_eq!(
+            upgrade_status(Some("http://evil.example")).await,
+            "HTTP/1.1 403 Forbidden"
+        );
+        // while the page's own still gets through
+        assert_eq!(
+            upgrade_status(None).await,
+            "HTTP/1.1 101 Switching Protocols"
+        );
+    }
+
+    #[test]
+    fn test_key_for_code() {
+        assert_eq!(key_for_code("KeyQ"), Some(Key::KEY_Q));
+        assert_eq!(key_for_code("Digit0"), Some(Key::KEY_0));
+        assert_eq!(key_for_code("ShiftRight"), Some(Key::KEY_RIGHTSHIFT));
+        assert_eq!(key_for_code("Numpad7"), Some(Key::KEY_KP7));
+        assert_eq!(key_for_code("Fn"), None);
+    }
+}
@@ -0,0 +1,23 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+fn main() {
+    // put memory.x where the linker looks for it
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("memory.x", out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    // the commit we're built from and whether it's been changed, which the firmware
+    // answers a HostMessage::Identify with, left empty outside a git checkout
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        output.status.success().then(|| stdout.trim().to_owned())
+    };
+    let commit = git(&["rev-parse", "HEAD"]).unwrap_or_default();
+    let dirty = git(&["status", "--porcelain"]).is_some_and(|changes| !changes.is_empty());
+    println!("cargo:rustc-env=GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=GIT_DIRTY={dirty}");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+}
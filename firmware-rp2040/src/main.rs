@@ -0,0 +1,368 @@
+//! The bridge's firmware for the Raspberry Pi Pico, and other RP2040 boards with the pico's
+//! pinout, for when there's no teensy to hand.
+//!
+//! It speaks the same protocol as the teensy's firmware but only has the keyboard: messages
+//! for the other devices are ignored, there's nowhere to save settings, and the link stays
+//! at the baud rate it starts at.
+
+#![no_std]
+#![no_main]
+
+mod panic;
+mod uart;
+
+#[rtic::app(device = rp_pico::hal::pac, peripherals = true, dispatchers = [TIMER_IRQ_1])]
+mod app {
+    use embedded_hal::digital::OutputPin;
+    use heapless::spsc::Queue;
+    use rp_pico::hal::{
+        self,
+        clocks::init_clocks_and_plls,
+        fugit::RateExtU32,
+        gpio,
+        uart::{DataBits, StopBits, UartConfig, UartPeripheral},
+        usb::UsbBus,
+        Clock,
+    };
+    use rtic::Mutex as _;
+    use rtic_monotonics::rp2040::prelude::*;
+    use shared::{
+        blink::Blinker,
+        board::{Board, Receiver},
+        crash::CrashReport,
+        link::SerialLink,
+    };
+    use usb_device::{
+        bus::UsbBusAllocator,
+        device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+    };
+    use usbd_hid::{
+        descriptor::{KeyboardReport, SerializedDescriptor},
+        hid_class::HIDClass,
+    };
+
+    use crate::{panic, uart::Uart};
+
+    rp2040_timer_monotonic!(Mono);
+
+    const VID_PID: UsbVidPid = UsbVidPid(shared::USB_VID, shared::USB_PID);
+    const PRODUCT: &str = "teensy-keyboard-bridge";
+
+    #[local]
+    struct Local {
+        device: UsbDevice<'static, UsbBus>,
+        uart: Uart,
+        led: gpio::Pin<gpio::bank0::Gpio25, gpio::FunctionSioOutput, gpio::PullDown>,
+        /// What the firmware panicked with before this boot, if it did.
+        crash_report: Option<CrashReport>,
+    }
+
+    #[shared]
+    struct Shared {
+        keyboard: HIDClass<'static, UsbBus>,
+        /// Whether the target has configured us, and so can be typed on.
+        configured: bool,
+        keys_to_press: Queue<KeyboardReport, 32>,
+        /// How long since we last heard from the client, None once we've let go of
+        /// everything for it going quiet.
+        silent_ms: Option<u32>,
+        /// Whether the client sends heartbeats, which gets it a shorter timeout.
+        heartbeats: bool,
+        /// How long to wait for a client without heartbeats, 0 for forever.
+        idle_timeout_ms: u32,
+        /// How often to send the target a keyboard report, which the client can change.
+        report_interval_ms: u32,
+        /// Pressed when the client goes silent, to lock the target.
+        lock_chord: Option<KeyboardReport>,
+        /// What's gone wrong so far, for the client to ask about.
+        telemetry: shared::Telemetry,
+    }
+
+    #[init(local = [bus: Option<UsbBusAllocator<UsbBus>> = None])]
+    fn init(ctx: init::Context) -> (Shared, Local) {
+        let crash_report = panic::take_crash_report();
+        let mut pac = ctx.device;
+        let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
+        let clocks = init_clocks_and_plls(
+            rp_pico::XOSC_CRYSTAL_FREQ,
+            pac.XOSC,
+            pac.CLOCKS,
+            pac.PLL_SYS,
+            pac.PLL_USB,
+            &mut pac.RESETS,
+            &mut watchdog,
+        )
+        .ok()
+        .unwrap();
+        Mono::start(pac.TIMER, &pac.RESETS);
+
+        let sio = hal::Sio::new(pac.SIO);
+        let pins = rp_pico::Pins::new(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut pac.RESETS,
+        );
+        let led = pins.led.into_push_pull_output();
+
+        let uart_pins = (
+            pins.gpio0.into_function::<gpio::FunctionUart>(),
+            pins.gpio1.into_function::<gpio::FunctionUart>(),
+        );
+        let config = UartConfig::new(
+            shared::DEFAULT_BAUD_RATE.Hz(),
+            DataBits::Eight,
+            None,
+            StopBits::One,
+        );
+        let mut uart = UartPeripheral::new(pac.UART0, uart_pins, &mut pac.RESETS)
+            .enable(config, clocks.peripheral_clock.freq())
+            .unwrap();
+        uart.enable_rx_interrupt();
+
+        let bus = ctx.local.bus.insert(UsbBusAllocator::new(UsbBus::new(
+            pac.USBCTRL_REGS,
+            pac.USBCTRL_DPRAM,
+            clocks.usb_clock,
+            true,
+            &mut pac.RESETS,
+        )));
+        let keyboard = HIDClass::new(bus, KeyboardReport::desc(), 1);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .strings(&[StringDescriptors::default().product(PRODUCT)])
+            .unwrap()
+            .build();
+
+        tick::spawn().ok();
+
+        (
+            Shared {
+                keyboard,
+                configured: false,
+                keys_to_press: Queue::new(),
+                silent_ms: None,
+                heartbeats: false,
+                idle_timeout_ms: shared::DEFAULT_IDLE_TIMEOUT_MS,
+                report_interval_ms: u32::from(shared::DEFAULT_REPORT_INTERVAL_MS),
+                lock_chord: None,
+                telemetry: shared::Telemetry::default(),
+            },
+            Local {
+                device,
+                uart: Uart::new(uart),
+                led,
+                crash_report,
+            },
+        )
+    }
+
+    #[task(binds = USBCTRL_IRQ, local = [device], shared = [keyboard, configured], priority = 2)]
+    fn usb(ctx: usb::Context) {
+        let device = ctx.local.device;
+        let mut keyboard = ctx.shared.keyboard;
+        let mut configured = ctx.shared.configured;
+        keyboard.lock(|keyboard| {
+            device.poll(&mut [keyboard]);
+            panic::set_keyboard(keyboard);
+        });
+        let state = device.state();
+        configured.lock(|configured| *configured = state == UsbDeviceState::Configured);
+    }
+
+    /// Send the target a keyboard report every so often, the next one queued if there
+    /// is one, and let go of everything once the client's gone quiet for too long.
+    #[task(local = [led, blinker: Blinker = Blinker::new()], shared = [keyboard, configured, keys_to_press, silent_ms, heartbeats, idle_timeout_ms, report_interval_ms, lock_chord, telemetry], priority = 1)]
+    async fn tick(ctx: tick::Context) {
+        let led = ctx.local.led;
+        let blinker = ctx.local.blinker;
+        let mut keyboard = ctx.shared.keyboard;
+        let mut configured = ctx.shared.configured;
+        let mut keys_to_press = ctx.shared.keys_to_press;
+        let mut silent_ms = ctx.shared.silent_ms;
+        let mut heartbeats = ctx.shared.heartbeats;
+        let mut idle_timeout_ms = ctx.shared.idle_timeout_ms;
+        let mut report_interval_ms = ctx.shared.report_interval_ms;
+        let mut lock_chord = ctx.shared.lock_chord;
+        let mut telemetry = ctx.shared.telemetry;
+
+        loop {
+            let interval_ms = report_interval_ms.lock(|interval| *interval);
+            Mono::delay(u64::from(interval_ms).millis()).await;
+
+            let timeout = if heartbeats.lock(|heartbeats| *heartbeats) {
+                shared::HEARTBEAT_TIMEOUT_MS
+            } else {
+                idle_timeout_ms.lock(|timeout| *timeout)
+            };
+            let timed_out = silent_ms.lock(|silent| {
+                let Some(ms) = silent else {
+                    return false;
+                };
+                *ms = ms.saturating_add(interval_ms);
+                if timeout == 0 || *ms < timeout {
+                    return false;
+                }
+                // wait for the next frame, so we only do this once
+                *silent = None;
+                true
+            });
+            if timed_out {
+                // as on the teensy, don't leave anything held down for a client that's gone
+                heartbeats.lock(|heartbeats| *heartbeats = false);
+                let chord = lock_chord.lock(|chord| *chord);
+                keys_to_press.lock(|keys| {
+                    while keys.dequeue().is_some() {}
+                    if let Some(chord) = chord {
+                        keys.enqueue(chord).ok();
+                        keys.enqueue(KeyboardReport::default()).ok();
+                    }
+                });
+            }
+
+            let (key, busy) = keys_to_press.lock(|keys| {
+                if keys.len() > 1 {
+                    // don't leave the buffer empty
+                    (keys.dequeue(), true)
+                } else {
+                    (keys.peek().copied(), false)
+                }
+            });
+            // as on the teensy, anything that's gone wrong takes over from the queue
+            let current = telemetry.lock(|telemetry| *telemetry);
+            let configured = configured.lock(|configured| *configured);
+            if blinker
+                .tick(interval_ms, configured, &current)
+                .unwrap_or(busy)
+            {
+                led.set_high().ok();
+            } else {
+                led.set_low().ok();
+            }
+            // if we have received no keypresses send an empty report
+            let pushed = keyboard
+                .lock(|keyboard| keyboard.push_input(&key.unwrap_or(KeyboardReport::default())));
+            // it's only still busy with the last one while the host isn't polling
+            if let Err(e) = pushed {
+                if e != usb_device::UsbError::WouldBlock {
+                    telemetry.lock(|telemetry| telemetry.usb_errors += 1);
+                }
+            }
+        }
+    }
+
+    /// Act on every frame that's come in from the client.
+    #[task(binds = UART0_IRQ, local = [uart, crash_report, receiver: Receiver = Receiver::new()], shared = [keys_to_press, silent_ms, heartbeats, idle_timeout_ms, report_interval_ms, lock_chord, telemetry], priority = 3)]
+    fn uart0(ctx: uart0::Context) {
+        let mut frames = Frames {
+            uart: ctx.local.uart,
+            shared: ctx.shared,
+            crash_report: ctx.local.crash_report,
+        };
+        ctx.local.receiver.poll(&mut frames);
+
+        let errors = frames.uart.take_errors();
+        if errors > 0 {
+            frames.telemetry(|telemetry| telemetry.uart_errors += errors);
+        }
+    }
+
+    /// Everything the client's frames change, for the [`Receiver`] to get at.
+    struct Frames<'a> {
+        uart: &'a mut Uart,
+        shared: uart0::SharedResources<'a>,
+        crash_report: &'a Option<CrashReport>,
+    }
+
+    impl SerialLink for Frames<'_> {
+        fn receive(&mut self) -> Option<u8> {
+            self.uart.receive()
+        }
+
+        fn send(&mut self, byte: u8) {
+            self.uart.send(byte);
+        }
+    }
+
+    impl Board for Frames<'_> {
+        fn reconnected(&mut self) -> bool {
+            self.uart.reconnected()
+        }
+
+        fn reset(&mut self) {
+            self.shared.keys_to_press.lock(|keys| {
+                while keys.dequeue().is_some() {}
+                keys.enqueue(KeyboardReport::default()).ok();
+            });
+        }
+
+        fn heard_from_client(&mut self) {
+            self.shared.silent_ms.lock(|silent| *silent = Some(0));
+        }
+
+        fn telemetry<R>(&mut self, f: impl FnOnce(&mut shared::Telemetry) -> R) -> R {
+            self.shared.telemetry.lock(f)
+        }
+
+        fn enqueue(&mut self, reports: impl Iterator<Item = KeyboardReport>) -> usize {
+            self.shared.keys_to_press.lock(|keys| {
+                reports
+                    .take_while(|&report| keys.enqueue(report).is_ok())
+                    .count()
+            })
+        }
+
+        fn room(&mut self) -> usize {
+            self.shared
+                .keys_to_press
+                .lock(|keys| keys.capacity() - keys.len())
+        }
+
+        fn queued(&mut self) -> usize {
+            self.shared.keys_to_press.lock(|keys| keys.len())
+        }
+
+        fn set_heartbeats(&mut self) {
+            self.shared.heartbeats.lock(|heartbeats| *heartbeats = true);
+        }
+
+        fn set_lock_chord(&mut self, chord: Option<KeyboardReport>) {
+            self.shared
+                .lock_chord
+                .lock(|lock_chord| *lock_chord = chord);
+        }
+
+        fn set_idle_timeout(&mut self, ms: u32) {
+            self.shared.idle_timeout_ms.lock(|timeout| *timeout = ms);
+        }
+
+        fn set_report_interval(&mut self, ms: u8) {
+            self.shared
+                .report_interval_ms
+                .lock(|interval| *interval = u32::from(ms));
+        }
+
+        fn crash_report(&self) -> Option<CrashReport> {
+            *self.crash_report
+        }
+
+        fn identity(&self) -> shared::Identity {
+            identity()
+        }
+    }
+
+    /// Which firmware this is, going by the crate's version and what build.rs found out.
+    fn identity() -> shared::Identity {
+        shared::Identity::new(
+            [
+                env!("CARGO_PKG_VERSION_MAJOR"),
+                env!("CARGO_PKG_VERSION_MINOR"),
+                env!("CARGO_PKG_VERSION_PATCH"),
+            ],
+            env!("GIT_COMMIT"),
+            env!("GIT_DIRTY") == "true",
+            Default::default(),
+            // only its flash chip has one, which can't be read while running from it
+            0,
+        )
+    }
+}
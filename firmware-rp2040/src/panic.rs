@@ -0,0 +1,69 @@
+//! What happens when the firmware panics, the same as on the teensy: let go of every key if
+//! it can, keep the message where the reset won't clear it, and reset, which drops it off
+//! the target's USB too.
+
+use core::{
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    ptr::{self, addr_of_mut},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+use rp_pico::hal::usb::UsbBus;
+use shared::crash::CrashReport;
+use usbd_hid::{descriptor::KeyboardReport, hid_class::HIDClass};
+
+/// How long to keep trying to get the empty report to the host before resetting anyway.
+const RELEASE_MS: u32 = 20;
+/// At the 125MHz `init_clocks_and_plls` runs the pico at.
+const CYCLES_PER_MS: u32 = 125_000;
+
+#[link_section = ".uninit.crash_report"]
+static mut CRASH_REPORT: MaybeUninit<CrashReport> = MaybeUninit::uninit();
+
+/// The keyboard, once the USB task's started, to send the empty report from here.
+static KEYBOARD: AtomicPtr<HIDClass<'static, UsbBus>> = AtomicPtr::new(ptr::null_mut());
+
+/// Set once we're panicking, so panicking again goes straight to the reset.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// What the last panic left, if the last reset was one, to be called once at boot.
+pub fn take_crash_report() -> Option<CrashReport> {
+    // SAFETY: only called from init, before anything can panic
+    unsafe { (*addr_of_mut!(CRASH_REPORT)).assume_init_mut().take() }
+}
+
+/// Where to find the keyboard, which never moves once the USB task has it.
+pub fn set_keyboard(keyboard: &HIDClass<'static, UsbBus>) {
+    KEYBOARD.store(ptr::from_ref(keyboard).cast_mut(), Ordering::Relaxed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    // no swap on the M0+, but with interrupts off there's nothing to race
+    if !PANICKING.load(Ordering::Relaxed) {
+        PANICKING.store(true, Ordering::Relaxed);
+        // SAFETY: interrupts are off, so nothing else is using it
+        unsafe { (*addr_of_mut!(CRASH_REPORT)).assume_init_mut().record(info) };
+        release_keys();
+    }
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+fn release_keys() {
+    // SAFETY: as on the teensy, it's in one of RTIC's statics and nothing else can be using
+    // it with interrupts off, unless it panicked in the middle of doing so
+    let Some(keyboard) = (unsafe { KEYBOARD.load(Ordering::Relaxed).as_ref() }) else {
+        return;
+    };
+    // the endpoint's likely still busy with the last report at first
+    for _ in 0..RELEASE_MS {
+        if keyboard.push_input(&KeyboardReport::default()).is_ok() {
+            // for the host to come and get it
+            cortex_m::asm::delay(RELEASE_MS * CYCLES_PER_MS);
+            return;
+        }
+        cortex_m::asm::delay(CYCLES_PER_MS);
+    }
+}
@@ -0,0 +1,63 @@
+//! The pico's end of [`shared::link`], UART0 on GP0 (TX) and GP1 (RX).
+
+use rp_pico::hal::{
+    gpio::{self, bank0},
+    pac,
+    uart::{Enabled, ReadErrorType, UartPeripheral},
+};
+use shared::link::SerialLink;
+
+type Pins = (
+    gpio::Pin<bank0::Gpio0, gpio::FunctionUart, gpio::PullDown>,
+    gpio::Pin<bank0::Gpio1, gpio::FunctionUart, gpio::PullDown>,
+);
+
+pub struct Uart {
+    peripheral: UartPeripheral<Enabled, pac::UART0, Pins>,
+    /// Bytes lost or garbled since they were last taken.
+    errors: u32,
+    /// Whether there's been a break on the line, which the client sends as it connects,
+    /// since last asked.
+    broke: bool,
+}
+
+impl Uart {
+    pub fn new(peripheral: UartPeripheral<Enabled, pac::UART0, Pins>) -> Self {
+        Self {
+            peripheral,
+            errors: 0,
+            broke: false,
+        }
+    }
+
+    pub fn take_errors(&mut self) -> u32 {
+        core::mem::take(&mut self.errors)
+    }
+
+    /// Whether a client's started afresh since last asked, so anything half-read from
+    /// before it can be thrown away.
+    pub fn reconnected(&mut self) -> bool {
+        core::mem::take(&mut self.broke)
+    }
+}
+
+impl SerialLink for Uart {
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        loop {
+            match self.peripheral.read_raw(&mut byte) {
+                Ok(_) => return Some(byte[0]),
+                Err(nb::Error::WouldBlock) => return None,
+                Err(nb::Error::Other(e)) if matches!(e.err_type, ReadErrorType::Break) => {
+                    self.broke = true;
+                }
+                // the frame it was in won't decode, so just carry on with the next
+                Err(nb::Error::Other(_)) => self.errors += 1,
+            }
+        }
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.peripheral.write_full_blocking(&[byte]);
+    }
+}
@@ -0,0 +1,25 @@
+use std::process::Command;
+
+fn main() {
+    // the commit we're built from and whether it's been changed, which the firmware
+    // answers a HostMessage::Identify with, left empty outside a git checkout
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        output.status.success().then(|| stdout.trim().to_owned())
+    };
+    let commit = git(&["rev-parse", "HEAD"]).unwrap_or_default();
+    let dirty = git(&["status", "--porcelain"]).is_some_and(|changes| !changes.is_empty());
+    println!("cargo:rustc-env=GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=GIT_DIRTY={dirty}");
+    // whether the client's on the UART, rather than a feature having moved it elsewhere
+    println!("cargo:rustc-check-cfg=cfg(uart)");
+    let elsewhere = ["SPI", "I2C", "CAN", "ETHERNET"]
+        .iter()
+        .any(|feature| std::env::var_os(format!("CARGO_FEATURE_{feature}")).is_some());
+    if !elsewhere {
+        println!("cargo:rustc-cfg=uart");
+    }
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+}
@@ -0,0 +1,69 @@
+//! The button on pin 4 which types the [`shared::ButtonMacro`] saved in flash, and the kill
+//! switch on pin 5. Both are wired to ground with the pin pulled up, so with nothing there
+//! the button's never pressed and the switch is never closed.
+
+use teensy4_bsp::{
+    hal::{gpio, iomuxc},
+    pins::t41::{P4, P5},
+};
+
+/// How long the button has to stay pressed, or released, to count, which is longer than it
+/// bounces for.
+const DEBOUNCE_MS: u32 = 20;
+
+/// Pulled up, so the pin reads high with nothing on it.
+pub const PIN_CONFIG: iomuxc::Config = iomuxc::Config::zero()
+    .set_hysteresis(iomuxc::Hysteresis::Enabled)
+    .set_pull_keeper(Some(iomuxc::PullKeeper::Pullup100k));
+
+pub struct MacroButton {
+    pin: gpio::Input<P4>,
+    /// Whether it's taken to be pressed.
+    pressed: bool,
+    /// How long it's read the other way since then.
+    changing_ms: u32,
+}
+
+impl MacroButton {
+    pub fn new(gpio4: &mut gpio::Port<4>, mut pin: P4) -> Self {
+        iomuxc::configure(&mut pin, PIN_CONFIG);
+        Self {
+            pin: gpio4.input(pin),
+            pressed: false,
+            changing_ms: 0,
+        }
+    }
+
+    /// Read it again after `elapsed_ms`, returning whether it's just been pressed.
+    pub fn poll(&mut self, elapsed_ms: u32) -> bool {
+        // pressing it pulls the pin low
+        let pressed = !self.pin.is_set();
+        if pressed == self.pressed {
+            self.changing_ms = 0;
+            return false;
+        }
+        self.changing_ms += elapsed_ms;
+        if self.changing_ms < DEBOUNCE_MS {
+            return false;
+        }
+        self.pressed = pressed;
+        self.changing_ms = 0;
+        pressed
+    }
+}
+
+/// A switch or jumper which, while it's closed, keeps anything from reaching the target.
+pub struct KillSwitch(gpio::Input<P5>);
+
+impl KillSwitch {
+    pub fn new(gpio4: &mut gpio::Port<4>, mut pin: P5) -> Self {
+        iomuxc::configure(&mut pin, PIN_CONFIG);
+        Self(gpio4.input(pin))
+    }
+
+    /// Whether it's closed, pulling the pin low. It isn't debounced, as bouncing only ever
+    /// means nothing gets through for a moment longer.
+    pub fn is_closed(&self) -> bool {
+        !self.0.is_set()
+    }
+}
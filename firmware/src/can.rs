@@ -0,0 +1,216 @@
+//! FlexCAN1 for the `can` feature, so the teensy can sit at the end of long, noisy cabling
+//! a UART wouldn't survive. It's classic CAN at [`BITRATE`], CAN FD being only on CAN3.
+//!
+//! The frames are the same byte stream as over the UART, cut into CAN messages of up to 8
+//! bytes: the client's with the standard ID [`HOST_ID`] and the teensy's with [`DEVICE_ID`].
+//! CAN keeps messages with one ID in order and checks each, and the frames inside have their
+//! own CRCs and sequence numbers on top.
+
+use core::ptr;
+
+use heapless::Deque;
+use teensy4_bsp::{
+    hal::iomuxc,
+    pins::common::{P22, P23},
+    ral::{self, can::CAN1, ccm::CCM},
+};
+
+use crate::transport::Transport;
+
+/// What the client's messages are sent with.
+pub const HOST_ID: u32 = 0x600;
+/// What the teensy's messages are sent with.
+pub const DEVICE_ID: u32 = 0x601;
+pub const BITRATE: u32 = 500_000;
+
+/// CAN's clock root, off the 24MHz oscillator.
+const CLOCK: u32 = 24_000_000;
+/// Time quanta in each bit: the sync segment, then the [`PROPSEG`], [`PSEG1`] and [`PSEG2`]
+/// fields each one more than they say, sampling three quarters of the way through.
+const QUANTA: u32 = 16;
+const PROPSEG: u32 = 5;
+const PSEG1: u32 = 4;
+const PSEG2: u32 = 3;
+
+/// The message buffers, which the ral doesn't name: four words each from here.
+const MB_OFFSET: usize = 0x80;
+const RX_MB: usize = 0;
+const TX_MB: usize = 1;
+
+/// The CODE field of a message buffer's control word.
+const CODE_RX_EMPTY: u32 = 0b0100;
+const CODE_RX_OVERRUN: u32 = 0b0110;
+const CODE_TX_INACTIVE: u32 = 0b1000;
+const CODE_TX_DATA: u32 = 0b1100;
+
+/// The daisy chain register picking which pad CAN1's RX comes from, and pin 23's.
+const FLEXCAN1_RX_SELECT_INPUT: *mut u32 = 0x401F_8444 as *mut u32;
+const DAISY_GPIO_AD_B1_09: u32 = 2;
+
+/// What can wait for the bus, past which bytes are dropped.
+const TX_LEN: usize = 256;
+/// What's come in, until the frame task gets to it.
+const RX_LEN: usize = 64;
+
+pub struct CanLink {
+    can: CAN1,
+    tx: Deque<u8, TX_LEN>,
+    rx: Deque<u8, RX_LEN>,
+    /// Whether the transmit buffer has a message in it the bus hasn't taken yet.
+    sending: bool,
+    /// Whether bytes coming in were lost or garbled since last asked.
+    lost: bool,
+}
+
+impl CanLink {
+    /// Join the bus on pins 22 (TX) and 23 (RX), through a transceiver.
+    pub fn new(mut tx: P22, mut rx: P23) -> Self {
+        // SAFETY: only CAN's clock gates and root are touched, nothing else uses them
+        let ccm = unsafe { CCM::instance() };
+        ral::modify_reg!(ral::ccm, ccm, CCGR0, CG7: 0b11, CG8: 0b11);
+        ral::modify_reg!(ral::ccm, ccm, CSCMR2, CAN_CLK_SEL: 1, CAN_CLK_PODF: 0);
+
+        iomuxc::alternate(&mut tx, 2);
+        iomuxc::alternate(&mut rx, 2);
+        // SAFETY: it's CAN1's own input select, and nothing else uses CAN1
+        unsafe { ptr::write_volatile(FLEXCAN1_RX_SELECT_INPUT, DAISY_GPIO_AD_B1_09) };
+
+        // SAFETY: nothing else touches CAN1
+        let can = unsafe { CAN1::instance() };
+        ral::modify_reg!(ral::can, can, MCR, MDIS: 0);
+        while ral::read_reg!(ral::can, can, MCR, LPMACK == 1) {}
+        ral::modify_reg!(ral::can, can, MCR, SOFTRST: 1);
+        while ral::read_reg!(ral::can, can, MCR, SOFTRST == 1) {}
+        // it comes out of the reset frozen, which is when it can be set up
+        while ral::read_reg!(ral::can, can, MCR, FRZACK == 0) {}
+
+        ral::write_reg!(ral::can, can, CTRL1,
+            PRESDIV: CLOCK / BITRATE / QUANTA - 1,
+            RJW: PSEG2,
+            PROPSEG: PROPSEG,
+            PSEG1: PSEG1,
+            PSEG2: PSEG2
+        );
+        // only the two buffers, and not hearing our own messages
+        ral::modify_reg!(ral::can, can, MCR, MAXMB: TX_MB as u32, SRXDIS: 1, IRMQ: 0);
+        ral::write_reg!(ral::can, can, RXMGMASK, 0x1FFF_FFFF);
+
+        let mut link = Self {
+            can,
+            tx: Deque::new(),
+            rx: Deque::new(),
+            sending: false,
+            lost: false,
+        };
+        link.write_mb(RX_MB, 1, HOST_ID << 18);
+        link.write_mb(RX_MB, 0, CODE_RX_EMPTY << 24);
+        link.write_mb(TX_MB, 0, CODE_TX_INACTIVE << 24);
+        ral::write_reg!(ral::can, link.can, IFLAG1, u32::MAX);
+        ral::write_reg!(ral::can, link.can, IMASK1, 1 << RX_MB | 1 << TX_MB);
+
+        ral::modify_reg!(ral::can, link.can, MCR, HALT: 0, FRZ: 0);
+        while ral::read_reg!(ral::can, link.can, MCR, FRZACK == 1) {}
+        while ral::read_reg!(ral::can, link.can, MCR, NOTRDY == 1) {}
+        link
+    }
+
+    /// Take in the message that's come, and start on the next one out if the last has gone,
+    /// for CAN1's interrupt, returning whether there's anything for the frame task.
+    pub fn service(&mut self) -> bool {
+        // reading it clears the errors
+        let errors = ral::read_reg!(ral::can, self.can, ESR1, CRCERR, FRMERR, STFERR);
+        if errors != (0, 0, 0) {
+            self.lost = true;
+        }
+
+        let flags = ral::read_reg!(ral::can, self.can, IFLAG1);
+        if flags & 1 << RX_MB != 0 {
+            // reading the control word locks the buffer until the free running timer's read
+            let control = self.read_mb(RX_MB, 0);
+            let words = [self.read_mb(RX_MB, 2), self.read_mb(RX_MB, 3)];
+            let _ = ral::read_reg!(ral::can, self.can, TIMER);
+            ral::write_reg!(ral::can, self.can, IFLAG1, 1 << RX_MB);
+
+            if control >> 24 & 0xF == CODE_RX_OVERRUN {
+                self.lost = true;
+            }
+            let len = (control >> 16 & 0xF).min(8) as usize;
+            let bytes = [words[0].to_be_bytes(), words[1].to_be_bytes()];
+            for &byte in bytes.as_flattened().iter().take(len) {
+                if self.rx.push_back(byte).is_err() {
+                    self.lost = true;
+                }
+            }
+        }
+        if flags & 1 << TX_MB != 0 {
+            ral::write_reg!(ral::can, self.can, IFLAG1, 1 << TX_MB);
+            self.sending = false;
+        }
+        self.transmit();
+        !self.rx.is_empty()
+    }
+
+    /// Send the next message, once there's a whole one's worth or the end of a frame.
+    fn transmit(&mut self) {
+        let ready = self.tx.len() >= 8 || self.tx.iter().any(|&byte| byte == 0);
+        if self.sending || !ready {
+            return;
+        }
+        let mut bytes = [0; 8];
+        let mut len = 0;
+        while len < bytes.len() {
+            let Some(byte) = self.tx.pop_front() else {
+                break;
+            };
+            bytes[len] = byte;
+            len += 1;
+        }
+        let (first, second) = bytes.split_at(4);
+        self.write_mb(TX_MB, 0, CODE_TX_INACTIVE << 24);
+        self.write_mb(TX_MB, 1, DEVICE_ID << 18);
+        self.write_mb(TX_MB, 2, u32::from_be_bytes(first.try_into().unwrap()));
+        self.write_mb(TX_MB, 3, u32::from_be_bytes(second.try_into().unwrap()));
+        self.write_mb(TX_MB, 0, CODE_TX_DATA << 24 | (len as u32) << 16);
+        self.sending = true;
+    }
+
+    fn mb(&self, mb: usize, word: usize) -> *mut u32 {
+        let base = ptr::from_ref(&*self.can).cast::<u8>().cast_mut();
+        // SAFETY: within CAN1's block, the buffers go up to 64
+        unsafe { base.add(MB_OFFSET + mb * 16 + word * 4).cast() }
+    }
+
+    fn read_mb(&self, mb: usize, word: usize) -> u32 {
+        // SAFETY: a message buffer word, which it has to itself
+        unsafe { ptr::read_volatile(self.mb(mb, word)) }
+    }
+
+    fn write_mb(&mut self, mb: usize, word: usize, value: u32) {
+        // SAFETY: as above
+        unsafe { ptr::write_volatile(self.mb(mb, word), value) }
+    }
+}
+
+impl Transport for CanLink {
+    /// The bit rate's fixed, every node on the bus has to agree on it.
+    const SWITCHES_BAUD: bool = false;
+
+    fn interrupted(&mut self) -> bool {
+        self.service();
+        core::mem::take(&mut self.lost)
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.rx.pop_front()
+    }
+
+    /// Never waits, the bus could be gone, so when there's no room the byte's dropped and
+    /// the client finds the frame damaged.
+    fn try_send(&mut self, byte: u8) -> bool {
+        let _ = self.tx.push_back(byte);
+        self.transmit();
+        true
+    }
+
+    fn set_baud(&mut self, _baud: u32) {}
+}
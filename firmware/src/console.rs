@@ -0,0 +1,73 @@
+//! Diagnostics, written as lines of text to the teensy's serial port on the target's side
+//! for watching with a serial terminal, e.g. `screen /dev/ttyACM0`.
+//!
+//! It's the same port the agent sends its frames to us on, as the teensy has no endpoints
+//! left for another, but nothing else is ever sent the other way. Lines are only written
+//! while something has the port open, and are dropped rather than waited for if the
+//! terminal isn't keeping up.
+
+use core::fmt::{self, Write as _};
+
+use heapless::{Deque, String};
+use usb_device::bus::UsbBus;
+use usbd_serial::SerialPort;
+
+/// The longest line, anything longer is cut short.
+const LINE_LEN: usize = 64;
+
+/// Write a line to the console behind a shared resource, with `format!`'s arguments.
+macro_rules! log {
+    ($console:expr, $($arg:tt)*) => {
+        $console.lock(|console| console.log(format_args!($($arg)*)))
+    };
+}
+pub(crate) use log;
+
+#[derive(Default)]
+pub struct Console {
+    lines: Deque<String<LINE_LEN>, 16>,
+    /// How much of the first line has been written already.
+    written: usize,
+    /// Lines which didn't fit since the last one which did.
+    dropped: u32,
+}
+
+impl Console {
+    pub fn log(&mut self, args: fmt::Arguments) {
+        if self.dropped > 0 && self.lines.len() < self.lines.capacity() - 1 {
+            let mut line = String::new();
+            let _ = write!(line, "({} lines dropped)\r\n", self.dropped);
+            let _ = self.lines.push_back(line);
+            self.dropped = 0;
+        }
+        let mut line = String::new();
+        // cut short if it's too long, keeping room for the line ending
+        let _ = line.write_fmt(args);
+        line.truncate(line.len().min(LINE_LEN - 2));
+        let _ = line.push_str("\r\n");
+        if self.lines.push_back(line).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    /// Write as much as `serial` will take without waiting, or throw it all away if
+    /// nothing has the port open.
+    pub fn flush<B: UsbBus>(&mut self, serial: &mut SerialPort<'_, B>) {
+        if !serial.dtr() {
+            self.lines.clear();
+            self.written = 0;
+            return;
+        }
+        while let Some(line) = self.lines.front() {
+            match serial.write(&line.as_bytes()[self.written..]) {
+                Ok(written) => self.written += written,
+                Err(_) => return,
+            }
+            if self.written < line.len() {
+                return;
+            }
+            self.lines.pop_front();
+            self.written = 0;
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! The teensy 4.1's ENET MAC and its DP83825 PHY, as a [`smoltcp`] device for the
+//! `ethernet` feature. Neither the BSP nor the HAL bring them up, so it's all done through
+//! registers here, following the schematic: RMII with the teensy driving the PHY's 50MHz
+//! reference clock, and the PHY's power and reset on GPIO2.
+
+use core::{
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+use smoltcp::{
+    phy::{self, Device, DeviceCapabilities, Medium},
+    time::Instant,
+};
+use teensy4_bsp::ral::{
+    self, ccm::CCM, ccm_analog::CCM_ANALOG, enet::ENET1, gpio::GPIO2, iomuxc_gpr::IOMUXC_GPR,
+    ocotp::OCOTP,
+};
+
+/// Room for the longest frame without its CRC, a multiple of 16 as the MAC wants.
+const BUFFER_LEN: usize = 1536;
+const RX_COUNT: usize = 4;
+const TX_COUNT: usize = 4;
+const MAX_FRAME_LEN: u32 = 1518;
+
+// the descriptor flags, in the legacy layout
+const EMPTY: u16 = 1 << 15;
+const READY: u16 = 1 << 15;
+const WRAP: u16 = 1 << 13;
+const LAST: u16 = 1 << 11;
+const TRANSMIT_CRC: u16 = 1 << 10;
+/// Too long, not a whole number of bytes, a bad CRC, overrun or truncated.
+const RX_ERRORS: u16 = 1 << 5 | 1 << 4 | 1 << 2 | 1 << 1 | 1 << 0;
+
+/// The PHY's address on MDIO, and the registers it needs setting.
+const PHY: u32 = 0;
+/// LED shows link status, active high.
+const PHY_LEDCR: (u32, u32) = (0x18, 0x0280);
+/// Take the 50MHz reference clock in, rather than a 25MHz crystal.
+const PHY_RCSR: (u32, u32) = (0x17, 0x0081);
+
+/// The pads ENET is on, their mux registers, with the alternate each is muxed to.
+const PAD_MUXES: [(usize, u32); 10] = [
+    (0x401F_818C, 3),        // GPIO_B1_04, RXD0
+    (0x401F_8190, 3),        // GPIO_B1_05, RXD1
+    (0x401F_8194, 3),        // GPIO_B1_06, CRS_DV
+    (0x401F_8198, 3),        // GPIO_B1_07, TXD0
+    (0x401F_819C, 3),        // GPIO_B1_08, TXD1
+    (0x401F_81A0, 3),        // GPIO_B1_09, TXEN
+    (0x401F_81A4, 6 | 0x10), // GPIO_B1_10, REF_CLK, looped back in as well
+    (0x401F_81A8, 3),        // GPIO_B1_11, RXER
+    (0x401F_81B4, 0),        // GPIO_B1_14, MDC
+    (0x401F_81B8, 0),        // GPIO_B1_15, MDIO
+];
+/// The daisy chain registers picking those pads for ENET's inputs.
+const SELECT_INPUTS: [(usize, u32); 6] = [
+    (0x401F_8424, 1), // IPG_CLK_RMII, GPIO_B1_10
+    (0x401F_8428, 2), // MDIO, GPIO_B1_15
+    (0x401F_842C, 1), // RXDATA0, GPIO_B1_04
+    (0x401F_8430, 1), // RXDATA1, GPIO_B1_05
+    (0x401F_8434, 1), // RXEN, GPIO_B1_06
+    (0x401F_8438, 1), // RXERR, GPIO_B1_11
+];
+/// GPIO_B0_14 and GPIO_B0_15's muxes, the PHY's reset and power, as GPIO2 14 and 15.
+const PHY_RESET_MUX: usize = 0x401F_8174;
+const PHY_POWER_MUX: usize = 0x401F_8178;
+const GPIO_ALT: u32 = 5;
+const PHY_RESET: u32 = 1 << 14;
+const PHY_POWER: u32 = 1 << 15;
+
+#[repr(C)]
+struct Descriptor {
+    len: u16,
+    flags: u16,
+    /// Where its buffer is, as an address so the rings can be shared between tasks.
+    buffer: u32,
+}
+
+#[repr(C, align(64))]
+struct Ring<const N: usize>([Descriptor; N]);
+
+#[repr(C, align(64))]
+struct Buffer([u8; BUFFER_LEN]);
+
+/// Everything the MAC reads and writes behind our back, which has to stay put.
+pub struct Buffers {
+    rx_ring: Ring<RX_COUNT>,
+    tx_ring: Ring<TX_COUNT>,
+    rx: [Buffer; RX_COUNT],
+    tx: [Buffer; TX_COUNT],
+}
+
+impl Buffers {
+    pub const fn new() -> Self {
+        const DESCRIPTOR: Descriptor = Descriptor {
+            len: 0,
+            flags: 0,
+            buffer: 0,
+        };
+        const BUFFER: Buffer = Buffer([0; BUFFER_LEN]);
+        Self {
+            rx_ring: Ring([DESCRIPTOR; RX_COUNT]),
+            tx_ring: Ring([DESCRIPTOR; TX_COUNT]),
+            rx: [BUFFER; RX_COUNT],
+            tx: [BUFFER; TX_COUNT],
+        }
+    }
+}
+
+/// One direction's ring of descriptors, with the buffers they point at.
+struct Queue<const N: usize> {
+    ring: &'static mut Ring<N>,
+    buffers: &'static mut [Buffer; N],
+    /// The descriptor the MAC gets to next.
+    next: usize,
+}
+
+impl<const N: usize> Queue<N> {
+    fn new(ring: &'static mut Ring<N>, buffers: &'static mut [Buffer; N], flags: u16) -> Self {
+        for (i, (descriptor, buffer)) in ring.0.iter_mut().zip(buffers.iter_mut()).enumerate() {
+            descriptor.buffer = buffer.0.as_mut_ptr() as u32;
+            descriptor.len = 0;
+            descriptor.flags = flags | if i + 1 == N { WRAP } else { 0 };
+        }
+        Self {
+            ring,
+            buffers,
+            next: 0,
+        }
+    }
+
+    fn descriptor(&mut self) -> *mut Descriptor {
+        &mut self.ring.0[self.next]
+    }
+
+    fn flags(&mut self) -> u16 {
+        // SAFETY: the MAC only ever writes the flags to hand it back
+        unsafe { ptr::read_volatile(ptr::addr_of!((*self.descriptor()).flags)) }
+    }
+
+    /// Give the current descriptor to the MAC and move on to the next.
+    fn hand_over(&mut self, len: u16, flags: u16) {
+        let descriptor = self.descriptor();
+        fence(Ordering::SeqCst);
+        // SAFETY: it's ours until the flags say it's the MAC's
+        unsafe {
+            let wrap = (*descriptor).flags & WRAP;
+            ptr::write_volatile(&mut (*descriptor).len, len);
+            ptr::write_volatile(&mut (*descriptor).flags, flags | wrap);
+        }
+        self.next = (self.next + 1) % N;
+    }
+}
+
+pub struct Enet {
+    enet: ENET1,
+    rx: Queue<RX_COUNT>,
+    tx: Queue<TX_COUNT>,
+}
+
+impl Enet {
+    /// Power up the PHY and start the MAC receiving, with the teensy's own MAC address.
+    pub fn new(buffers: &'static mut Buffers) -> Self {
+        // SAFETY: ENET, its PLL and pads aren't touched by anything else, the clock gate and
+        // GPR bits are only ENET's, and GPIO2's LED is set up already and only set and
+        // cleared after, which leaves the PHY's bits alone
+        let (ccm, ccm_analog, gpr, gpio2, enet) = unsafe {
+            (
+                CCM::instance(),
+                CCM_ANALOG::instance(),
+                IOMUXC_GPR::instance(),
+                GPIO2::instance(),
+                ENET1::instance(),
+            )
+        };
+        ral::modify_reg!(ral::ccm, ccm, CCGR1, CG5: 0b11);
+        // PLL6 at 50MHz, for RMII
+        ral::modify_reg!(
+            ral::ccm_analog,
+            ccm_analog,
+            PLL_ENET,
+            POWERDOWN: 0,
+            BYPASS: 1,
+            ENABLE: 1,
+            DIV_SELECT: 1
+        );
+        while ral::read_reg!(ral::ccm_analog, ccm_analog, PLL_ENET, LOCK == 0) {}
+        ral::modify_reg!(ral::ccm_analog, ccm_analog, PLL_ENET, BYPASS: 0);
+        // the teensy drives the reference clock out to the PHY
+        ral::modify_reg!(ral::iomuxc_gpr, gpr, GPR1, ENET1_TX_CLK_DIR: 1, ENET1_CLK_SEL: 0);
+
+        // SAFETY: they're the IOMUXC registers of pads only ENET uses
+        unsafe {
+            for (register, value) in PAD_MUXES.into_iter().chain(SELECT_INPUTS) {
+                ptr::write_volatile(register as *mut u32, value);
+            }
+            ptr::write_volatile(PHY_RESET_MUX as *mut u32, GPIO_ALT);
+            ptr::write_volatile(PHY_POWER_MUX as *mut u32, GPIO_ALT);
+        }
+        ral::modify_reg!(ral::gpio, gpio2, GDIR, |gdir| gdir | PHY_RESET | PHY_POWER);
+        ral::write_reg!(ral::gpio, gpio2, DR_CLEAR, PHY_RESET);
+        ral::write_reg!(ral::gpio, gpio2, DR_SET, PHY_POWER);
+        // the PHY wants its reset held for at least 25us once it's powered
+        cortex_m::asm::delay(teensy4_bsp::board::ARM_FREQUENCY / 20_000);
+        ral::write_reg!(ral::gpio, gpio2, DR_SET, PHY_RESET);
+
+        ral::write_reg!(ral::enet, enet, ECR, RESET: 1);
+        while ral::read_reg!(ral::enet, enet, ECR, RESET == 1) {}
+        ral::write_reg!(ral::enet, enet, EIMR, 0);
+        ral::write_reg!(ral::enet, enet, EIR, u32::MAX);
+        // MDC at 2.5MHz at most, off the 150MHz IPG clock
+        ral::write_reg!(ral::enet, enet, MSCR, MII_SPEED: 29, HOLDTIME: 0);
+        ral::write_reg!(
+            ral::enet,
+            enet,
+            RCR,
+            MAX_FL: MAX_FRAME_LEN,
+            RMII_MODE: 1,
+            MII_MODE: 1,
+            CRCFWD: 1,
+            FCE: 1
+        );
+        ral::write_reg!(ral::enet, enet, TCR, FDEN: 1);
+        ral::write_reg!(ral::enet, enet, TFWR, STRFWD: 1);
+        ral::write_reg!(ral::enet, enet, MRBR, R_BUF_SIZE: BUFFER_LEN as u32 >> 4);
+
+        let [a, b, c, d, e, f] = mac_address();
+        ral::write_reg!(ral::enet, enet, PALR, u32::from_be_bytes([a, b, c, d]));
+        ral::write_reg!(ral::enet, enet, PAUR, PADDR2: u32::from(e) << 8 | u32::from(f));
+        for register in [&enet.IALR, &enet.IAUR, &enet.GALR, &enet.GAUR] {
+            register.write(0);
+        }
+
+        let Buffers {
+            rx_ring,
+            tx_ring,
+            rx,
+            tx,
+        } = buffers;
+        let rx = Queue::new(rx_ring, rx, EMPTY);
+        let tx = Queue::new(tx_ring, tx, 0);
+        ral::write_reg!(ral::enet, enet, RDSR, ptr::addr_of!(*rx.ring) as u32);
+        ral::write_reg!(ral::enet, enet, TDSR, ptr::addr_of!(*tx.ring) as u32);
+        fence(Ordering::SeqCst);
+
+        // little endian descriptors
+        ral::write_reg!(ral::enet, enet, ECR, DBSWP: 1, ETHEREN: 1);
+        ral::write_reg!(ral::enet, enet, RDAR, RDAR: 1);
+        ral::write_reg!(ral::enet, enet, EIMR, RXF: 1);
+
+        let mut this = Self { enet, rx, tx };
+        this.write_phy(PHY_LEDCR);
+        this.write_phy(PHY_RCSR);
+        this
+    }
+
+    /// Clear the interrupt for a frame coming in, which [`Device::receive`] then picks up.
+    pub fn clear_interrupt(&mut self) {
+        ral::write_reg!(ral::enet, self.enet, EIR, RXF: 1);
+    }
+
+    fn write_phy(&mut self, (register, value): (u32, u32)) {
+        ral::write_reg!(
+            ral::enet,
+            self.enet,
+            MMFR,
+            ST: 1,
+            OP: 1,
+            PA: PHY,
+            RA: register,
+            TA: 2,
+            DATA: value
+        );
+        while ral::read_reg!(ral::enet, self.enet, EIR, MII == 0) {}
+        ral::write_reg!(ral::enet, self.enet, EIR, MII: 1);
+    }
+}
+
+/// The address PJRC burns into each teensy 4.1's fuses.
+pub fn mac_address() -> [u8; 6] {
+    // SAFETY: reading the fuses doesn't change anything
+    let ocotp = unsafe { OCOTP::instance() };
+    let high = ral::read_reg!(ral::ocotp, ocotp, MAC1).to_be_bytes();
+    let low = ral::read_reg!(ral::ocotp, ocotp, MAC0).to_be_bytes();
+    [high[2], high[3], low[0], low[1], low[2], low[3]]
+}
+
+impl Device for Enet {
+    type RxToken<'a> = RxToken<'a>;
+    type TxToken<'a> = TxToken<'a>;
+
+    fn receive(&mut self, _: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if self.rx.flags() & EMPTY != 0 || self.tx.flags() & READY != 0 {
+            return None;
+        }
+        Some((
+            RxToken(&self.enet, &mut self.rx),
+            TxToken(&self.enet, &mut self.tx),
+        ))
+    }
+
+    fn transmit(&mut self, _: Instant) -> Option<Self::TxToken<'_>> {
+        (self.tx.flags() & READY == 0).then_some(TxToken(&self.enet, &mut self.tx))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ethernet;
+        capabilities.max_transmission_unit = 1514;
+        capabilities.max_burst_size = Some(1);
+        capabilities
+    }
+}
+
+pub struct RxToken<'a>(&'a ENET1, &'a mut Queue<RX_COUNT>);
+
+impl phy::RxToken for RxToken<'_> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, f: F) -> R {
+        let RxToken(enet, rx) = self;
+        let flags = rx.flags();
+        // SAFETY: the MAC has handed it back, until it's marked empty again
+        let len = unsafe { ptr::read_volatile(ptr::addr_of!((*rx.descriptor()).len)) };
+        let len = if flags & RX_ERRORS == 0 && flags & LAST != 0 {
+            usize::from(len).min(BUFFER_LEN)
+        } else {
+            // smoltcp drops anything it can't parse, which nothing is
+            0
+        };
+        let result = f(&mut rx.buffers[rx.next].0[..len]);
+        rx.hand_over(0, EMPTY);
+        ral::write_reg!(ral::enet, enet, RDAR, RDAR: 1);
+        result
+    }
+}
+
+pub struct TxToken<'a>(&'a ENET1, &'a mut Queue<TX_COUNT>);
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+        let TxToken(enet, tx) = self;
+        let len = len.min(BUFFER_LEN);
+        let result = f(&mut tx.buffers[tx.next].0[..len]);
+        tx.hand_over(len as u16, READY | LAST | TRANSMIT_CRC);
+        ral::write_reg!(ral::enet, enet, TDAR, TDAR: 1);
+        result
+    }
+}
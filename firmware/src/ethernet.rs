@@ -0,0 +1,187 @@
+//! The teensy 4.1's Ethernet for the `ethernet` feature, so it can sit on the network by
+//! itself with no serial adapter or machine in between. It takes an address over DHCP and
+//! listens on TCP [`PORT`] for `client --send-to tcp://ADDRESS:4001`, one connection at a
+//! time, with the frames the same byte stream as over the UART.
+
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet, SocketStorage},
+    socket::{dhcpv4, tcp},
+    time::{Duration, Instant},
+    wire::{EthernetAddress, IpCidr, Ipv4Cidr},
+};
+
+use crate::{
+    enet::{self, Enet},
+    transport::Transport,
+};
+
+pub const PORT: u16 = 4001;
+
+/// Each way, for the TCP connection.
+const SOCKET_BUFFER_LEN: usize = 1024;
+/// How long a client can go quiet before it's taken as gone, which lets another connect.
+/// The client's heartbeats are much more often than this.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// Everything the network needs to outlive [`EthernetLink::new`], made once at boot.
+pub struct Storage {
+    enet: enet::Buffers,
+    sockets: [SocketStorage<'static>; 2],
+    rx: [u8; SOCKET_BUFFER_LEN],
+    tx: [u8; SOCKET_BUFFER_LEN],
+}
+
+impl Storage {
+    pub const fn new() -> Self {
+        Self {
+            enet: enet::Buffers::new(),
+            sockets: [SocketStorage::EMPTY; 2],
+            rx: [0; SOCKET_BUFFER_LEN],
+            tx: [0; SOCKET_BUFFER_LEN],
+        }
+    }
+}
+
+pub struct EthernetLink {
+    device: Enet,
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    tcp: SocketHandle,
+    dhcp: SocketHandle,
+    now_ms: i64,
+    /// The address DHCP gave us, or took away, since last asked.
+    address_changed: Option<Option<Ipv4Cidr>>,
+    /// Whether a connection's ended since last asked, so the next is a new client's.
+    reconnected: bool,
+}
+
+impl EthernetLink {
+    pub fn new(storage: &'static mut Storage) -> Self {
+        let Storage {
+            enet,
+            sockets,
+            rx,
+            tx,
+        } = storage;
+        let mut device = Enet::new(enet);
+        let mac = enet::mac_address();
+        let mut config = Config::new(EthernetAddress(mac).into());
+        config.random_seed = mac
+            .iter()
+            .fold(0, |seed, &byte| seed << 8 | u64::from(byte));
+        let iface = Interface::new(config, &mut device, Instant::ZERO);
+
+        let mut sockets = SocketSet::new(&mut sockets[..]);
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(&mut rx[..]),
+            tcp::SocketBuffer::new(&mut tx[..]),
+        );
+        socket.set_timeout(Some(CLIENT_TIMEOUT));
+        socket.set_keep_alive(Some(KEEP_ALIVE));
+        socket.set_nagle_enabled(false);
+        socket.listen(PORT).ok();
+        let tcp = sockets.add(socket);
+        let dhcp = sockets.add(dhcpv4::Socket::new());
+
+        Self {
+            device,
+            iface,
+            sockets,
+            tcp,
+            dhcp,
+            now_ms: 0,
+            address_changed: None,
+            reconnected: false,
+        }
+    }
+
+    /// Deal with what's come in, for ENET's interrupt, returning whether there's anything
+    /// for the frame task.
+    pub fn service(&mut self) -> bool {
+        self.device.clear_interrupt();
+        self.poll()
+    }
+
+    /// The address DHCP gave us, or None if it's been taken away, if either's happened
+    /// since last asked.
+    pub fn address_changed(&mut self) -> Option<Option<Ipv4Cidr>> {
+        self.address_changed.take()
+    }
+
+    /// Send and receive whatever's waiting, returning whether there's anything to read.
+    fn poll(&mut self) -> bool {
+        let now = Instant::from_millis(self.now_ms);
+        self.iface.poll(now, &mut self.device, &mut self.sockets);
+
+        match self.sockets.get_mut::<dhcpv4::Socket>(self.dhcp).poll() {
+            Some(dhcpv4::Event::Configured(config)) => {
+                self.iface.update_ip_addrs(|addresses| {
+                    addresses.clear();
+                    addresses.push(IpCidr::Ipv4(config.address)).ok();
+                });
+                if let Some(router) = config.router {
+                    self.iface.routes_mut().add_default_ipv4_route(router).ok();
+                }
+                self.address_changed = Some(Some(config.address));
+            }
+            Some(dhcpv4::Event::Deconfigured) => {
+                self.iface.update_ip_addrs(|addresses| addresses.clear());
+                self.iface.routes_mut().remove_default_ipv4_route();
+                self.address_changed = Some(None);
+            }
+            None => {}
+        }
+
+        let socket = self.sockets.get_mut::<tcp::Socket>(self.tcp);
+        if socket.state() == tcp::State::CloseWait {
+            // the client's hung up, so finish closing and listen for the next
+            socket.close();
+        }
+        if !socket.is_open() {
+            socket.listen(PORT).ok();
+            self.reconnected = true;
+        }
+        socket.can_recv()
+    }
+}
+
+impl Transport for EthernetLink {
+    const SWITCHES_BAUD: bool = false;
+
+    /// TCP resends anything that's lost, so nothing ever is.
+    fn interrupted(&mut self) -> bool {
+        self.service();
+        false
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        let socket = self.sockets.get_mut::<tcp::Socket>(self.tcp);
+        matches!(socket.recv_slice(&mut byte), Ok(1)).then_some(byte[0])
+    }
+
+    /// Never waits, as nothing goes out until it's polled, so when there's no room, or no
+    /// client, the byte's dropped. Each frame goes as soon as it's whole.
+    fn try_send(&mut self, byte: u8) -> bool {
+        let socket = self.sockets.get_mut::<tcp::Socket>(self.tcp);
+        if socket.may_send() {
+            socket.send_slice(&[byte]).ok();
+        }
+        if byte == 0 {
+            self.poll();
+        }
+        true
+    }
+
+    fn set_baud(&mut self, _baud: u32) {}
+
+    fn tick(&mut self, elapsed_ms: u32) -> bool {
+        self.now_ms += i64::from(elapsed_ms);
+        self.poll()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        core::mem::take(&mut self.reconnected)
+    }
+}
@@ -0,0 +1,138 @@
+//! Erasing and programming the teensy's own flash, the 8MB of NOR on FlexSPI the firmware
+//! runs from, through the driver in the i.MX RT's boot ROM.
+//!
+//! Nothing may be fetched from the flash while it's busy, so interrupts are off for every
+//! operation, and the driver itself runs from ROM. Everything else stalls until it's done,
+//! a few milliseconds to program a page and up to a few hundred to erase a sector, well
+//! inside the watchdog's second.
+
+use cortex_m::interrupt;
+
+/// Where the flash is mapped into memory, for reading it.
+const FLASH_BASE: usize = 0x6000_0000;
+/// The smallest piece which can be erased.
+pub const SECTOR_SIZE: usize = 4096;
+/// The most which can be programmed in one go, which mustn't cross a page boundary.
+const PAGE_SIZE: usize = 256;
+/// Which FlexSPI the ROM's driver should use, its first, which the flash is on.
+const INSTANCE: u32 = 0;
+
+/// The start of the ROM's table of APIs, which a pointer at this address points to, see
+/// "ROM APIs" in the i.MX RT1060 reference manual.
+const BOOTLOADER_TREE: usize = 0x0020_001c;
+
+#[repr(C)]
+struct BootloaderTree {
+    version: u32,
+    copyright: *const u8,
+    run_bootloader: unsafe extern "C" fn(*mut u32),
+    reserved: *const u32,
+    flexspi_nor: *const FlexspiNorDriver,
+}
+
+/// The first few of the ROM's FlexSPI NOR driver's functions, which return 0 on success.
+#[repr(C)]
+struct FlexspiNorDriver {
+    version: u32,
+    init: unsafe extern "C" fn(u32, *mut Config) -> i32,
+    program: unsafe extern "C" fn(u32, *mut Config, u32, *const u32) -> i32,
+    erase_all: unsafe extern "C" fn(u32, *mut Config) -> i32,
+    erase: unsafe extern "C" fn(u32, *mut Config, u32, u32) -> i32,
+    read: unsafe extern "C" fn(u32, *mut Config, *mut u32, u32, u32) -> i32,
+    clear_cache: unsafe extern "C" fn(u32),
+}
+
+/// The FlexSPI NOR configuration block the ROM booted from, which tells its driver how to
+/// talk to the flash.
+#[repr(C, align(4))]
+struct Config([u8; 512]);
+
+#[repr(C, align(4))]
+struct Page([u8; PAGE_SIZE]);
+
+/// The status the ROM's driver failed with.
+#[derive(Debug, Clone, Copy)]
+pub struct Error(pub i32);
+
+pub struct Flash {
+    driver: &'static FlexspiNorDriver,
+    config: Config,
+}
+
+impl Flash {
+    pub fn new() -> Self {
+        // SAFETY: the ROM is always there, and the configuration block is at the start of
+        // the flash, where the ROM has to find it to boot us
+        unsafe {
+            let tree = *(BOOTLOADER_TREE as *const *const BootloaderTree);
+            Self {
+                driver: &*(*tree).flexspi_nor,
+                config: core::ptr::read_volatile(FLASH_BASE as *const Config),
+            }
+        }
+    }
+
+    /// Fill `buf` from `offset` bytes into the flash.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // SAFETY: reads of the mapped flash have no side effects
+            *byte = unsafe { core::ptr::read_volatile((FLASH_BASE + offset + i) as *const u8) };
+        }
+    }
+
+    /// Erase the sector starting `offset` bytes into the flash, back to all 0xFF.
+    pub fn erase(&mut self, offset: usize) -> Result<(), Error> {
+        let driver = self.driver;
+        let config = &mut self.config;
+        // SAFETY: with interrupts off nothing runs from the flash while it's busy
+        let status = interrupt::free(|_| unsafe {
+            (driver.erase)(INSTANCE, config, offset as u32, SECTOR_SIZE as u32)
+        });
+        self.invalidate(offset, SECTOR_SIZE);
+        check(status)
+    }
+
+    /// Program `bytes` into erased flash `offset` bytes in. Programming can only clear
+    /// bits, so any already programmed must be left as they are.
+    pub fn program(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < bytes.len() {
+            let start = offset + done;
+            let page_start = start - start % PAGE_SIZE;
+            let len = (PAGE_SIZE - start % PAGE_SIZE).min(bytes.len() - done);
+            // the whole page is written, and 0xFF leaves the rest of it as it is
+            let mut page = Page([0xFF; PAGE_SIZE]);
+            page.0[start % PAGE_SIZE..][..len].copy_from_slice(&bytes[done..][..len]);
+
+            let driver = self.driver;
+            let config = &mut self.config;
+            // SAFETY: as in erase
+            let status = interrupt::free(|_| unsafe {
+                (driver.program)(INSTANCE, config, page_start as u32, page.0.as_ptr().cast())
+            });
+            self.invalidate(page_start, PAGE_SIZE);
+            check(status)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Throw away anything cached from before a change, so reads see what's there now.
+    fn invalidate(&mut self, offset: usize, len: usize) {
+        // SAFETY: the flash is never written through the cache, so nothing dirty is lost
+        unsafe {
+            (self.driver.clear_cache)(INSTANCE);
+            let mut peripherals = cortex_m::Peripherals::steal();
+            peripherals
+                .SCB
+                .invalidate_dcache_by_address(FLASH_BASE + offset, len);
+        }
+    }
+}
+
+fn check(status: i32) -> Result<(), Error> {
+    match status {
+        0 => Ok(()),
+        status => Err(Error(status)),
+    }
+}
@@ -0,0 +1,188 @@
+//! The HID interfaces the teensy shows up with on the target.
+//!
+//! Each kind of device gets an interface of its own rather than sharing one behind report
+//! IDs, so each keeps the descriptor it's always had and a host which only understands some
+//! of them (BIOS setup screens only look for a boot keyboard) can still use the rest.
+
+use usb_device::{
+    bus::{UsbBus, UsbBusAllocator},
+    class::UsbClass,
+    device::UsbDevice,
+};
+use usbd_hid::{
+    descriptor::{KeyboardReport, SerializedDescriptor as _},
+    hid_class::{
+        HIDClass, HidClassSettings, HidCountryCode, HidProtocol, HidProtocolMode, HidSubClass,
+        ProtocolModeConfig,
+    },
+};
+
+/// Poll every 1ms. At full speed bInterval counts 1ms frames, but at high speed it's
+/// 2^(bInterval-1) microframes of 125us, so "4" correlates to 1ms.
+const POLL_INTERVAL: u8 = if cfg!(feature = "full-speed") { 1 } else { 4 };
+
+/// The digitizer takes the absolute pointer's place, as there's no endpoint left for it.
+const POINTER_DESCRIPTOR: &[u8] = if cfg!(feature = "digitizer") {
+    shared::DIGITIZER_DESCRIPTOR
+} else {
+    shared::ABSOLUTE_POINTER_DESCRIPTOR
+};
+
+/// How often a report which hasn't changed is sent again anyway, in case the target missed
+/// it, say while it was switching protocol.
+const REFRESH_MS: u32 = 1000;
+
+pub struct Interfaces<'a, B: UsbBus> {
+    pub keyboard: HIDClass<'a, B>,
+    pub gamepad: HIDClass<'a, B>,
+    /// The absolute pointer, or the digitizer with the `digitizer` feature.
+    pub pointer: HIDClass<'a, B>,
+    pub consumer: HIDClass<'a, B>,
+    pub mouse: HIDClass<'a, B>,
+}
+
+impl<'a, B: UsbBus> Interfaces<'a, B> {
+    /// Allocate every interface, which has to happen before anything else on the bus so the
+    /// keyboard comes first.
+    pub fn new(bus: &'a UsbBusAllocator<B>) -> Self {
+        // a boot keyboard, so BIOS and UEFI setup screens, GRUB and disk encryption prompts
+        // which only speak the boot protocol can use it. It has to be the first interface,
+        // as some firmware never looks past it. The host switches between the boot and report
+        // protocols with SET_PROTOCOL, which the class answers along with GET_PROTOCOL
+        let keyboard = HIDClass::new_with_settings(
+            bus,
+            KeyboardReport::desc(),
+            POLL_INTERVAL,
+            HidClassSettings {
+                subclass: HidSubClass::Boot,
+                protocol: HidProtocol::Keyboard,
+                config: ProtocolModeConfig::DefaultBehavior,
+                locale: HidCountryCode::NotSupported,
+            },
+        );
+        // the rest only ever send, and the teensy has too few endpoints to give each an OUT
+        // endpoint it would never use as well as the serial port its three
+        Self {
+            keyboard,
+            gamepad: HIDClass::new_ep_in(bus, shared::GamepadReport::desc(), POLL_INTERVAL),
+            pointer: HIDClass::new_ep_in(bus, POINTER_DESCRIPTOR, POLL_INTERVAL),
+            consumer: HIDClass::new_ep_in(bus, shared::CONSUMER_DESCRIPTOR, POLL_INTERVAL),
+            mouse: HIDClass::new_ep_in(bus, shared::MOUSE_DESCRIPTOR, POLL_INTERVAL),
+        }
+    }
+
+    /// Poll the device with every interface, and `others` which aren't HID, returning
+    /// whether any of them has something to do.
+    pub fn poll(&mut self, device: &mut UsbDevice<'a, B>, others: &mut dyn UsbClass<B>) -> bool {
+        device.poll(&mut [
+            &mut self.keyboard,
+            &mut self.gamepad,
+            &mut self.pointer,
+            &mut self.consumer,
+            &mut self.mouse,
+            others,
+        ])
+    }
+
+    /// Which lock LEDs the target's just told the keyboard to light, if it has, see
+    /// [`shared::LED_CAPS_LOCK`] etc. It's the output report's only byte in either protocol.
+    /// Most hosts send it to the OUT endpoint, but some use SET_REPORT instead.
+    #[cfg(feature = "lock-leds")]
+    pub fn pull_leds(&mut self) -> Option<u8> {
+        let mut report = [0; 8];
+        if let Ok(1..) = self.keyboard.pull_raw_output(&mut report) {
+            return Some(report[0]);
+        }
+        let info = self.keyboard.pull_raw_report(&mut report).ok()?;
+        let output = matches!(info.report_type, usbd_hid::hid_class::ReportType::Output);
+        (output && info.len > 0).then_some(report[0])
+    }
+
+    /// Send a keyboard report in whichever protocol the host has asked for.
+    pub fn push_keyboard(&self, report: &KeyboardReport) -> usb_device::Result<usize> {
+        match self.keyboard.get_protocol_mode() {
+            // the host goes by the fixed boot layout rather than our report descriptor,
+            // which it may never have read
+            Ok(HidProtocolMode::Boot) => self.keyboard.push_raw_input(&boot_report(report)),
+            _ => self.keyboard.push_input(report),
+        }
+    }
+}
+
+/// A keyboard report laid out for the boot protocol: modifiers, a reserved byte, then six
+/// keys.
+pub fn boot_report(report: &KeyboardReport) -> [u8; 8] {
+    let mut boot = [0; 8];
+    boot[0] = report.modifier;
+    boot[2..].copy_from_slice(&report.keycodes);
+    boot
+}
+
+/// What was last sent on each interface which is sent its state every tick, so the target
+/// isn't sent the same thing over and over.
+pub struct Sent {
+    /// As [`boot_report`] lays it out.
+    pub keyboard: Dedup<[u8; 8]>,
+    pub gamepad: Dedup<shared::GamepadReport>,
+    /// The absolute pointer's or digitizer's raw report.
+    pub pointer: Dedup<[u8; 6]>,
+    pub consumer: Dedup<u16>,
+}
+
+impl Sent {
+    pub const fn new() -> Self {
+        Self {
+            keyboard: Dedup::new(),
+            gamepad: Dedup::new(),
+            pointer: Dedup::new(),
+            consumer: Dedup::new(),
+        }
+    }
+
+    /// Send everything next time whatever it is, as the target's starting afresh.
+    pub fn forget(&mut self) {
+        self.keyboard.forget();
+        self.gamepad.forget();
+        self.pointer.forget();
+        self.consumer.forget();
+    }
+}
+
+/// The last report sent to one interface, and how long ago.
+pub struct Dedup<R> {
+    last: Option<R>,
+    since_ms: u32,
+}
+
+impl<R: Copy + PartialEq> Dedup<R> {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            since_ms: 0,
+        }
+    }
+
+    /// Send `report` with `push` unless it's what went last, `elapsed_ms` ago or less, and
+    /// that was under [`REFRESH_MS`] ago. Whether it's sent or skipped the target has it
+    /// if this is Ok.
+    pub fn push(
+        &mut self,
+        report: R,
+        elapsed_ms: u32,
+        push: impl FnOnce() -> usb_device::Result<usize>,
+    ) -> usb_device::Result<()> {
+        self.since_ms = self.since_ms.saturating_add(elapsed_ms);
+        if self.last == Some(report) && self.since_ms < REFRESH_MS {
+            return Ok(());
+        }
+        push()?;
+        self.last = Some(report);
+        self.since_ms = 0;
+        Ok(())
+    }
+
+    /// Send the next report whatever it is.
+    pub fn forget(&mut self) {
+        self.last = None;
+    }
+}
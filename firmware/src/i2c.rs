@@ -0,0 +1,111 @@
+//! LPI2C1 as an I2C target, for the `i2c` feature, so a microcontroller or a hat can feed the
+//! teensy over two wires. The HAL only does controller mode, so like the SPI target it's
+//! driven through its registers.
+//!
+//! There aren't any registers to it, just a stream either way at [`ADDRESS`]: writes are
+//! frames the same as over the UART, and reads are the teensy's frames, zeros if it has
+//! nothing to say, which are only frame delimiters.
+
+use heapless::Deque;
+use teensy4_bsp::{
+    hal::iomuxc,
+    pins::common::{P18, P19},
+    ral::{self, lpi2c::LPI2C1},
+};
+
+use crate::transport::Transport;
+
+/// The teensy's 7 bit address on the bus.
+pub const ADDRESS: u32 = 0x2A;
+
+/// What can wait on the controller to read it, past which bytes are dropped.
+const TX_LEN: usize = 256;
+/// What's come in, until the frame task gets to it.
+const RX_LEN: usize = 64;
+
+/// As the board sets up its own I2C pins, open drain with the pull up in case there isn't
+/// one on the bus.
+const PIN_CONFIG: iomuxc::Config = iomuxc::Config::zero()
+    .set_open_drain(iomuxc::OpenDrain::Enabled)
+    .set_slew_rate(iomuxc::SlewRate::Fast)
+    .set_drive_strength(iomuxc::DriveStrength::R0_4)
+    .set_speed(iomuxc::Speed::Fast)
+    .set_pull_keeper(Some(iomuxc::PullKeeper::Pullup22k));
+
+pub struct I2cTarget {
+    lpi2c: LPI2C1,
+    tx: Deque<u8, TX_LEN>,
+    rx: Deque<u8, RX_LEN>,
+    /// Whether bytes coming in were lost or garbled since last asked.
+    lost: bool,
+}
+
+impl I2cTarget {
+    /// Start answering at [`ADDRESS`] on pins 18 (SDA) and 19 (SCL).
+    pub fn new(lpi2c: LPI2C1, mut sda: P18, mut scl: P19) -> Self {
+        iomuxc::configure(&mut sda, PIN_CONFIG);
+        iomuxc::configure(&mut scl, PIN_CONFIG);
+        iomuxc::lpi2c::prepare(&mut sda);
+        iomuxc::lpi2c::prepare(&mut scl);
+
+        ral::write_reg!(ral::lpi2c, lpi2c, SCR, RST: 1);
+        ral::write_reg!(ral::lpi2c, lpi2c, SCR, RST: 0, RTF: 1, RRF: 1);
+        ral::write_reg!(ral::lpi2c, lpi2c, SAMR, ADDR0: ADDRESS);
+        // hold the clock rather than lose a byte, or send one that isn't there yet
+        ral::write_reg!(ral::lpi2c, lpi2c, SCFGR1, ADDRCFG: 0, RXSTALL: 1, TXDSTALL: 1);
+        // transmit data is only asked for while the controller's reading, so it can stay on
+        ral::write_reg!(ral::lpi2c, lpi2c, SIER, RDIE: 1, TDIE: 1, BEIE: 1, FEIE: 1);
+        ral::write_reg!(ral::lpi2c, lpi2c, SCR, SEN: 1, FILTEN: 1);
+
+        Self {
+            lpi2c,
+            tx: Deque::new(),
+            rx: Deque::new(),
+            lost: false,
+        }
+    }
+
+    /// Take the byte that's come in and give the controller the next one it's reading, for
+    /// LPI2C1's interrupt, returning whether there's anything for the frame task.
+    pub fn service(&mut self) -> bool {
+        let (bit_error, fifo_error, received, wanted) =
+            ral::read_reg!(ral::lpi2c, self.lpi2c, SSR, BEF, FEF, RDF, TDF);
+        if bit_error == 1 || fifo_error == 1 {
+            ral::write_reg!(ral::lpi2c, self.lpi2c, SSR, BEF: 1, FEF: 1);
+            self.lost = true;
+        }
+        if received == 1 {
+            let byte = ral::read_reg!(ral::lpi2c, self.lpi2c, SRDR, DATA) as u8;
+            if self.rx.push_back(byte).is_err() {
+                self.lost = true;
+            }
+        }
+        if wanted == 1 {
+            let byte = self.tx.pop_front().unwrap_or(0);
+            ral::write_reg!(ral::lpi2c, self.lpi2c, STDR, DATA: u32::from(byte));
+        }
+        !self.rx.is_empty()
+    }
+}
+
+impl Transport for I2cTarget {
+    /// The controller sets the clock.
+    const SWITCHES_BAUD: bool = false;
+
+    fn interrupted(&mut self) -> bool {
+        self.service();
+        core::mem::take(&mut self.lost)
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.rx.pop_front()
+    }
+
+    /// Never waits, the same as the SPI target.
+    fn try_send(&mut self, byte: u8) -> bool {
+        let _ = self.tx.push_back(byte);
+        true
+    }
+
+    fn set_baud(&mut self, _baud: u32) {}
+}
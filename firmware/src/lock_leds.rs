@@ -0,0 +1,41 @@
+//! Indicator LEDs for the `lock-leds` feature, which show the target's num, caps and scroll
+//! lock on pins 9, 10 and 11 as the target sets them with the keyboard's output report.
+//! Each pin drives its LED through a resistor to ground; to use other pins, change them here
+//! and where they're taken in `init`.
+
+use teensy4_bsp::{
+    hal::gpio,
+    pins::t41::{P10, P11, P9},
+};
+
+pub struct LockLeds {
+    num_lock: gpio::Output<P9>,
+    caps_lock: gpio::Output<P10>,
+    scroll_lock: gpio::Output<P11>,
+}
+
+impl LockLeds {
+    /// All of them off until the target says otherwise.
+    pub fn new(gpio2: &mut gpio::Port<2>, num_lock: P9, caps_lock: P10, scroll_lock: P11) -> Self {
+        Self {
+            num_lock: gpio2.output(num_lock),
+            caps_lock: gpio2.output(caps_lock),
+            scroll_lock: gpio2.output(scroll_lock),
+        }
+    }
+
+    /// Light the ones set in `leds`, see [`shared::LED_CAPS_LOCK`] etc.
+    pub fn show(&self, leds: u8) {
+        set(&self.num_lock, leds & shared::LED_NUM_LOCK != 0);
+        set(&self.caps_lock, leds & shared::LED_CAPS_LOCK != 0);
+        set(&self.scroll_lock, leds & shared::LED_SCROLL_LOCK != 0);
+    }
+}
+
+fn set<P>(pin: &gpio::Output<P>, lit: bool) {
+    if lit {
+        pin.set();
+    } else {
+        pin.clear();
+    }
+}
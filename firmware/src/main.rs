@@ -6,39 +6,108 @@
 #![no_std]
 #![no_main]
 
-use teensy4_panic as _;
+mod button;
+#[cfg(feature = "can")]
+mod can;
+mod console;
+#[cfg(feature = "ethernet")]
+mod enet;
+#[cfg(feature = "ethernet")]
+mod ethernet;
+mod flash;
+mod hid;
+#[cfg(feature = "i2c")]
+mod i2c;
+#[cfg(feature = "lock-leds")]
+mod lock_leds;
+#[cfg(feature = "key-matrix")]
+mod matrix;
+mod panic;
+#[cfg(feature = "sd-payload")]
+mod payload;
+#[cfg(feature = "sd-payload")]
+mod sd;
+mod settings;
+#[cfg(feature = "spi")]
+mod spi;
+mod transport;
+#[cfg(uart)]
+mod uart;
+mod watchdog;
 
-#[rtic::app(device = teensy4_bsp, peripherals = false)]
+// RTIC checks every local resource is Send without its cfg, so the feature gated ones name
+// a type whether or not their feature's on
+#[cfg(feature = "sd-payload")]
+type SdHost = sd::Host;
+#[cfg(not(feature = "sd-payload"))]
+type SdHost = ();
+#[cfg(feature = "sd-payload")]
+type PayloadButton = payload::Button;
+#[cfg(not(feature = "sd-payload"))]
+type PayloadButton = ();
+#[cfg(feature = "lock-leds")]
+type LockLeds = lock_leds::LockLeds;
+#[cfg(not(feature = "lock-leds"))]
+type LockLeds = ();
+#[cfg(feature = "key-matrix")]
+type KeyMatrix = matrix::KeyMatrix;
+#[cfg(not(feature = "key-matrix"))]
+type KeyMatrix = ();
+
+#[rtic::app(device = teensy4_bsp, peripherals = false, dispatchers = [KPP])]
 mod app {
+    use core::fmt::Write as _;
+
     use heapless::spsc::Queue;
+    use rtic::Mutex as _;
     use rtic_monotonics::rtic_time::embedded_hal::digital::OutputPin;
     use teensy4_bsp::{self as bsp, board};
 
-    use bsp::hal::{
-        lpuart,
-        usbd::{gpt, BusAdapter, EndpointMemory, EndpointState, Speed},
-    };
+    use bsp::hal::usbd::{gpt, BusAdapter, EndpointMemory, EndpointState, Speed};
 
+    use shared::{
+        blink::Blinker,
+        board::{Board, Receiver},
+        crash::CrashReport,
+        link::{self, FrameReader, SerialLink},
+    };
     use usb_device::{
         bus::UsbBusAllocator,
         device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
     };
-    use usbd_hid::{
-        descriptor::{KeyboardReport, SerializedDescriptor as _},
-        hid_class::HIDClass,
+    use usbd_hid::descriptor::KeyboardReport;
+    use usbd_serial::SerialPort;
+
+    use crate::{
+        button::{KillSwitch, MacroButton},
+        console::{log, Console},
+        hid, panic,
+        settings::Settings,
+        transport::{Link, Locked, Transport},
+        watchdog::Watchdog,
     };
 
-    /// Change me if you want to play with a full-speed USB device.
-    const SPEED: Speed = Speed::High;
-    /// https://pid.codes/1209/C00B/
-    const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0xC00B);
+    /// Some older KVMs and BIOSes get on better with a full speed keyboard.
+    const SPEED: Speed = if cfg!(feature = "full-speed") {
+        Speed::LowFull
+    } else {
+        Speed::High
+    };
+    const VID_PID: UsbVidPid = UsbVidPid(shared::USB_VID, shared::USB_PID);
     const PRODUCT: &str = "teensy-keyboard-bridge";
     /// How frequently should we poll the logger?
     const LPUART_POLL_INTERVAL_MS: u32 = board::PERCLK_FREQUENCY / 1_000 * 100;
     /// The USB GPT timer we use to (infrequently) send mouse updates.
     const GPT_INSTANCE: gpt::Instance = gpt::Instance::Gpt0;
-    /// How frequently should we push keyboard updates to the host?
-    const KEYBOARD_UPDATE_INTERVAL_MS: u32 = 1;
+    /// How frequently should we push mouse updates to the host? Less often than keys, which
+    /// go every `report_interval_ms`, as movements which come in between are added
+    /// together into the next one.
+    const MOUSE_UPDATE_INTERVAL_MS: u32 = 4;
+    /// Once this many keyboard reports are waiting RTS is deasserted, which pauses a client
+    /// using --flow-control, until the queue is back down to [`RESUME_AT`]. That leaves room
+    /// for the frames already on their way, which can be a few with a USB serial adapter.
+    const PAUSE_AT: usize = 16;
+    const RESUME_AT: usize = 8;
 
     /// This allocation is shared across all USB endpoints. It needs to be large
     /// enough to hold the maximum packet size for *all* endpoints. If you start
@@ -50,42 +119,146 @@ mod app {
 
     type Bus = BusAdapter;
 
+    // for timing scheduled reports, and waiting on the report queue while playing a payload
+    rtic_monotonics::systick_monotonic!(Mono, 1_000);
+
     #[local]
     struct Local {
-        class: HIDClass<'static, Bus>,
+        /// The keyboard, gamepad, pointers and consumer control.
+        hid: hid::Interfaces<'static, Bus>,
+        /// Where the agent on the target talks to us, as a serial port on its side, and
+        /// where the console goes the other way.
+        serial: SerialPort<'static, Bus>,
         device: UsbDevice<'static, Bus>,
         led: board::Led,
-        lpuart2: board::Lpuart2,
+        /// Our RTS, the client's CTS, on pin 2 as LPUART2's own isn't broken out. There's
+        /// no XON/XOFF instead, as they can turn up in the middle of our frames to the client.
+        rts: bsp::hal::gpio::Output<bsp::pins::t41::P2>,
+        watchdog: Watchdog,
+        /// Types the macro saved in flash.
+        button: MacroButton,
+        kill_switch: KillSwitch,
+        /// What the firmware panicked with before this boot, if it did.
+        crash_report: Option<CrashReport>,
+        /// The SD card slot, and the button which plays its payload again.
+        #[cfg(feature = "sd-payload")]
+        sd: crate::SdHost,
+        #[cfg(feature = "sd-payload")]
+        payload_button: crate::PayloadButton,
+        /// Shows the target's lock keys.
+        #[cfg(feature = "lock-leds")]
+        lock_leds: crate::LockLeds,
+        /// Keys of the teensy's own, merged with the client's.
+        #[cfg(feature = "key-matrix")]
+        key_matrix: crate::KeyMatrix,
     }
 
     #[shared]
     struct Shared {
         keys_to_press: Queue<KeyboardReport, 32>,
+        /// Unlike keypresses we only care about the latest gamepad state.
+        gamepad_state: shared::GamepadReport,
+        /// Same for the absolute pointer.
+        pointer_state: shared::AbsolutePointerState,
+        /// Or the digitizer's pen, which takes its place with the `digitizer` feature.
+        digitizer_state: shared::DigitizerState,
+        /// And the media key held down, 0 for none.
+        consumer_usage: u16,
+        /// The relative mouse's movement still to send, added to by every message.
+        mouse_state: shared::MouseState,
+        /// How long since we last heard from the client, None once we've let go of
+        /// everything for it going quiet.
+        silent_ms: Option<u32>,
+        /// Whether the client sends heartbeats, which gets it a shorter timeout.
+        heartbeats: bool,
+        /// How long to wait for a client without heartbeats, 0 for forever.
+        idle_timeout_ms: u32,
+        /// How often to send the target a keyboard report, which the client can change.
+        report_interval_ms: u32,
+        /// Whether a keyboard report goes as soon as it comes in, if the last has gone,
+        /// rather than waiting for the next tick, which the client can change too.
+        immediate_reports: bool,
+        /// The baud rate the UART's at, which the client can change.
+        baud: u32,
+        /// How long since switching baud rate, until we hear something at the new one.
+        baud_unconfirmed_ms: Option<u32>,
+        /// Pressed when the client goes silent, to lock the target.
+        lock_chord: Option<KeyboardReport>,
+        /// Messages from the agent waiting to be passed on to the client.
+        agent_messages: Queue<shared::AgentMessage, 16>,
+        /// Reports from [`shared::HostMessage::Scheduled`] waiting for their turn.
+        scheduled: Queue<shared::ScheduledReport, 16>,
+        /// What's saved in flash.
+        settings: Settings,
+        /// What's gone wrong so far, for the client to ask about.
+        telemetry: shared::Telemetry,
+        /// Lines waiting to go out to a terminal on the target.
+        console: Console,
+        /// Where the client is: the UART, the SPI or I2C target, the CAN bus or the network.
+        transport: Link,
+        /// Whether the kill switch is closed, while which nothing reaches the target.
+        killed: bool,
     }
 
-    #[init(local = [bus: Option<UsbBusAllocator<Bus>> = None])]
+    #[init(local = [bus: Option<UsbBusAllocator<Bus>> = None, serial_number: heapless::String<16> = heapless::String::new()])]
     fn init(ctx: init::Context) -> (Shared, Local) {
+        let crash_report = panic::take_crash_report();
         let board::Resources {
             pit: (mut timer, _, _, _),
             usb: usbd,
             pins,
+            #[cfg(uart)]
             lpuart2,
+            #[cfg(feature = "i2c")]
+            lpi2c1,
             mut gpio2,
+            mut gpio4,
             ..
         } = board::t41(board::instances());
         let led = board::led(&mut gpio2, pins.p13);
+        let rts = gpio4.output(pins.p2);
+        // it's active low, go ahead
+        rts.clear();
+        let button = MacroButton::new(&mut gpio4, pins.p4);
+        let kill_switch = KillSwitch::new(&mut gpio4, pins.p5);
+        #[cfg(feature = "lock-leds")]
+        let lock_leds = crate::lock_leds::LockLeds::new(&mut gpio2, pins.p9, pins.p10, pins.p11);
+        #[cfg(feature = "key-matrix")]
+        let key_matrix = crate::matrix::KeyMatrix::new(
+            &mut gpio2,
+            (pins.p6, pins.p7, pins.p8, pins.p32),
+            (pins.p34, pins.p35, pins.p36, pins.p37),
+        );
+        let killed = kill_switch.is_closed();
 
         timer.set_load_timer_value(LPUART_POLL_INTERVAL_MS);
         timer.set_interrupt_enable(true);
         timer.enable();
 
-        let mut lpuart2: board::Lpuart2 = board::lpuart(lpuart2, pins.p14, pins.p15, 115200);
-        lpuart2.disable(|lpuart2| {
-            lpuart2.disable_fifo(lpuart::Direction::Tx);
-            lpuart2.disable_fifo(lpuart::Direction::Rx);
-            lpuart2.set_interrupts(lpuart::Interrupts::RECEIVE_FULL);
-            lpuart2.set_parity(None);
-        });
+        #[cfg(uart)]
+        let transport = {
+            use bsp::hal::lpuart;
+            let mut lpuart2: board::Lpuart2 =
+                board::lpuart(lpuart2, pins.p14, pins.p15, shared::DEFAULT_BAUD_RATE);
+            lpuart2.disable(|lpuart2| {
+                lpuart2.disable_fifo(lpuart::Direction::Tx);
+                lpuart2.disable_fifo(lpuart::Direction::Rx);
+                lpuart2.set_interrupts(lpuart::Interrupts::RECEIVE_FULL);
+                lpuart2.set_parity(None);
+            });
+            crate::uart::Uart::new(lpuart2)
+        };
+        #[cfg(feature = "spi")]
+        let transport = crate::spi::SpiTarget::new(pins.p0, pins.p1, pins.p26, pins.p27);
+        #[cfg(feature = "i2c")]
+        let transport = crate::i2c::I2cTarget::new(lpi2c1, pins.p18, pins.p19);
+        #[cfg(feature = "can")]
+        let transport = crate::can::CanLink::new(pins.p22, pins.p23);
+        #[cfg(feature = "ethernet")]
+        let transport = {
+            use crate::ethernet::{EthernetLink, Storage};
+            EthernetLink::new(cortex_m::singleton!(: Storage = Storage::new()).unwrap())
+        };
 
         let bus = BusAdapter::with_speed(usbd, &EP_MEMORY, &EP_STATE, SPEED);
         bus.set_interrupts(true);
@@ -94,61 +267,169 @@ mod app {
             gpt.clear_elapsed();
             gpt.set_interrupt_enabled(true);
             gpt.set_mode(gpt::Mode::Repeat);
-            gpt.set_load(KEYBOARD_UPDATE_INTERVAL_MS * 1000);
+            gpt.set_load(u32::from(shared::DEFAULT_REPORT_INTERVAL_MS) * 1000);
             gpt.reset();
             gpt.run();
         });
 
         let bus = ctx.local.bus.insert(UsbBusAllocator::new(bus));
-        // Note that "4" correlates to a 1ms polling interval. Since this is a high speed
-        // device, bInterval is computed differently.
-        let class = HIDClass::new(bus, KeyboardReport::desc(), 4);
+        let hid = hid::Interfaces::new(bus);
+        let serial = SerialPort::new(bus);
+        // so the target can tell bridges apart, and keeps seeing the same one as the same
+        let serial_number = ctx.local.serial_number;
+        write!(serial_number, "{:016X}", unique_id()).ok();
         // TODO: ? https://pid.codes/howto/
         let device = UsbDeviceBuilder::new(bus, VID_PID)
-            .strings(&[usb_device::device::StringDescriptors::default().product(PRODUCT)])
+            .strings(&[usb_device::device::StringDescriptors::default()
+                .product(PRODUCT)
+                .serial_number(serial_number)])
             .unwrap()
-            .device_class(usbd_serial::USB_CLASS_CDC)
+            // there's no class for the device as a whole, each interface has its own so the
+            // keyboard's seen as a boot keyboard wherever it's looked for. The serial port's
+            // two interfaces have to be associated to be seen as one, which needs the IAD
+            // device class, 0xEF/0x02/0x01, rather than CDC's
+            .composite_with_iads()
             .max_packet_size_0(64)
             .unwrap()
             .build();
 
+        let settings = Settings::load();
+        let report_interval_ms = match settings.get(shared::SettingKey::ReportInterval) {
+            Some(shared::Setting::ReportInterval(ms)) => {
+                ms.clamp(1, shared::MAX_REPORT_INTERVAL_MS)
+            }
+            _ => shared::DEFAULT_REPORT_INTERVAL_MS,
+        };
+
+        Mono::start(ctx.core.SYST, board::ARM_FREQUENCY);
+
+        #[cfg(feature = "sd-payload")]
+        let (sd, payload_button) = {
+            let button = crate::payload::Button::new(&mut gpio4, pins.p3);
+            // it'll wait for the target to take the reports
+            play_payload::spawn().ok();
+            (crate::sd::Host::new(), button)
+        };
+
+        // last, so setting everything else up doesn't count against it
+        let watchdog = Watchdog::start();
+
         (
             Shared {
                 keys_to_press: Queue::new(),
+                gamepad_state: shared::GamepadReport::default(),
+                pointer_state: shared::AbsolutePointerState::default(),
+                digitizer_state: shared::DigitizerState::default(),
+                consumer_usage: 0,
+                mouse_state: shared::MouseState::default(),
+                silent_ms: None,
+                heartbeats: false,
+                idle_timeout_ms: shared::DEFAULT_IDLE_TIMEOUT_MS,
+                report_interval_ms: u32::from(report_interval_ms),
+                immediate_reports: false,
+                baud: shared::DEFAULT_BAUD_RATE,
+                baud_unconfirmed_ms: None,
+                lock_chord: None,
+                agent_messages: Queue::new(),
+                scheduled: Queue::new(),
+                settings,
+                telemetry: shared::Telemetry::default(),
+                console: Console::default(),
+                transport,
+                killed,
             },
             Local {
-                class,
+                hid,
+                serial,
                 device,
                 led,
-                lpuart2,
+                rts,
+                watchdog,
+                button,
+                kill_switch,
+                crash_report,
+                #[cfg(feature = "sd-payload")]
+                sd,
+                #[cfg(feature = "sd-payload")]
+                payload_button,
+                #[cfg(feature = "lock-leds")]
+                lock_leds,
+                #[cfg(feature = "key-matrix")]
+                key_matrix,
             },
         )
     }
 
-    #[task(binds = USB_OTG1, local = [device, class, led, configured: bool = false], shared = [keys_to_press], priority = 2)]
+    #[task(binds = USB_OTG1, local = [device, hid, serial, led, rts, watchdog, button, kill_switch, blinker: Blinker = Blinker::new(), configured: bool = false, mouse_ms: u32 = 0, mouse_buttons: u8 = 0, tick_ms: u32 = shared::DEFAULT_REPORT_INTERVAL_MS as u32, agent_frames: FrameReader = FrameReader::new(), front_sent: bool = false, sent: hid::Sent = hid::Sent::new(), lock_leds, key_matrix], shared = [keys_to_press, gamepad_state, pointer_state, digitizer_state, consumer_usage, mouse_state, silent_ms, heartbeats, idle_timeout_ms, report_interval_ms, baud, baud_unconfirmed_ms, lock_chord, agent_messages, scheduled, settings, telemetry, console, transport, killed, immediate_reports], priority = 2)]
     fn usb1(ctx: usb1::Context) {
         let usb1::LocalResources {
-            class,
+            hid,
+            serial,
             device,
             led,
+            rts,
+            watchdog,
+            button,
+            kill_switch,
+            blinker,
             configured,
+            mouse_ms,
+            mouse_buttons,
+            tick_ms,
+            agent_frames,
+            front_sent,
+            sent,
+            #[cfg(feature = "lock-leds")]
+            lock_leds,
+            #[cfg(feature = "key-matrix")]
+            key_matrix,
             ..
         } = ctx.local;
         let mut keys_to_press = ctx.shared.keys_to_press;
+        let mut gamepad_state = ctx.shared.gamepad_state;
+        let mut pointer_state = ctx.shared.pointer_state;
+        let mut digitizer_state = ctx.shared.digitizer_state;
+        let mut consumer_usage = ctx.shared.consumer_usage;
+        let mut mouse_state = ctx.shared.mouse_state;
+        let mut silent_ms = ctx.shared.silent_ms;
+        let mut heartbeats = ctx.shared.heartbeats;
+        let mut idle_timeout_ms = ctx.shared.idle_timeout_ms;
+        let mut report_interval_ms = ctx.shared.report_interval_ms;
+        let mut baud = ctx.shared.baud;
+        let mut baud_unconfirmed_ms = ctx.shared.baud_unconfirmed_ms;
+        let mut lock_chord = ctx.shared.lock_chord;
+        let mut agent_messages = ctx.shared.agent_messages;
+        let mut scheduled = ctx.shared.scheduled;
+        let mut settings = ctx.shared.settings;
+        let mut telemetry = ctx.shared.telemetry;
+        let mut console = ctx.shared.console;
+        let mut transport = ctx.shared.transport;
+        let mut killed = ctx.shared.killed;
+        let mut immediate_reports = ctx.shared.immediate_reports;
+
+        // this runs at least every millisecond off the GPT, and below the UART's priority, so
+        // if either handler wedges or interrupts stop altogether it's starved and we reset
+        watchdog.feed();
+        panic::set_hid(hid);
 
-        device.poll(&mut [class]);
+        hid.poll(device, serial);
 
         if device.state() == UsbDeviceState::Configured {
             if !*configured {
                 device.bus().configure();
+                sent.forget();
+                log!(console, "Configured by the target");
             }
             *configured = true;
         } else {
             *configured = false;
         }
-
+        #[cfg(feature = "lock-leds")]
         if !*configured {
-            return;
+            // there's no target to show the lock keys of
+            lock_leds.show(0);
+        } else if let Some(leds) = hid.pull_leds() {
+            lock_leds.show(leds);
         }
 
         let elapsed = device.bus().gpt_mut(GPT_INSTANCE, |gpt| {
@@ -158,67 +439,738 @@ mod app {
             }
             elapsed
         });
+        if elapsed && transport.lock(|transport| transport.tick(*tick_ms)) {
+            rtic::pend(bsp::Interrupt::LPUART2);
+        }
+
+        if !*configured {
+            if elapsed {
+                // nothing's queued with nowhere to send it, so there's only the blink code
+                let current = telemetry.lock(|telemetry| *telemetry);
+                let lit = blinker.tick(*tick_ms, false, &current).unwrap_or(false);
+                set_led(led, lit);
+            }
+            return;
+        }
 
+        let mut chunk = [0; 64];
+        while let Ok(read) = serial.read(&mut chunk) {
+            if read == 0 {
+                break;
+            }
+            for &byte in &chunk[..read] {
+                // junk, too long or not a message, is dropped
+                let Some(Ok(message)) = agent_frames.push::<shared::AgentMessage>(byte) else {
+                    continue;
+                };
+                // if the client isn't keeping up there's nothing for it but to drop some
+                agent_messages.lock(|messages| messages.enqueue(message).ok());
+                // fails if it's already going, in which case it'll get to this one too
+                forward_agent_messages::spawn().ok();
+            }
+        }
+        console.lock(|console| console.flush(serial));
+
+        let immediate = immediate_reports.lock(|immediate| *immediate);
         if !elapsed {
+            if immediate && !killed.lock(|killed| *killed) {
+                // the front of the queue is sent again each tick until there's another, so
+                // once it's gone the next can go now, unless the last is still going
+                let key = keys_to_press.lock(|keys| {
+                    if *front_sent && keys.len() > 1 {
+                        keys.dequeue();
+                        *front_sent = false;
+                    }
+                    keys.peek().copied().filter(|_| !*front_sent)
+                });
+                if let Some(key) = key {
+                    #[cfg(feature = "key-matrix")]
+                    let key = key_matrix.merged(&key);
+                    let pushed = sent
+                        .keyboard
+                        .push(hid::boot_report(&key), 0, || hid.push_keyboard(&key));
+                    *front_sent = pushed.is_ok();
+                }
+            }
             return;
         }
 
-        if let Some(key) = keys_to_press.lock(|keys| {
+        // the tick that's just gone was the old length, the next is the new one
+        let interval_ms = *tick_ms;
+        let wanted_ms = report_interval_ms.lock(|interval| *interval);
+        if wanted_ms != interval_ms {
+            device.bus().gpt_mut(GPT_INSTANCE, |gpt| {
+                gpt.set_load(wanted_ms * 1000);
+                gpt.reset();
+            });
+            *tick_ms = wanted_ms;
+        }
+
+        let timeout = if heartbeats.lock(|heartbeats| *heartbeats) {
+            shared::HEARTBEAT_TIMEOUT_MS
+        } else {
+            idle_timeout_ms.lock(|timeout| *timeout)
+        };
+        let timed_out = silent_ms.lock(|silent| {
+            let Some(ms) = silent else {
+                return false;
+            };
+            *ms = ms.saturating_add(interval_ms);
+            if timeout == 0 || *ms < timeout {
+                return false;
+            }
+            // wait for the next frame, so we only do this once
+            *silent = None;
+            true
+        });
+        if timed_out {
+            log!(
+                console,
+                "Nothing from the client for {timeout}ms, letting go of everything"
+            );
+            // the client's gone, cable pulled or crashed, so let go of everything rather
+            // than leave a modifier held down on the host until it comes back
+            // whoever comes along next might not send heartbeats
+            heartbeats.lock(|heartbeats| *heartbeats = false);
+            let chord = lock_chord.lock(|chord| *chord);
+            scheduled.lock(|scheduled| while scheduled.dequeue().is_some() {});
+            keys_to_press.lock(|keys| {
+                while keys.dequeue().is_some() {}
+                // leaving someone else free to walk up and use it is worse than a stray chord
+                if let Some(chord) = chord {
+                    keys.enqueue(chord).ok();
+                    keys.enqueue(KeyboardReport::default()).ok();
+                }
+            });
+            *front_sent = false;
+            gamepad_state.lock(|gamepad| *gamepad = shared::GamepadReport::default());
+            pointer_state.lock(|pointer| {
+                pointer.buttons = 0;
+                pointer.wheel = 0;
+            });
+            digitizer_state.lock(|digitizer| *digitizer = shared::DigitizerState::default());
+            consumer_usage.lock(|usage| *usage = 0);
+            // any movement still to go is dropped too
+            mouse_state.lock(|mouse| *mouse = shared::MouseState::default());
+        }
+
+        let unconfirmed = baud_unconfirmed_ms.lock(|unconfirmed| {
+            let Some(ms) = unconfirmed else {
+                return false;
+            };
+            *ms += interval_ms;
+            if *ms < shared::BAUD_CONFIRM_MS {
+                return false;
+            }
+            *unconfirmed = None;
+            true
+        });
+        // back to where a client starting afresh expects us to be
+        let switched = baud.lock(|baud| *baud != shared::DEFAULT_BAUD_RATE);
+        if switched && (unconfirmed || timed_out) {
+            log!(console, "Back to {} baud", shared::DEFAULT_BAUD_RATE);
+            transport.lock(|transport| transport.set_baud(shared::DEFAULT_BAUD_RATE));
+            baud.lock(|baud| *baud = shared::DEFAULT_BAUD_RATE);
+        }
+
+        // the frame task drops everything from the client while it's closed, and anything
+        // that got in before it knew, or came from anywhere else, is let go of here
+        let closed = kill_switch.is_closed();
+        let was_closed = killed.lock(|killed| core::mem::replace(killed, closed));
+        if closed != was_closed {
+            if closed {
+                log!(
+                    console,
+                    "The kill switch is closed, sending the target nothing"
+                );
+            } else {
+                log!(console, "The kill switch is open again");
+            }
+        }
+        if closed {
+            scheduled.lock(|scheduled| while scheduled.dequeue().is_some() {});
+            keys_to_press.lock(|keys| while keys.dequeue().is_some() {});
+            *front_sent = false;
+            gamepad_state.lock(|gamepad| *gamepad = shared::GamepadReport::default());
+            pointer_state.lock(|pointer| {
+                pointer.buttons = 0;
+                pointer.wheel = 0;
+            });
+            digitizer_state.lock(|digitizer| *digitizer = shared::DigitizerState::default());
+            consumer_usage.lock(|usage| *usage = 0);
+            mouse_state.lock(|mouse| *mouse = shared::MouseState::default());
+        }
+
+        #[cfg(feature = "key-matrix")]
+        key_matrix.scan(interval_ms);
+
+        if button.poll(interval_ms) && !closed {
+            let saved = settings.lock(|settings| settings.get(shared::SettingKey::ButtonMacro));
+            if let Some(shared::Setting::ButtonMacro(button_macro)) = saved {
+                // all of it or none, so nothing's left held down
+                let queued = keys_to_press.lock(|keys| {
+                    let room = keys.capacity() - keys.len();
+                    let fits = room >= button_macro.chords().len() * 2;
+                    if fits {
+                        for report in button_macro.reports() {
+                            keys.enqueue(report.into()).ok();
+                        }
+                    }
+                    fits
+                });
+                if queued {
+                    log!(console, "Typing the button's macro");
+                } else {
+                    log!(console, "The queue's too full for the button's macro");
+                }
+            } else {
+                log!(
+                    console,
+                    "The button was pressed, but there's no macro saved for it"
+                );
+            }
+        }
+
+        let (key, busy) = keys_to_press.lock(|keys| {
+            if immediate && *front_sent && keys.len() > 1 {
+                // rather than sending it again first
+                keys.dequeue();
+            }
             if keys.len() > 1 {
                 // don't leave the buffer empty
-                led.set_high().ok();
-                keys.dequeue()
+                (keys.dequeue(), true)
             } else {
-                led.set_low().ok();
-                keys.peek().copied()
+                (keys.peek().copied(), false)
             }
-        }) {
-            class.push_input(&key).ok();
+        });
+        // anything that's gone wrong takes over from showing the queue's busy
+        let current = telemetry.lock(|telemetry| *telemetry);
+        set_led(
+            led,
+            blinker.tick(interval_ms, true, &current).unwrap_or(busy),
+        );
+        let waiting = keys_to_press.lock(|keys| keys.len());
+        if waiting >= PAUSE_AT && !rts.is_set() {
+            rts.set();
+            log!(console, "{waiting} reports queued, pausing the client");
+        } else if waiting <= RESUME_AT && rts.is_set() {
+            rts.clear();
+            log!(console, "Resuming the client");
+        }
+        // if we have received no keypresses send an empty report, unless it's just gone
+        let report = key.unwrap_or(KeyboardReport::default());
+        // the matrix is let go of while the kill switch is closed, like everything else
+        #[cfg(feature = "key-matrix")]
+        let report = if closed {
+            report
         } else {
-            // if we have received no keypresses return None
-            class.push_input(&KeyboardReport::default()).ok();
+            key_matrix.merged(&report)
+        };
+        let pushed = sent
+            .keyboard
+            .push(hid::boot_report(&report), interval_ms, || {
+                hid.push_keyboard(&report)
+            })
+            .is_ok();
+        if !pushed {
+            telemetry.lock(|telemetry| telemetry.usb_errors += 1);
+        }
+        // what was at the front's been sent and dequeued if there was more after it
+        *front_sent = pushed && key.is_some() && !busy;
+
+        let state = gamepad_state.lock(|state| *state);
+        sent.gamepad
+            .push(state, interval_ms, || hid.gamepad.push_input(&state))
+            .ok();
+
+        let (report, scrolling) = if cfg!(feature = "digitizer") {
+            (digitizer_state.lock(|state| state.to_report()), false)
+        } else {
+            // the wheel is relative, so only send each scroll once
+            pointer_state.lock(|state| {
+                let current = *state;
+                state.wheel = 0;
+                (current.to_report(), current.wheel != 0)
+            })
+        };
+        if scrolling {
+            // and the same scroll twice is two reports
+            sent.pointer.forget();
+        }
+        sent.pointer
+            .push(report, interval_ms, || hid.pointer.push_raw_input(&report))
+            .ok();
+
+        let usage = consumer_usage.lock(|usage| *usage);
+        sent.consumer
+            .push(usage, interval_ms, || {
+                hid.consumer.push_raw_input(&usage.to_le_bytes())
+            })
+            .ok();
+
+        *mouse_ms += interval_ms;
+        if *mouse_ms >= MOUSE_UPDATE_INTERVAL_MS {
+            *mouse_ms = 0;
+            // unlike the others it's relative, so only send when there's something new, and
+            // only take the movement off once the host has it
+            mouse_state.lock(|state| {
+                if !state.is_moving() && state.buttons == *mouse_buttons {
+                    return;
+                }
+                let mut rest = *state;
+                let report = rest.take_report();
+                if hid.mouse.push_raw_input(&report).is_ok() {
+                    *state = rest;
+                    *mouse_buttons = state.buttons;
+                }
+            });
+        }
+    }
+
+    /// With any feature putting the client somewhere else, LPUART2's left off and its
+    /// interrupt only comes from these, once what's come in has been taken so they don't
+    /// fire again straight away.
+    #[cfg(feature = "spi")]
+    #[task(binds = LPSPI3, shared = [transport], priority = 3)]
+    fn lpspi3_interrupt(mut ctx: lpspi3_interrupt::Context) {
+        if ctx.shared.transport.lock(|spi| spi.service()) {
+            rtic::pend(bsp::Interrupt::LPUART2);
+        }
+    }
+
+    #[cfg(feature = "i2c")]
+    #[task(binds = LPI2C1, shared = [transport], priority = 3)]
+    fn lpi2c1_interrupt(mut ctx: lpi2c1_interrupt::Context) {
+        if ctx.shared.transport.lock(|i2c| i2c.service()) {
+            rtic::pend(bsp::Interrupt::LPUART2);
+        }
+    }
+
+    #[cfg(feature = "can")]
+    #[task(binds = CAN1, shared = [transport], priority = 3)]
+    fn can1_interrupt(mut ctx: can1_interrupt::Context) {
+        if ctx.shared.transport.lock(|can| can.service()) {
+            rtic::pend(bsp::Interrupt::LPUART2);
+        }
+    }
+
+    #[cfg(feature = "ethernet")]
+    #[task(binds = ENET, shared = [transport, console], priority = 3)]
+    fn enet_interrupt(ctx: enet_interrupt::Context) {
+        let mut transport = ctx.shared.transport;
+        let mut console = ctx.shared.console;
+        let (ready, address) =
+            transport.lock(|ethernet| (ethernet.service(), ethernet.address_changed()));
+        match address {
+            Some(Some(address)) => {
+                log!(
+                    console,
+                    "Listening on {address}, port {}",
+                    crate::ethernet::PORT
+                );
+            }
+            Some(None) => log!(console, "Lost the DHCP lease"),
+            None => {}
+        }
+        if ready {
+            rtic::pend(bsp::Interrupt::LPUART2);
         }
     }
 
-    #[task(binds = LPUART2, local = [lpuart2, buf: heapless::Vec<u8, 32> = heapless::Vec::new()], shared = [keys_to_press], priority = 3)]
+    #[cfg(feature = "sd-payload")]
+    #[task(binds = GPIO4_COMBINED_0_15, local = [payload_button], priority = 2)]
+    fn payload_button_interrupt(ctx: payload_button_interrupt::Context) {
+        // fails if it's already playing, which is what the button bouncing does
+        if ctx.local.payload_button.pressed() {
+            play_payload::spawn().ok();
+        }
+    }
+
+    /// Act on every frame that's come in from the client.
+    #[task(binds = LPUART2, local = [crash_report, receiver: Receiver = Receiver::new()], shared = [keys_to_press, gamepad_state, pointer_state, digitizer_state, consumer_usage, mouse_state, silent_ms, heartbeats, idle_timeout_ms, report_interval_ms, baud, baud_unconfirmed_ms, lock_chord, scheduled, settings, telemetry, console, transport, killed, immediate_reports], priority = 3)]
     fn lpuart2_interrupt(ctx: lpuart2_interrupt::Context) {
-        use lpuart::Status;
-        let lpuart2 = ctx.local.lpuart2;
-        let mut keys_to_press = ctx.shared.keys_to_press;
-        let buf = ctx.local.buf;
+        let mut frames = Frames {
+            shared: ctx.shared,
+            crash_report: ctx.local.crash_report,
+        };
+        let interrupted = frames
+            .shared
+            .transport
+            .lock(|transport| transport.interrupted());
+        if interrupted {
+            frames.telemetry(|telemetry| telemetry.uart_errors += 1);
+            log!(
+                frames.shared.console,
+                "Lost or garbled bytes from the client"
+            );
+        }
+        let new_reports = ctx.local.receiver.poll(&mut frames);
+        if new_reports && frames.shared.immediate_reports.lock(|immediate| *immediate) {
+            rtic::pend(bsp::Interrupt::USB_OTG1);
+        }
+    }
 
-        let status = lpuart2.status();
-        lpuart2.clear_status(Status::W1C);
+    /// Everything the client's frames change, for the [`Receiver`] to get at.
+    struct Frames<'a> {
+        shared: lpuart2_interrupt::SharedResources<'a>,
+        crash_report: &'a Option<CrashReport>,
+    }
 
-        if status.contains(Status::RECEIVE_FULL) {
-            loop {
-                let data = lpuart2.read_data();
-                if data.flags().contains(lpuart::ReadFlags::RXEMPT) {
-                    break;
-                }
+    impl SerialLink for Frames<'_> {
+        fn receive(&mut self) -> Option<u8> {
+            Locked(&mut self.shared.transport).receive()
+        }
 
-                let byte = u8::from(data);
-                let is_full = buf.push(byte).is_err();
+        fn send(&mut self, byte: u8) {
+            Locked(&mut self.shared.transport).send(byte);
+        }
+    }
 
-                // if were full something's gone wrong, just bail
-                if is_full {
-                    buf.clear();
-                }
+    impl Board for Frames<'_> {
+        const SWITCHES_BAUD: bool = Link::SWITCHES_BAUD;
 
-                // end of COBS packet wheeee
-                if byte == 0 {
-                    let maybe_report = postcard::from_bytes_cobs::<
-                        '_,
-                        shared::WhyNoDeriveDeserializeManSadFaceHere,
-                    >(buf.as_mut_slice());
+        fn reconnected(&mut self) -> bool {
+            self.shared
+                .transport
+                .lock(|transport| transport.reconnected())
+        }
 
-                    if let Ok(report) = maybe_report {
-                        keys_to_press.lock(|keys| keys.enqueue(report.into()).ok());
-                    }
+        fn reset(&mut self) {
+            self.shared
+                .scheduled
+                .lock(|scheduled| while scheduled.dequeue().is_some() {});
+            self.shared.keys_to_press.lock(|keys| {
+                while keys.dequeue().is_some() {}
+                // twice, as the USB task may take the first for what it last sent
+                keys.enqueue(KeyboardReport::default()).ok();
+                keys.enqueue(KeyboardReport::default()).ok();
+            });
+            self.set_gamepad(shared::GamepadReport::default());
+            self.shared.pointer_state.lock(|pointer| {
+                pointer.buttons = 0;
+                pointer.wheel = 0;
+            });
+            self.set_digitizer(shared::DigitizerState::default());
+            self.set_consumer(0);
+            self.shared
+                .mouse_state
+                .lock(|mouse| *mouse = shared::MouseState::default());
+        }
+
+        fn heard_from_client(&mut self) {
+            self.shared.silent_ms.lock(|silent| *silent = Some(0));
+            // and that it's kept up with a change of baud rate
+            self.shared
+                .baud_unconfirmed_ms
+                .lock(|unconfirmed| *unconfirmed = None);
+        }
+
+        fn log(&mut self, args: core::fmt::Arguments) {
+            self.shared.console.lock(|console| console.log(args));
+        }
+
+        fn telemetry<R>(&mut self, f: impl FnOnce(&mut shared::Telemetry) -> R) -> R {
+            self.shared.telemetry.lock(f)
+        }
+
+        fn killed(&mut self) -> bool {
+            self.shared.killed.lock(|killed| *killed)
+        }
+
+        fn enqueue(&mut self, reports: impl Iterator<Item = KeyboardReport>) -> usize {
+            self.shared.keys_to_press.lock(|keys| {
+                reports
+                    .take_while(|&report| keys.enqueue(report).is_ok())
+                    .count()
+            })
+        }
+
+        fn room(&mut self) -> usize {
+            self.shared
+                .keys_to_press
+                .lock(|keys| keys.capacity() - keys.len())
+        }
+
+        fn queued(&mut self) -> usize {
+            self.shared.keys_to_press.lock(|keys| keys.len())
+        }
+
+        fn schedule(&mut self, steps: &[shared::ScheduledReport]) -> usize {
+            let queued = self.shared.scheduled.lock(|scheduled| {
+                steps
+                    .iter()
+                    .take_while(|&&step| scheduled.enqueue(step).is_ok())
+                    .count()
+            });
+            // fails if it's already going, in which case it'll get to these too
+            play_scheduled::spawn().ok();
+            queued
+        }
+
+        fn set_gamepad(&mut self, report: shared::GamepadReport) {
+            self.shared.gamepad_state.lock(|gamepad| *gamepad = report);
+        }
 
-                    buf.clear()
+        fn set_absolute_pointer(&mut self, state: shared::AbsolutePointerState) {
+            self.shared.pointer_state.lock(|pointer| *pointer = state);
+        }
+
+        fn set_digitizer(&mut self, state: shared::DigitizerState) {
+            self.shared
+                .digitizer_state
+                .lock(|digitizer| *digitizer = state);
+        }
+
+        fn set_consumer(&mut self, usage: u16) {
+            self.shared
+                .consumer_usage
+                .lock(|consumer| *consumer = usage);
+        }
+
+        fn move_mouse(&mut self, state: shared::MouseState) {
+            self.shared.mouse_state.lock(|mouse| mouse.add(state));
+        }
+
+        fn set_heartbeats(&mut self) {
+            self.shared.heartbeats.lock(|heartbeats| *heartbeats = true);
+        }
+
+        fn set_lock_chord(&mut self, chord: Option<KeyboardReport>) {
+            self.shared
+                .lock_chord
+                .lock(|lock_chord| *lock_chord = chord);
+        }
+
+        fn set_idle_timeout(&mut self, ms: u32) {
+            self.shared.idle_timeout_ms.lock(|timeout| *timeout = ms);
+        }
+
+        fn set_report_interval(&mut self, ms: u8) {
+            self.shared
+                .report_interval_ms
+                .lock(|interval| *interval = u32::from(ms));
+        }
+
+        fn set_immediate_reports(&mut self, immediate: bool) {
+            self.shared
+                .immediate_reports
+                .lock(|immediate_reports| *immediate_reports = immediate);
+        }
+
+        fn baud(&mut self) -> u32 {
+            self.shared.baud.lock(|baud| *baud)
+        }
+
+        fn switch_baud(&mut self, baud: u32) {
+            self.shared
+                .transport
+                .lock(|transport| transport.set_baud(baud));
+            self.shared.baud.lock(|current| *current = baud);
+            self.shared
+                .baud_unconfirmed_ms
+                .lock(|unconfirmed| *unconfirmed = Some(0));
+        }
+
+        fn setting(&mut self, key: shared::SettingKey) -> Option<shared::Setting> {
+            self.shared.settings.lock(|settings| settings.get(key))
+        }
+
+        /// Have [`save_setting`] save it, or if it's still busy with the last one answer
+        /// straight away with what's saved, as the client waits for each answer.
+        fn save_setting(&mut self, key: shared::SettingKey, value: Option<shared::Setting>) {
+            if save_setting::spawn(key, value).is_err() {
+                let saved = self.setting(key);
+                link::send_message(self, &shared::DeviceMessage::Setting(key, saved));
+            }
+        }
+
+        fn crash_report(&self) -> Option<CrashReport> {
+            *self.crash_report
+        }
+
+        fn identity(&self) -> shared::Identity {
+            identity()
+        }
+    }
+
+    /// Pass everything the agent has sent on to the client. This runs below the UART's
+    /// priority so it can go a byte at a time, without holding up frames coming the other way.
+    #[task(shared = [agent_messages, transport], priority = 1)]
+    async fn forward_agent_messages(ctx: forward_agent_messages::Context) {
+        let mut agent_messages = ctx.shared.agent_messages;
+        let mut transport = ctx.shared.transport;
+        while let Some(message) = agent_messages.lock(|messages| messages.dequeue()) {
+            send_message_slowly(&mut transport, &shared::DeviceMessage::Agent(message));
+        }
+    }
+
+    /// Hand the target each scheduled report in turn, then wait as long as it says before the
+    /// next. Each is sent until there's another after it, so it's held that long, give or
+    /// take a report interval, however the frames carrying them were spaced out.
+    #[task(shared = [scheduled, keys_to_press, immediate_reports], priority = 1)]
+    async fn play_scheduled(ctx: play_scheduled::Context) {
+        use rtic_monotonics::systick::prelude::*;
+
+        let mut scheduled = ctx.shared.scheduled;
+        let mut keys_to_press = ctx.shared.keys_to_press;
+        let mut immediate_reports = ctx.shared.immediate_reports;
+        while let Some(step) = scheduled.lock(|scheduled| scheduled.dequeue()) {
+            let report = KeyboardReport::from(step.report);
+            // a release going missing would leave the key held, so wait for room
+            while keys_to_press.lock(|keys| keys.enqueue(report).is_err()) {
+                Mono::delay(1.millis()).await;
+            }
+            if immediate_reports.lock(|immediate| *immediate) {
+                rtic::pend(bsp::Interrupt::USB_OTG1);
+            }
+            Mono::delay(u32::from(step.hold_ms).millis()).await;
+        }
+    }
+
+    /// Save a setting to flash, then say what's saved now, which is what was there before if
+    /// it couldn't be. Writing stalls everything, but the client's waiting for the answer.
+    #[task(shared = [settings, console, transport], priority = 1)]
+    async fn save_setting(
+        ctx: save_setting::Context,
+        key: shared::SettingKey,
+        value: Option<shared::Setting>,
+    ) {
+        let mut settings = ctx.shared.settings;
+        let mut console = ctx.shared.console;
+        let mut transport = ctx.shared.transport;
+        let (saved, result) = settings.lock(|settings| {
+            let result = settings.set(key, value);
+            (settings.get(key), result)
+        });
+        match result {
+            Ok(()) => log!(console, "Saved {key:?} to flash"),
+            Err(e) => log!(console, "Couldn't save {key:?} to flash: {e:?}"),
+        }
+        send_message_slowly(&mut transport, &shared::DeviceMessage::Setting(key, saved));
+    }
+
+    /// Play the payload on the SD card to the target, a report at a time as the queue has
+    /// room, alongside anything from a client.
+    #[cfg(feature = "sd-payload")]
+    #[task(local = [sd], shared = [keys_to_press, report_interval_ms, console], priority = 1)]
+    async fn play_payload(ctx: play_payload::Context) {
+        use rtic_monotonics::systick::prelude::*;
+        use shared::payload::FILE_NAME;
+
+        let mut keys_to_press = ctx.shared.keys_to_press;
+        let mut report_interval_ms = ctx.shared.report_interval_ms;
+        let mut console = ctx.shared.console;
+        let opened = ctx
+            .local
+            .sd
+            .card()
+            .map_err(crate::payload::Error::from)
+            .and_then(crate::payload::Payload::open);
+        let mut payload = match opened {
+            Ok(payload) => payload,
+            Err(e) => {
+                log!(console, "Couldn't open {FILE_NAME} on the SD card: {e:?}");
+                return;
+            }
+        };
+        let interval_ms = u32::from(payload.report_interval_ms());
+        report_interval_ms.lock(|interval| *interval = interval_ms);
+        log!(
+            console,
+            "Playing {FILE_NAME}, a report every {interval_ms}ms"
+        );
+
+        loop {
+            let (report, stopping) = match payload.next_report() {
+                Ok(Some(report)) => (report.into(), false),
+                Ok(None) => break,
+                Err(e) => {
+                    log!(console, "Couldn't read {FILE_NAME}, stopping: {e:?}");
+                    // so nothing's left held down
+                    (KeyboardReport::default(), true)
                 }
+            };
+            // leaving the client room, so it isn't paused
+            while keys_to_press.lock(|keys| keys.len() >= RESUME_AT) {
+                Mono::delay(interval_ms.millis()).await;
+            }
+            keys_to_press.lock(|keys| keys.enqueue(report).ok());
+            if stopping {
+                return;
             }
         }
+        log!(console, "Finished playing {FILE_NAME}");
+    }
+
+    /// Which firmware this is, going by the crate's version and what build.rs found out.
+    fn identity() -> shared::Identity {
+        let mut features = enumflags2::BitFlags::empty();
+        if cfg!(feature = "watchdog") {
+            features |= shared::FirmwareFeature::Watchdog;
+        }
+        if cfg!(feature = "full-speed") {
+            features |= shared::FirmwareFeature::FullSpeed;
+        }
+        if cfg!(feature = "spi") {
+            features |= shared::FirmwareFeature::Spi;
+        }
+        if cfg!(feature = "i2c") {
+            features |= shared::FirmwareFeature::I2c;
+        }
+        if cfg!(feature = "can") {
+            features |= shared::FirmwareFeature::Can;
+        }
+        if cfg!(feature = "ethernet") {
+            features |= shared::FirmwareFeature::Ethernet;
+        }
+        if cfg!(feature = "sd-payload") {
+            features |= shared::FirmwareFeature::SdPayload;
+        }
+        if cfg!(feature = "lock-leds") {
+            features |= shared::FirmwareFeature::LockLeds;
+        }
+        if cfg!(feature = "digitizer") {
+            features |= shared::FirmwareFeature::Digitizer;
+        }
+        if cfg!(feature = "key-matrix") {
+            features |= shared::FirmwareFeature::KeyMatrix;
+        }
+        shared::Identity::new(
+            [
+                env!("CARGO_PKG_VERSION_MAJOR"),
+                env!("CARGO_PKG_VERSION_MINOR"),
+                env!("CARGO_PKG_VERSION_PATCH"),
+            ],
+            env!("GIT_COMMIT"),
+            env!("GIT_DIRTY") == "true",
+            features,
+            unique_id(),
+        )
+    }
+
+    /// The unique ID NXP burns into each chip's fuses.
+    fn unique_id() -> u64 {
+        // SAFETY: reading the fuses doesn't change anything
+        let ocotp = unsafe { bsp::ral::ocotp::OCOTP::instance() };
+        let high = bsp::ral::read_reg!(bsp::ral::ocotp, ocotp, CFG1);
+        let low = bsp::ral::read_reg!(bsp::ral::ocotp, ocotp, CFG0);
+        u64::from(high) << 32 | u64::from(low)
+    }
+
+    fn set_led(led: &mut board::Led, lit: bool) {
+        if lit {
+            led.set_high().ok();
+        } else {
+            led.set_low().ok();
+        }
+    }
+
+    /// Write a message back to the client from below the UART's priority, a byte at a time
+    /// so frames coming the other way aren't held up.
+    fn send_message_slowly(
+        transport: &mut impl rtic::Mutex<T = Link>,
+        message: &shared::DeviceMessage,
+    ) {
+        link::send_message(&mut Locked(transport), message);
     }
 }
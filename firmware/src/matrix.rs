@@ -0,0 +1,188 @@
+//! A small key matrix for the `key-matrix` feature, so the teensy can be a macro pad or
+//! numpad of its own alongside what the client sends. The rows are pins 6, 7, 8 and 32 and
+//! the columns pins 34, 35, 36 and 37, with a switch and a diode, cathode towards the row,
+//! where each crosses. To use other pins or another size, change them here and where
+//! they're taken in `init`, and [`KEYMAP`] for what each key types.
+
+use teensy4_bsp::{
+    hal::{gpio, iomuxc},
+    pins::t41::{P32, P34, P35, P36, P37, P6, P7, P8},
+};
+use usbd_hid::descriptor::KeyboardReport;
+
+const ROWS: usize = 4;
+const COLS: usize = 4;
+
+/// The HID usage each key types, by row then column, with 0xE0 to 0xE7 the modifiers and
+/// 0 for nothing. It's a numpad to begin with.
+const KEYMAP: [[u8; COLS]; ROWS] = [
+    // 7, 8, 9, -
+    [0x5F, 0x60, 0x61, 0x56],
+    // 4, 5, 6, +
+    [0x5C, 0x5D, 0x5E, 0x57],
+    // 1, 2, 3, enter
+    [0x59, 0x5A, 0x5B, 0x58],
+    // 0, ., /, *
+    [0x62, 0x63, 0x54, 0x55],
+];
+
+/// How long a key has to read the other way to count, which is longer than switches
+/// bounce for but short enough not to be felt.
+const DEBOUNCE_MS: u32 = 5;
+
+/// What's in every key slot when more keys are held than a report has room for.
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// How long a row's left driven before its columns are read, for the lines to settle, in
+/// CPU cycles.
+const SETTLE_CYCLES: u32 = 600;
+
+pub struct KeyMatrix {
+    rows: (
+        gpio::Output<P6>,
+        gpio::Output<P7>,
+        gpio::Output<P8>,
+        gpio::Output<P32>,
+    ),
+    cols: (
+        gpio::Input<P34>,
+        gpio::Input<P35>,
+        gpio::Input<P36>,
+        gpio::Input<P37>,
+    ),
+    /// Which keys are taken to be held.
+    pressed: [[bool; COLS]; ROWS],
+    /// How long each has read the other way since then.
+    changing_ms: [[u32; COLS]; ROWS],
+    report: KeyboardReport,
+}
+
+impl KeyMatrix {
+    /// The rows are driven low one at a time, and idle high. The columns are pulled up,
+    /// so they read low where a key joins them to the row being driven.
+    pub fn new(
+        gpio2: &mut gpio::Port<2>,
+        rows: (P6, P7, P8, P32),
+        cols: (P34, P35, P36, P37),
+    ) -> Self {
+        let (mut c0, mut c1, mut c2, mut c3) = cols;
+        iomuxc::configure(&mut c0, crate::button::PIN_CONFIG);
+        iomuxc::configure(&mut c1, crate::button::PIN_CONFIG);
+        iomuxc::configure(&mut c2, crate::button::PIN_CONFIG);
+        iomuxc::configure(&mut c3, crate::button::PIN_CONFIG);
+        let matrix = Self {
+            rows: (
+                gpio2.output(rows.0),
+                gpio2.output(rows.1),
+                gpio2.output(rows.2),
+                gpio2.output(rows.3),
+            ),
+            cols: (
+                gpio2.input(c0),
+                gpio2.input(c1),
+                gpio2.input(c2),
+                gpio2.input(c3),
+            ),
+            pressed: [[false; COLS]; ROWS],
+            changing_ms: [[0; COLS]; ROWS],
+            report: KeyboardReport::default(),
+        };
+        for row in 0..ROWS {
+            matrix.drive(row, false);
+        }
+        matrix
+    }
+
+    /// Read every key again after `elapsed_ms`.
+    pub fn scan(&mut self, elapsed_ms: u32) {
+        let mut changed = false;
+        for row in 0..ROWS {
+            self.drive(row, true);
+            cortex_m::asm::delay(SETTLE_CYCLES);
+            let read = self.read_cols();
+            self.drive(row, false);
+            for (col, &pressed) in read.iter().enumerate() {
+                if pressed == self.pressed[row][col] {
+                    self.changing_ms[row][col] = 0;
+                    continue;
+                }
+                self.changing_ms[row][col] += elapsed_ms;
+                if self.changing_ms[row][col] >= DEBOUNCE_MS {
+                    self.pressed[row][col] = pressed;
+                    self.changing_ms[row][col] = 0;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.report = self.build_report();
+        }
+    }
+
+    /// `bridged` with the keys held here added to it.
+    pub fn merged(&self, bridged: &KeyboardReport) -> KeyboardReport {
+        let mut merged = *bridged;
+        merged.modifier |= self.report.modifier;
+        if merged.keycodes.contains(&ERROR_ROLL_OVER) {
+            return merged;
+        }
+        for &key in self.report.keycodes.iter().filter(|&&key| key != 0) {
+            if merged.keycodes.contains(&key) {
+                continue;
+            }
+            match merged.keycodes.iter_mut().find(|slot| **slot == 0) {
+                Some(slot) => *slot = key,
+                None => {
+                    merged.keycodes = [ERROR_ROLL_OVER; 6];
+                    break;
+                }
+            }
+        }
+        merged
+    }
+
+    fn build_report(&self) -> KeyboardReport {
+        let mut report = KeyboardReport::default();
+        let mut len = 0;
+        let keys = KEYMAP.iter().flatten().zip(self.pressed.iter().flatten());
+        for (&usage, &pressed) in keys {
+            if !pressed || usage == 0 {
+                continue;
+            }
+            if let 0xE0..=0xE7 = usage {
+                report.modifier |= 1 << (usage - 0xE0);
+            } else if len < report.keycodes.len() {
+                report.keycodes[len] = usage;
+                len += 1;
+            } else {
+                report.keycodes = [ERROR_ROLL_OVER; 6];
+            }
+        }
+        report
+    }
+
+    /// Drive `row` low to read it, or let it go back high.
+    fn drive(&self, row: usize, selected: bool) {
+        let (r0, r1, r2, r3) = &self.rows;
+        match row {
+            0 => set(r0, !selected),
+            1 => set(r1, !selected),
+            2 => set(r2, !selected),
+            _ => set(r3, !selected),
+        }
+    }
+
+    /// Which columns read low, joined to the row being driven by a key.
+    fn read_cols(&self) -> [bool; COLS] {
+        let (c0, c1, c2, c3) = &self.cols;
+        [!c0.is_set(), !c1.is_set(), !c2.is_set(), !c3.is_set()]
+    }
+}
+
+fn set<P>(pin: &gpio::Output<P>, high: bool) {
+    if high {
+        pin.set();
+    } else {
+        pin.clear();
+    }
+}
@@ -0,0 +1,70 @@
+//! What happens when the firmware panics. Rather than blinking forever, which would leave
+//! whatever was held down held on the target, it lets go of every key if it can, keeps the
+//! message where the reset won't clear it, and resets. The reset drops it off the target's
+//! USB too, which lets go of everything if the empty report didn't make it.
+
+use core::{
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    ptr::{self, addr_of_mut},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+use shared::crash::CrashReport;
+use teensy4_bsp::{board, hal::usbd::BusAdapter};
+use usbd_hid::descriptor::KeyboardReport;
+
+use crate::hid;
+
+/// How long to keep trying to get the empty report to the host before resetting anyway.
+const RELEASE_MS: u32 = 20;
+const CYCLES_PER_MS: u32 = board::ARM_FREQUENCY / 1000;
+
+#[link_section = ".uninit.crash_report"]
+static mut CRASH_REPORT: MaybeUninit<CrashReport> = MaybeUninit::uninit();
+
+/// The HID interfaces, once the USB task's started, to send the empty report from here.
+static HID: AtomicPtr<hid::Interfaces<'static, BusAdapter>> = AtomicPtr::new(ptr::null_mut());
+
+/// Set once we're panicking, so panicking again goes straight to the reset.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// What the last panic left, if the last reset was one, to be called once at boot.
+pub fn take_crash_report() -> Option<CrashReport> {
+    // SAFETY: only called from init, before anything can panic
+    unsafe { (*addr_of_mut!(CRASH_REPORT)).assume_init_mut().take() }
+}
+
+/// Where to find the HID interfaces, which never move once the USB task has them.
+pub fn set_hid(hid: &hid::Interfaces<'static, BusAdapter>) {
+    HID.store(ptr::from_ref(hid).cast_mut(), Ordering::Relaxed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        // SAFETY: interrupts are off, so nothing else is using it
+        unsafe { (*addr_of_mut!(CRASH_REPORT)).assume_init_mut().record(info) };
+        release_keys();
+    }
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+fn release_keys() {
+    // SAFETY: it's in one of RTIC's statics, and with interrupts off nothing else can be
+    // using it, unless the USB task was in the middle of it when it panicked. That's a risk
+    // worth taking over a stuck key, and the reset follows whatever happens.
+    let Some(hid) = (unsafe { HID.load(Ordering::Relaxed).as_ref() }) else {
+        return;
+    };
+    // the endpoint's likely still busy with the last report at first
+    for _ in 0..RELEASE_MS {
+        if hid.push_keyboard(&KeyboardReport::default()).is_ok() {
+            // for the host to come and get it
+            cortex_m::asm::delay(RELEASE_MS * CYCLES_PER_MS);
+            return;
+        }
+        cortex_m::asm::delay(CYCLES_PER_MS);
+    }
+}
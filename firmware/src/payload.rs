@@ -0,0 +1,132 @@
+//! Reading [`shared::payload`]'s reports off the SD card for the `sd-payload` feature, and
+//! the button that plays them again.
+
+use embedded_sdmmc::{Mode, RawFile, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use shared::{
+    payload::{self, HEADER_LEN, REPORT_LEN},
+    WhyNoDeriveDeserializeManSadFaceHere,
+};
+use teensy4_bsp::{
+    hal::{gpio, iomuxc},
+    pins::t41::P3,
+};
+
+use crate::sd::{self, Card};
+
+const BLOCK_LEN: usize = 512;
+
+#[derive(Debug)]
+pub enum Error {
+    // only ever read through Debug, for the console
+    #[allow(dead_code)]
+    Card(sd::Error),
+    #[allow(dead_code)]
+    Fat(embedded_sdmmc::Error<sd::Error>),
+    /// It isn't one `client compile-payload` wrote, or it's for a newer firmware.
+    NotAPayload,
+}
+
+impl From<sd::Error> for Error {
+    fn from(e: sd::Error) -> Self {
+        Self::Card(e)
+    }
+}
+
+impl From<embedded_sdmmc::Error<sd::Error>> for Error {
+    fn from(e: embedded_sdmmc::Error<sd::Error>) -> Self {
+        Self::Fat(e)
+    }
+}
+
+/// Nothing's written, so the time never matters.
+struct NoClock;
+
+impl TimeSource for NoClock {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 0,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+/// [`payload::FILE_NAME`], open and read a block at a time.
+pub struct Payload<'a> {
+    volumes: VolumeManager<Card<'a>, NoClock>,
+    file: RawFile,
+    report_interval_ms: u8,
+    block: [u8; BLOCK_LEN],
+    /// Where the next report starts in `block`, and where what's been read ends.
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Payload<'a> {
+    /// Open the payload in the root of the card's first partition.
+    pub fn open(card: Card<'a>) -> Result<Self, Error> {
+        let mut volumes = VolumeManager::new(card, NoClock);
+        let volume = volumes.open_raw_volume(VolumeIdx(0))?;
+        let root = volumes.open_root_dir(volume)?;
+        let file = volumes.open_file_in_dir(root, payload::FILE_NAME, Mode::ReadOnly)?;
+
+        let mut header = [0; HEADER_LEN];
+        if volumes.read(file, &mut header)? < HEADER_LEN {
+            return Err(Error::NotAPayload);
+        }
+        let report_interval_ms = payload::parse_header(&header).ok_or(Error::NotAPayload)?;
+        Ok(Self {
+            volumes,
+            file,
+            report_interval_ms,
+            block: [0; BLOCK_LEN],
+            start: 0,
+            end: 0,
+        })
+    }
+
+    /// How many milliseconds apart the reports go to the target.
+    pub fn report_interval_ms(&self) -> u8 {
+        self.report_interval_ms
+    }
+
+    /// The next report, or None once they've all been played. A few bytes left over at the
+    /// end aren't a whole one, so they're skipped.
+    pub fn next_report(&mut self) -> Result<Option<WhyNoDeriveDeserializeManSadFaceHere>, Error> {
+        if self.end - self.start < REPORT_LEN {
+            // reads fill the block unless the file's ended, so only the last leaves a piece
+            self.end = self.volumes.read(self.file, &mut self.block)?;
+            self.start = 0;
+            if self.end < REPORT_LEN {
+                return Ok(None);
+            }
+        }
+        let report = self.block[self.start..][..REPORT_LEN].try_into().unwrap();
+        self.start += REPORT_LEN;
+        Ok(Some(payload::decode_report(report)))
+    }
+}
+
+/// The button on pin 3 which plays the payload again, to ground with the pin pulled up.
+pub struct Button(gpio::Input<P3>);
+
+impl Button {
+    /// Interrupt on `gpio4` as the button goes down.
+    pub fn new(gpio4: &mut gpio::Port<4>, mut pin: P3) -> Self {
+        iomuxc::configure(&mut pin, crate::button::PIN_CONFIG);
+        let input = gpio4.input(pin);
+        gpio4.set_interrupt(&input, Some(gpio::Trigger::FallingEdge));
+        Self(input)
+    }
+
+    /// Clear its interrupt, returning whether it was pressed. It bounces, so there's a few
+    /// of these to each press.
+    pub fn pressed(&mut self) -> bool {
+        let pressed = self.0.is_triggered();
+        self.0.clear_triggered();
+        pressed
+    }
+}
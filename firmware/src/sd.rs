@@ -0,0 +1,349 @@
+//! The teensy 4.1's SD card slot on uSDHC1, for the `sd-payload` feature. The HAL doesn't
+//! drive it, so like the Ethernet it's done through registers here: just enough of the SD
+//! spec to bring a card up on all four data lines and read blocks off it a word at a time,
+//! as [`embedded_sdmmc`] asks for them.
+
+use core::ptr;
+
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+use teensy4_bsp::{
+    board::ARM_FREQUENCY,
+    ral::{self, ccm::CCM, usdhc::USDHC1},
+};
+
+/// The pads the slot's on, GPIO_SD_B0_00 to 05, their mux and config registers. They're
+/// the command line, the clock, then the four data lines, all uSDHC1's with alternate 0.
+const PADS: [(usize, usize); 6] = [
+    (0x401F_81BC, 0x401F_83AC),
+    (0x401F_81C0, 0x401F_83B0),
+    (0x401F_81C4, 0x401F_83B4),
+    (0x401F_81C8, 0x401F_83B8),
+    (0x401F_81CC, 0x401F_83BC),
+    (0x401F_81D0, 0x401F_83C0),
+];
+const CLOCK_PAD: usize = 1;
+/// A 47k pull up, which the command and data lines need, a middling speed and drive.
+const PAD_CONFIG: u32 = 0x7088;
+/// The same without the pull up.
+const CLOCK_PAD_CONFIG: u32 = 0x1088;
+
+/// The root clock's divider, off PLL2's 396MHz PFD, for 198MHz.
+const ROOT_PODF: u32 = 1;
+/// The prescaler, a power of two, and divider from there to about 390kHz while the card's
+/// identified, and 25MHz once it's up.
+const IDENTIFY_CLOCK: (u32, u32) = (0x80, 1);
+const TRANSFER_CLOCK: (u32, u32) = (0x04, 0);
+
+const BLOCK_LEN: u32 = 512;
+const BLOCK_WORDS: u32 = BLOCK_LEN / 4;
+
+// INT_STATUS's flags
+const COMMAND_COMPLETE: u32 = 1 << 0;
+const TRANSFER_COMPLETE: u32 = 1 << 1;
+const BUFFER_READ_READY: u32 = 1 << 5;
+const COMMAND_TIMEOUT: u32 = 1 << 16;
+/// The timeout, then CRC, end bit and index errors.
+const COMMAND_ERRORS: u32 = 0xF << 16;
+/// A timeout, CRC or end bit error.
+const DATA_ERRORS: u32 = 0x7 << 20;
+
+// the commands, and those which follow APP_CMD
+const GO_IDLE_STATE: u32 = 0;
+const ALL_SEND_CID: u32 = 2;
+const SEND_RELATIVE_ADDR: u32 = 3;
+const SELECT_CARD: u32 = 7;
+const SEND_IF_COND: u32 = 8;
+const SEND_CSD: u32 = 9;
+const SET_BLOCKLEN: u32 = 16;
+const READ_SINGLE_BLOCK: u32 = 17;
+const APP_CMD: u32 = 55;
+const SET_BUS_WIDTH: u32 = 6;
+const SD_SEND_OP_COND: u32 = 41;
+
+/// 2.7 to 3.6V, and a pattern the card echoes back if it's version 2 or later.
+const IF_COND: u32 = 0x1AA;
+/// 3.2 to 3.4V, which the teensy's at.
+const OCR_VOLTAGES: u32 = 0x0030_0000;
+/// Sent, that we can take a high capacity card, and answered, that it is one.
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+const OCR_READY: u32 = 1 << 31;
+/// How many milliseconds a card gets to power up, a second being what the spec allows.
+const POWER_UP_MS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// Nothing answered, most likely as there's no card in.
+    Timeout,
+    /// A command's answer came back garbled.
+    Command,
+    /// A block came back garbled, or not at all.
+    Data,
+    /// The card's too old, or isn't an SD card.
+    Unsupported,
+    /// Only reading is done here.
+    ReadOnly,
+}
+
+/// How the card answers a command, and what of it the controller checks.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Response {
+    None,
+    /// A short answer, checked for its index and CRC.
+    Short,
+    /// The same, then the card busy until it's done.
+    ShortBusy,
+    /// 136 bits, checked for its CRC.
+    Long,
+    /// The OCR, which is neither.
+    Ocr,
+}
+
+pub struct Host {
+    usdhc: USDHC1,
+}
+
+impl Host {
+    /// Clock uSDHC1 and mux the slot's pads to it, ready for a card.
+    pub fn new() -> Self {
+        // SAFETY: only uSDHC1's clock gate, mux and divider are touched, nothing else uses
+        // them, nor uSDHC1 and its pads
+        let (ccm, usdhc) = unsafe { (CCM::instance(), USDHC1::instance()) };
+        ral::modify_reg!(ral::ccm, ccm, CCGR6, CG1: 0b11);
+        ral::modify_reg!(ral::ccm, ccm, CSCMR1, USDHC1_CLK_SEL: 0);
+        ral::modify_reg!(ral::ccm, ccm, CSCDR1, USDHC1_PODF: ROOT_PODF);
+
+        // SAFETY: they're the IOMUXC registers of pads only the slot uses
+        unsafe {
+            for (i, (mux, config)) in PADS.into_iter().enumerate() {
+                let value = if i == CLOCK_PAD {
+                    CLOCK_PAD_CONFIG
+                } else {
+                    PAD_CONFIG
+                };
+                ptr::write_volatile(mux as *mut u32, 0);
+                ptr::write_volatile(config as *mut u32, value);
+            }
+        }
+        Self { usdhc }
+    }
+
+    /// Bring up whatever card is in the slot, from scratch so it can have been swapped.
+    pub fn card(&mut self) -> Result<Card<'_>, Error> {
+        ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTA: 1);
+        while ral::read_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTA == 1) {}
+        self.set_clock(IDENTIFY_CLOCK);
+        ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, DTOCV: 0xE);
+        ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS_EN, u32::MAX);
+        ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DTW: 0, EMODE: 2);
+        // a whole block at a time
+        ral::modify_reg!(ral::usdhc, self.usdhc, WTMK_LVL, RD_WML: BLOCK_WORDS);
+        // the card wants 74 clocks to wake up, which this gives it
+        ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, INITA: 1);
+        while ral::read_reg!(ral::usdhc, self.usdhc, SYS_CTRL, INITA == 1) {}
+
+        self.command(GO_IDLE_STATE, 0, Response::None)?;
+        // only version 2 cards answer, and only they can be high capacity
+        let version_2 = match self.command(SEND_IF_COND, IF_COND, Response::Short) {
+            Ok(answer) if answer & 0xFFF == IF_COND => true,
+            Ok(_) => return Err(Error::Unsupported),
+            Err(Error::Timeout) => false,
+            Err(e) => return Err(e),
+        };
+        let mut condition = OCR_VOLTAGES;
+        if version_2 {
+            condition |= OCR_HIGH_CAPACITY;
+        }
+        let mut waited_ms = 0;
+        let ocr = loop {
+            let ocr = self.app_command(0, SD_SEND_OP_COND, condition, Response::Ocr)?;
+            if ocr & OCR_READY != 0 {
+                break ocr;
+            }
+            if waited_ms == POWER_UP_MS {
+                return Err(Error::Timeout);
+            }
+            cortex_m::asm::delay(ARM_FREQUENCY / 1000);
+            waited_ms += 1;
+        };
+
+        self.command(ALL_SEND_CID, 0, Response::Long)?;
+        let rca = self.command(SEND_RELATIVE_ADDR, 0, Response::Short)? & 0xFFFF_0000;
+        self.command(SEND_CSD, rca, Response::Long)?;
+        let blocks = blocks_from_csd([
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP0),
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP1),
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP2),
+            ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP3),
+        ]);
+        self.command(SELECT_CARD, rca, Response::ShortBusy)?;
+        self.app_command(rca, SET_BUS_WIDTH, 0b10, Response::Short)?;
+        ral::modify_reg!(ral::usdhc, self.usdhc, PROT_CTRL, DTW: 1);
+        let high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+        if !high_capacity {
+            self.command(SET_BLOCKLEN, BLOCK_LEN, Response::Short)?;
+        }
+        self.set_clock(TRANSFER_CLOCK);
+
+        Ok(Card {
+            host: self,
+            high_capacity,
+            blocks,
+        })
+    }
+
+    fn set_clock(&self, (prescaler, divider): (u32, u32)) {
+        ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, SDCLKFS: prescaler, DVS: divider);
+        while ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, SDSTB == 0) {}
+    }
+
+    fn app_command(
+        &self,
+        rca: u32,
+        index: u32,
+        argument: u32,
+        response: Response,
+    ) -> Result<u32, Error> {
+        self.command(APP_CMD, rca, Response::Short)?;
+        self.command(index, argument, response)
+    }
+
+    fn command(&self, index: u32, argument: u32, response: Response) -> Result<u32, Error> {
+        self.send(index, argument, response, false)
+    }
+
+    /// Send a command, returning the first word of the card's answer.
+    fn send(
+        &self,
+        index: u32,
+        argument: u32,
+        response: Response,
+        data: bool,
+    ) -> Result<u32, Error> {
+        while ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, CIHB == 1) {}
+        if data || response == Response::ShortBusy {
+            while ral::read_reg!(ral::usdhc, self.usdhc, PRES_STATE, CDIHB == 1) {}
+        }
+        ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, u32::MAX);
+        ral::write_reg!(ral::usdhc, self.usdhc, CMD_ARG, argument);
+        let (kind, index_check, crc_check) = match response {
+            Response::None => (0b00, 0, 0),
+            Response::Long => (0b01, 0, 1),
+            Response::Short => (0b10, 1, 1),
+            Response::Ocr => (0b10, 0, 0),
+            Response::ShortBusy => (0b11, 1, 1),
+        };
+        ral::write_reg!(ral::usdhc, self.usdhc, CMD_XFR_TYP,
+            CMDINX: index,
+            RSPTYP: kind,
+            CICEN: index_check,
+            CCCEN: crc_check,
+            DPSEL: u32::from(data)
+        );
+
+        let status = self.wait(COMMAND_COMPLETE | COMMAND_ERRORS);
+        if status & COMMAND_ERRORS != 0 {
+            ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTC: 1);
+            while ral::read_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTC == 1) {}
+            return Err(if status & COMMAND_TIMEOUT != 0 {
+                Error::Timeout
+            } else {
+                Error::Command
+            });
+        }
+        if response == Response::ShortBusy {
+            self.wait(TRANSFER_COMPLETE);
+        }
+        Ok(ral::read_reg!(ral::usdhc, self.usdhc, CMD_RSP0))
+    }
+
+    fn read_block(&self, address: u32, block: &mut Block) -> Result<(), Error> {
+        ral::write_reg!(ral::usdhc, self.usdhc, BLK_ATT, BLKSIZE: BLOCK_LEN, BLKCNT: 1);
+        ral::modify_reg!(ral::usdhc, self.usdhc, MIX_CTRL,
+            DTDSEL: 1,
+            MSBSEL: 0,
+            BCEN: 0,
+            AC12EN: 0,
+            DMAEN: 0
+        );
+        self.send(READ_SINGLE_BLOCK, address, Response::Short, true)?;
+
+        let mut status = self.wait(BUFFER_READ_READY | DATA_ERRORS);
+        if status & DATA_ERRORS == 0 {
+            for word in block.contents.chunks_exact_mut(4) {
+                let data = ral::read_reg!(ral::usdhc, self.usdhc, DATA_BUFF_ACC_PORT);
+                word.copy_from_slice(&data.to_le_bytes());
+            }
+            status = self.wait(TRANSFER_COMPLETE | DATA_ERRORS);
+        }
+        if status & DATA_ERRORS != 0 {
+            ral::modify_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTD: 1);
+            while ral::read_reg!(ral::usdhc, self.usdhc, SYS_CTRL, RSTD == 1) {}
+            return Err(Error::Data);
+        }
+        Ok(())
+    }
+
+    /// Spin until any of `flags` are set, clearing and returning them. The controller times
+    /// out by itself, so it doesn't spin forever.
+    fn wait(&self, flags: u32) -> u32 {
+        loop {
+            let status = ral::read_reg!(ral::usdhc, self.usdhc, INT_STATUS) & flags;
+            if status != 0 {
+                ral::write_reg!(ral::usdhc, self.usdhc, INT_STATUS, status);
+                return status;
+            }
+        }
+    }
+}
+
+/// How many blocks the card has, going by its CSD, which the controller gives without its
+/// CRC so each field's 8 bits lower than the spec has it.
+fn blocks_from_csd(csd: [u32; 4]) -> u32 {
+    if csd[3] >> 22 & 0b11 == 1 {
+        // version 2, in units of 512KiB
+        let size = csd[1] >> 8 & 0x3F_FFFF;
+        (size + 1) * 1024
+    } else {
+        let size = (csd[2] & 0b11) << 10 | csd[1] >> 22;
+        let multiplier = csd[1] >> 7 & 0b111;
+        let block_len = csd[2] >> 8 & 0xF;
+        (size + 1) << (multiplier + 2 + block_len.saturating_sub(9))
+    }
+}
+
+/// A card that's up and selected, until the next [`Host::card`].
+pub struct Card<'a> {
+    host: &'a Host,
+    /// Whether it's addressed by block rather than byte.
+    high_capacity: bool,
+    blocks: u32,
+}
+
+impl BlockDevice for Card<'_> {
+    type Error = Error;
+
+    fn read(
+        &self,
+        blocks: &mut [Block],
+        start_block_idx: BlockIdx,
+        _reason: &str,
+    ) -> Result<(), Error> {
+        for (index, block) in (start_block_idx.0..).zip(blocks) {
+            let address = if self.high_capacity {
+                index
+            } else {
+                index * BLOCK_LEN
+            };
+            self.host.read_block(address, block)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, _blocks: &[Block], _start_block_idx: BlockIdx) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Error> {
+        Ok(BlockCount(self.blocks))
+    }
+}
@@ -0,0 +1,183 @@
+//! The [`shared::Setting`]s, kept in flash so they're the same after unplugging the teensy.
+//!
+//! Flash can only be erased a sector at a time, and wears out after enough erases, so rather
+//! than erasing to write each change, changes are added to the end of a log in one sector
+//! and the last one for each setting wins. Once it's full, what's current is written out to
+//! the other sector and the log carries on from there. Every record has a check byte and
+//! each sector's header goes on last, so losing power partway through a write loses that
+//! change at worst.
+
+use shared::{Setting, SettingKey};
+
+use crate::flash::{self, Flash, SECTOR_SIZE};
+
+/// The two sectors the log goes back and forth between, at the end of the flash but clear
+/// of its last sector, where the teensy's bootloader keeps its restore program.
+const SECTORS: [usize; 2] = [0x7F_D000, 0x7F_E000];
+/// A sector with a log in it starts with this, then the log's generation, which goes up
+/// each time it moves sectors, so the newer is the one with the higher generation.
+const MAGIC: [u8; 4] = *b"TKBS";
+const HEADER_LEN: usize = 8;
+/// Where a record's length would be in erased flash, so where the log ends.
+const ERASED: u8 = 0xFF;
+/// The most a record takes up: its length, a setting and its key, and the check byte.
+const RECORD_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    Encode,
+    Flash(flash::Error),
+}
+
+impl From<flash::Error> for Error {
+    fn from(e: flash::Error) -> Self {
+        Self::Flash(e)
+    }
+}
+
+pub struct Settings {
+    flash: Flash,
+    /// Which of [`SECTORS`] the log's in, and its generation.
+    sector: usize,
+    generation: u32,
+    /// Where the next record goes, or None if the log can't be added to, as there isn't one
+    /// yet or its end was left half written.
+    end: Option<usize>,
+    values: [Option<Setting>; SettingKey::ALL.len()],
+}
+
+impl Settings {
+    /// Read back whatever's been saved.
+    pub fn load() -> Self {
+        let flash = Flash::new();
+        let mut settings = Self {
+            flash,
+            sector: 0,
+            generation: 0,
+            end: None,
+            values: [None; SettingKey::ALL.len()],
+        };
+
+        let mut newest = None;
+        for (sector, &offset) in SECTORS.iter().enumerate() {
+            let mut header = [0; HEADER_LEN];
+            settings.flash.read(offset, &mut header);
+            if header[..4] != MAGIC {
+                continue;
+            }
+            let generation = u32::from_le_bytes(header[4..].try_into().unwrap());
+            if newest.is_none_or(|(_, newest)| generation > newest) {
+                newest = Some((sector, generation));
+            }
+        }
+        let Some((sector, generation)) = newest else {
+            return settings;
+        };
+        settings.sector = sector;
+        settings.generation = generation;
+
+        let start = SECTORS[sector];
+        let mut offset = HEADER_LEN;
+        while offset < SECTOR_SIZE {
+            let mut len = [0];
+            settings.flash.read(start + offset, &mut len);
+            let len = usize::from(len[0]);
+            if len == usize::from(ERASED) {
+                settings.end = Some(offset);
+                break;
+            }
+            if len == 0 || offset + len + 2 > SECTOR_SIZE {
+                break;
+            }
+            let mut record = [0; RECORD_LEN];
+            let record = &mut record[..len + 1];
+            settings.flash.read(start + offset + 1, record);
+            let (payload, check_byte) = record.split_at(len);
+            if check(payload) != check_byte[0] {
+                break;
+            }
+            // one from newer firmware which we don't know about is skipped
+            let record = postcard::from_bytes::<(SettingKey, Option<Setting>)>(payload);
+            if let Ok((key, value)) = record {
+                settings.values[key as usize] = value;
+            }
+            offset += len + 2;
+        }
+        settings
+    }
+
+    pub fn get(&self, key: SettingKey) -> Option<Setting> {
+        self.values[key as usize]
+    }
+
+    /// Save `value` for `key`, or forget it with None, leaving it as it was on failure.
+    pub fn set(&mut self, key: SettingKey, value: Option<Setting>) -> Result<(), Error> {
+        let mut buf = [0; RECORD_LEN];
+        let len = encode(key, value, &mut buf)?;
+        match self.end {
+            Some(end) if end + len <= SECTOR_SIZE => {
+                self.flash
+                    .program(SECTORS[self.sector] + end, &buf[..len])?;
+                self.end = Some(end + len);
+                self.values[key as usize] = value;
+            }
+            _ => {
+                let mut values = self.values;
+                values[key as usize] = value;
+                self.move_log(values)?;
+                self.values = values;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the log again in the other sector with just `values` in it.
+    fn move_log(&mut self, values: [Option<Setting>; SettingKey::ALL.len()]) -> Result<(), Error> {
+        let sector = 1 - self.sector;
+        let start = SECTORS[sector];
+        self.flash.erase(start)?;
+        let mut offset = HEADER_LEN;
+        for (key, value) in SettingKey::ALL.into_iter().zip(values) {
+            // forgotten ones have nothing to override any more
+            if value.is_none() {
+                continue;
+            }
+            let mut buf = [0; RECORD_LEN];
+            let len = encode(key, value, &mut buf)?;
+            self.flash.program(start + offset, &buf[..len])?;
+            offset += len;
+        }
+        // last, so a half written log is never the newest
+        let generation = self.generation.wrapping_add(1);
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&generation.to_le_bytes());
+        self.flash.program(start, &header)?;
+
+        self.sector = sector;
+        self.generation = generation;
+        self.end = Some(offset);
+        Ok(())
+    }
+}
+
+/// Write the record for `key` having `value` into `buf`, returning its length.
+fn encode(
+    key: SettingKey,
+    value: Option<Setting>,
+    buf: &mut [u8; RECORD_LEN],
+) -> Result<usize, Error> {
+    let len = postcard::to_slice(&(key, value), &mut buf[1..RECORD_LEN - 1])
+        .map_err(|_| Error::Encode)?
+        .len();
+    buf[0] = len as u8;
+    buf[len + 1] = check(&buf[1..len + 1]);
+    Ok(len + 2)
+}
+
+/// Enough to spot a record which was only partly written.
+fn check(payload: &[u8]) -> u8 {
+    payload.iter().fold(payload.len() as u8, |check, &byte| {
+        check.rotate_left(1) ^ byte
+    })
+}
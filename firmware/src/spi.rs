@@ -0,0 +1,116 @@
+//! LPSPI3 as an SPI target, for the `spi` feature, so an SBC or an FPGA without a spare UART
+//! can drive the teensy as its SPI controller. The HAL only does controller mode, so like the
+//! watchdog it's driven through its registers.
+//!
+//! Frames go the same way as over the UART, in mode 0 with 8 bit words. The controller has
+//! to clock out zeros, which are only frame delimiters, to read what the teensy has to send.
+
+use heapless::Deque;
+use teensy4_bsp::{
+    hal::iomuxc,
+    pins::common::{P0, P1, P26, P27},
+    ral::{self, lpspi::LPSPI3},
+};
+
+use crate::transport::Transport;
+
+/// Words in each of LPSPI3's FIFOs.
+const FIFO_LEN: u32 = 16;
+
+/// What can wait on the controller to clock it out, past which bytes are dropped.
+const TX_LEN: usize = 256;
+/// What's come in from the FIFO, until the frame task gets to it.
+const RX_LEN: usize = 64;
+
+pub struct SpiTarget {
+    lpspi: LPSPI3,
+    tx: Deque<u8, TX_LEN>,
+    rx: Deque<u8, RX_LEN>,
+    /// Whether bytes coming in were lost since last asked.
+    lost: bool,
+}
+
+impl SpiTarget {
+    /// Start listening as a target on the teensy's SPI1: pins 0 (CS), 1 (MISO), 26 (MOSI)
+    /// and 27 (SCK).
+    pub fn new(mut cs: P0, mut miso: P1, mut mosi: P26, mut sck: P27) -> Self {
+        iomuxc::lpspi::prepare(&mut cs);
+        iomuxc::lpspi::prepare(&mut miso);
+        iomuxc::lpspi::prepare(&mut mosi);
+        iomuxc::lpspi::prepare(&mut sck);
+
+        // SAFETY: nothing else touches LPSPI3, the board leaves it alone
+        let lpspi = unsafe { LPSPI3::instance() };
+        ral::write_reg!(ral::lpspi, lpspi, CR, RST: 1);
+        ral::write_reg!(ral::lpspi, lpspi, CR, RST: 0, RRF: 1, RTF: 1);
+        // in on SDO, out on SDI, so pin 26 is MOSI and pin 1 MISO as labelled
+        ral::write_reg!(ral::lpspi, lpspi, CFGR1, MASTER: 0, PINCFG: 3);
+        ral::write_reg!(ral::lpspi, lpspi, TCR, CPOL: 0, CPHA: 0, FRAMESZ: 7);
+        // an interrupt for each byte in, and whenever there's room for more going out
+        ral::write_reg!(ral::lpspi, lpspi, FCR, RXWATER: 0, TXWATER: FIFO_LEN / 2);
+        ral::write_reg!(ral::lpspi, lpspi, IER, RDIE: 1, REIE: 1);
+        ral::write_reg!(ral::lpspi, lpspi, CR, MEN: 1);
+
+        Self {
+            lpspi,
+            tx: Deque::new(),
+            rx: Deque::new(),
+            lost: false,
+        }
+    }
+
+    /// Empty the receive FIFO and top up the transmit one, for LPSPI3's interrupt, returning
+    /// whether there's anything for the frame task.
+    pub fn service(&mut self) -> bool {
+        if ral::read_reg!(ral::lpspi, self.lpspi, SR, REF == 1) {
+            ral::write_reg!(ral::lpspi, self.lpspi, SR, REF: 1);
+            self.lost = true;
+        }
+        // running out to send isn't an error here, the controller just gets zeros
+        ral::write_reg!(ral::lpspi, self.lpspi, SR, TEF: 1);
+        while ral::read_reg!(ral::lpspi, self.lpspi, FSR, RXCOUNT) > 0 {
+            let byte = ral::read_reg!(ral::lpspi, self.lpspi, RDR) as u8;
+            if self.rx.push_back(byte).is_err() {
+                self.lost = true;
+            }
+        }
+        self.refill();
+        !self.rx.is_empty()
+    }
+
+    fn refill(&mut self) {
+        while ral::read_reg!(ral::lpspi, self.lpspi, FSR, TXCOUNT) < FIFO_LEN {
+            let Some(byte) = self.tx.pop_front() else {
+                break;
+            };
+            ral::write_reg!(ral::lpspi, self.lpspi, TDR, u32::from(byte));
+        }
+        // only while there's something to send, it'd fire forever otherwise
+        let more = u32::from(!self.tx.is_empty());
+        ral::modify_reg!(ral::lpspi, self.lpspi, IER, TDIE: more);
+    }
+}
+
+impl Transport for SpiTarget {
+    /// The controller sets the clock.
+    const SWITCHES_BAUD: bool = false;
+
+    fn interrupted(&mut self) -> bool {
+        self.service();
+        core::mem::take(&mut self.lost)
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.rx.pop_front()
+    }
+
+    /// Never waits, as it could be forever if the controller isn't reading, so when there's
+    /// no room the byte's dropped and the client finds the frame damaged.
+    fn try_send(&mut self, byte: u8) -> bool {
+        let _ = self.tx.push_back(byte);
+        self.refill();
+        true
+    }
+
+    fn set_baud(&mut self, _baud: u32) {}
+}
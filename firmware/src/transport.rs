@@ -0,0 +1,70 @@
+//! Where the client's frames come in and ours go back out: LPUART2, LPSPI3 with the `spi`
+//! feature, LPI2C1 with the `i2c` feature, FlexCAN1 with the `can` feature or ENET with the
+//! `ethernet` feature. Each goes through [`shared::link`] the same way.
+
+use shared::link::SerialLink;
+
+const _: () = assert!(
+    cfg!(feature = "spi") as u8
+        + cfg!(feature = "i2c") as u8
+        + cfg!(feature = "can") as u8
+        + cfg!(feature = "ethernet") as u8
+        <= 1,
+    "the client can only be on one of SPI, I2C, CAN and Ethernet"
+);
+
+#[cfg(uart)]
+pub type Link = crate::uart::Uart;
+#[cfg(feature = "spi")]
+pub type Link = crate::spi::SpiTarget;
+#[cfg(feature = "i2c")]
+pub type Link = crate::i2c::I2cTarget;
+#[cfg(feature = "can")]
+pub type Link = crate::can::CanLink;
+#[cfg(feature = "ethernet")]
+pub type Link = crate::ethernet::EthernetLink;
+
+/// The little the firmware needs from the peripheral the client's on.
+pub trait Transport {
+    /// Whether the client can move it to another baud rate.
+    const SWITCHES_BAUD: bool;
+
+    /// Deal with its interrupt, returning whether bytes coming in were lost or garbled.
+    fn interrupted(&mut self) -> bool;
+
+    /// The next byte from the client, if there is one.
+    fn receive(&mut self) -> Option<u8>;
+
+    /// Send a byte if there's room for it, returning whether there was.
+    fn try_send(&mut self, byte: u8) -> bool;
+
+    /// Switch to `baud`, once everything already written has gone at the old one.
+    fn set_baud(&mut self, baud: u32);
+
+    /// Move its clock on by `elapsed_ms`, for those with timers of their own, returning
+    /// whether anything's come in for the frame task.
+    fn tick(&mut self, _elapsed_ms: u32) -> bool {
+        false
+    }
+
+    /// Whether a client's started afresh since last asked, for those which can tell, so
+    /// anything half-read from before it can be thrown away. Asked after each
+    /// [`Transport::receive`], as what's received after it is the new client's.
+    fn reconnected(&mut self) -> bool {
+        false
+    }
+}
+
+/// The transport behind its lock, taken a byte at a time, so from below its interrupt's
+/// priority frames coming the other way aren't held up.
+pub struct Locked<'a, M>(pub &'a mut M);
+
+impl<T: Transport, M: rtic::Mutex<T = T>> SerialLink for Locked<'_, M> {
+    fn receive(&mut self) -> Option<u8> {
+        self.0.lock(|link| link.receive())
+    }
+
+    fn send(&mut self, byte: u8) {
+        while !self.0.lock(|link| link.try_send(byte)) {}
+    }
+}
@@ -0,0 +1,75 @@
+//! The teensy's end of [`shared::link`], LPUART2 on pins 14 and 15.
+
+use teensy4_bsp::{board, hal::lpuart};
+
+use crate::transport::Transport;
+
+/// LPUART2, keeping track of breaks on the line, which the client sends as it connects.
+pub struct Uart {
+    lpuart: board::Lpuart2,
+    broke: bool,
+}
+
+impl Uart {
+    pub fn new(lpuart: board::Lpuart2) -> Self {
+        Self {
+            lpuart,
+            broke: false,
+        }
+    }
+}
+
+impl Transport for Uart {
+    const SWITCHES_BAUD: bool = true;
+
+    fn interrupted(&mut self) -> bool {
+        use lpuart::Status;
+        let status = self.lpuart.status();
+        self.lpuart.clear_status(Status::W1C);
+        status.intersects(
+            Status::OVERRUN | Status::NOISY | Status::FRAME_ERROR | Status::PARITY_ERROR,
+        )
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        loop {
+            let data = self.lpuart.read_data();
+            let flags = data.flags();
+            if flags.contains(lpuart::ReadFlags::RXEMPT) {
+                return None;
+            }
+            let byte = u8::from(data);
+            // a break reads as a zero with no stop bit, which isn't a byte from the client
+            if byte == 0 && flags.contains(lpuart::ReadFlags::FRAME_ERROR) {
+                self.broke = true;
+                continue;
+            }
+            return Some(byte);
+        }
+    }
+
+    fn try_send(&mut self, byte: u8) -> bool {
+        let empty = self
+            .lpuart
+            .status()
+            .contains(lpuart::Status::TRANSMIT_EMPTY);
+        if empty {
+            self.lpuart.write_byte(byte);
+        }
+        empty
+    }
+
+    fn set_baud(&mut self, baud: u32) {
+        while !self
+            .lpuart
+            .status()
+            .contains(lpuart::Status::TRANSMIT_COMPLETE)
+        {}
+        let baud = lpuart::Baud::compute(board::UART_FREQUENCY, baud);
+        self.lpuart.disable(|lpuart2| lpuart2.set_baud(&baud));
+    }
+
+    fn reconnected(&mut self) -> bool {
+        core::mem::take(&mut self.broke)
+    }
+}
@@ -0,0 +1,36 @@
+//! The i.MX RT's WDOG1, which resets the teensy if it isn't fed in time. A reset drops it off
+//! the target's USB, which lets go of everything, so a lockup can't leave a key held down.
+
+use teensy4_bsp::ral::{self, wdog::WDOG1};
+
+/// How long the firmware can go without feeding the watchdog before it resets, in the WT
+/// field's half seconds less one, so a second.
+const TIMEOUT: u16 = 1;
+
+pub struct Watchdog(WDOG1);
+
+impl Watchdog {
+    /// Start the watchdog, after which it can't be stopped again. Without the watchdog
+    /// feature it's left off, and feeding it does nothing.
+    pub fn start() -> Self {
+        // SAFETY: nothing else touches WDOG1
+        let wdog = unsafe { WDOG1::instance() };
+        if !cfg!(feature = "watchdog") {
+            return Self(wdog);
+        }
+        // otherwise the power down counter resets us 16 seconds after boot whatever happens
+        ral::write_reg!(ral::wdog, wdog, WMCR, PDE: 0);
+        // keep running in debug and wait modes, a wedged handler is a wedged handler, and
+        // leave SRS and WDA alone as clearing them resets us straight away
+        ral::modify_reg!(ral::wdog, wdog, WCR, WT: TIMEOUT, WDBG: 0, WDZST: 0, WDE: 1);
+        let mut watchdog = Self(wdog);
+        watchdog.feed();
+        watchdog
+    }
+
+    /// Put off the reset for another [`TIMEOUT`].
+    pub fn feed(&mut self) {
+        ral::write_reg!(ral::wdog, self.0, WSR, 0x5555);
+        ral::write_reg!(ral::wdog, self.0, WSR, 0xAAAA);
+    }
+}
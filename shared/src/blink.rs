@@ -0,0 +1,216 @@
+//! Blink codes for the firmware's LED, so what's gone wrong can be told at a glance without
+//! a debugger or the console: some number of short flashes, a pause, then again.
+//!
+//! | Flashes | Meaning                                                  |
+//! |---------|----------------------------------------------------------|
+//! | 1       | The target hasn't configured us, so nothing gets typed   |
+//! | 2       | The UART lost or garbled bytes                           |
+//! | 3       | The queue of keyboard reports was full, so some went     |
+//! | 4       | Frames from the client didn't decode                     |
+//!
+//! All but the first carry on for a few seconds after it last happened. With nothing wrong
+//! the LED is left to show whether reports are queued.
+
+use crate::Telemetry;
+
+/// How long each flash, and the gap after it, lasts.
+const FLASH_MS: u32 = 200;
+/// How long the LED stays dark after the last flash, before they start again.
+const PAUSE_MS: u32 = 1000;
+/// How long a fault is blinked for after it last happened.
+const HOLD_MS: u32 = 5000;
+
+/// What the LED blinks, most important first, which goes first if there's more than one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    NotConfigured,
+    UartErrors,
+    QueueOverflow,
+    BadFrames,
+}
+
+impl Fault {
+    /// How many times the LED flashes for it.
+    pub fn flashes(self) -> u32 {
+        self as u32 + 1
+    }
+
+    fn length_ms(self) -> u32 {
+        self.flashes() * 2 * FLASH_MS + PAUSE_MS
+    }
+}
+
+/// Works out what the LED should be doing, from the firmware's counts of what's gone wrong.
+pub struct Blinker {
+    /// The counts last time, to see which have gone up.
+    seen: Telemetry,
+    /// How much longer to blink for the UART, the queue and bad frames.
+    hold_ms: [u32; 3],
+    /// What's being blinked and how far into it we are.
+    blinking: Option<(Fault, u32)>,
+}
+
+impl Default for Blinker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blinker {
+    /// Const so it can be a task's local resource.
+    pub const fn new() -> Self {
+        Self {
+            seen: Telemetry {
+                queued_reports: 0,
+                reports_dropped: 0,
+                bad_frames: 0,
+                uart_errors: 0,
+                usb_errors: 0,
+            },
+            hold_ms: [0; 3],
+            blinking: None,
+        }
+    }
+
+    /// Move on by `elapsed_ms`, returning whether the LED should be lit, or None if there's
+    /// nothing to blink and it's free for something else.
+    pub fn tick(
+        &mut self,
+        elapsed_ms: u32,
+        configured: bool,
+        telemetry: &Telemetry,
+    ) -> Option<bool> {
+        let counts = [
+            (telemetry.uart_errors, self.seen.uart_errors),
+            (telemetry.reports_dropped, self.seen.reports_dropped),
+            (telemetry.bad_frames, self.seen.bad_frames),
+        ];
+        for (hold_ms, (now, before)) in self.hold_ms.iter_mut().zip(counts) {
+            *hold_ms = if now == before {
+                hold_ms.saturating_sub(elapsed_ms)
+            } else {
+                HOLD_MS
+            };
+        }
+        self.seen = *telemetry;
+
+        // only moving on to another once one's finished, so each is blinked whole
+        self.blinking = match self.blinking {
+            Some((fault, ms)) if ms + elapsed_ms < fault.length_ms() => {
+                Some((fault, ms + elapsed_ms))
+            }
+            _ => self.worst(configured).map(|fault| (fault, 0)),
+        };
+        let (fault, ms) = self.blinking?;
+        Some(ms < fault.flashes() * 2 * FLASH_MS && (ms / FLASH_MS).is_multiple_of(2))
+    }
+
+    fn worst(&self, configured: bool) -> Option<Fault> {
+        if !configured {
+            return Some(Fault::NotConfigured);
+        }
+        [Fault::UartErrors, Fault::QueueOverflow, Fault::BadFrames]
+            .into_iter()
+            .zip(self.hold_ms)
+            .find_map(|(fault, hold_ms)| (hold_ms > 0).then_some(fault))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP_MS: u32 = 100;
+
+    /// Tick `N` steps on, returning what the LED was doing after each.
+    fn walk<const N: usize>(
+        blinker: &mut Blinker,
+        configured: bool,
+        telemetry: &Telemetry,
+    ) -> [Option<bool>; N] {
+        core::array::from_fn(|_| blinker.tick(STEP_MS, configured, telemetry))
+    }
+
+    fn fault(blinker: &Blinker) -> Option<Fault> {
+        blinker.blinking.map(|(fault, _)| fault)
+    }
+
+    #[test]
+    fn test_flashes() {
+        let mut blinker = Blinker::default();
+        let telemetry = Telemetry::default();
+        // one flash then the pause, 1400ms in all, over and over
+        for _ in 0..2 {
+            let lit: [_; 14] = walk(&mut blinker, false, &telemetry);
+            assert_eq!(lit[..2], [Some(true); 2]);
+            assert_eq!(lit[2..], [Some(false); 12]);
+        }
+
+        let mut blinker = Blinker::default();
+        let telemetry = Telemetry {
+            reports_dropped: 1,
+            ..Default::default()
+        };
+        let lit: [_; 22] = walk(&mut blinker, true, &telemetry);
+        let flashes = lit.windows(2).filter(|w| w == &[Some(true), Some(false)]);
+        assert_eq!(flashes.count() as u32, Fault::QueueOverflow.flashes());
+        assert_eq!(lit[12..], [Some(false); 10]);
+    }
+
+    #[test]
+    fn test_nothing_wrong() {
+        let mut blinker = Blinker::default();
+        let lit: [_; 100] = walk(&mut blinker, true, &Telemetry::default());
+        assert_eq!(lit, [None; 100]);
+    }
+
+    #[test]
+    fn test_lowest_first() {
+        let mut blinker = Blinker::default();
+        let mut telemetry = Telemetry {
+            bad_frames: 1,
+            ..Default::default()
+        };
+        blinker.tick(STEP_MS, true, &telemetry);
+        assert_eq!(fault(&blinker), Some(Fault::BadFrames));
+
+        // what's being blinked is finished first
+        telemetry.uart_errors = 1;
+        let length = Fault::BadFrames.length_ms() / STEP_MS;
+        for _ in 1..length {
+            blinker.tick(STEP_MS, true, &telemetry);
+            assert_eq!(fault(&blinker), Some(Fault::BadFrames));
+        }
+        blinker.tick(STEP_MS, true, &telemetry);
+        assert_eq!(fault(&blinker), Some(Fault::UartErrors));
+
+        // and not being configured comes before anything
+        blinker.blinking = None;
+        blinker.tick(STEP_MS, false, &telemetry);
+        assert_eq!(fault(&blinker), Some(Fault::NotConfigured));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut blinker = Blinker::default();
+        let telemetry = Telemetry {
+            reports_dropped: 1,
+            ..Default::default()
+        };
+        let lit: [_; (HOLD_MS / STEP_MS) as usize] = walk(&mut blinker, true, &telemetry);
+        assert!(lit.iter().all(Option::is_some));
+        // it's still blinked until the end of the last time round
+        let length = Fault::QueueOverflow.length_ms() / STEP_MS;
+        let lit: [_; 30] = walk(&mut blinker, true, &telemetry);
+        assert!(lit[length as usize..].iter().all(Option::is_none));
+        assert_eq!(fault(&blinker), None);
+
+        // until it happens again
+        let telemetry = Telemetry {
+            reports_dropped: 2,
+            ..Default::default()
+        };
+        blinker.tick(STEP_MS, true, &telemetry);
+        assert_eq!(fault(&blinker), Some(Fault::QueueOverflow));
+    }
+}
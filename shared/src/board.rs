@@ -0,0 +1,466 @@
+//! What the firmware does with the client's frames, the same on every board. Each board
+//! only says how to get at what it has through [`Board`], and leaves out what it doesn't,
+//! like the pico's missing mouse and settings.
+
+use core::fmt;
+
+use usbd_hid::descriptor::KeyboardReport;
+
+use crate::{
+    crash::CrashReport,
+    link::{self, FrameError, FrameReader, Sequence, SerialLink, Verdict},
+    AbsolutePointerState, DeviceMessage, DigitizerState, GamepadReport, HostMessage, Identity,
+    MouseState, ScheduledReport, Setting, SettingKey, Telemetry,
+};
+
+/// Write a line to the board's log, with `format!`'s arguments.
+macro_rules! log {
+    ($board:expr, $($arg:tt)*) => {
+        $board.log(format_args!($($arg)*))
+    };
+}
+
+/// Everything the frames from the client can change on a board, and the link they come in
+/// on. Those with a default are for devices and features a board can do without.
+pub trait Board: SerialLink + Sized {
+    /// Whether the client can move the link to another baud rate.
+    const SWITCHES_BAUD: bool = false;
+
+    /// Whether a client's started afresh since last asked, for links which can tell. Asked
+    /// after each [`SerialLink::receive`], as what's received after it is the new client's.
+    fn reconnected(&mut self) -> bool {
+        false
+    }
+
+    /// Let go of everything held for a client that's started afresh.
+    fn reset(&mut self);
+
+    /// A frame's arrived intact, so the client's still there.
+    fn heard_from_client(&mut self);
+
+    /// Write a line of diagnostics somewhere, for boards with anywhere to write them.
+    fn log(&mut self, _args: fmt::Arguments) {}
+
+    /// Count something going wrong, or look at what has.
+    fn telemetry<R>(&mut self, f: impl FnOnce(&mut Telemetry) -> R) -> R;
+
+    /// Whether the kill switch is closed, so nothing from the client reaches the target.
+    fn killed(&mut self) -> bool {
+        false
+    }
+
+    /// Add `reports` to the queue for the target in turn until it's full, returning how
+    /// many fit.
+    fn enqueue(&mut self, reports: impl Iterator<Item = KeyboardReport>) -> usize;
+
+    /// How many more reports fit in the queue.
+    fn room(&mut self) -> usize;
+
+    /// How many reports are waiting to go to the target.
+    fn queued(&mut self) -> usize;
+
+    /// Hold each of `steps` as long as it says before the next, returning how many fit.
+    /// Without a timer to hold them for they go like any others.
+    fn schedule(&mut self, steps: &[ScheduledReport]) -> usize {
+        self.enqueue(steps.iter().map(|step| step.report.into()))
+    }
+
+    fn set_gamepad(&mut self, _report: GamepadReport) {}
+
+    fn set_absolute_pointer(&mut self, _state: AbsolutePointerState) {}
+
+    fn set_digitizer(&mut self, _state: DigitizerState) {}
+
+    /// Hold down `usage`, already checked to be one the keyboard reports.
+    fn set_consumer(&mut self, _usage: u16) {}
+
+    /// Add `state`'s movement on to whatever hasn't gone to the target yet.
+    fn move_mouse(&mut self, _state: MouseState) {}
+
+    fn set_heartbeats(&mut self);
+
+    fn set_lock_chord(&mut self, chord: Option<KeyboardReport>);
+
+    fn set_idle_timeout(&mut self, ms: u32);
+
+    /// Already clamped to what the firmware goes along with.
+    fn set_report_interval(&mut self, ms: u8);
+
+    fn set_immediate_reports(&mut self, _immediate: bool) {}
+
+    /// The baud rate the link's at.
+    fn baud(&mut self) -> u32 {
+        crate::DEFAULT_BAUD_RATE
+    }
+
+    /// Switch the link to `baud`, once the client's been told and everything before has
+    /// been acknowledged at the old rate.
+    fn switch_baud(&mut self, _baud: u32) {}
+
+    /// What's saved for `key`, for boards with anywhere to save settings.
+    fn setting(&mut self, _key: SettingKey) -> Option<Setting> {
+        None
+    }
+
+    /// Save `value` for `key`, or forget it with None, then tell the client what's saved,
+    /// which with nowhere to save it is nothing.
+    fn save_setting(&mut self, key: SettingKey, _value: Option<Setting>) {
+        let saved = self.setting(key);
+        link::send_message(self, &DeviceMessage::Setting(key, saved));
+    }
+
+    /// What the firmware panicked with before this boot, if it did.
+    fn crash_report(&self) -> Option<CrashReport>;
+
+    fn identity(&self) -> Identity;
+}
+
+/// Reads the client's frames as they come in and acts on each.
+pub struct Receiver {
+    reader: FrameReader,
+    sequence: Sequence,
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Receiver {
+    /// Const so it can be a task's local resource.
+    pub const fn new() -> Self {
+        Self {
+            reader: FrameReader::new(),
+            sequence: Sequence::new(),
+        }
+    }
+
+    /// Act on every frame that's come in from the client, returning whether any keyboard
+    /// reports were queued.
+    pub fn poll<B: Board>(&mut self, board: &mut B) -> bool {
+        let mut new_reports = false;
+        loop {
+            let byte = board.receive();
+            if board.reconnected() {
+                log!(board, "The client's reconnected, starting afresh");
+                self.reader.reset();
+                self.sequence = Sequence::new();
+                board.reset();
+                new_reports = true;
+            }
+            let Some(byte) = byte else {
+                break;
+            };
+            let Some(frame) = self.reader.push_host(byte) else {
+                continue;
+            };
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    board.telemetry(|telemetry| telemetry.bad_frames += 1);
+                    if e == FrameError::TooLong {
+                        log!(board, "Dropped a frame too long to be a message");
+                    } else {
+                        log!(board, "Dropped a damaged frame");
+                    }
+                    nak(board, self.sequence.damaged());
+                    continue;
+                }
+            };
+
+            board.heard_from_client();
+            match self.sequence.intact(&frame) {
+                Verdict::Accept => {}
+                Verdict::Drop(expected) => {
+                    nak(board, expected);
+                    continue;
+                }
+                Verdict::Duplicate => {
+                    log!(board, "Dropped frame {} as it's been acted on", frame.seq);
+                    continue;
+                }
+            }
+            let Some(message) = frame.message else {
+                board.telemetry(|telemetry| telemetry.bad_frames += 1);
+                log!(board, "Skipped a message this firmware doesn't know");
+                continue;
+            };
+            new_reports |= self.handle(message, board);
+        }
+        // one for everything acted on, now there's nothing more waiting
+        self.ack(board);
+        new_reports
+    }
+
+    /// Act on one message, returning whether any keyboard reports were queued.
+    fn handle<B: Board>(&mut self, message: HostMessage, board: &mut B) -> bool {
+        // the rest still goes through, so the client can tell what's going on
+        let input = matches!(
+            message,
+            HostMessage::Report(_)
+                | HostMessage::Reports { .. }
+                | HostMessage::Gamepad(_)
+                | HostMessage::AbsolutePointer(_)
+                | HostMessage::Digitizer(_)
+                | HostMessage::Consumer(_)
+                | HostMessage::Mouse(_)
+                | HostMessage::TypeCodepoint { .. }
+                | HostMessage::Scheduled { .. }
+        );
+        if input && board.killed() {
+            return false;
+        }
+
+        match message {
+            HostMessage::Hello { nonce } => {
+                log!(board, "Hello from the client");
+                link::send_message(board, &DeviceMessage::HelloAck { nonce });
+            }
+            HostMessage::Probe { nonce } => {
+                link::send_message(board, &DeviceMessage::HelloAck { nonce });
+            }
+            HostMessage::Report(report) => {
+                let queued = board.enqueue(core::iter::once(report.into()));
+                if queued == 0 {
+                    log!(board, "The queue's full, dropped a report");
+                    overflowed(board, 1);
+                }
+                return queued > 0;
+            }
+            HostMessage::Reports { count, reports } => {
+                let count = usize::from(count).min(reports.len());
+                let queued = board.enqueue(reports[..count].iter().map(|&report| report.into()));
+                // the client has to know, or a release can go missing unnoticed
+                if queued < count {
+                    let dropped = count - queued;
+                    log!(board, "The queue's full, dropped {dropped} reports");
+                    overflowed(board, dropped);
+                }
+                return queued > 0;
+            }
+            HostMessage::Scheduled { count, reports } => {
+                let count = usize::from(count).min(reports.len());
+                let queued = board.schedule(&reports[..count]);
+                if queued < count {
+                    let dropped = count - queued;
+                    log!(board, "Too much scheduled, dropped {dropped} reports");
+                    overflowed(board, dropped);
+                }
+            }
+            HostMessage::TypeCodepoint { codepoint, os } => {
+                let reports = os.codepoint_reports(codepoint);
+                let count = reports.clone().count();
+                // all or nothing, as half of one could leave alt held or start something
+                // the next character ends up in
+                if board.room() < count {
+                    log!(board, "The queue's full, dropped {codepoint:?}");
+                    overflowed(board, count);
+                    return false;
+                }
+                board.enqueue(reports.map(Into::into));
+                return true;
+            }
+            HostMessage::Gamepad(state) => board.set_gamepad(state.into()),
+            HostMessage::AbsolutePointer(state) => board.set_absolute_pointer(state),
+            HostMessage::Digitizer(state) => board.set_digitizer(state),
+            HostMessage::Heartbeat => board.set_heartbeats(),
+            HostMessage::LockChord(chord) => board.set_lock_chord(chord.map(Into::into)),
+            HostMessage::Consumer(usage) => {
+                let usage = if usage > crate::CONSUMER_USAGE_MAX {
+                    0
+                } else {
+                    usage
+                };
+                board.set_consumer(usage);
+            }
+            HostMessage::Mouse(state) => board.move_mouse(state),
+            HostMessage::IdleTimeout(ms) => board.set_idle_timeout(ms),
+            HostMessage::ImmediateReports(immediate) => {
+                board.set_immediate_reports(immediate);
+                if immediate {
+                    log!(board, "Sending the target reports as soon as they come in");
+                } else {
+                    log!(board, "Sending the target reports on the tick");
+                }
+            }
+            HostMessage::ReportInterval(ms) => {
+                let ms = ms.clamp(1, crate::MAX_REPORT_INTERVAL_MS);
+                board.set_report_interval(ms);
+                log!(board, "Sending the target a report every {ms}ms");
+            }
+            HostMessage::SetBaud(requested) => {
+                let possible = crate::DEFAULT_BAUD_RATE..=crate::MAX_BAUD_RATE;
+                let switching = B::SWITCHES_BAUD && possible.contains(&requested);
+                let answer = if switching { requested } else { board.baud() };
+                link::send_message(board, &DeviceMessage::Baud(answer));
+                log!(board, "Asked for {requested} baud, going with {answer}");
+                if switching {
+                    // while the client can still hear it
+                    self.ack(board);
+                    board.switch_baud(requested);
+                }
+            }
+            HostMessage::GetSetting(key) => {
+                let value = board.setting(key);
+                link::send_message(board, &DeviceMessage::Setting(key, value));
+            }
+            HostMessage::SetSetting(setting) => board.save_setting(setting.key(), Some(setting)),
+            HostMessage::ForgetSetting(key) => board.save_setting(key, None),
+            HostMessage::Identify => {
+                if let Some(report) = board.crash_report() {
+                    log!(board, "Reset after a panic: {}", report.as_str());
+                    for message in report.messages() {
+                        link::send_message(board, &message);
+                    }
+                }
+                let identity = board.identity();
+                link::send_message(board, &DeviceMessage::Identity(identity));
+            }
+            HostMessage::GetTelemetry => {
+                let mut current = board.telemetry(|telemetry| *telemetry);
+                current.queued_reports = board.queued() as u8;
+                link::send_message(board, &DeviceMessage::Telemetry(current));
+            }
+        }
+        false
+    }
+
+    /// Tell the client which frames have been acted on, if any have since it was last told.
+    fn ack(&mut self, board: &mut impl Board) {
+        if let Some(seq) = self.sequence.ack() {
+            link::send_message(board, &DeviceMessage::Ack { seq });
+        }
+    }
+}
+
+/// Ask the client to send everything again from `expected`, if it's time to.
+fn nak(board: &mut impl Board, expected: Option<u8>) {
+    if let Some(expected) = expected {
+        log!(
+            board,
+            "Asking the client for everything from frame {expected} again"
+        );
+        link::send_message(board, &DeviceMessage::Nak { expected });
+    }
+}
+
+/// `dropped` reports didn't fit, which the client has to hear about.
+fn overflowed(board: &mut impl Board, dropped: usize) {
+    let dropped = dropped as u8;
+    board.telemetry(|telemetry| telemetry.reports_dropped += u32::from(dropped));
+    link::send_message(board, &DeviceMessage::Overflow { dropped });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{link::encode_host_frame, WhyNoDeriveDeserializeManSadFaceHere, MAX_FRAME_LEN};
+
+    /// A board with room for two reports, taking its bytes from `input`.
+    struct FakeBoard<'a> {
+        input: &'a [u8],
+        output: [u8; 256],
+        written: usize,
+        queued: usize,
+        telemetry: Telemetry,
+    }
+
+    impl SerialLink for FakeBoard<'_> {
+        fn receive(&mut self) -> Option<u8> {
+            let (&byte, rest) = self.input.split_first()?;
+            self.input = rest;
+            Some(byte)
+        }
+
+        fn send(&mut self, byte: u8) {
+            self.output[self.written] = byte;
+            self.written += 1;
+        }
+    }
+
+    impl Board for FakeBoard<'_> {
+        fn reset(&mut self) {}
+
+        fn heard_from_client(&mut self) {}
+
+        fn telemetry<R>(&mut self, f: impl FnOnce(&mut Telemetry) -> R) -> R {
+            f(&mut self.telemetry)
+        }
+
+        fn enqueue(&mut self, reports: impl Iterator<Item = KeyboardReport>) -> usize {
+            let fits = reports.take(self.room()).count();
+            self.queued += fits;
+            fits
+        }
+
+        fn room(&mut self) -> usize {
+            2 - self.queued
+        }
+
+        fn queued(&mut self) -> usize {
+            self.queued
+        }
+
+        fn set_heartbeats(&mut self) {}
+
+        fn set_lock_chord(&mut self, _chord: Option<KeyboardReport>) {}
+
+        fn set_idle_timeout(&mut self, _ms: u32) {}
+
+        fn set_report_interval(&mut self, _ms: u8) {}
+
+        fn crash_report(&self) -> Option<CrashReport> {
+            None
+        }
+
+        fn identity(&self) -> Identity {
+            Identity::new(["0", "1", "0"], "", false, Default::default(), 0)
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        let report = WhyNoDeriveDeserializeManSadFaceHere {
+            modifier: 0,
+            keys: [0x04, 0, 0, 0, 0, 0],
+        };
+        let messages = [
+            HostMessage::Hello { nonce: 7 },
+            HostMessage::Reports {
+                count: 3,
+                reports: [report; crate::MAX_PACKED_REPORTS],
+            },
+        ];
+        let mut input = [0; 2 * MAX_FRAME_LEN];
+        let mut len = 0;
+        for (seq, message) in messages.iter().enumerate() {
+            len += encode_host_frame(seq as u8, message, &mut input[len..])
+                .unwrap()
+                .len();
+        }
+        let mut board = FakeBoard {
+            input: &input[..len],
+            output: [0; 256],
+            written: 0,
+            queued: 0,
+            telemetry: Telemetry::default(),
+        };
+
+        assert!(Receiver::new().poll(&mut board));
+        assert_eq!(board.queued, 2);
+        assert_eq!(board.telemetry.reports_dropped, 1);
+        // the client hears about the one which didn't fit, then that both frames arrived
+        let mut reader = FrameReader::new();
+        let mut sent = board.output[..board.written]
+            .iter()
+            .filter_map(|&byte| reader.push::<DeviceMessage>(byte));
+        assert_eq!(sent.next(), Some(Ok(DeviceMessage::HelloAck { nonce: 7 })));
+        assert_eq!(
+            sent.next(),
+            Some(Ok(DeviceMessage::Overflow { dropped: 1 }))
+        );
+        assert_eq!(sent.next(), Some(Ok(DeviceMessage::Ack { seq: 1 })));
+        assert_eq!(sent.next(), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! What the firmware leaves behind when it panics, somewhere in RAM the reset it does
+//! afterwards doesn't clear, so once it's back it can tell the client what went wrong.
+
+use core::fmt;
+
+use crate::{AgentText, DeviceMessage, AGENT_TEXT_LEN};
+
+/// The most of a panic's message kept, the rest is cut off.
+pub const CRASH_REPORT_LEN: usize = 96;
+
+/// Marks a report as written by a panic, rather than whatever was in RAM at power on.
+const MAGIC: u32 = 0xDEAD_C0DE;
+
+/// A panic's message, for a static in RAM which isn't initialised at boot.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CrashReport {
+    magic: u32,
+    len: u32,
+    bytes: [u8; CRASH_REPORT_LEN],
+}
+
+impl CrashReport {
+    /// Keep as much of `message` as fits.
+    pub fn record(&mut self, message: impl fmt::Display) {
+        self.len = 0;
+        let _ = fmt::write(self, format_args!("{message}"));
+        self.magic = MAGIC;
+    }
+
+    /// What a panic left before the last reset, if there was one, clearing it so a reset
+    /// for any other reason doesn't find it again.
+    pub fn take(&mut self) -> Option<Self> {
+        let found = self.magic == MAGIC && self.len as usize <= CRASH_REPORT_LEN;
+        self.magic = 0;
+        found.then_some(*self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// The [`DeviceMessage::Crashed`]s it goes to the client in.
+    pub fn messages(&self) -> impl Iterator<Item = DeviceMessage> + '_ {
+        let pieces = self.as_str().as_bytes().chunks(AGENT_TEXT_LEN);
+        let count = pieces.len();
+        pieces
+            .enumerate()
+            .map(move |(i, piece)| DeviceMessage::Crashed {
+                text: AgentText::new(piece),
+                first: i == 0,
+                last: i + 1 == count,
+            })
+    }
+}
+
+impl fmt::Write for CrashReport {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.len as usize;
+        // only whole characters, so it's still UTF-8 when it's cut off
+        let mut fits = s.len().min(CRASH_REPORT_LEN - len);
+        while !s.is_char_boundary(fits) {
+            fits -= 1;
+        }
+        self.bytes[len..len + fits].copy_from_slice(&s.as_bytes()[..fits]);
+        self.len += fits as u32;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::{size_of, transmute};
+
+    use super::*;
+
+    /// What might be in RAM at power on, which the report's read from as it is.
+    fn uninit(byte: u8) -> CrashReport {
+        // every field's a plain integer, so any bytes are one
+        unsafe { transmute([byte; size_of::<CrashReport>()]) }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut report = uninit(0xA5);
+        report.record(format_args!(
+            "panicked at {}:{}: {}",
+            "main.rs", 42, "oh no, ü"
+        ));
+        let taken = report.take().unwrap();
+        assert_eq!(taken.as_str(), "panicked at main.rs:42: oh no, ü");
+        // a reset for anything else doesn't find it again
+        assert!(report.take().is_none());
+
+        let mut text = [0; CRASH_REPORT_LEN];
+        let mut len = 0;
+        let mut messages = taken.messages().peekable();
+        let mut first = true;
+        while let Some(DeviceMessage::Crashed {
+            text: piece,
+            first: is_first,
+            last,
+        }) = messages.next()
+        {
+            assert_eq!(is_first, first);
+            assert_eq!(last, messages.peek().is_none());
+            text[len..len + piece.as_bytes().len()].copy_from_slice(piece.as_bytes());
+            len += piece.as_bytes().len();
+            first = false;
+        }
+        assert_eq!(&text[..len], taken.as_str().as_bytes());
+    }
+
+    #[test]
+    fn test_garbage() {
+        for byte in [0x00, 0xA5, 0xFF] {
+            assert!(uninit(byte).take().is_none());
+        }
+        // the magic's no good without a length that fits
+        let mut report = uninit(0xFF);
+        report.magic = MAGIC;
+        assert!(report.take().is_none());
+        // nor does a length that fits make anything readable out of what's there
+        let mut report = uninit(0xFF);
+        report.magic = MAGIC;
+        report.len = 10;
+        assert_eq!(report.take().unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut report = uninit(0);
+        let long = "é".repeat(CRASH_REPORT_LEN);
+        report.record(&long);
+        let taken = report.take().unwrap();
+        // cut off at the last whole character that fits
+        assert_eq!(taken.as_str().len(), CRASH_REPORT_LEN);
+        assert!(long.starts_with(taken.as_str()));
+
+        // three bytes each after the first, so the last doesn't fit
+        let mut report = uninit(0);
+        report.record(format_args!("a{}", "€".repeat(CRASH_REPORT_LEN)));
+        let taken = report.take().unwrap();
+        assert_eq!(taken.as_str().len(), 1 + (CRASH_REPORT_LEN - 1) / 3 * 3);
+        assert!(taken.as_str().ends_with('€'));
+    }
+}
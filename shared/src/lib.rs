@@ -1,10 +1,65 @@
 #![no_std]
 
+use enumflags2::{bitflags, BitFlags};
 use serde::{Deserialize, Serialize};
-use usbd_hid::descriptor::KeyboardReport;
+use usbd_hid::descriptor::{generator_prelude::*, KeyboardReport};
+
+pub mod blink;
+pub mod board;
+pub mod crash;
+pub mod link;
+pub mod payload;
+
+/// The USB vendor and product IDs the teensy shows up with on the target,
+/// https://pid.codes/1209/C00B/
+pub const USB_VID: u16 = 0x1209;
+pub const USB_PID: u16 = 0xC00B;
+
+/// Bumped whenever a message changes in a way the other end would misread. Adding one on
+/// the end of [`HostMessage`] or [`DeviceMessage`] doesn't need it, as an end which doesn't
+/// know the new one just can't decode it.
+pub const PROTOCOL_VERSION: u16 = 3;
+
+/// The baud rate both ends of the serial link use unless told otherwise.
+pub const DEFAULT_BAUD_RATE: u32 = 115200;
+
+/// The fastest the client can have the firmware switch the serial link to with a
+/// [`HostMessage::SetBaud`].
+pub const MAX_BAUD_RATE: u32 = 2_000_000;
+/// How long the firmware waits to hear something at a new baud rate before going back to
+/// [`DEFAULT_BAUD_RATE`], in case the client couldn't follow it there.
+pub const BAUD_CONFIRM_MS: u32 = 1000;
+
+/// The largest COBS frame either side will send, including the trailing zero, and the
+/// client's sequence number and CRC.
+pub const MAX_FRAME_LEN: usize = 40;
+
+/// The most keyboard reports one [`HostMessage::Reports`] carries, as many as fit in a frame.
+pub const MAX_PACKED_REPORTS: usize = 4;
+
+/// The most reports one [`HostMessage::Scheduled`] carries, as many as fit in a frame.
+pub const MAX_SCHEDULED_REPORTS: usize = 3;
+
+/// How often the client sends a [`HostMessage::Heartbeat`] while it's running.
+pub const HEARTBEAT_INTERVAL_MS: u32 = 250;
+/// Once it has had a heartbeat, how long the firmware goes without hearing anything
+/// before it decides the client is gone and lets go of everything, then presses the
+/// [`HostMessage::LockChord`] if it's been given one.
+pub const HEARTBEAT_TIMEOUT_MS: u32 = 1000;
+/// How long the firmware goes without hearing anything from a client which doesn't send
+/// heartbeats before it lets go of everything, until it's told otherwise with a
+/// [`HostMessage::IdleTimeout`].
+pub const DEFAULT_IDLE_TIMEOUT_MS: u32 = 5000;
+
+/// How often the firmware sends the target a keyboard report, unless told otherwise with a
+/// [`HostMessage::ReportInterval`].
+pub const DEFAULT_REPORT_INTERVAL_MS: u8 = 1;
+/// The longest [`HostMessage::ReportInterval`] the firmware goes along with, anything longer
+/// gets this.
+pub const MAX_REPORT_INTERVAL_MS: u8 = 20;
 
 // A struct to pass a KeySet across to the firmware...
-#[derive(Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct WhyNoDeriveDeserializeManSadFaceHere {
     pub modifier: u8,
     pub keys: [u8; 6],
@@ -20,3 +75,784 @@ impl From<WhyNoDeriveDeserializeManSadFaceHere> for KeyboardReport {
         }
     }
 }
+
+/// A 16 button gamepad with two sticks and two analogue triggers.
+// 0x05 is the generic desktop gamepad usage
+#[gen_hid_descriptor(
+    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = 0x05) = {
+        // a byte at a time, as the macro takes a reference to the field and the struct's
+        // packed, which a u16 can't be referenced in
+        (usage_page = BUTTON, usage_min = BUTTON_1, usage_max = BUTTON_8) = {
+            #[packed_bits 8] #[item_settings data,variable,absolute] buttons_low=input;
+        };
+        (usage_page = BUTTON, usage_min = 0x09, usage_max = 0x10) = {
+            #[packed_bits 8] #[item_settings data,variable,absolute] buttons_high=input;
+        };
+        (usage_page = GENERIC_DESKTOP,) = {
+            (usage = X,) = {
+                #[item_settings data,variable,absolute] x=input;
+            };
+            (usage = Y,) = {
+                #[item_settings data,variable,absolute] y=input;
+            };
+            (usage = Z,) = {
+                #[item_settings data,variable,absolute] z=input;
+            };
+            // rx, ry and rz, which the macro has no names for
+            (usage = 0x33,) = {
+                #[item_settings data,variable,absolute] rx=input;
+            };
+            (usage = 0x34,) = {
+                #[item_settings data,variable,absolute] ry=input;
+            };
+            (usage = 0x35,) = {
+                #[item_settings data,variable,absolute] rz=input;
+            };
+        };
+    }
+)]
+#[derive(Default)]
+pub struct GamepadReport {
+    pub buttons_low: u8,
+    pub buttons_high: u8,
+    pub x: i8,
+    pub y: i8,
+    pub z: i8,
+    pub rx: i8,
+    pub ry: i8,
+    pub rz: i8,
+}
+
+// ...and the same dance again for the gamepad
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GamepadState {
+    /// One bit per button, see the client for which evdev button lands where.
+    pub buttons: u16,
+    /// Left stick, then the left trigger.
+    pub x: i8,
+    pub y: i8,
+    pub z: i8,
+    /// Right stick, then the right trigger.
+    pub rx: i8,
+    pub ry: i8,
+    pub rz: i8,
+}
+
+impl From<GamepadState> for GamepadReport {
+    fn from(value: GamepadState) -> Self {
+        let [buttons_low, buttons_high] = value.buttons.to_le_bytes();
+        GamepadReport {
+            buttons_low,
+            buttons_high,
+            x: value.x,
+            y: value.y,
+            z: value.z,
+            rx: value.rx,
+            ry: value.ry,
+            rz: value.rz,
+        }
+    }
+}
+
+/// The largest value either axis of the absolute pointer can take.
+pub const ABSOLUTE_POINTER_MAX: u16 = 32767;
+
+/// A three button pointer reporting absolute positions, like a tablet or touchscreen,
+/// with a scroll wheel.
+///
+/// Written out by hand as the descriptor macro can't do 16 bit logical ranges.
+#[rustfmt::skip]
+pub const ABSOLUTE_POINTER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x02,       // Usage (Mouse)
+    0xA1, 0x01,       // Collection (Application)
+    0x09, 0x01,       //   Usage (Pointer)
+    0xA1, 0x00,       //   Collection (Physical)
+    0x05, 0x09,       //     Usage Page (Button)
+    0x19, 0x01,       //     Usage Minimum (1)
+    0x29, 0x03,       //     Usage Maximum (3)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x95, 0x03,       //     Report Count (3)
+    0x75, 0x01,       //     Report Size (1)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0x95, 0x01,       //     Report Count (1)
+    0x75, 0x05,       //     Report Size (5)
+    0x81, 0x03,       //     Input (Constant, Variable, Absolute)
+    0x05, 0x01,       //     Usage Page (Generic Desktop)
+    0x09, 0x30,       //     Usage (X)
+    0x09, 0x31,       //     Usage (Y)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x26, 0xFF, 0x7F, //     Logical Maximum (32767)
+    0x75, 0x10,       //     Report Size (16)
+    0x95, 0x02,       //     Report Count (2)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0x09, 0x38,       //     Usage (Wheel)
+    0x15, 0x81,       //     Logical Minimum (-127)
+    0x25, 0x7F,       //     Logical Maximum (127)
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x01,       //     Report Count (1)
+    0x81, 0x06,       //     Input (Data, Variable, Relative)
+    0xC0,             //   End Collection
+    0xC0,             // End Collection
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AbsolutePointerState {
+    /// Left, right and middle in the bottom three bits.
+    pub buttons: u8,
+    /// Both axes go from 0 to [`ABSOLUTE_POINTER_MAX`] across the whole screen.
+    pub x: u16,
+    pub y: u16,
+    /// Scroll wheel clicks since the last report, positive is away from the user.
+    pub wheel: i8,
+}
+
+impl AbsolutePointerState {
+    /// The raw input report matching [`ABSOLUTE_POINTER_DESCRIPTOR`].
+    pub fn to_report(self) -> [u8; 6] {
+        let [x_lo, x_hi] = self.x.min(ABSOLUTE_POINTER_MAX).to_le_bytes();
+        let [y_lo, y_hi] = self.y.min(ABSOLUTE_POINTER_MAX).to_le_bytes();
+        // -128 is outside the logical range
+        let wheel = self.wheel.max(-127) as u8;
+        [self.buttons & 0b111, x_lo, x_hi, y_lo, y_hi, wheel]
+    }
+}
+
+/// The most [`DigitizerState::pressure`] can be.
+pub const DIGITIZER_PRESSURE_MAX: u8 = 255;
+
+/// A pen on a tablet, as some VM consoles, UEFI setup screens and KVMs only take absolute
+/// positions from a digitizer and not a mouse, with the same range as
+/// [`ABSOLUTE_POINTER_DESCRIPTOR`]. It takes the absolute pointer's place, as there aren't
+/// the endpoints for both. The pad is about 30cm square, which hosts only use to size
+/// things like handwriting, as it's mapped over the whole screen.
+#[rustfmt::skip]
+pub const DIGITIZER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0D,       // Usage Page (Digitizers)
+    0x09, 0x02,       // Usage (Pen)
+    0xA1, 0x01,       // Collection (Application)
+    0x09, 0x20,       //   Usage (Stylus)
+    0xA1, 0x00,       //   Collection (Physical)
+    0x09, 0x42,       //     Usage (Tip Switch)
+    0x09, 0x44,       //     Usage (Barrel Switch)
+    0x09, 0x32,       //     Usage (In Range)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x95, 0x03,       //     Report Count (3)
+    0x75, 0x01,       //     Report Size (1)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0x95, 0x01,       //     Report Count (1)
+    0x75, 0x05,       //     Report Size (5)
+    0x81, 0x03,       //     Input (Constant, Variable, Absolute)
+    0x05, 0x01,       //     Usage Page (Generic Desktop)
+    0x09, 0x30,       //     Usage (X)
+    0x09, 0x31,       //     Usage (Y)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x26, 0xFF, 0x7F, //     Logical Maximum (32767)
+    0x35, 0x00,       //     Physical Minimum (0)
+    0x46, 0xB8, 0x0B, //     Physical Maximum (3000)
+    0x65, 0x11,       //     Unit (Centimeter)
+    0x55, 0x0E,       //     Unit Exponent (-2)
+    0x75, 0x10,       //     Report Size (16)
+    0x95, 0x02,       //     Report Count (2)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0x05, 0x0D,       //     Usage Page (Digitizers)
+    0x09, 0x30,       //     Usage (Tip Pressure)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x45, 0x00,       //     Physical Maximum (0)
+    0x65, 0x00,       //     Unit (None)
+    0x55, 0x00,       //     Unit Exponent (0)
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x01,       //     Report Count (1)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0xC0,             //   End Collection
+    0xC0,             // End Collection
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DigitizerState {
+    /// Whether the pen's near enough to point with, without which the rest is ignored.
+    pub in_range: bool,
+    /// Touching the tablet, which is a click.
+    pub tip: bool,
+    /// The button on the side of the pen, which is usually a right click.
+    pub barrel: bool,
+    /// Both axes go from 0 to [`ABSOLUTE_POINTER_MAX`] across the whole screen.
+    pub x: u16,
+    pub y: u16,
+    /// How hard the tip's pressed, up to [`DIGITIZER_PRESSURE_MAX`].
+    pub pressure: u8,
+}
+
+impl DigitizerState {
+    /// The raw input report matching [`DIGITIZER_DESCRIPTOR`].
+    pub fn to_report(self) -> [u8; 6] {
+        let [x_lo, x_hi] = self.x.min(ABSOLUTE_POINTER_MAX).to_le_bytes();
+        let [y_lo, y_hi] = self.y.min(ABSOLUTE_POINTER_MAX).to_le_bytes();
+        let switches =
+            u8::from(self.tip) | u8::from(self.barrel) << 1 | u8::from(self.in_range) << 2;
+        [switches, x_lo, x_hi, y_lo, y_hi, self.pressure]
+    }
+}
+
+impl From<AbsolutePointerState> for DigitizerState {
+    /// A pen that's always in range, with the left button as its tip, pressed all the way,
+    /// and the right as its barrel button. The rest, and the wheel, don't have anywhere to go.
+    fn from(value: AbsolutePointerState) -> Self {
+        let tip = value.buttons & 0b001 != 0;
+        Self {
+            in_range: true,
+            tip,
+            barrel: value.buttons & 0b010 != 0,
+            x: value.x,
+            y: value.y,
+            pressure: if tip { DIGITIZER_PRESSURE_MAX } else { 0 },
+        }
+    }
+}
+
+/// A five button mouse reporting relative movements, with a scroll wheel.
+#[rustfmt::skip]
+pub const MOUSE_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x02,       // Usage (Mouse)
+    0xA1, 0x01,       // Collection (Application)
+    0x09, 0x01,       //   Usage (Pointer)
+    0xA1, 0x00,       //   Collection (Physical)
+    0x05, 0x09,       //     Usage Page (Button)
+    0x19, 0x01,       //     Usage Minimum (1)
+    0x29, 0x05,       //     Usage Maximum (5)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x95, 0x05,       //     Report Count (5)
+    0x75, 0x01,       //     Report Size (1)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0x95, 0x01,       //     Report Count (1)
+    0x75, 0x03,       //     Report Size (3)
+    0x81, 0x03,       //     Input (Constant, Variable, Absolute)
+    0x05, 0x01,       //     Usage Page (Generic Desktop)
+    0x09, 0x30,       //     Usage (X)
+    0x09, 0x31,       //     Usage (Y)
+    0x09, 0x38,       //     Usage (Wheel)
+    0x15, 0x81,       //     Logical Minimum (-127)
+    0x25, 0x7F,       //     Logical Maximum (127)
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x03,       //     Report Count (3)
+    0x81, 0x06,       //     Input (Data, Variable, Relative)
+    0xC0,             //   End Collection
+    0xC0,             // End Collection
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MouseState {
+    /// Left, right, middle, back and forward in the bottom five bits.
+    pub buttons: u8,
+    /// How far the mouse has moved since the last one, positive is right and down. This can
+    /// be more than fits in one report, the firmware spreads it over as many as it takes.
+    pub x: i16,
+    pub y: i16,
+    /// Scroll wheel clicks since the last one, positive is away from the user.
+    pub wheel: i8,
+}
+
+impl MouseState {
+    /// Add the movement in `other` on to what's still to go, taking its buttons.
+    pub fn add(&mut self, other: MouseState) {
+        self.buttons = other.buttons;
+        self.x = self.x.saturating_add(other.x);
+        self.y = self.y.saturating_add(other.y);
+        self.wheel = self.wheel.saturating_add(other.wheel);
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.x != 0 || self.y != 0 || self.wheel != 0
+    }
+
+    /// The raw input report matching [`MOUSE_DESCRIPTOR`] for as much of the movement as
+    /// fits in one, which is taken off what's still to go.
+    pub fn take_report(&mut self) -> [u8; 4] {
+        // -128 is outside the logical range
+        let x = self.x.clamp(-127, 127);
+        let y = self.y.clamp(-127, 127);
+        let wheel = self.wheel.max(-127);
+        self.x -= x;
+        self.y -= y;
+        self.wheel -= wheel;
+        [self.buttons & 0b11111, x as u8, y as u8, wheel as u8]
+    }
+}
+
+/// The highest consumer control usage the keyboard's consumer collection reports, which
+/// covers media transport, volume, brightness and the application launch and control keys.
+pub const CONSUMER_USAGE_MAX: u16 = 0x03FF;
+
+/// A consumer control, for media keys, reporting the one usage held down at a time as
+/// a 16 bit number, or 0 for none.
+#[rustfmt::skip]
+pub const CONSUMER_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0C,       // Usage Page (Consumer)
+    0x09, 0x01,       // Usage (Consumer Control)
+    0xA1, 0x01,       // Collection (Application)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xFF, 0x03, //   Logical Maximum (0x3FF)
+    0x19, 0x00,       //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03, //   Usage Maximum (0x3FF)
+    0x75, 0x10,       //   Report Size (16)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x00,       //   Input (Data, Array, Absolute)
+    0xC0,             // End Collection
+];
+
+/// Everything the client can send to the firmware, one message per COBS frame, framed by
+/// [`link::encode_host_frame`].
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum HostMessage {
+    /// Sent after connecting, the firmware echoes the nonce back so we know the link works.
+    /// It starts the count of sequence numbers again, so it's only sent as a client starts.
+    Hello { nonce: u32 },
+    /// Replace the currently pressed keys with these ones.
+    Report(WhyNoDeriveDeserializeManSadFaceHere),
+    /// The first `count` of these, one after another, packed into one frame to save on
+    /// framing while typing.
+    Reports {
+        count: u8,
+        reports: [WhyNoDeriveDeserializeManSadFaceHere; MAX_PACKED_REPORTS],
+    },
+    /// Replace the state of the gamepad with this one.
+    Gamepad(GamepadState),
+    /// Move the absolute pointer and set which of its buttons are held.
+    AbsolutePointer(AbsolutePointerState),
+    /// Sent every [`HEARTBEAT_INTERVAL_MS`] so the firmware knows we're still here, a
+    /// client which never sends one never has its keys let go of behind its back.
+    Heartbeat,
+    /// Press and release these keys after letting go of everything when the heartbeats stop,
+    /// e.g. Win+L to lock the target, or nothing if None.
+    LockChord(Option<WhyNoDeriveDeserializeManSadFaceHere>),
+    /// Hold down this consumer control usage, e.g. 0xE9 for volume up, instead of whichever
+    /// was held, or let go of it with 0.
+    Consumer(u16),
+    /// Move the relative mouse on from wherever it's got to and set which of its buttons
+    /// are held.
+    Mouse(MouseState),
+    /// Let go of everything after this many milliseconds without a frame, rather than
+    /// [`DEFAULT_IDLE_TIMEOUT_MS`], or never with 0. Once there are heartbeats it's
+    /// [`HEARTBEAT_TIMEOUT_MS`] whatever this is.
+    IdleTimeout(u32),
+    /// Switch the serial link to this baud rate, from [`DEFAULT_BAUD_RATE`] up to
+    /// [`MAX_BAUD_RATE`]. The firmware answers with a [`DeviceMessage::Baud`] at the old rate
+    /// before switching, and goes back to the default if it doesn't hear anything at the new
+    /// one within [`BAUD_CONFIRM_MS`], or once the client goes quiet.
+    SetBaud(u32),
+    /// Ask for a setting kept in flash, answered with a [`DeviceMessage::Setting`].
+    GetSetting(SettingKey),
+    /// Save a setting to flash, answered with a [`DeviceMessage::Setting`] once it's
+    /// written, which is slow enough to hold everything else up for a moment.
+    SetSetting(Setting),
+    /// Forget a setting, so it's back to its default, answered like a
+    /// [`HostMessage::SetSetting`].
+    ForgetSetting(SettingKey),
+    /// Ask which firmware this is, answered with a [`DeviceMessage::Identity`].
+    Identify,
+    /// Ask how the firmware's getting on, answered with a [`DeviceMessage::Telemetry`].
+    GetTelemetry,
+    /// Send the target a keyboard report this many milliseconds apart, from 1 up to
+    /// [`MAX_REPORT_INTERVAL_MS`], rather than [`DEFAULT_REPORT_INTERVAL_MS`]. Some older
+    /// hosts and KVMs miss keys sent as fast as they'll poll for them.
+    ReportInterval(u8),
+    /// Where the digitizer's pen is, which only goes anywhere with firmware built with
+    /// [`FirmwareFeature::Digitizer`].
+    Digitizer(DigitizerState),
+    /// Send the target each keyboard report as soon as it comes in if the last one's gone,
+    /// rather than waiting for the next report interval, or go back to waiting with false.
+    /// Cuts up to a report interval off each keystroke.
+    ImmediateReports(bool),
+    /// Type any character by its code point the way `os` lets it be, whatever the target's
+    /// layout, see [`TargetOs::codepoint_reports`].
+    TypeCodepoint { codepoint: char, os: TargetOs },
+    /// The first `count` of these, each held for as long as it says before the next, timed
+    /// by the firmware so jitter on the link doesn't change how long a key's held. They
+    /// follow on from any still playing from the last of these.
+    Scheduled {
+        count: u8,
+        reports: [ScheduledReport; MAX_SCHEDULED_REPORTS],
+    },
+    /// Answered like a [`HostMessage::Hello`], to time the round trip while connected,
+    /// without starting the count of sequence numbers again.
+    Probe { nonce: u32 },
+}
+
+/// A keyboard report, and how long the target's left with it before the next one in a
+/// [`HostMessage::Scheduled`].
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct ScheduledReport {
+    pub report: WhyNoDeriveDeserializeManSadFaceHere,
+    pub hold_ms: u16,
+}
+
+/// Everything the firmware can send back to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DeviceMessage {
+    /// Reply to a [`HostMessage::Hello`] or [`HostMessage::Probe`] carrying the same nonce.
+    HelloAck { nonce: u32 },
+    /// Passed on as it is from the agent on the target.
+    Agent(AgentMessage),
+    /// The queue of keyboard reports waiting for the target was full, so the last `dropped`
+    /// reports in the frame which just came in didn't fit and were thrown away.
+    Overflow { dropped: u8 },
+    /// The baud rate the link is switching to in answer to a [`HostMessage::SetBaud`], which
+    /// is the one it's already at if it can't do the rate asked for.
+    Baud(u32),
+    /// What's saved in flash for a setting, or None if it's never been set, in answer to
+    /// a [`HostMessage::GetSetting`]. After a [`HostMessage::SetSetting`] or
+    /// [`HostMessage::ForgetSetting`] it's still the old value if it couldn't be saved.
+    Setting(SettingKey, Option<Setting>),
+    /// Which firmware this is, in answer to a [`HostMessage::Identify`].
+    Identity(Identity),
+    /// How the firmware's getting on, in answer to a [`HostMessage::GetTelemetry`].
+    Telemetry(Telemetry),
+    /// A frame from the client arrived damaged or not at all, so the firmware's dropping
+    /// everything after it until it's sent again, starting with sequence number `expected`.
+    Nak { expected: u8 },
+    /// Every frame from the client up to and including sequence number `seq` arrived and
+    /// was acted on, sent once the firmware's caught up with a burst of them.
+    Ack { seq: u8 },
+    /// What the firmware panicked with before it last reset, sent ahead of every
+    /// [`DeviceMessage::Identity`] until it resets again, as a run of these like
+    /// [`AgentMessage::Clipboard`] from the `first` to the `last`.
+    Crashed {
+        text: AgentText,
+        first: bool,
+        last: bool,
+    },
+}
+
+/// How the firmware's getting on, the counts since it started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Telemetry {
+    /// How many keyboard reports are waiting to go to the target right now.
+    pub queued_reports: u8,
+    /// Keyboard reports thrown away as the queue was full.
+    pub reports_dropped: u32,
+    /// Frames from the client which didn't decode, or were too long to be one.
+    pub bad_frames: u32,
+    /// Times the UART lost or garbled bytes coming in, too fast to read or from line noise.
+    pub uart_errors: u32,
+    /// Keyboard reports the target's USB host didn't take in time.
+    pub usb_errors: u32,
+}
+
+/// What the firmware says about itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Identity {
+    /// The [`PROTOCOL_VERSION`] it was built with.
+    pub protocol: u16,
+    /// Its crate's version, major, minor and patch.
+    pub version: [u8; 3],
+    /// The start of the commit it was built from, all zeroes if it wasn't built from a git
+    /// checkout, and whether there were changes on top of it.
+    pub commit: [u8; 4],
+    pub dirty: bool,
+    pub features: BitFlags<FirmwareFeature>,
+    /// The chip's unique ID, which the teensy's USB serial number is made from, or zero if
+    /// the board doesn't have one.
+    pub unique_id: u64,
+}
+
+impl Identity {
+    /// For firmware at `version`, its crate's major, minor and patch, built from `commit` as
+    /// `git rev-parse HEAD` gives it, empty if it wasn't built from a git checkout.
+    pub fn new(
+        version: [&str; 3],
+        commit: &str,
+        dirty: bool,
+        features: BitFlags<FirmwareFeature>,
+        unique_id: u64,
+    ) -> Self {
+        let commit = commit.get(..8).unwrap_or_default();
+        Self {
+            protocol: PROTOCOL_VERSION,
+            version: version.map(|part| part.parse().unwrap_or(u8::MAX)),
+            commit: u32::from_str_radix(commit, 16)
+                .unwrap_or_default()
+                .to_be_bytes(),
+            dirty,
+            features,
+            unique_id,
+        }
+    }
+}
+
+/// The parts of the firmware it can be built with or without, as cargo features of the same
+/// name.
+#[bitflags]
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareFeature {
+    /// Resetting the teensy if the firmware locks up, which gets in the way of a debugger.
+    Watchdog,
+    /// Showing up on the target as a full speed USB device rather than high speed, which
+    /// some older KVMs and BIOSes get on with better.
+    FullSpeed,
+    /// Taking frames over SPI, as the target of an SBC or FPGA, instead of the UART.
+    Spi,
+    /// Taking frames over I2C, as a target on the bus, instead of the UART.
+    I2c,
+    /// Taking frames over a CAN bus instead of the UART.
+    Can,
+    /// Taking frames over TCP on the teensy 4.1's Ethernet instead of the UART.
+    Ethernet,
+    /// Playing a payload from the teensy 4.1's SD card at boot and on a button press.
+    SdPayload,
+    /// Showing the target's lock keys on LEDs wired to the teensy.
+    LockLeds,
+    /// Showing up on the target as a pen on a tablet rather than an absolute pointer.
+    Digitizer,
+    /// Scanning a key matrix wired to the teensy, whose keys go to the target along with
+    /// the client's.
+    KeyMatrix,
+}
+
+/// Something the firmware keeps in flash, so it's the same after unplugging it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Setting {
+    /// The name of the keyboard layout the target is set to, for clients which aren't told.
+    Layout(AgentText),
+    /// What the button on the teensy types on the target when it's pressed.
+    ButtonMacro(ButtonMacro),
+    /// The [`HostMessage::ReportInterval`] to start with, for targets which miss keys even
+    /// before a client has connected, like when the button's pressed.
+    ReportInterval(u8),
+}
+
+/// Which [`Setting`] a message is about. New ones go on the end, so what's already in flash
+/// still reads back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SettingKey {
+    Layout,
+    ButtonMacro,
+    ReportInterval,
+}
+
+impl SettingKey {
+    pub const ALL: [Self; 3] = [Self::Layout, Self::ButtonMacro, Self::ReportInterval];
+}
+
+impl Setting {
+    pub fn key(&self) -> SettingKey {
+        match self {
+            Self::Layout(_) => SettingKey::Layout,
+            Self::ButtonMacro(_) => SettingKey::ButtonMacro,
+            Self::ReportInterval(_) => SettingKey::ReportInterval,
+        }
+    }
+}
+
+/// The most chords a [`ButtonMacro`] holds, as many as fit in a frame.
+pub const BUTTON_MACRO_LEN: usize = 12;
+
+/// Chords pressed and released one after another, like Ctrl+Alt+Del or a KVM's escape
+/// sequence, each a modifier byte and the usage of at most one other key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ButtonMacro {
+    len: u8,
+    chords: [(u8, u8); BUTTON_MACRO_LEN],
+}
+
+impl ButtonMacro {
+    /// As many of `chords` as fit.
+    pub fn new(chords: &[(u8, u8)]) -> Self {
+        let len = chords.len().min(BUTTON_MACRO_LEN);
+        let mut button_macro = Self {
+            len: len as u8,
+            chords: [(0, 0); BUTTON_MACRO_LEN],
+        };
+        button_macro.chords[..len].copy_from_slice(&chords[..len]);
+        button_macro
+    }
+
+    pub fn chords(&self) -> &[(u8, u8)] {
+        &self.chords[..usize::from(self.len).min(BUTTON_MACRO_LEN)]
+    }
+
+    /// The keyboard reports to send the target, each chord then nothing held.
+    pub fn reports(&self) -> impl Iterator<Item = WhyNoDeriveDeserializeManSadFaceHere> + '_ {
+        self.chords().iter().flat_map(|&(modifier, key)| {
+            let pressed = WhyNoDeriveDeserializeManSadFaceHere {
+                modifier,
+                keys: [key, 0, 0, 0, 0, 0],
+            };
+            let released = WhyNoDeriveDeserializeManSadFaceHere {
+                modifier: 0,
+                keys: [0; 6],
+            };
+            [pressed, released]
+        })
+    }
+}
+
+/// The most keyboard reports [`TargetOs::codepoint_reports`] types a character with.
+pub const MAX_CODEPOINT_REPORTS: usize = 18;
+
+/// How the target's OS lets any character be typed by its code point, for
+/// [`HostMessage::TypeCodepoint`]. The hex digits are pressed where they are on a US
+/// keyboard, so they only come out right on layouts with them in the same place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TargetOs {
+    /// Ctrl+Shift+U, the code point in hex, then space, which GTK and Qt apps and anything
+    /// using IBus understand.
+    Linux,
+    /// Alt held while pressing + then the code point in hex on the keypad, which needs
+    /// `EnableHexNumpad` set to "1" under `HKCU\Control Panel\Input Method` and num lock on.
+    Windows,
+    /// The code point in hex then Alt+X, which only Word, WordPad and the like understand,
+    /// and which takes in any hex digits typed just before it.
+    WindowsAltX,
+    /// Option held while typing each UTF-16 unit as four hex digits, with the Unicode Hex
+    /// Input source selected.
+    MacOs,
+}
+
+impl TargetOs {
+    /// The keyboard reports which type `c` on this OS, leaving nothing held.
+    pub fn codepoint_reports(
+        self,
+        c: char,
+    ) -> impl Iterator<Item = WhyNoDeriveDeserializeManSadFaceHere> + Clone {
+        const LEFT_CTRL: u8 = 0x01;
+        const LEFT_SHIFT: u8 = 0x02;
+        const LEFT_ALT: u8 = 0x04;
+        const KEY_U: u8 = 0x18;
+        const KEY_X: u8 = 0x1B;
+        const KEY_SPACE: u8 = 0x2C;
+        const KEYPAD_PLUS: u8 = 0x57;
+
+        let released = WhyNoDeriveDeserializeManSadFaceHere {
+            modifier: 0,
+            keys: [0; 6],
+        };
+        let mut reports = [released; MAX_CODEPOINT_REPORTS];
+        let mut len = 0;
+        let mut push = |modifier, key| {
+            reports[len] = WhyNoDeriveDeserializeManSadFaceHere {
+                modifier,
+                keys: [key, 0, 0, 0, 0, 0],
+            };
+            len += 1;
+        };
+        let codepoint = u32::from(c);
+        match self {
+            Self::Linux => {
+                push(LEFT_CTRL | LEFT_SHIFT, KEY_U);
+                push(0, 0);
+                for digit in hex_digits(codepoint, 1) {
+                    push(0, hex_digit_usage(digit, false));
+                    push(0, 0);
+                }
+                push(0, KEY_SPACE);
+                push(0, 0);
+            }
+            Self::Windows => {
+                // alt goes down first, as it's only an alt code if it's held before the +
+                push(LEFT_ALT, 0);
+                push(LEFT_ALT, KEYPAD_PLUS);
+                push(LEFT_ALT, 0);
+                for digit in hex_digits(codepoint, 1) {
+                    push(LEFT_ALT, hex_digit_usage(digit, true));
+                    push(LEFT_ALT, 0);
+                }
+                push(0, 0);
+            }
+            Self::WindowsAltX => {
+                for digit in hex_digits(codepoint, 1) {
+                    push(0, hex_digit_usage(digit, false));
+                    push(0, 0);
+                }
+                push(LEFT_ALT, KEY_X);
+                push(0, 0);
+            }
+            Self::MacOs => {
+                push(LEFT_ALT, 0);
+                for &unit in c.encode_utf16(&mut [0; 2]).iter() {
+                    for digit in hex_digits(u32::from(unit), 4) {
+                        push(LEFT_ALT, hex_digit_usage(digit, false));
+                        push(LEFT_ALT, 0);
+                    }
+                }
+                push(0, 0);
+            }
+        }
+        reports.into_iter().take(len)
+    }
+}
+
+/// `n`'s hex digits, most significant first, with at least `min` of them.
+fn hex_digits(n: u32, min: u32) -> impl Iterator<Item = u8> {
+    let len = (8 - n.leading_zeros() / 4).max(min);
+    (0..len).rev().map(move |i| (n >> (i * 4)) as u8 & 0xF)
+}
+
+/// The usage of the key for a hex digit from 0 to 15, on the keypad for 0 to 9 if `keypad`.
+fn hex_digit_usage(digit: u8, keypad: bool) -> u8 {
+    match digit {
+        // 0 comes after 9 on the keyboard and keypad alike
+        0 if keypad => 0x62,
+        0 => 0x27,
+        1..=9 if keypad => 0x59 + digit - 1,
+        1..=9 => 0x1E + digit - 1,
+        // a to f
+        _ => 0x04 + digit - 10,
+    }
+}
+
+/// The most bytes of text an [`AgentMessage`] carries, so it still fits in a frame
+/// once it's wrapped in a [`DeviceMessage`].
+pub const AGENT_TEXT_LEN: usize = 24;
+
+/// The bits of [`AgentMessage::Leds`], the same as in a keyboard's LED output report.
+pub const LED_NUM_LOCK: u8 = 1 << 0;
+pub const LED_CAPS_LOCK: u8 = 1 << 1;
+pub const LED_SCROLL_LOCK: u8 = 1 << 2;
+
+/// Everything the optional agent running on the target can tell the client, sent one
+/// message per COBS frame to the teensy's serial port on the target's side, which passes
+/// it on over the UART.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AgentMessage {
+    /// Which lock LEDs are lit, see [`LED_CAPS_LOCK`] etc.
+    Leds(u8),
+    /// The name of the keyboard layout in use, e.g. `us` or `de`.
+    Layout(AgentText),
+    /// A piece of the clipboard's contents, which is sent as a run of these from the
+    /// `first` to the `last`, possibly the same one.
+    Clipboard {
+        text: AgentText,
+        first: bool,
+        last: bool,
+    },
+}
+
+/// Up to [`AGENT_TEXT_LEN`] bytes of UTF-8, which a longer string may have been split up
+/// into anywhere, even in the middle of a character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AgentText {
+    len: u8,
+    bytes: [u8; AGENT_TEXT_LEN],
+}
+
+impl AgentText {
+    /// As much of the start of `bytes` as fits.
+    pub fn new(bytes: &[u8]) -> Self {
+        let len = bytes.len().min(AGENT_TEXT_LEN);
+        let mut text = Self {
+            len: len as u8,
+            bytes: [0; AGENT_TEXT_LEN],
+        };
+        text.bytes[..len].copy_from_slice(&bytes[..len]);
+        text
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len).min(AGENT_TEXT_LEN)]
+    }
+}
@@ -0,0 +1,488 @@
+//! The serial link's framing, for the firmware on any board: one message per COBS frame of
+//! postcard, ending in a zero.
+//!
+//! Frames from the client also carry a sequence number before the message and a CRC after
+//! it, so the firmware notices one which arrives damaged or not at all, and asks for it
+//! again with a [`DeviceMessage::Nak`] rather than acting on whatever comes next. Those it
+//! does act on it acknowledges with a [`DeviceMessage::Ack`], so the client knows they
+//! arrived.
+
+use postcard::ser_flavors::{Cobs, Flavor, Slice};
+use serde::de::DeserializeOwned;
+
+use crate::{DeviceMessage, HostMessage, MAX_FRAME_LEN};
+
+/// How many frames in a row are dropped after a [`DeviceMessage::Nak`] before sending
+/// another, in case the first was lost or the frames sent again didn't make it either.
+const NAK_EVERY: u8 = 16;
+
+/// The little a board's UART has to do for the firmware.
+pub trait SerialLink {
+    /// The next byte received, if one's waiting.
+    fn receive(&mut self) -> Option<u8>;
+    /// Send a byte, waiting for room for it.
+    fn send(&mut self, byte: u8);
+}
+
+/// Why a frame didn't come out as a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// It went on for longer than [`MAX_FRAME_LEN`], so its start was thrown away.
+    TooLong,
+    /// It didn't decode, from line noise or a message this firmware doesn't know.
+    Undecodable,
+    /// Its CRC didn't match, so it was damaged on the way.
+    Corrupt,
+}
+
+/// A frame from the client.
+#[derive(Debug, Clone, Copy)]
+pub struct HostFrame {
+    /// Goes up by one with every frame, wrapping.
+    pub seq: u8,
+    /// None if it arrived intact but isn't a message this firmware knows.
+    pub message: Option<HostMessage>,
+}
+
+/// Collects bytes into frames, decoding each once it's complete.
+pub struct FrameReader {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameReader {
+    /// Const so it can be a task's local resource.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Add the next byte, returning the message if it's the end of a frame, or why there
+    /// wasn't one.
+    pub fn push<T: DeserializeOwned>(&mut self, byte: u8) -> Option<Result<T, FrameError>> {
+        self.push_raw(byte)
+            .map(|frame| postcard::from_bytes_cobs(frame?).map_err(|_| FrameError::Undecodable))
+    }
+
+    /// The same for a frame from the client, checking its CRC.
+    pub fn push_host(&mut self, byte: u8) -> Option<Result<HostFrame, FrameError>> {
+        self.push_raw(byte).map(|frame| {
+            let frame = frame?;
+            let len = decode_cobs(frame).ok_or(FrameError::Corrupt)?;
+            // a sequence number and a CRC at least
+            if len < 3 {
+                return Err(FrameError::Corrupt);
+            }
+            let (body, crc) = frame[..len].split_at(len - 2);
+            if crc16(body).to_le_bytes() != crc {
+                return Err(FrameError::Corrupt);
+            }
+            Ok(HostFrame {
+                seq: body[0],
+                message: postcard::from_bytes(&body[1..]).ok(),
+            })
+        })
+    }
+
+    /// Throw away whatever's been read of a frame, as the client's starting afresh.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// The whole frame once `byte` ends it, still COBS encoded.
+    fn push_raw(&mut self, byte: u8) -> Option<Result<&mut [u8], FrameError>> {
+        if self.len == self.buf.len() {
+            // something's gone wrong, start again
+            self.len = 0;
+            return Some(Err(FrameError::TooLong));
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if byte != 0 {
+            return None;
+        }
+        let len = core::mem::take(&mut self.len);
+        Some(Ok(&mut self.buf[..len]))
+    }
+}
+
+/// What to do with a frame from the client that arrived intact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Act on it, it's the next one.
+    Accept,
+    /// Drop it, as one before it went missing, and if Some tell the client which that
+    /// was with a [`DeviceMessage::Nak`].
+    Drop(Option<u8>),
+    /// Drop it, as it's one already acted on which the client sent again, but ACK it so
+    /// the client stops.
+    Duplicate,
+}
+
+/// Keeps track of the client's sequence numbers, so its frames are acted on in order and
+/// none are skipped. Once one goes missing everything after it is dropped until the client
+/// sends them all again, starting from the missing one, and any it sends again which were
+/// already acted on are dropped.
+pub struct Sequence {
+    /// The next frame's sequence number, None until we've heard from a client.
+    expected: Option<u8>,
+    /// Frames dropped since the last one accepted.
+    dropped: u8,
+    /// Whether a frame's been accepted since the last [`DeviceMessage::Ack`].
+    unacked: bool,
+    /// The sequence number and nonce of the last hello accepted.
+    hello: Option<(u8, u32)>,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sequence {
+    /// Const so it can be a task's local resource.
+    pub const fn new() -> Self {
+        Self {
+            expected: None,
+            dropped: 0,
+            unacked: false,
+            hello: None,
+        }
+    }
+
+    /// `frame` arrived intact. A hello starts the count again from it, as that's how a
+    /// client starts, unless it's the last one sent again.
+    pub fn intact(&mut self, frame: &HostFrame) -> Verdict {
+        let seq = frame.seq;
+        let hello = match frame.message {
+            Some(HostMessage::Hello { nonce }) => Some((seq, nonce)),
+            _ => None,
+        };
+        let Some(expected) = self.expected else {
+            return self.accept(seq, hello);
+        };
+        if seq == expected || hello.is_some_and(|hello| self.hello != Some(hello)) {
+            return self.accept(seq, hello);
+        }
+        // how far behind the next one it is, as they wrap
+        if seq.wrapping_sub(expected) >= 0x80 {
+            self.unacked = true;
+            return Verdict::Duplicate;
+        }
+        Verdict::Drop(self.dropped_one())
+    }
+
+    /// A frame arrived damaged, returning the sequence number to NAK, if it's time to.
+    pub fn damaged(&mut self) -> Option<u8> {
+        self.dropped_one()
+    }
+
+    /// The sequence number to ACK, if a frame's been accepted since the last one. Asked
+    /// once there's nothing more waiting, so a burst of frames only takes one.
+    pub fn ack(&mut self) -> Option<u8> {
+        let expected = self.expected?;
+        core::mem::take(&mut self.unacked).then(|| expected.wrapping_sub(1))
+    }
+
+    fn accept(&mut self, seq: u8, hello: Option<(u8, u32)>) -> Verdict {
+        self.expected = Some(seq.wrapping_add(1));
+        self.dropped = 0;
+        self.unacked = true;
+        if hello.is_some() {
+            self.hello = hello;
+        }
+        Verdict::Accept
+    }
+
+    fn dropped_one(&mut self) -> Option<u8> {
+        let expected = self.expected?;
+        let nak = self.dropped.is_multiple_of(NAK_EVERY);
+        self.dropped = self.dropped.wrapping_add(1);
+        nak.then_some(expected)
+    }
+}
+
+/// Send a message to the client, waiting until `link` has taken every byte.
+pub fn send_message(link: &mut impl SerialLink, message: &DeviceMessage) {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let Ok(frame) = postcard::to_slice_cobs(message, &mut buf) else {
+        return;
+    };
+    for &byte in frame.iter() {
+        link.send(byte);
+    }
+}
+
+/// Frame `message` as the client's `seq`th into `buf`, zero and all.
+pub fn encode_host_frame<'a>(
+    seq: u8,
+    message: &HostMessage,
+    buf: &'a mut [u8],
+) -> postcard::Result<&'a mut [u8]> {
+    let mut checked = Checked {
+        inner: Cobs::try_new(Slice::new(buf))?,
+        crc: CRC_INIT,
+    };
+    checked.try_push(seq)?;
+    postcard::serialize_with_flavor(message, checked)
+}
+
+/// CRC-16/CCITT-FALSE, which catches any burst of errors up to 16 bits long.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(CRC_INIT, |crc, &byte| crc16_update(crc, byte))
+}
+
+const CRC_INIT: u16 = 0xFFFF;
+
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+        if crc & 0x8000 != 0 {
+            (crc << 1) ^ 0x1021
+        } else {
+            crc << 1
+        }
+    })
+}
+
+/// Passes everything through to `inner`, then the CRC of it all.
+struct Checked<F> {
+    inner: F,
+    crc: u16,
+}
+
+impl<F: Flavor> Flavor for Checked<F> {
+    type Output = F::Output;
+
+    fn try_push(&mut self, byte: u8) -> postcard::Result<()> {
+        self.crc = crc16_update(self.crc, byte);
+        self.inner.try_push(byte)
+    }
+
+    fn finalize(mut self) -> postcard::Result<F::Output> {
+        for byte in self.crc.to_le_bytes() {
+            self.inner.try_push(byte)?;
+        }
+        self.inner.finalize()
+    }
+}
+
+/// Undo COBS in place on a frame ending in its zero, returning how long it is decoded.
+fn decode_cobs(frame: &mut [u8]) -> Option<usize> {
+    let end = frame.len().checked_sub(1)?;
+    let (mut read, mut write) = (0, 0);
+    while read < end {
+        let code = usize::from(frame[read]);
+        if code == 0 || read + code > end {
+            return None;
+        }
+        frame.copy_within(read + 1..read + code, write);
+        write += code - 1;
+        read += code;
+        // every block but the last and the longest stood in for a zero
+        if code < 0xFF && read < end {
+            frame[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(seq: u8) -> HostFrame {
+        HostFrame {
+            seq,
+            message: Some(HostMessage::Heartbeat),
+        }
+    }
+
+    fn hello(seq: u8, nonce: u32) -> HostFrame {
+        HostFrame {
+            seq,
+            message: Some(HostMessage::Hello { nonce }),
+        }
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let mut sequence = Sequence::default();
+        // nothing to ACK or NAK before there's a client
+        assert_eq!(sequence.ack(), None);
+        assert_eq!(sequence.damaged(), None);
+
+        assert_eq!(sequence.intact(&frame(7)), Verdict::Accept);
+        assert_eq!(sequence.ack(), Some(7));
+        // sent again as its ACK went missing, so it's ACKed again but not acted on
+        assert_eq!(sequence.intact(&frame(7)), Verdict::Duplicate);
+        assert_eq!(sequence.ack(), Some(7));
+        assert_eq!(sequence.intact(&frame(8)), Verdict::Accept);
+    }
+
+    #[test]
+    fn test_gap() {
+        let mut sequence = Sequence::default();
+        assert_eq!(sequence.intact(&frame(0)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(2)), Verdict::Drop(Some(1)));
+        for seq in 3..3 + NAK_EVERY - 1 {
+            assert_eq!(sequence.intact(&frame(seq)), Verdict::Drop(None));
+        }
+        // in case the first NAK was lost
+        let seq = 3 + NAK_EVERY - 1;
+        assert_eq!(sequence.intact(&frame(seq)), Verdict::Drop(Some(1)));
+        assert_eq!(sequence.damaged(), None);
+        assert_eq!(sequence.intact(&frame(1)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(2)), Verdict::Accept);
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let mut sequence = Sequence::default();
+        assert_eq!(sequence.intact(&frame(254)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(255)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(0)), Verdict::Accept);
+        assert_eq!(sequence.ack(), Some(0));
+        // just behind, across the wrap
+        assert_eq!(sequence.intact(&frame(255)), Verdict::Duplicate);
+        assert_eq!(sequence.intact(&frame(2)), Verdict::Drop(Some(1)));
+    }
+
+    #[test]
+    fn test_hello() {
+        let mut sequence = Sequence::default();
+        assert_eq!(sequence.intact(&hello(40, 9)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(41)), Verdict::Accept);
+        // the same hello sent again doesn't start the count again
+        assert_eq!(sequence.intact(&hello(40, 9)), Verdict::Duplicate);
+        assert_eq!(sequence.intact(&frame(41)), Verdict::Duplicate);
+        assert_eq!(sequence.intact(&frame(42)), Verdict::Accept);
+        // but a new client's does, wherever it starts
+        assert_eq!(sequence.intact(&hello(0, 10)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(1)), Verdict::Accept);
+        assert_eq!(sequence.intact(&frame(43)), Verdict::Drop(Some(2)));
+    }
+
+    #[test]
+    fn test_ack() {
+        let mut sequence = Sequence::default();
+        for seq in 10..15 {
+            assert_eq!(sequence.intact(&frame(seq)), Verdict::Accept);
+        }
+        // one for the whole burst, for the last of it
+        assert_eq!(sequence.ack(), Some(14));
+        assert_eq!(sequence.ack(), None);
+        assert_eq!(sequence.intact(&frame(15)), Verdict::Accept);
+        assert_eq!(sequence.ack(), Some(15));
+    }
+
+    /// What the last of `bytes` made of a frame, if anything.
+    fn push_all(reader: &mut FrameReader, bytes: &[u8]) -> Option<Result<HostFrame, FrameError>> {
+        bytes
+            .iter()
+            .filter_map(|&byte| reader.push_host(byte))
+            .last()
+    }
+
+    #[test]
+    fn test_crc16() {
+        // the check value for CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), CRC_INIT);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let message = HostMessage::Hello { nonce: 0x1234_5678 };
+        let encoded = encode_host_frame(3, &message, &mut buf).unwrap();
+        let mut reader = FrameReader::default();
+        let decoded = push_all(&mut reader, encoded).unwrap().unwrap();
+        assert_eq!(decoded.seq, 3);
+        assert!(matches!(
+            decoded.message,
+            Some(HostMessage::Hello { nonce: 0x1234_5678 })
+        ));
+
+        // a bit flipped anywhere is caught, by the CRC or by COBS not decoding
+        let len = encoded.len();
+        for i in 0..len - 1 {
+            for bit in 0..8 {
+                let mut flipped = [0; MAX_FRAME_LEN];
+                flipped[..len].copy_from_slice(encoded);
+                flipped[i] ^= 1 << bit;
+                if flipped[i] == 0 {
+                    continue;
+                }
+                let result = push_all(&mut reader, &flipped[..len]);
+                let error = result.unwrap().unwrap_err();
+                assert_eq!(error, FrameError::Corrupt, "byte {i} bit {bit}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_short() {
+        let mut reader = FrameReader::default();
+        // a sequence number and one byte of CRC, COBS encoded
+        let result = push_all(&mut reader, &[3, 1, 0xAA, 0]);
+        assert_eq!(result.unwrap().unwrap_err(), FrameError::Corrupt);
+        let result = push_all(&mut reader, &[0]);
+        assert_eq!(result.unwrap().unwrap_err(), FrameError::Corrupt);
+    }
+
+    #[test]
+    fn test_bad_cobs() {
+        let mut frame = [5, 1, 2, 0];
+        assert_eq!(decode_cobs(&mut frame), None);
+        // a zero can only end the frame
+        let mut frame = [0, 1, 0];
+        assert_eq!(decode_cobs(&mut frame), None);
+        let mut reader = FrameReader::default();
+        let result = push_all(&mut reader, &[5, 1, 2, 3, 0]);
+        assert_eq!(result.unwrap().unwrap_err(), FrameError::Corrupt);
+    }
+
+    #[test]
+    fn test_long_run() {
+        // more than 254 bytes without a zero, then a zero and some more
+        let mut data = [0; 300];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i % 255) as u8 + 1;
+        }
+        data[280] = 0;
+        let mut buf = [0; 310];
+        let mut cobs = Cobs::try_new(Slice::new(&mut buf)).unwrap();
+        for &byte in &data {
+            cobs.try_push(byte).unwrap();
+        }
+        let encoded = cobs.finalize().unwrap();
+        assert_eq!(encoded[0], 0xFF);
+        let len = decode_cobs(encoded).unwrap();
+        assert_eq!(&encoded[..len], &data[..]);
+    }
+
+    #[test]
+    fn test_too_long() {
+        let mut reader = FrameReader::default();
+        let junk = [0x11; MAX_FRAME_LEN + 1];
+        let result = push_all(&mut reader, &junk);
+        assert_eq!(result.unwrap().unwrap_err(), FrameError::TooLong);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let encoded = encode_host_frame(0, &HostMessage::Heartbeat, &mut buf).unwrap();
+        let decoded = push_all(&mut reader, encoded).unwrap().unwrap();
+        assert!(matches!(decoded.message, Some(HostMessage::Heartbeat)));
+    }
+}
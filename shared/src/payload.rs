@@ -0,0 +1,52 @@
+//! A payload on an SD card, which the firmware plays out to the target by itself with no
+//! client, as `client compile-payload` writes it.
+//!
+//! It's a [`HEADER_LEN`] byte header, then keyboard reports of [`REPORT_LEN`] bytes each,
+//! laid out as the target gets them: the modifier byte, a reserved zero, then six keys. The
+//! firmware sends one each report interval, so holding a chord or waiting is the same report
+//! over and over.
+
+use crate::{WhyNoDeriveDeserializeManSadFaceHere, MAX_REPORT_INTERVAL_MS};
+
+/// What the firmware looks for in the card's root directory.
+pub const FILE_NAME: &str = "PAYLOAD.BIN";
+
+pub const HEADER_LEN: usize = 8;
+pub const REPORT_LEN: usize = 8;
+
+/// Bumped if the layout after it changes.
+const MAGIC: [u8; 5] = *b"TKBP1";
+
+/// The start of the file: [`MAGIC`], how many milliseconds apart the reports go, then two
+/// reserved zeros.
+pub fn header(report_interval_ms: u8) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..MAGIC.len()].copy_from_slice(&MAGIC);
+    header[MAGIC.len()] = report_interval_ms;
+    header
+}
+
+/// How many milliseconds apart the reports go, or None if it isn't a payload this firmware
+/// can play.
+pub fn parse_header(header: &[u8; HEADER_LEN]) -> Option<u8> {
+    let (magic, rest) = header.split_at(MAGIC.len());
+    let interval_ms = rest[0];
+    let playable = magic == MAGIC && (1..=MAX_REPORT_INTERVAL_MS).contains(&interval_ms);
+    playable.then_some(interval_ms)
+}
+
+pub fn encode_report(report: WhyNoDeriveDeserializeManSadFaceHere) -> [u8; REPORT_LEN] {
+    let mut bytes = [0; REPORT_LEN];
+    bytes[0] = report.modifier;
+    bytes[2..].copy_from_slice(&report.keys);
+    bytes
+}
+
+pub fn decode_report(bytes: &[u8; REPORT_LEN]) -> WhyNoDeriveDeserializeManSadFaceHere {
+    let mut keys = [0; 6];
+    keys.copy_from_slice(&bytes[2..]);
+    WhyNoDeriveDeserializeManSadFaceHere {
+        modifier: bytes[0],
+        keys,
+    }
+}